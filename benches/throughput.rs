@@ -0,0 +1,133 @@
+// Packets per second through the whole listener: a sender on loopback keeps the socket
+// full while the logger receives, formats and writes to a file.
+//
+// cargo bench --bench throughput
+//
+// Each benchmark logs as many packets as criterion asks for and is timed from the start
+// of the run until the log is synced at the end. The sender never waits for the
+// listener, so the kernel drops whatever the listener can't keep up with, as it would
+// for real traffic; only packets actually logged are counted.
+
+use std::net::UdpSocket;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use udp_listener::{Config, FlushPolicy, Format, Output, UdpLogger, Verbosity};
+
+/// The payload of every packet sent: a typical short log line.
+const PAYLOAD: &[u8] = b"<134>1 2024-06-01T12:00:00Z host app 1234 - - benchmark message";
+
+/// Logs `packets` packets with a listener set up by `config`, returning how long it
+/// took.
+fn time_packets(config: &Config, packets: u64) -> Duration {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        bind_addr: "127.0.0.1:0".parse().unwrap(),
+        output: Output::File(dir.path().join("bench.log")),
+        verbosity: Verbosity::Quiet,
+        ..config.clone()
+    };
+    let mut logger = UdpLogger::new(config).unwrap();
+    let addr = logger.local_addr().unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let sender = {
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            while !done.load(Ordering::Relaxed) {
+                // A full socket buffer can make the send itself fail; keep going.
+                let _ = socket.send_to(PAYLOAD, addr);
+            }
+        })
+    };
+
+    let mut logged = 0;
+    let start = Instant::now();
+    logger
+        .run_with(|_| {
+            logged += 1;
+            if logged < packets {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        })
+        .unwrap();
+    let elapsed = start.elapsed();
+
+    done.store(true, Ordering::Relaxed);
+    sender.join().unwrap();
+    elapsed
+}
+
+fn formats(c: &mut Criterion) {
+    let mut group = c.benchmark_group("format");
+    group.throughput(Throughput::Elements(1));
+    for (name, format) in [
+        ("text", Format::Text),
+        ("hex", Format::Hex),
+        ("json", Format::Json),
+        ("base64", Format::Base64 { prefix: true }),
+        ("pcap", Format::Pcap),
+        ("bin", Format::Bin),
+    ] {
+        let config = Config {
+            format,
+            ..Config::default()
+        };
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_custom(|packets| time_packets(&config, packets))
+        });
+    }
+    group.finish();
+}
+
+fn flush_policies(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flush");
+    group.throughput(Throughput::Elements(1));
+    for (name, flush) in [
+        ("every", FlushPolicy::Every),
+        (
+            "interval-100ms",
+            FlushPolicy::Interval(Duration::from_millis(100)),
+        ),
+        ("never", FlushPolicy::Never),
+    ] {
+        let config = Config {
+            flush,
+            ..Config::default()
+        };
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_custom(|packets| time_packets(&config, packets))
+        });
+    }
+    group.finish();
+}
+
+fn workers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("workers");
+    group.throughput(Throughput::Elements(1));
+    for workers in [0, 1, 4] {
+        let config = Config {
+            workers,
+            ..Config::default()
+        };
+        group.bench_function(BenchmarkId::from_parameter(workers), |b| {
+            b.iter_custom(|packets| time_packets(&config, packets))
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // Each sample starts a listener, so fewer, longer samples than the default.
+    config = Criterion::default().sample_size(20).measurement_time(Duration::from_secs(5));
+    targets = formats, flush_policies, workers
+}
+criterion_main!(benches);
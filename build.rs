@@ -0,0 +1,56 @@
+// Records where the binary came from, for `--version`: the git commit it was built
+// from and when it was built.
+
+use std::process::Command;
+
+fn main() {
+    let commit = git(&["rev-parse", "--short=12", "HEAD"]).map(|sha| {
+        // Uncommitted changes mean the binary doesn't match that commit exactly.
+        let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+            .is_some_and(|status| !status.is_empty());
+        if dirty { format!("{}-dirty", sha) } else { sha }
+    });
+    println!(
+        "cargo:rustc-env=UDP_LISTENER_GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+
+    // `SOURCE_DATE_EPOCH` pins the timestamp for reproducible builds.
+    let built_at = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .expect("SOURCE_DATE_EPOCH is a number of seconds"),
+        Err(_) => chrono::Utc::now(),
+    };
+    println!(
+        "cargo:rustc-env=UDP_LISTENER_BUILD_TIME={}",
+        built_at.format("%Y-%m-%dT%H:%M:%SZ")
+    );
+
+    // Rerun whenever the sources change, so the timestamp and the dirty marker stay
+    // current, and whenever git moves to another commit: that changes HEAD or the
+    // branch it points at. Without a list, Cargo would rerun for any file in the
+    // package, including logs written into the checkout.
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        for path in ["HEAD", "index", "refs/heads", "packed-refs"] {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, path);
+        }
+    }
+}
+
+/// The trimmed output of a git command, or `None` if git isn't installed or this isn't
+/// a git checkout, as when building from a published crate.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
@@ -0,0 +1,56 @@
+// Captures packets from inside a mio event loop, with no thread of its own.
+//
+// cargo run --example mio_app --features mio
+//
+// Packets sent to 127.0.0.1:8080 are logged to `udp_packets.log`. The loop wakes at
+// least once a second for the application's own work (here, a message every few
+// seconds), and Ctrl-C stops it.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use mio::{Events, Interest, Poll, Token};
+use udp_listener::{Config, RecvOutcome, UdpLogger};
+
+const LISTENER: Token = Token(0);
+
+fn main() -> std::io::Result<()> {
+    let mut logger = UdpLogger::new(Config::default())?;
+    let mut poll = Poll::new()?;
+    poll.registry()
+        .register(&mut logger, LISTENER, Interest::READABLE)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&stop))?;
+
+    let mut events = Events::with_capacity(64);
+    let mut last_tick = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        match poll.poll(&mut events, Some(Duration::from_secs(1))) {
+            Ok(()) => {}
+            // Ctrl-C interrupts the wait; the loop condition then sees the flag.
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        for event in &events {
+            if event.token() == LISTENER {
+                // Readiness is edge-triggered, so take everything that's waiting.
+                while let RecvOutcome::Packet(packet) = logger.poll_recv()? {
+                    // Failures have already been reported.
+                    let _ = logger.log_packet(&packet);
+                }
+            }
+        }
+
+        // Stand-in for the application's own work.
+        if last_tick.elapsed() >= Duration::from_secs(5) {
+            println!("application is still running");
+            last_tick = Instant::now();
+        }
+    }
+
+    poll.registry().deregister(&mut logger)?;
+    println!("Shutting down: {}", logger.stats().summary());
+    Ok(())
+}
@@ -0,0 +1,38 @@
+// Runs the listener as one task inside an existing tokio application.
+//
+// cargo run --example tokio_app --features tokio
+//
+// Packets sent to 127.0.0.1:8080 are logged to `udp_packets.log` while the rest of the
+// application (here, a ticker) keeps running. Ctrl-C stops both.
+
+use std::time::Duration;
+
+use udp_listener::{Config, run_async};
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    // The listener is just another task on the runtime.
+    let mut listener = tokio::spawn(run_async(Config::default()));
+
+    // Stand-in for the application's own work.
+    let ticker = tokio::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            println!("application is still running");
+        }
+    });
+
+    tokio::select! {
+        // `run_async` only finishes on its own if the listener fails, for example
+        // because the port is already in use.
+        finished = &mut listener => finished.map_err(std::io::Error::other)??,
+        signal = tokio::signal::ctrl_c() => {
+            signal?;
+            listener.abort();
+        }
+    }
+    ticker.abort();
+    println!("Shutting down");
+    Ok(())
+}
@@ -0,0 +1,286 @@
+// The receive loop on tokio, for embedding the listener in an async application.
+
+use std::io;
+use std::sync::Arc;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::UdpSocket;
+
+use crate::config::{Config, FlushPolicy, Format, Output, Verbosity, create_log_file};
+use crate::console::Console;
+use crate::diag;
+use crate::format::format_entry;
+use crate::limit::SourceLimiter;
+use crate::logger::{accept, decode};
+use crate::packet::Packet;
+use crate::pcap::{PcapWriter, global_header as pcap_global_header};
+use crate::records::{capture_header, encode_record};
+use crate::sample::Sampler;
+use crate::socket::{
+    RecvErrorAction, bind_socket, classify_recv_error, is_icmp_error, join_multicast,
+};
+use crate::stats::Stats;
+
+/// Receives and logs packets on the current tokio runtime, the async counterpart of
+/// [`UdpLogger::run`](crate::UdpLogger::run).
+///
+/// The socket is bound and the packets are filtered, formatted and echoed exactly as in
+/// the blocking logger. The log is written with `tokio::fs::File` (or tokio's stdout),
+/// so writes don't block the runtime's threads.
+///
+/// The future runs until it's dropped, so stop it with `tokio::select!` or by aborting
+/// its task. It returns early only if the listener can't start or a receive fails
+/// fatally. Rotation, splitting by source, an output directory, worker threads,
+/// buffered flushing, syncing to disk, host name lookups, GeoIP lookups, capture
+/// triggers, reassembly, deduplication, forwarding, compression, traffic alerts,
+/// sequence number tracking, latency measurement, recording IP header fields, more
+/// than one bind address, the packet ring, the metrics server, the event socket and the
+/// SQLite database aren't available here, and asking for them is an `InvalidInput`
+/// error.
+///
+/// ```no_run
+/// # async fn example() -> std::io::Result<()> {
+/// use udp_listener::{Config, run_async};
+///
+/// tokio::select! {
+///     result = run_async(Config::default()) => result?,
+///     _ = tokio::signal::ctrl_c() => {}
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn run_async(mut config: Config) -> io::Result<()> {
+    if config.max_log_size.is_some() || config.rotate.is_some() {
+        return Err(unsupported("log rotation"));
+    }
+    if config.split_by_source.is_some() {
+        return Err(unsupported("splitting by source"));
+    }
+    if config.output_dir.is_some() {
+        return Err(unsupported("an output directory"));
+    }
+    if config.workers > 0 {
+        return Err(unsupported("worker threads"));
+    }
+    if config.resolve {
+        return Err(unsupported("resolving host names"));
+    }
+    if config.geoip.is_some() {
+        return Err(unsupported("GeoIP lookups"));
+    }
+    if config.ring_size.is_some() {
+        return Err(unsupported("a packet ring"));
+    }
+    if config.metrics_addr.is_some() {
+        return Err(unsupported("serving metrics"));
+    }
+    if config.stats_file.is_some() {
+        return Err(unsupported("a stats file"));
+    }
+    if config.event_socket.is_some() {
+        return Err(unsupported("an event socket"));
+    }
+    if config.triggers.start.is_some() || config.triggers.stop.is_some() {
+        return Err(unsupported("capture triggers"));
+    }
+    if config.output_sqlite.is_some() {
+        return Err(unsupported("an SQLite database"));
+    }
+    if config.reassemble.is_some() {
+        return Err(unsupported("reassembly"));
+    }
+    if config.dedup_window.is_some() {
+        return Err(unsupported("deduplication"));
+    }
+    if !config.forward.is_empty() {
+        return Err(unsupported("forwarding"));
+    }
+    if config.compress.is_some() || config.compress_rotated {
+        return Err(unsupported("compression"));
+    }
+    if config.alert_pps.is_some() || config.alert_bps.is_some() {
+        return Err(unsupported("traffic alerts"));
+    }
+    if config.seq_offset.is_some() {
+        return Err(unsupported("sequence number tracking"));
+    }
+    if config.latency_offset.is_some() {
+        return Err(unsupported("latency measurement"));
+    }
+    if config.ip_fields || config.dst_ip {
+        return Err(unsupported("recording IP header fields"));
+    }
+    if !config.extra_binds.is_empty() {
+        return Err(unsupported("more than one bind address"));
+    }
+    if config.connect.is_some() {
+        return Err(unsupported("connecting to a peer"));
+    }
+    if config.throttle.is_some() {
+        return Err(unsupported("throttling"));
+    }
+    // The future is stopped by dropping it, which leaves no chance for a final flush,
+    // so only flushing after every packet is safe.
+    if config.flush != FlushPolicy::Every {
+        return Err(unsupported("a flush policy other than 'every'"));
+    }
+    if config.fsync {
+        return Err(unsupported("syncing the log to disk"));
+    }
+
+    // Binding goes through the same code as the blocking logger, so dual-stack and
+    // multicast settings behave identically. Tokio needs the socket in non-blocking mode.
+    let socket = bind_socket(&config, config.bind_addr)?;
+    join_multicast(&socket, &config.multicast)?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket)?;
+    let local_addr = socket.local_addr()?;
+
+    // The file is opened like the blocking logger's, so an existing one is dealt with
+    // the same way; only the writing is asynchronous.
+    let (mut out, has_header): (Box<dyn AsyncWrite + Unpin + Send>, bool) = match &config.output {
+        Output::Stdout => (Box::new(tokio::io::stdout()), false),
+        Output::File(path) => {
+            let file = create_log_file(path, config.existing_log)?;
+            let has_content = file.metadata()?.len() > 0;
+            (Box::new(tokio::fs::File::from_std(file)), has_content)
+        }
+    };
+
+    // Pcap records are assembled by the same `PcapWriter` as the blocking logger, into
+    // a buffer that is then written asynchronously.
+    let mut pcap = PcapWriter::headerless(Vec::new(), local_addr);
+    let session_id = config.session_id();
+    match config.format {
+        _ if has_header => {}
+        Format::Pcap => out.write_all(&pcap_global_header()).await?,
+        Format::Bin => out.write_all(&capture_header(&session_id)).await?,
+        _ => {}
+    }
+
+    let mut console = Console::new(&config);
+    let mut stats = Stats::new().with_max_sources(config.max_tracked_sources);
+    stats.session_id = Some(Arc::clone(&session_id));
+    let mut sampler = config.sample.map(Sampler::new);
+    let mut limiter = config
+        .per_source_limit
+        .map(|pps| SourceLimiter::new(pps, config.max_tracked_sources));
+    let started = config.clock.instant();
+    let mut last_index = 0;
+    let mut buf = vec![0; config.buffer_size.max(1)];
+    loop {
+        let (number_of_bytes, src) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) if is_icmp_error(&e) => {
+                stats.icmp_errors += 1;
+                if config.verbosity == Verbosity::Verbose {
+                    eprintln!("Ignored an ICMP error for an earlier echo: {}", e);
+                }
+                continue;
+            }
+            Err(e) => match classify_recv_error(&e) {
+                RecvErrorAction::Retry => continue,
+                RecvErrorAction::Ignore => {
+                    diag::error!("Error receiving packet: {}", e);
+                    continue;
+                }
+                RecvErrorAction::Fatal => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("Stopped receiving, as the socket can't be read: {}", e),
+                    ));
+                }
+            },
+        };
+        let mut pkt = Packet::received(
+            buf[..number_of_bytes].to_vec(),
+            buf.len(),
+            src,
+            config.clock.now(),
+            &mut stats,
+        );
+        pkt.monotonic = Some(config.clock.instant().saturating_duration_since(started));
+        pkt.session_id = Some(Arc::clone(&session_id));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(src = %pkt.src, len = pkt.original_len, "received a packet");
+        if !accept(&pkt, &config, &mut stats) {
+            continue;
+        }
+        pkt.checksum = config.checksum.map(|algorithm| algorithm.of(&pkt.data));
+        decode(&mut pkt, &config, local_addr.port(), &mut stats);
+
+        // The echo carries the whole payload; only what's logged is cut to the snap length.
+        if config.echo {
+            match socket.send_to(&pkt.data, pkt.src).await {
+                Ok(_) => stats.echoed += 1,
+                Err(e) => {
+                    stats.echo_failures += 1;
+                    diag::error!("Error echoing packet to {}: {}", pkt.src, e);
+                }
+            }
+        }
+        if !sampler.as_mut().is_none_or(Sampler::keep) {
+            stats.unsampled += 1;
+            continue;
+        }
+        if let Some(limiter) = &mut limiter
+            && !limiter.allow(pkt.src)
+        {
+            stats.rate_limited += 1;
+            continue;
+        }
+        if let Some(snaplen) = config.snaplen {
+            pkt.snap(snaplen);
+        }
+        if config.index {
+            last_index += 1;
+            pkt.index = Some(last_index);
+        }
+
+        if let Some(console) = &mut console
+            && let Err(e) = console.print_packet(&pkt)
+        {
+            diag::error!("Error writing to the console: {}", e);
+        }
+        let written = match config.format {
+            Format::Pcap => {
+                pcap.get_mut().clear();
+                match pcap.write_logged(&pkt) {
+                    Ok(()) => write_entry(&mut out, pcap.get_mut()).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Format::Bin => write_entry(&mut out, &encode_record(&pkt)).await,
+            _ => {
+                write_entry(
+                    &mut out,
+                    format_entry(
+                        &pkt,
+                        config.format,
+                        &config.time_format,
+                        &config.line_ending,
+                    )
+                    .as_bytes(),
+                )
+                .await
+            }
+        };
+        if let Err(e) = written {
+            stats.write_errors += 1;
+            diag::error!("Error writing to {}: {}", config.output, e);
+        }
+    }
+}
+
+/// Writes one entry and flushes it, like the blocking logger does for every packet.
+async fn write_entry(out: &mut (dyn AsyncWrite + Unpin + Send), entry: &[u8]) -> io::Result<()> {
+    out.write_all(entry).await?;
+    out.flush().await
+}
+
+fn unsupported(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} isn't supported by run_async", feature),
+    )
+}
@@ -0,0 +1,136 @@
+// Listening on several addresses at once, with a thread receiving on each socket.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, SendTimeoutError};
+
+use crate::ip_fields::IpFields;
+use crate::logger::recv_datagram;
+use crate::pool::BufferPool;
+use crate::socket::{RecvErrorAction, classify_recv_error};
+
+/// How many datagrams received by the threads may wait for the logger to take them.
+/// Beyond this the threads wait, and new datagrams queue in the sockets' own buffers.
+pub(crate) const QUEUED_DATAGRAMS: usize = 1024;
+
+/// A datagram received by one of the threads.
+pub(crate) struct Datagram {
+    /// What was received, at most the buffer size, in a buffer from the pool.
+    pub(crate) data: Vec<u8>,
+    pub(crate) src: SocketAddr,
+    pub(crate) fields: IpFields,
+    /// The address of the socket it arrived on.
+    pub(crate) bind: SocketAddr,
+}
+
+/// Receives on every bound socket at once, for [`Config::extra_binds`](crate::Config::extra_binds),
+/// and hands the datagrams to the logger through one queue in the order they arrived.
+///
+/// Each socket is read by a thread of its own. Receive errors are queued like
+/// datagrams, for the logger to report or stop on; a thread stops after a fatal one.
+/// The threads run until the `BindReceivers` is dropped.
+pub(crate) struct BindReceivers {
+    datagrams: Receiver<io::Result<Datagram>>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BindReceivers {
+    /// Starts a thread for each of `sockets`, receiving into buffers of `buffer_size`
+    /// bytes, with the IP header fields if `with_ip_fields` is set, and copying each
+    /// payload into a buffer from `buffers`. The sockets' read timeout, and
+    /// `poll_interval` while the queue is full, bound how long a thread takes to notice
+    /// it's to stop.
+    pub(crate) fn spawn<'a>(
+        sockets: impl IntoIterator<Item = &'a UdpSocket>,
+        buffer_size: usize,
+        with_ip_fields: bool,
+        buffers: Arc<BufferPool>,
+        poll_interval: Duration,
+    ) -> io::Result<BindReceivers> {
+        let (queue, datagrams) = crossbeam_channel::bounded(QUEUED_DATAGRAMS);
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::new();
+        for socket in sockets {
+            let socket = socket.try_clone()?;
+            let bind = socket.local_addr()?;
+            let queue = queue.clone();
+            let stop = Arc::clone(&stop);
+            let buffers = Arc::clone(&buffers);
+            let thread = thread::Builder::new()
+                .name(format!("recv-{}", bind))
+                .spawn(move || {
+                    let mut buf = vec![0; buffer_size];
+                    while !stop.load(Ordering::Relaxed) {
+                        let received = match recv_datagram(&socket, &mut buf, with_ip_fields, None)
+                        {
+                            Ok((len, src, fields)) => Ok(Datagram {
+                                data: buffers.copy_of(&buf[..len.min(buf.len())]),
+                                src,
+                                fields,
+                                bind,
+                            }),
+                            Err(e) => match classify_recv_error(&e) {
+                                RecvErrorAction::Retry => continue,
+                                _ => Err(e),
+                            },
+                        };
+                        let fatal = received
+                            .as_ref()
+                            .is_err_and(|e| classify_recv_error(e) == RecvErrorAction::Fatal);
+                        // Wait for room in the queue, but not past a request to stop.
+                        let mut item = received;
+                        loop {
+                            match queue.send_timeout(item, poll_interval) {
+                                Ok(()) => break,
+                                Err(SendTimeoutError::Timeout(back)) => {
+                                    if stop.load(Ordering::Relaxed) {
+                                        return;
+                                    }
+                                    item = back;
+                                }
+                                Err(SendTimeoutError::Disconnected(_)) => return,
+                            }
+                        }
+                        if fatal {
+                            return;
+                        }
+                    }
+                })?;
+            threads.push(thread);
+        }
+        Ok(BindReceivers {
+            datagrams,
+            stop,
+            threads,
+        })
+    }
+
+    /// The next datagram or receive error from any of the sockets, or `None` if nothing
+    /// arrived within `timeout`.
+    pub(crate) fn recv(&self, timeout: Duration) -> io::Result<Option<Datagram>> {
+        match self.datagrams.recv_timeout(timeout) {
+            Ok(received) => received.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            // Every thread has stopped, each after a fatal error already handed on.
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "no socket is being received from",
+            )),
+        }
+    }
+}
+
+impl Drop for BindReceivers {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
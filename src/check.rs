@@ -0,0 +1,159 @@
+// `--check`: looking over the settings for problems without starting the listener.
+
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use udp_listener::{Config, ExistingLog, Output};
+
+use crate::cli::Process;
+
+/// Looks for what would stop the listener once it has started, without binding a
+/// socket or creating a file: bind addresses this machine doesn't have, files and
+/// directories it couldn't write, and files it couldn't read. The options themselves,
+/// such as filter ranges and patterns, were already checked when they were parsed.
+/// Returns one message for each problem found.
+pub fn check(config: &Config, process: &Process) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let binds = std::iter::once(&config.bind_addr).chain(&config.extra_binds);
+    problems.extend(binds.filter_map(|&addr| check_bind(addr).err()));
+
+    let mut writes = Vec::new();
+    match (&config.split_by_source, &config.output_dir, &config.output) {
+        (Some(dir), _, _) | (None, Some(dir), _) => {
+            problems.extend(check_directory("the log directory", dir).err());
+        }
+        (None, None, Output::File(path)) => writes.push(("the log", path.as_path())),
+        (None, None, Output::Stdout) => {}
+    }
+    writes.extend(config.output_sqlite.as_deref().map(|p| ("the database", p)));
+    writes.extend(config.stats_file.as_deref().map(|p| ("the stats file", p)));
+    writes.extend(
+        config
+            .event_socket
+            .as_deref()
+            .map(|p| ("the event socket", p)),
+    );
+    writes.extend(process.daemon_log.as_deref().map(|p| ("the daemon log", p)));
+    writes.extend(process.pid_file.as_deref().map(|p| ("the PID file", p)));
+    writes.extend(process.summary_csv.as_deref().map(|p| ("the summary", p)));
+    problems.extend(
+        writes
+            .into_iter()
+            .filter_map(|(what, path)| check_creatable(what, path).err()),
+    );
+
+    if config.existing_log == ExistingLog::Refuse
+        && config.split_by_source.is_none()
+        && config.output_dir.is_none()
+        && let Output::File(path) = &config.output
+        && path.exists()
+    {
+        problems.push(format!(
+            "The log '{}' already exists, and --no-clobber won't overwrite it",
+            path.display()
+        ));
+    }
+    if let Some(path) = &config.geoip
+        && let Err(e) = fs::File::open(path)
+    {
+        problems.push(format!(
+            "Couldn't read the GeoIP database '{}': {}",
+            path.display(),
+            e
+        ));
+    }
+    if config.output_sqlite.is_some() && !cfg!(feature = "sqlite") {
+        problems.push("--output-sqlite needs a build with the 'sqlite' feature".to_string());
+    }
+    problems
+}
+
+/// A bind address must be a wildcard, a multicast group or one of this machine's own
+/// addresses.
+fn check_bind(addr: SocketAddr) -> Result<(), String> {
+    let ip = addr.ip();
+    if ip.is_unspecified() || ip.is_multicast() {
+        return Ok(());
+    }
+    // If the interfaces can't be listed, binding will tell.
+    let Ok(interfaces) = if_addrs::get_if_addrs() else {
+        return Ok(());
+    };
+    let local = |ip: IpAddr| interfaces.iter().any(|interface| interface.ip() == ip);
+    if local(ip) || local(ip.to_canonical()) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Couldn't bind to {}: {} isn't an address of this machine",
+            addr, ip
+        ))
+    }
+}
+
+/// `dir` must be an existing directory that files can be created in.
+fn check_directory(what: &str, dir: &Path) -> Result<(), String> {
+    let problem = |e: io::Error| format!("Couldn't write {} '{}': {}", what, dir.display(), e);
+    if !fs::metadata(dir).map_err(problem)?.is_dir() {
+        return Err(problem(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            "not a directory",
+        )));
+    }
+    writable(dir).map_err(problem)
+}
+
+/// `path` must be a file that can be written, or not exist yet in a directory where it
+/// can be created.
+fn check_creatable(what: &str, path: &Path) -> Result<(), String> {
+    let problem = |e: io::Error| format!("Couldn't write {} '{}': {}", what, path.display(), e);
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Err(problem(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            "it's a directory",
+        ))),
+        Ok(_) => writable(path).map_err(problem),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            match fs::metadata(parent) {
+                Ok(metadata) if metadata.is_dir() => writable(parent).map_err(problem),
+                Ok(_) => Err(problem(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!("'{}' isn't a directory", parent.display()),
+                ))),
+                Err(e) => Err(problem(e)),
+            }
+        }
+        Err(e) => Err(problem(e)),
+    }
+}
+
+/// Whether this process may write to `path`, which exists, asked of the system rather
+/// than by trying.
+#[cfg(unix)]
+fn writable(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a NUL-terminated string that outlives the call.
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn writable(path: &Path) -> io::Result<()> {
+    if fs::metadata(path)?.permissions().readonly() {
+        Err(io::Error::from(io::ErrorKind::PermissionDenied))
+    } else {
+        Ok(())
+    }
+}
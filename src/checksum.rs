@@ -0,0 +1,54 @@
+// Checksums of payloads, for comparing captures of the same traffic.
+
+use std::fmt;
+
+use crate::config::Checksum;
+
+/// The checksum of a packet's payload, when [`Config::checksum`](crate::Config::checksum)
+/// is set. Shown as `crc32=1a2b3c4d` or `xxhash=0123456789abcdef`, in the same hex the
+/// usual command-line tools print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadChecksum {
+    /// How it was computed.
+    pub algorithm: Checksum,
+    /// The checksum, in the low 32 bits for CRC-32.
+    pub value: u64,
+}
+
+impl Checksum {
+    /// Checksums `data`.
+    pub(crate) fn of(self, data: &[u8]) -> PayloadChecksum {
+        let value = match self {
+            Checksum::Crc32 => u64::from(crc32fast::hash(data)),
+            Checksum::Xxhash => xxhash_rust::xxh64::xxh64(data, 0),
+        };
+        PayloadChecksum {
+            algorithm: self,
+            value,
+        }
+    }
+
+    /// The name the checksum is logged under.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Checksum::Crc32 => "crc32",
+            Checksum::Xxhash => "xxhash",
+        }
+    }
+}
+
+impl PayloadChecksum {
+    /// The value in hex, padded to the checksum's full width.
+    pub fn hex(&self) -> String {
+        match self.algorithm {
+            Checksum::Crc32 => format!("{:08x}", self.value),
+            Checksum::Xxhash => format!("{:016x}", self.value),
+        }
+    }
+}
+
+impl fmt::Display for PayloadChecksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.algorithm.name(), self.hex())
+    }
+}
@@ -0,0 +1,1001 @@
+// Command-line argument parsing for the `udp_listener` binary.
+
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use udp_listener::{
+    Config, DEFAULT_BIND_ADDR, DEFAULT_DEDUP_WINDOW, ExistingLog, FlushPolicy, Format,
+    LengthFilter, Output, ParseMap, Payload, ReplayConfig, Sample, SendConfig, Verbosity,
+    check_per_source_limit, check_session_id, check_throttle, check_time_pattern, load_config,
+    parse_ip_net, parse_payload_hex, parse_payload_regex,
+};
+
+/// Text printed for `--help`.
+pub const USAGE: &str = "\
+Usage: udp_listener [OPTIONS]
+       udp_listener send --to <ADDR> [OPTIONS]
+       udp_listener replay <FILE> --to <ADDR> [OPTIONS]
+
+Listen for UDP datagrams and log them to a file. The `send` command sends test
+traffic instead, and `replay` sends a capture made with --format bin; see
+`udp_listener send --help` and `udp_listener replay --help`.
+
+Options:
+      --config <FILE>  Read settings from a TOML file, keyed by option name (e.g.
+                       bind = \"0.0.0.0:5140\"); options given here override it
+      --check          Check the settings without binding or writing anything, print
+                       them and exit: nonzero if the listener couldn't start with them
+  -b, --bind <ADDR>    Address and port to listen on; repeat to listen on several at
+                       once [default: 127.0.0.1:8080]
+                       Use [::]:PORT to receive both IPv6 and IPv4 on one socket
+      --allow <CIDR>   Only log sources in this range; repeat to allow several
+      --deny <CIDR>    Never log sources in this range (wins over --allow); repeatable
+      --min-len <BYTES>
+                       Drop datagrams with fewer payload bytes; 1 drops empty ones
+      --max-len <BYTES>
+                       Drop datagrams with more payload bytes than this
+      --match <REGEX>  Only log payloads matching this regular expression; repeat to
+                       log payloads matching any of several
+      --match-hex <HEX>
+                       Only log payloads containing these bytes, e.g. deadbeef;
+                       combines with --match like a repeat of it
+      --filter <EXPR>  Only log packets meeting this condition, e.g.
+                       \"src net 10.0.0.0/8 and len > 100 and payload ~ /ERROR/\"
+      --start-trigger <HEX>
+                       Ignore everything until a payload containing these bytes
+                       arrives
+      --stop-trigger <HEX>
+                       Stop once a payload containing these bytes arrives
+      --log-triggers   Also log the trigger packets themselves
+      --dedup          Don't log a datagram that repeats one from the same source
+                       within the dedup window
+      --dedup-window <MS>
+                       How long a repeat counts as a duplicate [default: 1000]
+      --checksum <ALGO>
+                       Log a checksum of each payload: 'crc32' or 'xxhash'
+      --index          Number the logged packets from 1, as '#N' before each text
+                       record and an \"idx\" field in JSON
+      --reassemble <ID,INDEX,TOTAL>
+                       Log whole messages split into chunks, each starting with a
+                       header of these big-endian field sizes, e.g. 4,2,2
+      --reassemble-timeout <MS>
+                       How long to wait for a message's missing chunks [default: 5000]
+      --buffer-size <BYTES>
+                       Largest datagram received in full [default: 65535]
+      --snaplen <BYTES>
+                       Log only the first BYTES of each payload, keeping its real length
+      --recv-buffer <BYTES>
+                       Kernel socket receive buffer to ask for, to survive bursts
+      --resolve        Show each source's host name (reverse DNS) in the log
+      --geoip <MMDB>   Show each source's country and city from a MaxMind database
+      --echo           Send each received datagram back to its sender
+      --forward <ADDR> Also send each logged datagram, unchanged, to ADDR; repeat to
+                       forward to several
+      --ipv6-only      With an IPv6 bind address, don't also receive IPv4
+      --ip-fields      Log the TTL (hop limit) and ToS byte each datagram arrived with
+                       (Linux only)
+      --dst-ip         Log which local address each datagram was sent to, and echo
+                       from it; for wildcard binds (Linux only)
+      --reuse-port     Share the port with other listeners started the same way; the
+                       kernel spreads the senders across them (Linux only)
+      --interface <NAME>
+                       Receive only datagrams arriving on the network interface NAME,
+                       such as eth1 (Linux only)
+      --connect <ADDR> Receive only from ADDR, over a connected socket that also
+                       reports ICMP errors such as port unreachable from it
+      --multicast <GROUP>[,<IFACE>]
+                       Join a multicast group; repeat to join several. IFACE is a
+                       local IPv4 address or an IPv6 interface index
+  -o, --output <PATH>  File to write log lines to, or '-' for stdout [default: udp_packets.log]
+      --append         Add to the end of an existing log file instead of truncating it
+      --no-clobber     Refuse to start if the log file already exists
+  -f, --format <FMT>   How packet data is written: 'text', 'hex', 'pcap', 'json',
+                       'bin' (binary records) or 'base64' (one payload per line)
+                       [default: text]
+      --base64-prefix  Start each base64 line with the time and source
+      --line-ending <END>
+                       What ends each text, hex, JSON or base64 record: 'lf', 'crlf',
+                       'nul' or 'custom:<TEXT>', where TEXT may use \\n, \\r, \\t, \\0,
+                       \\\\ and \\xHH [default: lf]
+      --parse <PROTO>  Decode payloads and log their fields: 'syslog', 'dns', 'dtls'
+                       (record headers only) or 'ntp'. Payloads that don't decode are
+                       logged raw
+      --parse-map <PORT=PROTO,...>
+                       Decode what arrives on each bound PORT as PROTO, and other
+                       ports as --parse says, such as '53=dns,514=syslog'
+      --flush <POLICY> When to flush the log: 'every' packet, 'interval:<MS>' or
+                       'never' (only when the buffer fills and at exit) [default: every]
+      --write-buffer <BYTES>
+                       With a buffered --flush, size of the buffer entries collect in
+                       [default: 65536]
+      --fsync          Wait for each flush to reach the disk, so entries survive an
+                       OS crash; slow with --flush every (intervals always sync)
+      --timezone <TZ>  Clock for log timestamps: 'local' or 'utc' [default: local]
+      --session-id <ID>
+                       Name this run in JSON records, binary captures and the summary
+                       [default: a random UUID]
+      --time-format <PATTERN>
+                       strftime pattern for timestamps, e.g. '%Y-%m-%dT%H:%M:%S%.3fZ'
+      --compress <ALGO>
+                       Compress the log, each rotated file separately: 'gzip'.
+                       Works best with a buffered --flush
+      --compress-rotated
+                       Gzip each file in the background once it has been rotated,
+                       while capture carries on in the next
+      --max-size <BYTES>
+                       Rotate the output file once it reaches this size
+      --max-files <N>  Number of rotated files (.1, .2, ...) to keep [default: 5]
+      --rotate <PERIOD>
+                       Start a new dated output file 'daily' or 'hourly'. Rotated
+                       logs keep <OUTPUT>.current linked to the file being written,
+                       or, without symbolic links, holding its name
+      --split-by-source <DIR>
+                       Write each source to its own file in DIR instead of --output
+      --output-dir <DIR>
+                       Write numbered segments, split by --max-size and --rotate, to
+                       DIR instead of --output, with an index in DIR/manifest.json
+      --max-open-files <N>
+                       Per-source files kept open at once [default: 64]
+      --idle-timeout <SECS>
+                       Exit once no datagram has arrived for this many seconds
+      --count <N>      Exit after capturing N packets that pass the filters
+      --count-includes-filtered
+                       Count filtered packets towards --count too
+      --ring <N>       Keep only the last N packets in memory and write them to the
+                       log on SIGUSR2, instead of logging every packet
+      --workers <N>    Write packets on N threads so receiving never waits for the log
+                       [default: 0, receive and write on one thread]
+      --queue-size <N> With --workers, how many packets may wait to be written
+                       [default: 1024]
+      --on-overflow <POLICY>
+                       With --workers, when the queue is full: 'block' or 'drop'
+                       [default: block]
+  -q, --quiet          Don't print each packet on the console, only startup and
+                       shutdown messages; the log still gets every packet
+  -v, --verbose        Also show each packet's receive time, and whether it was
+                       truncated or failed to decode, on the console
+      --print-packets  Print each packet on the console even when stdout isn't a
+                       terminal; by default they're only printed to a terminal
+      --console-sample <N>
+                       Print only one in N packets on the console
+      --console-format <FMT>
+                       How the console shows each packet, like --format: 'hex' for
+                       a dump while the log is pcap [default: the log's format]
+      --tui            Show a live dashboard of rates, top sources, recent packets
+                       and drops instead of each packet; needs a terminal and a
+                       build with the 'tui' feature
+      --sample <N>     Log only one in N packets; all are still counted
+      --sample-rate <P>
+                       Log each packet with probability P (0 to 1) instead
+      --per-source-limit <PPS>
+                       Log at most PPS packets per second from each source; the
+                       rest are still counted
+      --throttle <PPS> Take at most PPS packets per second from the socket, leaving
+                       the rest to queue and be dropped by the kernel, to test how
+                       a pipeline copes with a slow consumer
+      --max-tracked-sources <N>
+                       Sources remembered in each per-source table before the least
+                       recently heard from is forgotten [default: 65536]
+      --color <WHEN>   Colour the console's packet lines: 'auto' (when it's a
+                       terminal), 'always' or 'never'; the log stays plain [default: auto]
+      --seq-offset <BYTES>
+                       Read a 4-byte big-endian sequence number at this offset in
+                       each payload and warn about gaps and reordering
+      --latency-offset <BYTES>
+                       Read an 8-byte big-endian send time (nanoseconds since 1970)
+                       at this offset and report one-way latency at exit
+      --alert-pps <N>  Warn when more than N packets per second arrive, averaged
+                       over 5 seconds
+      --alert-bps <N>  Warn when more than N payload bits per second arrive
+      --exit-on-alert  Stop, with exit status 1, when an alert threshold is crossed
+      --metrics-addr <ADDR>
+                       Serve Prometheus metrics at http://ADDR/metrics
+      --stats-file <PATH>
+                       Keep the packet and byte totals in PATH, carrying them on
+                       from earlier runs rather than starting again at zero
+      --event-socket <PATH>
+                       Stream each logged packet as a JSON line to processes connected
+                       to a Unix socket created at PATH
+      --output-sqlite <DB>
+                       Also store each logged packet in the SQLite database DB
+      --sqlite-batch <ROWS>
+                       Packets inserted per transaction [default: 1000]
+      --daemon         Detach and keep running in the background (Unix only)
+      --daemon-log <PATH>
+                       With --daemon, file for the program's own messages
+                       [default: udp_listener.out]
+      --pid-file <PATH>
+                       Write the process ID to PATH while running
+      --summary-csv <PATH>
+                       At shutdown, also write every source's packet and byte counts
+                       and first and last times to PATH as CSV
+  -h, --help           Print this help and exit
+  -V, --version        Print the version, git commit and build time, and exit
+";
+
+/// Text printed for `--version`: the package version, then the commit the binary was
+/// built from (marked `-dirty` if there were uncommitted changes) and when, as recorded
+/// by the build script.
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    " ",
+    env!("CARGO_PKG_VERSION"),
+    " (commit ",
+    env!("UDP_LISTENER_GIT_COMMIT"),
+    ", built ",
+    env!("UDP_LISTENER_BUILD_TIME"),
+    ")\n"
+);
+
+/// Text printed for `send --help`.
+pub const SEND_USAGE: &str = "\
+Usage: udp_listener send --to <ADDR> [OPTIONS]
+
+Send test datagrams, for example to a running listener.
+
+Options:
+      --to <ADDR>      Address and port to send to
+      --count <N>      Number of datagrams to send [default: 1]
+      --size <BYTES>   Payload size of each datagram [default: 64]
+      --rate <N>       Datagrams per second [default: as fast as possible]
+      --payload <FILE|random>
+                       Send the contents of FILE, or random bytes, instead of
+                       numbered text
+      --template <FILE>
+                       Send the contents of FILE with each {{seq}} replaced by a
+                       4-byte big-endian sequence number, {{timestamp}} by the
+                       8-byte send time and {{uuid}} by a new UUID, for the
+                       listener's --seq-offset and --latency-offset; a field's
+                       offset is the length of everything before it once replaced
+      --timestamp-at <BYTES>
+                       Write the send time at this offset in each payload, for the
+                       listener's --latency-offset
+  -h, --help           Print this help and exit
+";
+
+/// Text printed for `replay --help`.
+pub const REPLAY_USAGE: &str = "\
+Usage: udp_listener replay <FILE> --to <ADDR> [OPTIONS]
+
+Send the payloads in a capture made with --format bin, in order.
+
+Options:
+      --to <ADDR>      Address and port to send to
+      --preserve-timing
+                       Space the datagrams out as they were captured
+      --speed <X>      With --preserve-timing, play X times faster [default: 1]
+      --rate <N>       Datagrams per second instead [default: as fast as possible]
+      --loop           Start again after the last datagram, until interrupted
+  -h, --help           Print this help and exit
+";
+
+/// What the command line asked the program to do.
+pub enum Command {
+    /// Start listening with the given configuration. Boxed because `Config` is large
+    /// next to the other variants.
+    Listen(Box<Config>, Process),
+    /// Send test datagrams.
+    Send(SendConfig),
+    /// Send a capture back out.
+    Replay(ReplayConfig),
+    /// Print this usage text and exit.
+    Help(&'static str),
+    /// Print [`VERSION`] and exit.
+    Version,
+}
+
+/// Settings for how the listener process itself runs, as opposed to what it logs.
+#[derive(Default)]
+pub struct Process {
+    /// Run in the background, with the program's own output appended to this file.
+    pub daemon_log: Option<PathBuf>,
+    /// File to write the process ID to.
+    pub pid_file: Option<PathBuf>,
+    /// File to write the per-source table to, as CSV, at shutdown.
+    pub summary_csv: Option<PathBuf>,
+    /// Only check the settings and print them, instead of listening.
+    pub check: bool,
+    /// Show the live dashboard while listening.
+    pub tui: bool,
+}
+
+/// Where a daemon's own messages go unless `--daemon-log` says otherwise.
+const DEFAULT_DAEMON_LOG: &str = "udp_listener.out";
+
+/// Parses command-line arguments (without the program name) into a `Command`.
+/// Flags accept their value either as the next argument (`--bind 0.0.0.0:9000`)
+/// or joined with an equals sign (`--bind=0.0.0.0:9000`).
+///
+/// With `--config`, the file is read first, wherever the option appears, and the other
+/// options are applied on top of it. Repeatable options such as `--allow` add to the
+/// file's list rather than replacing it.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> io::Result<Command> {
+    let args: Vec<String> = args.into_iter().collect();
+    match args.first().map(String::as_str) {
+        Some("send") => return parse_send_args(args.into_iter().skip(1)),
+        Some("replay") => return parse_replay_args(args.into_iter().skip(1)),
+        _ => {}
+    }
+
+    let mut config = match config_file_arg(&args)? {
+        Some(path) => load_config(&path)?,
+        None => Config::default(),
+    };
+    let mut args = args.into_iter();
+    // Options that only mean something with `--workers`, which may come later.
+    let mut queue_options_given = false;
+    let mut sample_flags_given = 0;
+    let mut existing_log_flags_given = 0;
+    let mut sqlite_batch_given = false;
+    let mut reassemble_timeout_given = false;
+    let mut write_buffer_given = false;
+    let mut dedup = false;
+    let mut dedup_window = None;
+    let mut base64_prefix = false;
+    let mut binds = Vec::new();
+    let mut process = Process::default();
+    let mut daemon = false;
+    let mut daemon_log = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = split_flag(arg);
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(USAGE)),
+            "-V" | "--version" => return Ok(Command::Version),
+            // Already read by `config_file_arg`.
+            "--config" => {
+                flag_value(&flag, inline_value, &mut args)?;
+            }
+            "-b" | "--bind" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                binds.push(parse_flag_value(&flag, &value)?);
+            }
+            "--allow" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let net = parse_ip_net(&value).map_err(|e| value_error(&flag, &value, e))?;
+                config.source_filter.allow.push(net);
+            }
+            "--deny" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let net = parse_ip_net(&value).map_err(|e| value_error(&flag, &value, e))?;
+                config.source_filter.deny.push(net);
+            }
+            "--min-len" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.length_filter.min = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--max-len" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.length_filter.max = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--match" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let regex =
+                    parse_payload_regex(&value).map_err(|e| value_error(&flag, &value, e))?;
+                config.payload_filter.patterns.push(regex);
+            }
+            "--match-hex" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let regex = parse_payload_hex(&value).map_err(|e| value_error(&flag, &value, e))?;
+                config.payload_filter.patterns.push(regex);
+            }
+            "--filter" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.filter = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--start-trigger" | "--stop-trigger" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let regex = parse_payload_hex(&value).map_err(|e| value_error(&flag, &value, e))?;
+                if flag == "--start-trigger" {
+                    config.triggers.start = Some(regex);
+                } else {
+                    config.triggers.stop = Some(regex);
+                }
+            }
+            "--log-triggers" => config.triggers.log = true,
+            "--dedup" => dedup = true,
+            "--dedup-window" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let ms: u64 = parse_flag_value(&flag, &value)?;
+                if ms == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                dedup_window = Some(Duration::from_millis(ms));
+            }
+            "--checksum" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.checksum = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--reassemble" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.reassemble = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--reassemble-timeout" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let ms: u64 = parse_flag_value(&flag, &value)?;
+                if ms == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.reassemble_timeout = Duration::from_millis(ms);
+                reassemble_timeout_given = true;
+            }
+            "--buffer-size" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.buffer_size = parse_flag_value(&flag, &value)?;
+                if config.buffer_size == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+            }
+            "--fsync" => config.fsync = true,
+            "--session-id" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                check_session_id(&value).map_err(|e| value_error(&flag, &value, e))?;
+                config.session_id = Some(value);
+            }
+            "--write-buffer" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let size = parse_flag_value(&flag, &value)?;
+                if size == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.write_buffer = size;
+                write_buffer_given = true;
+            }
+            "--snaplen" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let snaplen = parse_flag_value(&flag, &value)?;
+                if snaplen == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.snaplen = Some(snaplen);
+            }
+            "--recv-buffer" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.recv_buffer_size = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--resolve" => config.resolve = true,
+            "--geoip" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.geoip = Some(PathBuf::from(value));
+            }
+            "--echo" => config.echo = true,
+            "--index" => config.index = true,
+            "--forward" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.forward.push(parse_flag_value(&flag, &value)?);
+            }
+            "--ipv6-only" => config.ipv6_only = true,
+            "--ip-fields" => config.ip_fields = true,
+            "--dst-ip" => config.dst_ip = true,
+            "--reuse-port" => config.reuse_port = true,
+            "--connect" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.connect = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--interface" => {
+                config.interface = Some(flag_value(&flag, inline_value, &mut args)?);
+            }
+            "--multicast" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.multicast.push(parse_flag_value(&flag, &value)?);
+            }
+            "-o" | "--output" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.output = Output::from_arg(&value);
+            }
+            "--append" => {
+                config.existing_log = ExistingLog::Append;
+                existing_log_flags_given += 1;
+            }
+            "--no-clobber" => {
+                config.existing_log = ExistingLog::Refuse;
+                existing_log_flags_given += 1;
+            }
+            "--max-size" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.max_log_size = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--max-files" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.max_log_files = parse_flag_value(&flag, &value)?;
+            }
+            "--rotate" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.rotate = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--split-by-source" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.split_by_source = Some(value.into());
+            }
+            "--output-dir" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.output_dir = Some(value.into());
+            }
+            "--max-open-files" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.max_open_files = parse_flag_value(&flag, &value)?;
+            }
+            "--timezone" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.time_format.timezone = parse_flag_value(&flag, &value)?;
+            }
+            "--time-format" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                check_time_pattern(&value).map_err(|e| value_error(&flag, &value, e))?;
+                config.time_format.pattern = Some(value);
+            }
+            "--flush" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.flush = parse_flag_value(&flag, &value)?;
+            }
+            "--idle-timeout" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let secs: f64 = parse_flag_value(&flag, &value)?;
+                let timeout = Duration::try_from_secs_f64(secs)
+                    .ok()
+                    .filter(|timeout| !timeout.is_zero())
+                    .ok_or_else(|| value_error(&flag, &value, "must be a positive number"))?;
+                config.idle_timeout = Some(timeout);
+            }
+            "--count" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.max_packets = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--count-includes-filtered" => config.count_includes_filtered = true,
+            "--ring" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let size = parse_flag_value(&flag, &value)?;
+                if size == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.ring_size = Some(size);
+            }
+            "--workers" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.workers = parse_flag_value(&flag, &value)?;
+            }
+            "--queue-size" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.queue_size = parse_flag_value(&flag, &value)?;
+                if config.queue_size == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                queue_options_given = true;
+            }
+            "--on-overflow" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.on_overflow = parse_flag_value(&flag, &value)?;
+                queue_options_given = true;
+            }
+            "-q" | "--quiet" => config.verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => config.verbosity = Verbosity::Verbose,
+            "--print-packets" => config.verbosity = Verbosity::Normal,
+            "--console-format" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.console_format = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--console-sample" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let every = parse_flag_value(&flag, &value)?;
+                if every == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.console_sample = Some(every);
+            }
+            "--sample" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let every = parse_flag_value(&flag, &value)?;
+                if every == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.sample = Some(Sample::Every(every));
+                sample_flags_given += 1;
+            }
+            "--sample-rate" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let rate: f64 = parse_flag_value(&flag, &value)?;
+                if !(0.0..=1.0).contains(&rate) {
+                    return Err(value_error(&flag, &value, "must be between 0 and 1"));
+                }
+                config.sample = Some(Sample::Rate(rate));
+                sample_flags_given += 1;
+            }
+            "--throttle" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let pps: f64 = parse_flag_value(&flag, &value)?;
+                check_throttle(pps).map_err(|e| value_error(&flag, &value, e))?;
+                config.throttle = Some(pps);
+            }
+            "--per-source-limit" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let pps: f64 = parse_flag_value(&flag, &value)?;
+                check_per_source_limit(pps).map_err(|e| value_error(&flag, &value, e))?;
+                config.per_source_limit = Some(pps);
+            }
+            "--max-tracked-sources" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let sources = parse_flag_value(&flag, &value)?;
+                if sources == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.max_tracked_sources = sources;
+            }
+            "--color" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.color = parse_flag_value(&flag, &value)?;
+            }
+            "--seq-offset" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.seq_offset = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--latency-offset" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.latency_offset = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--alert-pps" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.alert_pps = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--alert-bps" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.alert_bps = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--exit-on-alert" => config.exit_on_alert = true,
+            "--metrics-addr" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.metrics_addr = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--stats-file" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.stats_file = Some(value.into());
+            }
+            "--event-socket" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.event_socket = Some(value.into());
+            }
+            "--output-sqlite" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.output_sqlite = Some(value.into());
+            }
+            "--sqlite-batch" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let rows = parse_flag_value(&flag, &value)?;
+                if rows == 0 {
+                    return Err(value_error(&flag, &value, "must be at least 1"));
+                }
+                config.sqlite_batch = rows;
+                sqlite_batch_given = true;
+            }
+            "-f" | "--format" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.format = parse_flag_value(&flag, &value)?;
+            }
+            "--base64-prefix" => base64_prefix = true,
+            "--line-ending" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.line_ending = parse_flag_value(&flag, &value)?;
+            }
+            "--compress-rotated" => config.compress_rotated = true,
+            "--compress" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.compress = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--parse" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.parse = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--parse-map" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let map: ParseMap = parse_flag_value(&flag, &value)?;
+                for (port, protocol) in map.iter() {
+                    config.parse_map.insert(port, protocol);
+                }
+            }
+            "--daemon" => daemon = true,
+            "--daemon-log" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                daemon_log = Some(PathBuf::from(value));
+            }
+            "--pid-file" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                process.pid_file = Some(value.into());
+            }
+            "--check" => process.check = true,
+            "--tui" => process.tui = true,
+            "--summary-csv" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                process.summary_csv = Some(value.into());
+            }
+            other => return Err(invalid_input(format!("unknown option '{}'", other))),
+        }
+    }
+
+    match (daemon, daemon_log) {
+        (true, log) => {
+            if matches!(config.output, Output::Stdout) {
+                return Err(invalid_input(
+                    "--daemon can't log to stdout; give an --output file".to_string(),
+                ));
+            }
+            if process.tui {
+                return Err(invalid_input(
+                    "--tui needs a terminal, which --daemon leaves".to_string(),
+                ));
+            }
+            process.daemon_log = Some(log.unwrap_or_else(|| DEFAULT_DAEMON_LOG.into()));
+            // Nobody is watching the console of a daemon.
+            config.verbosity = Verbosity::Quiet;
+        }
+        (false, Some(_)) => {
+            return Err(invalid_input("--daemon-log needs --daemon".to_string()));
+        }
+        (false, None) => {}
+    }
+
+    // Binds given here replace any from a config file.
+    if let Some((&first, rest)) = binds.split_first() {
+        config.bind_addr = first;
+        config.extra_binds = rest.to_vec();
+    }
+
+    if base64_prefix {
+        match &mut config.format {
+            Format::Base64 { prefix } => *prefix = true,
+            _ => {
+                return Err(invalid_input(
+                    "--base64-prefix needs --format base64".to_string(),
+                ));
+            }
+        }
+    }
+
+    // A config file may already have turned deduplication on.
+    let dedup = dedup || config.dedup_window.is_some();
+    match (dedup, dedup_window) {
+        (true, window) => {
+            config.dedup_window = window.or(config.dedup_window);
+            config.dedup_window.get_or_insert(DEFAULT_DEDUP_WINDOW);
+        }
+        (false, Some(_)) => {
+            return Err(invalid_input("--dedup-window needs --dedup".to_string()));
+        }
+        (false, None) => {}
+    }
+
+    if reassemble_timeout_given && config.reassemble.is_none() {
+        return Err(invalid_input(
+            "--reassemble-timeout needs --reassemble".to_string(),
+        ));
+    }
+    if sqlite_batch_given && config.output_sqlite.is_none() {
+        return Err(invalid_input(
+            "--sqlite-batch needs --output-sqlite".to_string(),
+        ));
+    }
+    if config.triggers.log && config.triggers.start.is_none() && config.triggers.stop.is_none() {
+        return Err(invalid_input(
+            "--log-triggers needs --start-trigger or --stop-trigger".to_string(),
+        ));
+    }
+    if config.exit_on_alert && config.alert_pps.is_none() && config.alert_bps.is_none() {
+        return Err(invalid_input(
+            "--exit-on-alert needs --alert-pps or --alert-bps".to_string(),
+        ));
+    }
+
+    if sample_flags_given > 1 {
+        return Err(invalid_input(
+            "--sample and --sample-rate can't be combined".to_string(),
+        ));
+    }
+    if existing_log_flags_given > 1 {
+        return Err(invalid_input(
+            "--append and --no-clobber can't be combined".to_string(),
+        ));
+    }
+
+    // Checked against the final policy, which may come from a config file.
+    if write_buffer_given && config.flush == FlushPolicy::Every {
+        return Err(invalid_input(
+            "--write-buffer needs --flush interval:<MS> or never; with 'every' nothing is buffered"
+                .to_string(),
+        ));
+    }
+
+    if queue_options_given && config.workers == 0 {
+        return Err(invalid_input(
+            "--queue-size and --on-overflow need --workers".to_string(),
+        ));
+    }
+
+    if config.ring_size.is_some() && config.workers > 0 {
+        return Err(invalid_input(
+            "--ring can't be combined with --workers".to_string(),
+        ));
+    }
+
+    if let LengthFilter {
+        min: Some(min),
+        max: Some(max),
+    } = config.length_filter
+        && min > max
+    {
+        return Err(invalid_input(format!(
+            "--min-len ({}) is greater than --max-len ({}), so every packet would be dropped",
+            min, max
+        )));
+    }
+
+    Ok(Command::Listen(Box::new(config), process))
+}
+
+/// Finds the `--config` file among the arguments, so it can be read before the options
+/// that override it.
+fn config_file_arg(args: &[String]) -> io::Result<Option<PathBuf>> {
+    let mut path = None;
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = split_flag(arg);
+        if flag == "--config" {
+            path = Some(flag_value(&flag, inline_value, &mut args)?.into());
+        }
+    }
+    Ok(path)
+}
+
+/// Parses the arguments after `send`.
+fn parse_send_args(mut args: impl Iterator<Item = String>) -> io::Result<Command> {
+    let mut to = None;
+    // The destination is filled in once `--to` has been seen.
+    let mut config = SendConfig::new(DEFAULT_BIND_ADDR.parse().expect("valid address"), 1);
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = split_flag(arg);
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(SEND_USAGE)),
+            "--to" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                to = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--count" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.count = parse_flag_value(&flag, &value)?;
+            }
+            "--size" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.size = parse_flag_value(&flag, &value)?;
+            }
+            "--rate" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let rate: f64 = parse_flag_value(&flag, &value)?;
+                if !(rate > 0.0 && rate.is_finite()) {
+                    return Err(value_error(&flag, &value, "must be a positive number"));
+                }
+                config.rate = Some(rate);
+            }
+            "--payload" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.payload = Payload::from_arg(&value);
+            }
+            "--template" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.payload = Payload::Template(PathBuf::from(value));
+            }
+            "--timestamp-at" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                config.timestamp_offset = Some(parse_flag_value(&flag, &value)?);
+            }
+            other => return Err(invalid_input(format!("unknown option '{}'", other))),
+        }
+    }
+
+    config.to = to.ok_or_else(|| invalid_input("send needs a --to address".to_string()))?;
+    Ok(Command::Send(config))
+}
+
+/// Parses the arguments after `replay`.
+fn parse_replay_args(mut args: impl Iterator<Item = String>) -> io::Result<Command> {
+    let mut path = None;
+    let mut to = None;
+    let mut preserve_timing = false;
+    let mut speed = None;
+    let mut rate = None;
+    let mut repeat = false;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = split_flag(arg);
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(REPLAY_USAGE)),
+            "--to" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                to = Some(parse_flag_value(&flag, &value)?);
+            }
+            "--preserve-timing" => preserve_timing = true,
+            "--speed" | "--rate" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let number: f64 = parse_flag_value(&flag, &value)?;
+                if !(number > 0.0 && number.is_finite()) {
+                    return Err(value_error(&flag, &value, "must be a positive number"));
+                }
+                if Duration::try_from_secs_f64(1.0 / number).is_err() {
+                    return Err(value_error(&flag, &value, "is too small"));
+                }
+                if flag == "--speed" {
+                    speed = Some(number);
+                } else {
+                    rate = Some(number);
+                }
+            }
+            "--loop" => repeat = true,
+            other if other.starts_with('-') => {
+                return Err(invalid_input(format!("unknown option '{}'", other)));
+            }
+            _ if path.is_some() => {
+                return Err(invalid_input(format!("unexpected argument '{}'", flag)));
+            }
+            _ => path = Some(PathBuf::from(flag)),
+        }
+    }
+
+    let path = path.ok_or_else(|| invalid_input("replay needs a capture file".to_string()))?;
+    let to = to.ok_or_else(|| invalid_input("replay needs a --to address".to_string()))?;
+    if preserve_timing && rate.is_some() {
+        return Err(invalid_input(
+            "--rate can't be combined with --preserve-timing".to_string(),
+        ));
+    }
+    if speed.is_some() && !preserve_timing {
+        return Err(invalid_input("--speed needs --preserve-timing".to_string()));
+    }
+    let mut config = ReplayConfig::new(path, to);
+    config.preserve_timing = preserve_timing;
+    config.speed = speed.unwrap_or(1.0);
+    config.rate = rate;
+    config.repeat = repeat;
+    Ok(Command::Replay(config))
+}
+
+/// Splits `--flag=value` into its two halves so both spellings are handled the same way.
+fn split_flag(arg: String) -> (String, Option<String>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => {
+            (flag.to_string(), Some(value.to_string()))
+        }
+        _ => (arg, None),
+    }
+}
+
+/// Returns the value for `flag`, taken from `--flag=value` if present or otherwise
+/// from the next command-line argument.
+fn flag_value<I: Iterator<Item = String>>(
+    flag: &str,
+    inline_value: Option<String>,
+    args: &mut I,
+) -> io::Result<String> {
+    inline_value
+        .or_else(|| args.next())
+        .ok_or_else(|| invalid_input(format!("option '{}' requires a value", flag)))
+}
+
+/// Parses a flag's value with `FromStr`, naming the flag in the error message.
+fn parse_flag_value<T>(flag: &str, value: &str) -> io::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e| value_error(flag, value, e))
+}
+
+/// Builds the error for a flag whose value was rejected.
+fn value_error(flag: &str, value: &str, reason: impl std::fmt::Display) -> io::Error {
+    invalid_input(format!(
+        "invalid value '{}' for '{}': {}",
+        value, flag, reason
+    ))
+}
+
+/// Builds an `InvalidInput` error, used for anything wrong with the command line.
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,87 @@
+// Where the listener gets the time of day from, so tests can fix it.
+
+use std::fmt::Debug;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, TimeDelta};
+
+/// The source of wall-clock time for packet timestamps and time-based rotation, set by
+/// [`Config::clock`](crate::Config::clock), and of the monotonic time each packet is
+/// also stamped with, [`Packet::monotonic`](crate::Packet::monotonic).
+///
+/// The time of day comes from here. Durations, like the idle timeout and the packet
+/// rate, are measured with [`Instant`], which can't jump backwards with the system
+/// clock.
+pub trait Clock: Debug + Send + Sync {
+    /// The current date and time.
+    fn now(&self) -> DateTime<Local>;
+
+    /// The current monotonic time, which only ever moves forward, whatever is done to
+    /// the time of day. The system's [`Instant::now`] unless overridden.
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The system clock, which the listener uses unless told otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// A clock that stands still until it's moved, for tests that check timestamps or
+/// rotation. Moving it forward moves its monotonic time too; setting it, like a wall
+/// clock being corrected, doesn't.
+///
+/// ```
+/// use std::time::Duration;
+/// use chrono::{Local, TimeZone};
+/// use udp_listener::{Clock, MockClock};
+///
+/// let start = Local.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+/// let clock = MockClock::new(start);
+/// clock.advance(Duration::from_secs(90));
+/// assert_eq!(clock.now(), Local.with_ymd_and_hms(2024, 6, 1, 12, 1, 30).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<DateTime<Local>>,
+    instant: Mutex<Instant>,
+}
+
+impl MockClock {
+    /// A clock showing `now`.
+    pub fn new(now: DateTime<Local>) -> MockClock {
+        MockClock {
+            now: Mutex::new(now),
+            instant: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Sets the clock to `now`, which may be earlier than its current time.
+    pub fn set(&self, now: DateTime<Local>) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        let delta = TimeDelta::from_std(by).expect("a duration a clock can be moved by");
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += delta;
+        *self.instant.lock().unwrap_or_else(PoisonError::into_inner) += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Local> {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn instant(&self) -> Instant {
+        *self.instant.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,60 @@
+// Colouring the console's packet lines, so flows are easy to tell apart while watching.
+
+use std::io::IsTerminal;
+use std::net::IpAddr;
+
+use crate::config::ColorChoice;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+
+/// The colours sources are drawn in: the six ordinary ANSI colours followed by their
+/// bright versions, leaving out black and white, which vanish on one background or the
+/// other.
+const SOURCE_COLORS: [&str; 12] = [
+    "\x1b[31m", "\x1b[32m", "\x1b[33m", "\x1b[34m", "\x1b[35m", "\x1b[36m", "\x1b[91m", "\x1b[92m",
+    "\x1b[93m", "\x1b[94m", "\x1b[95m", "\x1b[96m",
+];
+
+/// Whether console lines should be coloured. For [`ColorChoice::Auto`] that means
+/// stdout is a terminal, and neither `NO_COLOR` is set nor `TERM` is `dumb`; the check
+/// is checked when the console is set up.
+pub(crate) fn enabled(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+        }
+    }
+}
+
+/// `text` in the colour belonging to `ip`. The colour comes from a fixed hash of the
+/// address, so a source keeps its colour from one run to the next.
+pub(crate) fn source(ip: IpAddr, text: &str) -> String {
+    // FNV-1a: tiny, and unlike the standard library's hashers, the same in every build.
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    };
+    let hash = octets
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    let color = SOURCE_COLORS[(hash % SOURCE_COLORS.len() as u64) as usize];
+    format!("{}{}{}", color, text, RESET)
+}
+
+/// `text` in bold, used for byte counts.
+pub(crate) fn bold(text: &str) -> String {
+    format!("{}{}{}", BOLD, text, RESET)
+}
+
+/// `text` dimmed, used for timestamps.
+pub(crate) fn dim(text: &str) -> String {
+    format!("{}{}{}", DIM, text, RESET)
+}
@@ -0,0 +1,95 @@
+// Compressing log files as they're written.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::Compression as Level;
+use flate2::write::GzEncoder;
+
+use crate::config::Compression;
+
+/// A destination that is written to either as is or through a compressor.
+///
+/// A gzip stream is only complete once its trailer is written. [`finish`](Self::finish)
+/// writes it explicitly; dropping the writer also does, but without a chance to report
+/// an error. `flush` pushes everything written so far through the compressor, so the
+/// file can be decompressed up to that point even before it's finished.
+pub(crate) enum Compressor<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> Compressor<W> {
+    pub(crate) fn new(out: W, compression: Option<Compression>) -> Compressor<W> {
+        match compression {
+            None => Compressor::Plain(out),
+            Some(Compression::Gzip) => Compressor::Gzip(GzEncoder::new(out, Level::default())),
+        }
+    }
+
+    /// The destination the (possibly compressed) data goes to.
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        match self {
+            Compressor::Plain(out) => out,
+            Compressor::Gzip(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Ends the compressed stream and flushes the destination.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            Compressor::Plain(mut out) => out.flush(),
+            Compressor::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+/// Compresses the file at `path` with gzip into `path.gz`, and deletes `path` once the
+/// compressed copy is complete and synced to disk, returning where the copy is. On an
+/// error the original is kept, and a partly written copy removed.
+pub(crate) fn gzip_file(path: &Path) -> io::Result<PathBuf> {
+    let gz = gz_path(path);
+    let compress = || -> io::Result<()> {
+        let mut input = File::open(path)?;
+        let mut encoder = GzEncoder::new(File::create(&gz)?, Level::default());
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.sync_all()
+    };
+    if let Err(e) = compress() {
+        let _ = fs::remove_file(&gz);
+        return Err(e);
+    }
+    fs::remove_file(path)?;
+    Ok(gz)
+}
+
+/// `udp_packets.log.1` becomes `udp_packets.log.1.gz`.
+pub(crate) fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressor::Plain(out) => out.write(buf),
+            Compressor::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Compressor::Plain(out) => out.write_all(buf),
+            Compressor::Gzip(encoder) => encoder.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressor::Plain(out) => out.flush(),
+            Compressor::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
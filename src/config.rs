@@ -0,0 +1,1116 @@
+// Settings that control what the listener binds to and how it writes packets.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock};
+use crate::compress::Compressor;
+use crate::diag;
+use crate::filter::{FilterExpr, IpFilter, LengthFilter, PayloadFilter, Triggers};
+use crate::format::TimeFormat;
+use crate::session::new_session_id;
+use crate::writer::SyncWrite;
+
+/// The address used when no bind address is configured.
+/// "127.0.0.1:8080" means it will listen on the local loopback interface (your computer)
+/// on port 8080. Use "0.0.0.0:8080" to listen on all available network interfaces.
+pub const DEFAULT_BIND_ADDR: &str = "127.0.0.1:8080";
+
+/// The log file used when no output is configured.
+pub const DEFAULT_OUTPUT_PATH: &str = "udp_packets.log";
+
+/// How many rotated log files are kept when no other number is configured.
+pub const DEFAULT_MAX_LOG_FILES: usize = 5;
+
+/// The receive buffer size used when no other size is configured: the largest payload
+/// a UDP header's 16-bit length field can describe, so no ordinary datagram is cut off.
+pub const DEFAULT_BUFFER_SIZE: usize = 65535;
+
+/// How many received packets may wait for a worker when no other size is configured.
+pub const DEFAULT_QUEUE_SIZE: usize = 1024;
+
+/// How long a repeated payload counts as a duplicate when no other window is configured.
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_millis(1000);
+
+/// How big the buffer that collects log entries between flushes is when no other size
+/// is configured.
+pub const DEFAULT_WRITE_BUFFER: usize = 64 * 1024;
+
+/// How many packets go into the database in one transaction when no other number is
+/// configured.
+pub const DEFAULT_SQLITE_BATCH: usize = 1000;
+
+/// How long the chunks of a message are kept waiting for the rest when no other
+/// timeout is configured.
+pub const DEFAULT_REASSEMBLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many per-source files may be open at once when no other number is configured.
+pub const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
+/// How many sources each per-source table may remember when no other number is
+/// configured.
+pub const DEFAULT_MAX_TRACKED_SOURCES: usize = 65_536;
+
+/// Runtime settings for the listener.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The local address and port the UDP socket binds to.
+    pub bind_addr: SocketAddr,
+    /// More addresses to listen on at the same time, such as other ports. Each gets a
+    /// socket of its own, set up like the first, read by a thread of its own, and its
+    /// packets are logged with the rest, noting where they arrived (see
+    /// [`Packet::bind`](crate::Packet::bind)). Multicast groups are only joined on the
+    /// first. Empty (the default) listens on `bind_addr` alone.
+    pub extra_binds: Vec<SocketAddr>,
+    /// Size in bytes of the buffer datagrams are received into. Anything longer is
+    /// truncated to this size and marked with [`Packet::truncated`](crate::Packet::truncated).
+    pub buffer_size: usize,
+    /// Log at most this many bytes of each payload, like tcpdump's `-s`, while still
+    /// recording each datagram's full length (see [`Packet::original_len`]). Filters,
+    /// decoding, echoing and forwarding all see the whole payload; only what's logged and
+    /// printed is cut. `None` (the default) logs payloads in full.
+    ///
+    /// [`Packet::original_len`]: crate::Packet::original_len
+    pub snaplen: Option<usize>,
+    /// Ask the kernel for a socket receive buffer (`SO_RCVBUF`) of this many bytes, so
+    /// bursts of traffic aren't dropped before they're received. `None` (the default)
+    /// keeps the operating system's default size.
+    pub recv_buffer_size: Option<usize>,
+    /// Where log lines are written.
+    pub output: Output,
+    /// What to do when the log file is already there from an earlier run: start it
+    /// afresh (the default, with a warning if it wasn't empty), add to the end of it,
+    /// or refuse to start. Per-source files are always added to, and an output
+    /// directory never overwrites a segment, so this is only about `output`.
+    pub existing_log: ExistingLog,
+    /// How each packet's payload is written to the log.
+    pub format: Format,
+    /// The clock and layout used for timestamps in the log.
+    pub time_format: TimeFormat,
+    /// What ends each record in the text, hex, JSON and base64 formats: a newline by
+    /// default, or CRLF, NUL or a separator of your own.
+    pub line_ending: LineEnding,
+    /// Where packet timestamps, and the time that decides when a dated log file is
+    /// started, come from. The system clock by default; tests can set a
+    /// [`MockClock`](crate::MockClock) to get the same output on every run.
+    pub clock: Arc<dyn Clock>,
+    /// Identifies this run in every JSON record, in the header of a binary capture and
+    /// in the summary, so records from several runs feeding one collector can be told
+    /// apart. `None` (the default) gets a random UUID when the listener starts. See
+    /// [`check_session_id`](crate::check_session_id) for what an ID may contain.
+    pub session_id: Option<String>,
+    /// For IPv6 bind addresses, whether to accept only IPv6 traffic. When `false` (the
+    /// default) an IPv6 socket is dual-stack and also receives IPv4 datagrams.
+    pub ipv6_only: bool,
+    /// Record the TTL (IPv6 hop limit) and ToS byte (IPv6 traffic class) each datagram
+    /// arrived with, in [`Packet::ttl`](crate::Packet::ttl) and
+    /// [`Packet::tos`](crate::Packet::tos), and show them in the log. Linux only;
+    /// elsewhere a warning is printed and the fields stay empty.
+    pub ip_fields: bool,
+    /// Record the local address each datagram was sent to, in
+    /// [`Packet::dst_ip`](crate::Packet::dst_ip), and show it in the log. Useful when
+    /// bound to every interface, where the bind address doesn't say. Echoes are then
+    /// sent from that address, and forwarded copies too where it can be used. Linux
+    /// only, like `ip_fields`.
+    pub dst_ip: bool,
+    /// Multicast groups to join after binding. When any are given the socket is also
+    /// bound with `SO_REUSEADDR`, so several listeners can share the port.
+    pub multicast: Vec<MulticastGroup>,
+    /// Send every received datagram straight back to its sender, turning the listener
+    /// into a simple echo server.
+    pub echo: bool,
+    /// Rotate the log file once it reaches this many bytes. `None` (the default) lets
+    /// the file grow without limit. As with `rotate`, `<output>.current` then follows the
+    /// file being written.
+    pub max_log_size: Option<u64>,
+    /// How many rotated files (`udp_packets.log.1`, `.2`, ...) to keep when rotating.
+    pub max_log_files: usize,
+    /// Start a new dated log file every day or hour. Can be combined with
+    /// `max_log_size`, in which case each dated file also rotates by size.
+    ///
+    /// `<output>.current` is kept as a symbolic link to the file being written. Where
+    /// there are no symbolic links it's a small file holding that file's name on one
+    /// line instead, rather than an entry in a manifest.
+    pub rotate: Option<RotatePeriod>,
+    /// Instead of a single output, write each source address to its own file in this
+    /// directory, named like `192.168.1.5_5000.log`.
+    pub split_by_source: Option<PathBuf>,
+    /// Instead of a single output, write numbered segment files into this directory,
+    /// with a `manifest.json` giving each one's time range, packet count and byte
+    /// count. `max_log_size` and `rotate` decide when a new segment starts.
+    pub output_dir: Option<PathBuf>,
+    /// With `split_by_source`, how many per-source files may be open at once.
+    pub max_open_files: usize,
+    /// Which source addresses are logged. Packets from other sources are counted as
+    /// filtered and otherwise ignored.
+    pub source_filter: IpFilter,
+    /// Which payload lengths are logged. Packets outside the range are counted
+    /// separately from those dropped by `source_filter`.
+    pub length_filter: LengthFilter,
+    /// Patterns the payload must match to be logged. Packets that match none of them
+    /// are counted separately again.
+    pub payload_filter: PayloadFilter,
+    /// A condition combining the packet's fields, which packets must also meet to be
+    /// logged, such as `src net 10.0.0.0/8 and len > 100`. Packets that don't are
+    /// counted separately again.
+    pub filter: Option<FilterExpr>,
+    /// Payloads that start and stop the capture; by default it runs from the outset
+    /// until shut down. A stop trigger makes [`UdpLogger::run`](crate::UdpLogger::run)
+    /// return, and [`UdpLogger::trigger_stopped`](crate::UdpLogger::trigger_stopped)
+    /// then says so.
+    pub triggers: Triggers,
+    /// Treat each payload as one chunk of a larger message, with a header laid out like
+    /// this, and log whole messages instead of chunks once every chunk has arrived.
+    /// Echoes and forwarded copies are still sent per chunk, and the filters see the
+    /// chunks. `None` (the default) logs each datagram as it is.
+    pub reassemble: Option<ChunkLayout>,
+    /// With `reassemble`, how long after its first chunk a message that is still
+    /// missing chunks is given up on. Given-up messages are reported and counted in
+    /// [`Stats::reassembly_incomplete`](crate::Stats::reassembly_incomplete).
+    pub reassemble_timeout: Duration,
+    /// Number of worker threads that write packets to the log. With `0` (the default)
+    /// a single thread both receives and writes. With one or more, a receiver thread
+    /// hands packets to the workers through a queue, so a slow write or flush doesn't
+    /// hold up the socket.
+    pub workers: usize,
+    /// With `workers`, how many packets the queue between the receiver and the workers
+    /// can hold.
+    pub queue_size: usize,
+    /// With `workers`, what the receiver does when the queue is full.
+    pub on_overflow: OverflowPolicy,
+    /// When written packets are flushed through to the log.
+    pub flush: FlushPolicy,
+    /// Size in bytes of the buffer that collects log entries between flushes, with a
+    /// `flush` policy other than [`FlushPolicy::Every`]. A bigger buffer means fewer,
+    /// larger writes.
+    pub write_buffer: usize,
+    /// Wait for each flush to reach the disk, not just the operating system. See
+    /// [`FlushPolicy`] for which flushes sync without it.
+    pub fsync: bool,
+    /// Stop listening once no datagram has arrived for this long. `None` (the default)
+    /// listens until a shutdown is requested.
+    pub idle_timeout: Option<Duration>,
+    /// Stop listening after this many packets. Only packets that pass the filters
+    /// count, unless `count_includes_filtered` is set.
+    pub max_packets: Option<u64>,
+    /// Count every received packet towards `max_packets`, filtered or not.
+    pub count_includes_filtered: bool,
+    /// Look up each source address's host name and include it in the log. Lookups run
+    /// in the background, so a source's first packets may be logged before its name is
+    /// known; addresses without a name are logged as they are.
+    pub resolve: bool,
+    /// Look up where each source address is in this MaxMind database (a GeoIP2 or
+    /// GeoLite2 City or Country `.mmdb` file) and include it in the log. Like host
+    /// names, locations are looked up in the background and cached per address, so a
+    /// source's first packets may be logged without one.
+    pub geoip: Option<PathBuf>,
+    /// Keep only the last this-many packets in memory instead of writing each one to
+    /// the log and the other [sinks](crate::Sink), and write them all out when asked to
+    /// (see [`UdpLogger::dump_handle`](crate::UdpLogger::dump_handle)).
+    pub ring_size: Option<usize>,
+    /// Serve the statistics at `/metrics` on this address, for Prometheus to scrape.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Keep the packet and byte totals, overall and for each source, in this file:
+    /// load them when the listener starts, so the counts carry on from earlier runs
+    /// rather than starting again at zero, and save them every ten seconds and when it
+    /// stops. A file that exists but can't be read makes
+    /// [`UdpLogger::new`](crate::UdpLogger::new) fail, rather than being started over.
+    pub stats_file: Option<PathBuf>,
+    /// Create a Unix domain socket here and send each logged packet, as a line of JSON,
+    /// to every process connected to it, like another log (see [`Sink`](crate::Sink)).
+    /// Subscribers that fall behind are disconnected rather than slowing the listener
+    /// down. Unix only.
+    pub event_socket: Option<PathBuf>,
+    /// Also store each logged packet as a row in this SQLite database, creating it and
+    /// its `packets` table if need be. Needs the `sqlite` feature; without it, setting
+    /// this makes [`UdpLogger::new`](crate::UdpLogger::new) fail.
+    pub output_sqlite: Option<PathBuf>,
+    /// With `output_sqlite`, how many packets are inserted per transaction. A
+    /// transaction is also committed whenever the log is flushed by the
+    /// [`FlushPolicy::Interval`] timer and at shutdown.
+    pub sqlite_batch: usize,
+    /// Decode each payload as this protocol. Text logs then show its fields instead of
+    /// the raw bytes and JSON records gain an object of them; hex and pcap logs are
+    /// unchanged. Payloads that don't decode are logged as usual (or, for a binary
+    /// protocol, as a hex dump in text logs) and counted in
+    /// [`Stats::unparsed`](crate::Stats::unparsed).
+    pub parse: Option<Protocol>,
+    /// Decode the payloads arriving on particular local ports as particular protocols,
+    /// so one listener bound to several ports (see `extra_binds`) can decode each
+    /// port's traffic as what it carries. A port in the map takes its protocol from
+    /// there, and any other from `parse`. Empty by default.
+    pub parse_map: ParseMap,
+    /// Leave out packets whose source and payload repeat one logged within this window,
+    /// such as retransmissions. `None` (the default) logs every packet.
+    pub dedup_window: Option<Duration>,
+    /// Compute a checksum of each payload and log it with the packet, so captures of
+    /// the same traffic taken in different places can be compared. It covers the whole
+    /// payload, even when only part of it is logged. Deduplication then compares
+    /// packets by this checksum instead of hashing the payload a second time.
+    pub checksum: Option<Checksum>,
+    /// Number the logged packets from 1, in [`Packet::index`](crate::Packet::index), and
+    /// start each text, hex and prefixed base64 record with the number, as in `#10542`,
+    /// and each JSON record with an `"idx"` field. The count carries on across rotations
+    /// and reopens, and starts again only with a new run.
+    pub index: bool,
+    /// Send a copy of every logged datagram, byte for byte, to each of these addresses,
+    /// turning the listener into a relay.
+    pub forward: Vec<SocketAddr>,
+    /// Compress the log as it's written. With rotation every file is a complete
+    /// compressed file of its own, but `max_log_size` still counts the uncompressed
+    /// bytes. Flushing after every packet costs some compression, so this works best
+    /// with a buffered [`FlushPolicy`].
+    pub compress: Option<Compression>,
+    /// With rotation, write each file plainly and gzip it in the background once it's
+    /// been rotated away from, while capture carries on in the next one. The plain file
+    /// is deleted once the `.gz` is complete and synced; shutting down waits for a
+    /// compression still under way. Can't be combined with `compress` or `output_dir`.
+    pub compress_rotated: bool,
+    /// How much the console shows about each logged packet. By default packets are
+    /// only printed when stdout is a terminal. When the log itself goes to stdout
+    /// nothing is printed per packet, as the log already shows it.
+    pub verbosity: Verbosity,
+    /// Print only one in this many packets on the console, for a sample of heavy
+    /// traffic. The log still gets every packet. `None` prints them all.
+    pub console_sample: Option<u64>,
+    /// How the console shows each packet's payload, such as a hex dump while the log is
+    /// pcap. `None` (the default) follows [`Config::format`].
+    pub console_format: Option<Format>,
+    /// Log only a sample of the packets that pass the filters, to keep the log's size
+    /// bounded under heavy traffic. Every packet is still counted in the statistics,
+    /// echoed and forwarded; the ones left out are counted in
+    /// [`Stats::unsampled`](crate::Stats::unsampled). `None` logs them all.
+    pub sample: Option<Sample>,
+    /// Log at most this many packets per second from each source address, averaged
+    /// over a second, so one noisy sender can't flood the log. Packets over the limit
+    /// are still counted in the statistics, echoed and forwarded; they're counted in
+    /// [`Stats::rate_limited`](crate::Stats::rate_limited). `None` (the default) sets
+    /// no limit.
+    pub per_source_limit: Option<f64>,
+    /// Take at most this many packets a second from the socket, sleeping in between,
+    /// to act as a slow consumer: the rest wait in the receive buffer until it fills
+    /// and the kernel drops what doesn't fit, which shows in
+    /// [`Stats::kernel_drops`](crate::Stats::kernel_drops) where the system reports it.
+    /// For testing how a capture pipeline copes. `None` (the default) takes packets as
+    /// fast as they come.
+    pub throttle: Option<f64>,
+    /// The most entries each table the listener keeps about individual sources may
+    /// hold: the per-source totals in the [`Stats`](crate::Stats), the token buckets
+    /// for `per_source_limit`, the sequence numbers for `seq_offset`, the recent packets
+    /// for `dedup_window`, and the host names and locations for `resolve` and `geoip`.
+    /// Once a table is full, a new source makes it forget the one heard from longest
+    /// ago, so a flood from spoofed addresses can't use up the memory. Each forgotten
+    /// entry is counted in [`Stats::sources_evicted`](crate::Stats::sources_evicted).
+    ///
+    /// An entry takes at most about 500 bytes, counting the tables' spare room and a
+    /// host name of the longest kind, so each table stays under about 32 MB with the
+    /// default of [`DEFAULT_MAX_TRACKED_SOURCES`]. Splitting by source is already
+    /// bounded by `max_open_files`, and reassembly by its own limit on waiting
+    /// messages.
+    pub max_tracked_sources: usize,
+    /// Whether the console's packet lines are coloured. The log itself is always plain.
+    pub color: ColorChoice,
+    /// Warn when more than this many packets per second arrive, averaged over the last
+    /// few seconds. Every received packet counts, including filtered ones.
+    pub alert_pps: Option<u64>,
+    /// Warn when more than this many payload bits per second arrive, averaged the same
+    /// way as `alert_pps`.
+    pub alert_bps: Option<u64>,
+    /// Stop the listener when one of the alert thresholds is crossed. See
+    /// [`UdpLogger::rate_alert_stopped`](crate::UdpLogger::rate_alert_stopped).
+    pub exit_on_alert: bool,
+    /// Read a 32-bit big-endian sequence number at this byte offset in each payload,
+    /// and warn about, and count, numbers each source skips or sends out of order.
+    pub seq_offset: Option<usize>,
+    /// Read the time each packet was sent, as a 64-bit big-endian count of nanoseconds
+    /// since the Unix epoch, at this byte offset in each payload, and record the
+    /// one-way latency in [`Stats::latency`](crate::Stats::latency).
+    pub latency_offset: Option<usize>,
+    /// Set `SO_REUSEPORT` so several listeners can bind the same port, with the kernel
+    /// sharing the datagrams out between them. Linux only; elsewhere binding fails
+    /// with an `Unsupported` error.
+    pub reuse_port: bool,
+    /// Receive only datagrams that arrive on the network interface with this name,
+    /// such as `eth1`, with `SO_BINDTODEVICE`. Unlike binding to one of its addresses,
+    /// this tells apart interfaces that share an address, like VLANs. Linux only, and
+    /// before Linux 5.7 it also needs `CAP_NET_RAW`; elsewhere binding fails with an
+    /// `Unsupported` error.
+    pub interface: Option<String>,
+    /// Connect the socket to this one peer, so the kernel only delivers datagrams from
+    /// it and reports the ICMP errors it sends back, such as port unreachable, on the
+    /// next receive, where they're counted and reported as warnings. Needs a single bind
+    /// address. `None` (the default) receives from anyone.
+    pub connect: Option<SocketAddr>,
+}
+
+impl Config {
+    /// The session ID, first generating one if none is set, so that everything the
+    /// listener writes names the same session.
+    pub(crate) fn session_id(&mut self) -> Arc<str> {
+        self.session_id
+            .get_or_insert_with(new_session_id)
+            .as_str()
+            .into()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_addr: DEFAULT_BIND_ADDR
+                .parse()
+                .expect("default bind address is valid"),
+            extra_binds: Vec::new(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            snaplen: None,
+            recv_buffer_size: None,
+            output: Output::File(PathBuf::from(DEFAULT_OUTPUT_PATH)),
+            existing_log: ExistingLog::default(),
+            format: Format::Text,
+            line_ending: LineEnding::Lf,
+            session_id: None,
+            time_format: TimeFormat::default(),
+            clock: Arc::new(SystemClock),
+            ipv6_only: false,
+            multicast: Vec::new(),
+            echo: false,
+            max_log_size: None,
+            max_log_files: DEFAULT_MAX_LOG_FILES,
+            rotate: None,
+            split_by_source: None,
+            output_dir: None,
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            source_filter: IpFilter::default(),
+            length_filter: LengthFilter::default(),
+            payload_filter: PayloadFilter::default(),
+            filter: None,
+            triggers: Triggers::default(),
+            reassemble: None,
+            reassemble_timeout: DEFAULT_REASSEMBLE_TIMEOUT,
+            workers: 0,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            on_overflow: OverflowPolicy::Block,
+            flush: FlushPolicy::Every,
+            write_buffer: DEFAULT_WRITE_BUFFER,
+            fsync: false,
+            idle_timeout: None,
+            max_packets: None,
+            count_includes_filtered: false,
+            resolve: false,
+            geoip: None,
+            ring_size: None,
+            metrics_addr: None,
+            stats_file: None,
+            event_socket: None,
+            output_sqlite: None,
+            sqlite_batch: DEFAULT_SQLITE_BATCH,
+            parse: None,
+            parse_map: ParseMap::default(),
+            dedup_window: None,
+            checksum: None,
+            index: false,
+            forward: Vec::new(),
+            compress: None,
+            compress_rotated: false,
+            verbosity: Verbosity::Auto,
+            console_sample: None,
+            console_format: None,
+            sample: None,
+            per_source_limit: None,
+            throttle: None,
+            max_tracked_sources: DEFAULT_MAX_TRACKED_SOURCES,
+            color: ColorChoice::Auto,
+            alert_pps: None,
+            alert_bps: None,
+            exit_on_alert: false,
+            seq_offset: None,
+            latency_offset: None,
+            reuse_port: false,
+            interface: None,
+            connect: None,
+            ip_fields: false,
+            dst_ip: false,
+        }
+    }
+}
+
+/// Which checksum is logged for each payload, for [`Config::checksum`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-32 (IEEE), as computed by zlib and `crc32`.
+    Crc32,
+    /// 64-bit xxHash (XXH64) with seed 0, as printed by `xxhsum`. Much faster than
+    /// CRC-32 on large payloads, and less likely to collide.
+    Xxhash,
+}
+
+impl std::str::FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "crc32" => Ok(Checksum::Crc32),
+            "xxhash" => Ok(Checksum::Xxhash),
+            _ => Err("expected 'crc32' or 'xxhash'".to_string()),
+        }
+    }
+}
+
+/// How log files are compressed, for [`Config::compress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// A gzip stream, readable with `zcat` or `gunzip`.
+    Gzip,
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err("expected 'gzip'".to_string()),
+        }
+    }
+}
+
+/// What happens to a log file that already exists, for [`Config::existing_log`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExistingLog {
+    /// Truncate it and start again, warning first if it held anything.
+    #[default]
+    Truncate,
+    /// Carry on at its end. A pcap or binary log only gets a header if it was empty.
+    Append,
+    /// Fail to start, leaving it untouched.
+    Refuse,
+}
+
+/// How much is printed on the console for each logged packet, for
+/// [`Config::verbosity`]. Startup and shutdown messages are printed at every level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// `Normal` when stdout is a terminal and `Quiet` when it's redirected, so a
+    /// listener whose output goes to a pipe or a file spends nothing on packet lines
+    /// nobody is watching.
+    #[default]
+    Auto,
+    /// Nothing per packet.
+    Quiet,
+    /// A line with the size, source and payload of each packet, wherever stdout goes.
+    Normal,
+    /// The same, with the time it was received and whether it was truncated or failed
+    /// to decode.
+    Verbose,
+}
+
+impl std::str::FromStr for Verbosity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Verbosity::Auto),
+            "quiet" => Ok(Verbosity::Quiet),
+            "normal" => Ok(Verbosity::Normal),
+            "verbose" => Ok(Verbosity::Verbose),
+            _ => Err("expected 'auto', 'quiet', 'normal' or 'verbose'".to_string()),
+        }
+    }
+}
+
+/// Which packets are logged, for [`Config::sample`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// The first of every this-many packets, so exactly one in N.
+    Every(u64),
+    /// Each packet independently with this probability, between 0 and 1.
+    Rate(f64),
+}
+
+/// Where a chunk's header fields are, for [`Config::reassemble`].
+///
+/// The header is at the start of the payload: the message ID, then the chunk's index
+/// (counting from 0), then the total number of chunks in the message, each a
+/// big-endian unsigned integer of 1, 2, 4 or 8 bytes. The rest of the payload is the
+/// chunk's share of the message. Written `ID,INDEX,TOTAL` on the command line, such as
+/// `4,2,2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLayout {
+    pub id_len: usize,
+    pub index_len: usize,
+    pub total_len: usize,
+}
+
+impl ChunkLayout {
+    /// How many bytes the header takes up.
+    pub fn header_len(&self) -> usize {
+        self.id_len + self.index_len + self.total_len
+    }
+}
+
+impl std::str::FromStr for ChunkLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || {
+            "expected the sizes in bytes of the ID, index and total fields, each 1, 2, 4 or \
+             8, such as '4,2,2'"
+                .to_string()
+        };
+        let lens: Vec<usize> = s
+            .split(',')
+            .map(|len| match len.trim().parse() {
+                Ok(len @ (1 | 2 | 4 | 8)) => Ok(len),
+                _ => Err(expected()),
+            })
+            .collect::<Result<_, _>>()?;
+        let [id_len, index_len, total_len] = lens[..] else {
+            return Err(expected());
+        };
+        Ok(ChunkLayout {
+            id_len,
+            index_len,
+            total_len,
+        })
+    }
+}
+
+/// When the console's packet lines are coloured, for [`Config::color`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when stdout is a terminal and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err("expected 'auto', 'always' or 'never'".to_string()),
+        }
+    }
+}
+
+/// How the payload of each packet is rendered in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The payload as UTF-8 text, with invalid sequences replaced.
+    Text,
+    /// A hex dump with offsets and an ASCII gutter, safe for binary payloads.
+    Hex,
+    /// A libpcap capture file that Wireshark and tcpdump can open.
+    Pcap,
+    /// One JSON object per line, with the payload base64-encoded.
+    Json,
+    /// Binary records, each a fixed header and the raw payload, for fast
+    /// post-processing, after a header naming the session. See
+    /// [`capture_header`](crate::capture_header) and
+    /// [`encode_record`](crate::encode_record) for the layout and
+    /// [`read_records`](crate::read_records) for reading them back.
+    Bin,
+    /// Each payload base64-encoded on a line of its own, and nothing else, for tools
+    /// that read lines of text. With `prefix`, each line starts with the receive time
+    /// and the source, as in `2024-06-01T12:00:00.123+02:00 192.0.2.1:5000 aGVsbG8=`.
+    /// See [`format_base64`](crate::format_base64).
+    Base64 { prefix: bool },
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "hex" => Ok(Format::Hex),
+            "pcap" => Ok(Format::Pcap),
+            "json" => Ok(Format::Json),
+            "bin" => Ok(Format::Bin),
+            "base64" => Ok(Format::Base64 { prefix: false }),
+            _ => Err("expected 'text', 'hex', 'pcap', 'json', 'bin' or 'base64'".to_string()),
+        }
+    }
+}
+
+/// What ends each record of a text format, for [`Config::line_ending`].
+///
+/// Hex entries run over several lines; their rows end in CRLF with
+/// [`Crlf`](LineEnding::Crlf) and in a newline otherwise, and only the last row ends in
+/// the separator, so it still marks where one packet's entry stops.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// A newline, `\n`.
+    #[default]
+    Lf,
+    /// A carriage return and a newline, `\r\n`, for Windows tools.
+    Crlf,
+    /// A NUL byte, which no text or JSON entry can contain.
+    Nul,
+    /// Any other separator, such as the ASCII record separator `\x1e`.
+    Custom(String),
+}
+
+impl LineEnding {
+    /// What's written after each record.
+    pub fn record_end(&self) -> &str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Nul => "\0",
+            LineEnding::Custom(separator) => separator,
+        }
+    }
+
+    /// What's written after the lines within a record, those of a hex entry.
+    pub fn line_end(&self) -> &str {
+        match self {
+            LineEnding::Crlf => "\r\n",
+            _ => "\n",
+        }
+    }
+}
+
+/// Parses `lf`, `crlf`, `nul` or `custom:<TEXT>`, where the text may use the escapes
+/// `\n`, `\r`, `\t`, `\0`, `\\` and `\xHH` (up to `\x7f`), as in `custom:\x1e`.
+impl std::str::FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            "nul" => Ok(LineEnding::Nul),
+            _ => match s.strip_prefix("custom:") {
+                Some("") => Err("the custom separator can't be empty".to_string()),
+                Some(text) => unescape(text).map(LineEnding::Custom),
+                None => Err("expected 'lf', 'crlf', 'nul' or 'custom:<TEXT>'".to_string()),
+            },
+        }
+    }
+}
+
+/// `text` with its backslash escapes replaced by the characters they stand for.
+fn unescape(text: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let hex = digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit());
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) if hex && byte.is_ascii() => out.push(byte as char),
+                    _ => {
+                        return Err(format!(
+                            "'\\x{}' is not an escape from \\x00 to \\x7f",
+                            digits
+                        ));
+                    }
+                }
+            }
+            Some(other) => return Err(format!("'\\{}' is not a known escape", other)),
+            None => return Err("a trailing '\\' escapes nothing".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+/// A protocol whose payloads the listener can decode, for [`Config::parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Syslog messages, RFC 5424 or RFC 3164. See [`parse_syslog`](crate::parse_syslog).
+    Syslog,
+    /// DNS queries and responses. See [`parse_dns`](crate::parse_dns).
+    Dns,
+    /// DTLS record headers, without decrypting anything. See
+    /// [`parse_dtls`](crate::parse_dtls).
+    Dtls,
+    /// NTP packet headers. See [`parse_ntp`](crate::parse_ntp).
+    Ntp,
+}
+
+impl Protocol {
+    /// Whether payloads are binary, so that ones which don't decode are better shown
+    /// as a hex dump than as text.
+    pub fn is_binary(self) -> bool {
+        match self {
+            Protocol::Syslog => false,
+            Protocol::Dns | Protocol::Dtls | Protocol::Ntp => true,
+        }
+    }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "syslog" => Ok(Protocol::Syslog),
+            "dns" => Ok(Protocol::Dns),
+            "dtls" => Ok(Protocol::Dtls),
+            "ntp" => Ok(Protocol::Ntp),
+            _ => Err("expected 'syslog', 'dns', 'dtls' or 'ntp'".to_string()),
+        }
+    }
+}
+
+/// The protocol to decode the payloads arriving on each local port as, for
+/// [`Config::parse_map`]. Written `PORT=PROTO,...` on the command line, such as
+/// `53=dns,514=syslog`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseMap {
+    ports: BTreeMap<u16, Protocol>,
+}
+
+impl ParseMap {
+    /// Decodes what arrives on `port` as `protocol`, instead of anything given for the
+    /// port before.
+    pub fn insert(&mut self, port: u16, protocol: Protocol) {
+        self.ports.insert(port, protocol);
+    }
+
+    /// The protocol for payloads arriving on `port`, if it has one.
+    pub fn protocol_for(&self, port: u16) -> Option<Protocol> {
+        self.ports.get(&port).copied()
+    }
+
+    /// Every port and its protocol, in port order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, Protocol)> + '_ {
+        self.ports.iter().map(|(&port, &protocol)| (port, protocol))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+}
+
+impl std::str::FromStr for ParseMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = ParseMap::default();
+        for entry in s.split(',') {
+            let Some((port, protocol)) = entry.split_once('=') else {
+                return Err(format!(
+                    "expected PORT=PROTO, such as '53=dns', not '{}'",
+                    entry.trim()
+                ));
+            };
+            let port = match port.trim().parse() {
+                Ok(port) if port > 0 => port,
+                _ => return Err(format!("'{}' isn't a port number", port.trim())),
+            };
+            map.insert(port, protocol.trim().parse()?);
+        }
+        Ok(map)
+    }
+}
+
+/// Which clock log timestamps (and the dates in rotated file names) use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timezone {
+    /// The host's local time, as configured in the operating system.
+    #[default]
+    Local,
+    /// Coordinated Universal Time, unambiguous when logs are collected from several
+    /// regions.
+    Utc,
+}
+
+impl std::str::FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Timezone::Local),
+            "utc" => Ok(Timezone::Utc),
+            _ => Err("expected 'utc' or 'local'".to_string()),
+        }
+    }
+}
+
+/// How often time-based rotation starts a new log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotatePeriod {
+    /// A new file at local midnight.
+    Daily,
+    /// A new file at the start of every local hour.
+    Hourly,
+}
+
+impl std::str::FromStr for RotatePeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(RotatePeriod::Daily),
+            "hourly" => Ok(RotatePeriod::Hourly),
+            _ => Err("expected 'daily' or 'hourly'".to_string()),
+        }
+    }
+}
+
+/// When the log is flushed.
+///
+/// Flushing after every packet (the default) means nothing is lost if the listener is
+/// killed, but costs one write system call per packet, which limits throughput,
+/// especially on spinning disks. The other policies collect entries in a buffer
+/// ([`Config::write_buffer`], 64 KiB by default) and write it out in one go, which
+/// typically sustains several times the packet rate. The buffer is always written out
+/// when it fills up and when the listener shuts down cleanly.
+///
+/// A flush only hands entries to the operating system. That is enough if just the
+/// listener dies, but the operating system may hold them in memory for many seconds
+/// before writing them to the disk, and a crash or power cut loses them. Flushes that
+/// don't come from a full buffer (each interval, a reopen, and shutdown) therefore also
+/// wait for the file to reach the disk (fsync): so `Interval(1s)` syncs once a second,
+/// and everything logged more than about a second before a crash survives.
+///
+/// The per-packet flushes of `Every` only sync with [`Config::fsync`], since waiting
+/// for the disk on every packet limits the rate to what the disk can commit: perhaps
+/// a few hundred packets a second on a spinning disk, a few thousand on an SSD. Nothing
+/// that was logged is then lost, which suits a low rate of packets that matter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after every packet.
+    Every,
+    /// Flush at least this often, from a background thread, so entries reach the log
+    /// within roughly this delay even when traffic stops.
+    Interval(Duration),
+    /// Flush only when the buffer fills up and at shutdown.
+    Never,
+}
+
+impl std::str::FromStr for FlushPolicy {
+    type Err = String;
+
+    /// Parses `every`, `never` or `interval:<ms>`, for example `interval:500`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "every" => Ok(FlushPolicy::Every),
+            "never" => Ok(FlushPolicy::Never),
+            _ => match s.strip_prefix("interval:") {
+                Some(ms) => match ms.parse::<u64>() {
+                    Ok(ms) if ms > 0 => Ok(FlushPolicy::Interval(Duration::from_millis(ms))),
+                    _ => Err(format!("'{}' is not a positive number of milliseconds", ms)),
+                },
+                None => Err("expected 'every', 'never' or 'interval:<ms>'".to_string()),
+            },
+        }
+    }
+}
+
+/// What to do with a packet when the worker queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for room in the queue. Nothing is dropped by the listener itself, but while
+    /// it waits the socket's own buffer may fill and the kernel drops packets instead.
+    Block,
+    /// Drop the packet and count it, so the socket keeps being read.
+    Drop,
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop" => Ok(OverflowPolicy::Drop),
+            _ => Err("expected 'block' or 'drop'".to_string()),
+        }
+    }
+}
+
+/// A multicast group to join, and the local interface to join it on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MulticastGroup {
+    /// An IPv4 group. The interface is given by one of its addresses, with
+    /// `0.0.0.0` letting the operating system choose.
+    V4 {
+        group: Ipv4Addr,
+        interface: Ipv4Addr,
+    },
+    /// An IPv6 group. The interface is given by index, with `0` letting the operating
+    /// system choose.
+    V6 { group: Ipv6Addr, interface: u32 },
+}
+
+impl std::str::FromStr for MulticastGroup {
+    type Err = String;
+
+    /// Parses `<group>[,<interface>]`, for example `239.255.255.250`,
+    /// `239.255.255.250,192.168.1.10` or `ff02::c,2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (group, interface) = match s.split_once(',') {
+            Some((group, interface)) => (group, Some(interface)),
+            None => (s, None),
+        };
+        let group: IpAddr = group
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address", group))?;
+        if !group.is_multicast() {
+            return Err(format!("{} is not a multicast address", group));
+        }
+
+        match group {
+            IpAddr::V4(group) => {
+                let interface = match interface {
+                    Some(iface) => iface
+                        .parse()
+                        .map_err(|_| format!("'{}' is not an IPv4 interface address", iface))?,
+                    None => Ipv4Addr::UNSPECIFIED,
+                };
+                Ok(MulticastGroup::V4 { group, interface })
+            }
+            IpAddr::V6(group) => {
+                let interface = match interface {
+                    Some(iface) => iface
+                        .parse()
+                        .map_err(|_| format!("'{}' is not an IPv6 interface index", iface))?,
+                    None => 0,
+                };
+                Ok(MulticastGroup::V6 { group, interface })
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MulticastGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MulticastGroup::V4 { group, interface } if interface.is_unspecified() => {
+                write!(f, "{}", group)
+            }
+            MulticastGroup::V4 { group, interface } => write!(f, "{} on {}", group, interface),
+            MulticastGroup::V6 {
+                group,
+                interface: 0,
+            } => write!(f, "{}", group),
+            MulticastGroup::V6 { group, interface } => {
+                write!(f, "{} on interface {}", group, interface)
+            }
+        }
+    }
+}
+
+/// The destination for log lines.
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// Write to a file, creating it or dealing with an existing one as
+    /// [`Config::existing_log`] says.
+    File(PathBuf),
+    /// Write to standard output so the log can be piped into another program.
+    Stdout,
+}
+
+impl Output {
+    /// Interprets an output argument, where `-` means standard output.
+    pub fn from_arg(value: &str) -> Output {
+        if value == "-" {
+            Output::Stdout
+        } else {
+            Output::File(PathBuf::from(value))
+        }
+    }
+
+    /// Opens the destination for writing, compressed if `compression` is set, with a
+    /// file that already exists dealt with as `existing` says. Also returns whether
+    /// there's something in it already, as there is when appending to an earlier log.
+    pub(crate) fn open(
+        &self,
+        compression: Option<Compression>,
+        existing: ExistingLog,
+    ) -> io::Result<(Box<dyn SyncWrite>, bool)> {
+        match self {
+            Output::Stdout => Ok((Box::new(Compressor::new(io::stdout(), compression)), false)),
+            Output::File(path) => {
+                let file = create_log_file(path, existing)?;
+                let has_content = file.metadata()?.len() > 0;
+                Ok((Box::new(Compressor::new(file, compression)), has_content))
+            }
+        }
+    }
+}
+
+/// Creates a log file, or opens one that already exists as `existing` says.
+/// A missing parent directory is reported by name, since the operating system's
+/// own "No such file or directory" doesn't say which part of the path is missing.
+pub(crate) fn create_log_file(path: &Path, existing: ExistingLog) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        // An empty parent means a bare file name in the current directory.
+        if !parent.as_os_str().is_empty() && !parent.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Couldn't create '{}': directory '{}' does not exist",
+                    path.display(),
+                    parent.display()
+                ),
+            ));
+        }
+    }
+    let mut options = OpenOptions::new();
+    match existing {
+        ExistingLog::Truncate => {
+            if let Ok(metadata) = fs::metadata(path)
+                && metadata.is_file()
+                && metadata.len() > 0
+            {
+                diag::warning!(
+                    "overwriting '{}', which held {} bytes from an earlier run \
+                     (--append would add to it, and --no-clobber keep it)",
+                    path.display(),
+                    metadata.len()
+                );
+            }
+            options.write(true).create(true).truncate(true)
+        }
+        ExistingLog::Append => options.append(true).create(true),
+        ExistingLog::Refuse => options.write(true).create_new(true),
+    };
+    options.open(path).map_err(|e| {
+        if e.kind() == io::ErrorKind::AlreadyExists {
+            return io::Error::new(
+                e.kind(),
+                format!(
+                    "'{}' already exists; move it away, or use --append to add to it",
+                    path.display()
+                ),
+            );
+        }
+        io::Error::new(
+            e.kind(),
+            format!("Couldn't create or open '{}': {}", path.display(), e),
+        )
+    })
+}
+
+impl std::fmt::Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Output::File(path) => write!(f, "'{}'", path.display()),
+            Output::Stdout => write!(f, "stdout"),
+        }
+    }
+}
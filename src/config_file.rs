@@ -0,0 +1,462 @@
+// Reading the listener's settings from a TOML file.
+
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde::de::{Deserializer, Error as _};
+
+use crate::config::{
+    Checksum, ChunkLayout, ColorChoice, Compression, Config, DEFAULT_DEDUP_WINDOW, ExistingLog,
+    FlushPolicy, Format, LineEnding, MulticastGroup, Output, OverflowPolicy, ParseMap, Protocol,
+    RotatePeriod, Sample, Timezone, Verbosity,
+};
+use crate::filter::{FilterExpr, parse_ip_net, parse_payload_hex, parse_payload_regex};
+use crate::format::check_time_pattern;
+use crate::limit::check_per_source_limit;
+use crate::session::check_session_id;
+use crate::throttle::check_throttle;
+
+/// Reads a [`Config`] from a TOML file. Settings the file leaves out keep their
+/// defaults.
+///
+/// The keys are the command-line option names without the leading dashes, and values
+/// are written as they would be on the command line, with options that can be repeated
+/// given as arrays:
+///
+/// ```toml
+/// bind = "0.0.0.0:5140"
+/// output = "/var/log/udp/packets.log"
+/// format = "json"
+/// allow = ["10.0.0.0/8", "192.168.1.5"]
+/// max-size = 10_000_000
+/// flush = "interval:500"
+/// ```
+///
+/// The console's level of detail is `verbosity = "auto"` (the default), `"quiet"`,
+/// `"normal"` or `"verbose"`.
+/// A key that isn't a known option, a value of the wrong type and a value the option
+/// wouldn't accept are all `InvalidData` errors that name the file and the key.
+pub fn load_config(path: &Path) -> io::Result<Config> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Couldn't read config file '{}': {}", path.display(), e),
+        )
+    })?;
+    let invalid = |message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid config file '{}': {}", path.display(), message),
+        )
+    };
+    let file: ConfigFile =
+        toml::from_str(&text).map_err(|e| invalid(e.to_string().trim_end().to_string()))?;
+    let mut config = Config::default();
+    file.apply(&mut config).map_err(invalid)?;
+    Ok(config)
+}
+
+/// The contents of a config file, one field per command-line option.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    #[serde(deserialize_with = "one_or_many")]
+    bind: Vec<SocketAddr>,
+    #[serde(deserialize_with = "nets")]
+    allow: Vec<IpNet>,
+    #[serde(deserialize_with = "nets")]
+    deny: Vec<IpNet>,
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+    #[serde(rename = "match")]
+    match_: Vec<String>,
+    match_hex: Vec<String>,
+    #[serde(deserialize_with = "parsed")]
+    filter: Option<FilterExpr>,
+    start_trigger: Option<String>,
+    stop_trigger: Option<String>,
+    log_triggers: bool,
+    dedup: bool,
+    dedup_window: Option<u64>,
+    #[serde(deserialize_with = "parsed")]
+    checksum: Option<Checksum>,
+    index: bool,
+    #[serde(deserialize_with = "parsed")]
+    reassemble: Option<ChunkLayout>,
+    reassemble_timeout: Option<u64>,
+    buffer_size: Option<usize>,
+    snaplen: Option<usize>,
+    recv_buffer: Option<usize>,
+    resolve: bool,
+    geoip: Option<PathBuf>,
+    echo: bool,
+    forward: Vec<SocketAddr>,
+    ipv6_only: bool,
+    reuse_port: bool,
+    interface: Option<String>,
+    connect: Option<SocketAddr>,
+    ip_fields: bool,
+    dst_ip: bool,
+    #[serde(deserialize_with = "parsed_list")]
+    multicast: Vec<MulticastGroup>,
+    output: Option<String>,
+    append: bool,
+    no_clobber: bool,
+    #[serde(deserialize_with = "parsed")]
+    format: Option<Format>,
+    base64_prefix: bool,
+    #[serde(deserialize_with = "parsed")]
+    line_ending: Option<LineEnding>,
+    #[serde(deserialize_with = "parsed")]
+    parse: Option<Protocol>,
+    #[serde(deserialize_with = "parsed")]
+    parse_map: Option<ParseMap>,
+    #[serde(deserialize_with = "parsed")]
+    flush: Option<FlushPolicy>,
+    write_buffer: Option<usize>,
+    fsync: bool,
+    #[serde(deserialize_with = "parsed")]
+    timezone: Option<Timezone>,
+    time_format: Option<String>,
+    session_id: Option<String>,
+    #[serde(deserialize_with = "parsed")]
+    compress: Option<Compression>,
+    compress_rotated: bool,
+    max_size: Option<u64>,
+    max_files: Option<usize>,
+    #[serde(deserialize_with = "parsed")]
+    rotate: Option<RotatePeriod>,
+    split_by_source: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    max_open_files: Option<usize>,
+    idle_timeout: Option<f64>,
+    count: Option<u64>,
+    count_includes_filtered: bool,
+    ring: Option<usize>,
+    workers: Option<usize>,
+    queue_size: Option<usize>,
+    #[serde(deserialize_with = "parsed")]
+    on_overflow: Option<OverflowPolicy>,
+    metrics_addr: Option<SocketAddr>,
+    stats_file: Option<PathBuf>,
+    event_socket: Option<PathBuf>,
+    output_sqlite: Option<PathBuf>,
+    sqlite_batch: Option<usize>,
+    #[serde(deserialize_with = "parsed")]
+    verbosity: Option<Verbosity>,
+    console_sample: Option<u64>,
+    #[serde(deserialize_with = "parsed")]
+    console_format: Option<Format>,
+    sample: Option<u64>,
+    sample_rate: Option<f64>,
+    per_source_limit: Option<f64>,
+    throttle: Option<f64>,
+    max_tracked_sources: Option<usize>,
+    #[serde(deserialize_with = "parsed")]
+    color: Option<ColorChoice>,
+    alert_pps: Option<u64>,
+    alert_bps: Option<u64>,
+    exit_on_alert: bool,
+    seq_offset: Option<usize>,
+    latency_offset: Option<usize>,
+}
+
+impl ConfigFile {
+    /// Copies the settings the file gives into `config`, checking the limits the
+    /// command line also enforces.
+    fn apply(self, config: &mut Config) -> Result<(), String> {
+        let at_least_one = |key: &str, value: u64| {
+            if value == 0 {
+                Err(format!("'{}' must be at least 1", key))
+            } else {
+                Ok(())
+            }
+        };
+
+        if let Some((&first, rest)) = self.bind.split_first() {
+            config.bind_addr = first;
+            config.extra_binds = rest.to_vec();
+        }
+        config.source_filter.allow = self.allow;
+        config.source_filter.deny = self.deny;
+        config.length_filter.min = self.min_len;
+        config.length_filter.max = self.max_len;
+        for pattern in &self.match_ {
+            let regex = parse_payload_regex(pattern)
+                .map_err(|e| format!("invalid value '{}' for 'match': {}", pattern, e))?;
+            config.payload_filter.patterns.push(regex);
+        }
+        for pattern in &self.match_hex {
+            let regex = parse_payload_hex(pattern)
+                .map_err(|e| format!("invalid value '{}' for 'match-hex': {}", pattern, e))?;
+            config.payload_filter.patterns.push(regex);
+        }
+        config.filter = self.filter;
+        let trigger = |key: &str, hex: &Option<String>| {
+            hex.as_deref()
+                .map(|hex| {
+                    parse_payload_hex(hex)
+                        .map_err(|e| format!("invalid value '{}' for '{}': {}", hex, key, e))
+                })
+                .transpose()
+        };
+        config.triggers.start = trigger("start-trigger", &self.start_trigger)?;
+        config.triggers.stop = trigger("stop-trigger", &self.stop_trigger)?;
+        if self.log_triggers && config.triggers.start.is_none() && config.triggers.stop.is_none() {
+            return Err("'log-triggers' needs 'start-trigger' or 'stop-trigger'".to_string());
+        }
+        config.triggers.log = self.log_triggers;
+        match (self.dedup, self.dedup_window) {
+            (true, window) => {
+                if let Some(ms) = window {
+                    at_least_one("dedup-window", ms)?;
+                }
+                config.dedup_window =
+                    Some(window.map_or(DEFAULT_DEDUP_WINDOW, Duration::from_millis));
+            }
+            (false, Some(_)) => return Err("'dedup-window' needs 'dedup = true'".to_string()),
+            (false, None) => {}
+        }
+        config.checksum = self.checksum;
+        config.index = self.index;
+        if let Some(size) = self.buffer_size {
+            at_least_one("buffer-size", size as u64)?;
+            config.buffer_size = size;
+        }
+        if let Some(snaplen) = self.snaplen {
+            at_least_one("snaplen", snaplen as u64)?;
+            config.snaplen = Some(snaplen);
+        }
+        config.recv_buffer_size = self.recv_buffer;
+        config.resolve = self.resolve;
+        config.geoip = self.geoip;
+        config.echo = self.echo;
+        config.forward = self.forward;
+        config.ipv6_only = self.ipv6_only;
+        config.reuse_port = self.reuse_port;
+        config.interface = self.interface;
+        config.connect = self.connect;
+        config.ip_fields = self.ip_fields;
+        config.dst_ip = self.dst_ip;
+        config.multicast = self.multicast;
+        if let Some(output) = self.output {
+            config.output = Output::from_arg(&output);
+        }
+        config.existing_log = match (self.append, self.no_clobber) {
+            (true, true) => {
+                return Err("'append' and 'no-clobber' can't be combined".to_string());
+            }
+            (true, false) => ExistingLog::Append,
+            (false, true) => ExistingLog::Refuse,
+            (false, false) => ExistingLog::Truncate,
+        };
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+        if self.base64_prefix {
+            match &mut config.format {
+                Format::Base64 { prefix } => *prefix = true,
+                _ => return Err("'base64-prefix' needs 'format = \"base64\"'".to_string()),
+            }
+        }
+        if let Some(line_ending) = self.line_ending {
+            config.line_ending = line_ending;
+        }
+        config.parse = self.parse;
+        config.parse_map = self.parse_map.unwrap_or_default();
+        if let Some(flush) = self.flush {
+            config.flush = flush;
+        }
+        if let Some(write_buffer) = self.write_buffer {
+            at_least_one("write-buffer", write_buffer as u64)?;
+            config.write_buffer = write_buffer;
+        }
+        config.fsync = self.fsync;
+        if let Some(timezone) = self.timezone {
+            config.time_format.timezone = timezone;
+        }
+        if let Some(pattern) = self.time_format {
+            check_time_pattern(&pattern).map_err(|e| format!("'time-format': {}", e))?;
+            config.time_format.pattern = Some(pattern);
+        }
+        if let Some(id) = self.session_id {
+            check_session_id(&id).map_err(|e| format!("'session-id': {}", e))?;
+            config.session_id = Some(id);
+        }
+        config.compress = self.compress;
+        config.compress_rotated = self.compress_rotated;
+        config.max_log_size = self.max_size;
+        if let Some(files) = self.max_files {
+            config.max_log_files = files;
+        }
+        config.rotate = self.rotate;
+        config.split_by_source = self.split_by_source;
+        config.output_dir = self.output_dir;
+        if let Some(files) = self.max_open_files {
+            config.max_open_files = files;
+        }
+        if let Some(secs) = self.idle_timeout {
+            let timeout = Duration::try_from_secs_f64(secs)
+                .ok()
+                .filter(|timeout| !timeout.is_zero())
+                .ok_or_else(|| "'idle-timeout' must be a positive number".to_string())?;
+            config.idle_timeout = Some(timeout);
+        }
+        config.max_packets = self.count;
+        config.count_includes_filtered = self.count_includes_filtered;
+        if let Some(size) = self.ring {
+            at_least_one("ring", size as u64)?;
+            config.ring_size = Some(size);
+        }
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+        if let Some(size) = self.queue_size {
+            at_least_one("queue-size", size as u64)?;
+            config.queue_size = size;
+        }
+        if let Some(policy) = self.on_overflow {
+            config.on_overflow = policy;
+        }
+        config.metrics_addr = self.metrics_addr;
+        config.stats_file = self.stats_file;
+        config.event_socket = self.event_socket;
+        if let Some(ms) = self.reassemble_timeout {
+            if self.reassemble.is_none() {
+                return Err("'reassemble-timeout' needs 'reassemble'".to_string());
+            }
+            at_least_one("reassemble-timeout", ms)?;
+            config.reassemble_timeout = Duration::from_millis(ms);
+        }
+        config.reassemble = self.reassemble;
+        if let Some(rows) = self.sqlite_batch {
+            if self.output_sqlite.is_none() {
+                return Err("'sqlite-batch' needs 'output-sqlite'".to_string());
+            }
+            at_least_one("sqlite-batch", rows as u64)?;
+            config.sqlite_batch = rows;
+        }
+        config.output_sqlite = self.output_sqlite;
+        if let Some(verbosity) = self.verbosity {
+            config.verbosity = verbosity;
+        }
+        if self.console_format.is_some() {
+            config.console_format = self.console_format;
+        }
+        if let Some(every) = self.console_sample {
+            at_least_one("console-sample", every)?;
+            config.console_sample = Some(every);
+        }
+        config.sample = match (self.sample, self.sample_rate) {
+            (Some(_), Some(_)) => {
+                return Err("'sample' and 'sample-rate' can't be combined".to_string());
+            }
+            (Some(every), None) => {
+                at_least_one("sample", every)?;
+                Some(Sample::Every(every))
+            }
+            (None, Some(rate)) if !(0.0..=1.0).contains(&rate) => {
+                return Err(format!(
+                    "'sample-rate' must be between 0 and 1, not {}",
+                    rate
+                ));
+            }
+            (None, Some(rate)) => Some(Sample::Rate(rate)),
+            (None, None) => None,
+        };
+        if let Some(pps) = self.per_source_limit {
+            check_per_source_limit(pps).map_err(|e| format!("'per-source-limit': {}", e))?;
+            config.per_source_limit = Some(pps);
+        }
+        if let Some(pps) = self.throttle {
+            check_throttle(pps).map_err(|e| format!("'throttle': {}", e))?;
+            config.throttle = Some(pps);
+        }
+        if let Some(sources) = self.max_tracked_sources {
+            at_least_one("max-tracked-sources", sources as u64)?;
+            config.max_tracked_sources = sources;
+        }
+        if let Some(color) = self.color {
+            config.color = color;
+        }
+        config.alert_pps = self.alert_pps;
+        config.alert_bps = self.alert_bps;
+        config.exit_on_alert = self.exit_on_alert;
+        config.seq_offset = self.seq_offset;
+        config.latency_offset = self.latency_offset;
+        Ok(())
+    }
+}
+
+/// Deserializes a string with the type's `FromStr`, the way the command line parses
+/// the same option.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = String::deserialize(deserializer)?;
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| D::Error::custom(format!("invalid value '{}': {}", value, e)))
+}
+
+/// A single value, or an array of them for an option given several times, as
+/// `bind = ["0.0.0.0:53", "0.0.0.0:123"]`.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Like [`parsed`], for an array of strings.
+fn parsed_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| D::Error::custom(format!("invalid value '{}': {}", value, e)))
+        })
+        .collect()
+}
+
+/// An array of networks, where a plain address stands for just that host, as for
+/// `--allow` and `--deny`.
+fn nets<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| {
+            parse_ip_net(value)
+                .map_err(|e| D::Error::custom(format!("invalid value '{}': {}", value, e)))
+        })
+        .collect()
+}
@@ -0,0 +1,342 @@
+// The per-packet lines printed on the console, separate from the log itself.
+
+// `std::fmt::Write` lets `write!` append to a `String`.
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+
+use crate::color;
+use crate::config::{Config, Format, Output, Verbosity};
+use crate::format::{
+    TimeFormat, checksum_note, destination_note, format_hex, index_note, ip_fields_note,
+    payload_text,
+};
+use crate::packet::Packet;
+use crate::sink::Sink;
+
+/// Prints logged packets to stdout as [`Config::verbosity`],
+/// [`Config::console_sample`] and [`Config::console_format`] ask, with colour if
+/// [`Config::color`] allows.
+///
+/// It's one of the logger's [sinks](Sink), the first, and only there when something is
+/// to be printed: a quiet console, or one left on [`Verbosity::Auto`] when stdout isn't
+/// a terminal, isn't a sink at all, so packets cost nothing to not print. Nobody reads
+/// packet lines scrolling into a pipe or a file, and formatting them and locking stdout
+/// for every packet slows down a busy capture.
+pub(crate) struct Console {
+    out: Box<dyn Write + Send>,
+    verbosity: Verbosity,
+    format: Format,
+    time_format: TimeFormat,
+    color: bool,
+    /// Print one packet in this many.
+    sample: u64,
+    /// Packets offered for printing so far, printed or not.
+    seen: u64,
+}
+
+impl Console {
+    /// The console for `config`, printing to stdout, or `None` if it wouldn't print
+    /// anything.
+    pub(crate) fn new(config: &Config) -> Option<Console> {
+        Console::with_output(config, io::stdout().is_terminal(), Box::new(io::stdout()))
+    }
+
+    /// The console for `config`, printing to `out`, which is a terminal if `terminal`
+    /// is set.
+    pub(crate) fn with_output(
+        config: &Config,
+        terminal: bool,
+        out: Box<dyn Write + Send>,
+    ) -> Option<Console> {
+        let verbosity = match config.verbosity {
+            // When the log itself goes to stdout, the log line already shows the packet.
+            _ if matches!(config.output, Output::Stdout) => Verbosity::Quiet,
+            Verbosity::Auto if terminal => Verbosity::Normal,
+            Verbosity::Auto => Verbosity::Quiet,
+            verbosity => verbosity,
+        };
+        if verbosity == Verbosity::Quiet {
+            return None;
+        }
+        Some(Console {
+            out,
+            verbosity,
+            format: config.console_format.unwrap_or(config.format),
+            time_format: config.time_format.clone(),
+            color: color::enabled(config.color),
+            sample: config.console_sample.unwrap_or(1).max(1),
+            seen: 0,
+        })
+    }
+
+    /// Prints the line for a logged packet, unless it falls outside the sample.
+    pub(crate) fn print_packet(&mut self, pkt: &Packet) -> io::Result<()> {
+        let seen = self.seen;
+        self.seen += 1;
+        if !seen.is_multiple_of(self.sample) {
+            return Ok(());
+        }
+        let line = self.line(pkt);
+        self.out.write_all(line.as_bytes())
+    }
+
+    /// The line for one packet. It shows the same rendering as the log entry, without
+    /// the timestamp; verbose lines add the timestamp and anything unusual about the
+    /// packet. A numbered packet's line starts with its number, as in the log.
+    fn line(&self, pkt: &Packet) -> String {
+        let mut time = String::new();
+        let mut bytes = pkt.original_len.to_string();
+        let mut source = pkt.source();
+        let mut notes = destination_note(pkt) + &ip_fields_note(pkt) + &checksum_note(pkt);
+        if self.verbosity == Verbosity::Verbose {
+            time = format!("[{}]", self.time_format.text(&pkt.received_at));
+            if pkt.truncated {
+                notes.push_str(" [truncated]");
+            }
+            if pkt.is_snapped() {
+                let _ = write!(notes, " [first {} captured]", pkt.captured_len);
+            }
+            if pkt.malformed {
+                notes.push_str(" [didn't decode]");
+            }
+        }
+        if self.color {
+            if !time.is_empty() {
+                time = color::dim(&time);
+            }
+            bytes = color::bold(&bytes);
+            source = color::source(pkt.src.ip(), &source);
+        }
+        if !time.is_empty() {
+            time.push(' ');
+        }
+        time.insert_str(0, &index_note(pkt));
+        match self.format {
+            // A base64 payload isn't readable, so the console shows it as text, unless
+            // it's binary data that didn't decode.
+            Format::Text | Format::Json | Format::Base64 { .. } if !pkt.malformed => format!(
+                "{}Received {} bytes from {}{}: {}\n",
+                time,
+                bytes,
+                source,
+                notes,
+                payload_text(pkt)
+            ),
+            // The dump already ends each row with a newline.
+            Format::Text | Format::Json | Format::Base64 { .. } | Format::Hex => format!(
+                "{}Received {} bytes from {}{}:\n{}",
+                time,
+                bytes,
+                source,
+                notes,
+                format_hex(&pkt.data)
+            ),
+            // Pcap and binary records aren't readable, so only the summary is shown.
+            Format::Pcap | Format::Bin => format!(
+                "{}Received {} bytes from {}{}\n",
+                time, bytes, source, notes
+            ),
+        }
+    }
+}
+
+impl Sink for Console {
+    fn write(&mut self, pkt: &Packet) -> io::Result<()> {
+        self.print_packet(pkt)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn name(&self) -> String {
+        "the console".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use chrono::Local;
+
+    use super::*;
+    use crate::sink::Sinks;
+
+    /// Somewhere to print to that the test can read back.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn packet(payload: &str) -> Packet {
+        Packet {
+            data: payload.as_bytes().to_vec(),
+            captured_len: payload.len(),
+            original_len: payload.len(),
+            src: "192.0.2.1:5000".parse().unwrap(),
+            received_at: Local::now(),
+            monotonic: None,
+            truncated: false,
+            ttl: None,
+            tos: None,
+            dst_ip: None,
+            bind: None,
+            session_id: None,
+            src_host: None,
+            src_geo: None,
+            parsed: None,
+            malformed: false,
+            checksum: None,
+            index: None,
+        }
+    }
+
+    fn config(verbosity: Verbosity) -> Config {
+        Config {
+            verbosity,
+            color: crate::config::ColorChoice::Never,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn auto_only_prints_to_a_terminal() {
+        let config = config(Verbosity::Auto);
+        let terminal = Captured::default();
+        let mut console = Console::with_output(&config, true, Box::new(terminal.clone()))
+            .expect("a terminal gets packet lines");
+        console.write(&packet("hello")).unwrap();
+        assert_eq!(
+            terminal.text(),
+            "Received 5 bytes from 192.0.2.1:5000: hello\n"
+        );
+
+        assert!(Console::with_output(&config, false, Box::new(io::sink())).is_none());
+    }
+
+    #[test]
+    fn asking_for_packets_prints_them_anywhere() {
+        for verbosity in [Verbosity::Normal, Verbosity::Verbose] {
+            let out = Captured::default();
+            let mut console =
+                Console::with_output(&config(verbosity), false, Box::new(out.clone())).unwrap();
+            console.write(&packet("hello")).unwrap();
+            assert!(out.text().contains("Received 5 bytes from 192.0.2.1:5000"));
+        }
+    }
+
+    #[test]
+    fn nothing_is_printed_when_quiet_or_logging_to_stdout() {
+        assert!(
+            Console::with_output(&config(Verbosity::Quiet), true, Box::new(io::sink())).is_none()
+        );
+        let to_stdout = Config {
+            output: Output::Stdout,
+            ..config(Verbosity::Verbose)
+        };
+        assert!(Console::with_output(&to_stdout, true, Box::new(io::sink())).is_none());
+    }
+
+    #[test]
+    fn the_console_format_is_independent_of_the_log() {
+        let pcap = Config {
+            format: Format::Pcap,
+            ..config(Verbosity::Normal)
+        };
+        let out = Captured::default();
+        let mut console = Console::with_output(&pcap, false, Box::new(out.clone())).unwrap();
+        console.write(&packet("hi")).unwrap();
+        assert_eq!(out.text(), "Received 2 bytes from 192.0.2.1:5000\n");
+
+        let hex = Config {
+            console_format: Some(Format::Hex),
+            ..pcap
+        };
+        let out = Captured::default();
+        let mut console = Console::with_output(&hex, false, Box::new(out.clone())).unwrap();
+        console.write(&packet("hi")).unwrap();
+        assert_eq!(
+            out.text(),
+            "Received 2 bytes from 192.0.2.1:5000:\n\
+             00000000  68 69                                             |hi|\n"
+        );
+    }
+
+    #[test]
+    fn the_sample_prints_one_in_n() {
+        let config = Config {
+            console_sample: Some(3),
+            ..config(Verbosity::Normal)
+        };
+        let out = Captured::default();
+        let mut console = Console::with_output(&config, false, Box::new(out.clone())).unwrap();
+        for n in 0..7 {
+            console.write(&packet(&n.to_string())).unwrap();
+        }
+        let text = out.text();
+        let payloads: Vec<&str> = text
+            .lines()
+            .map(|line| line.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(payloads, ["0", "3", "6"]);
+    }
+
+    /// How long writing `packets` to the sinks takes, with the console among them if
+    /// there is one.
+    fn time_sinks(console: Option<Console>, packets: &[Packet]) -> Duration {
+        let mut sinks = Sinks::default();
+        if let Some(console) = console {
+            sinks.push(Box::new(console));
+        }
+        let start = Instant::now();
+        for pkt in packets {
+            assert_eq!(sinks.write(pkt), 0);
+        }
+        start.elapsed()
+    }
+
+    /// What a redirected listener saves by not printing packets. Run it with
+    /// `cargo test --release -- --ignored --nocapture console_cost`. On a typical
+    /// machine the console costs several hundred nanoseconds a packet even when the
+    /// lines go nowhere, against a few nanoseconds without it.
+    #[test]
+    #[ignore = "a benchmark; run with --ignored --nocapture"]
+    fn console_cost() {
+        let packets: Vec<Packet> = (0..200_000)
+            .map(|n| packet(&format!("{} abcdefghijklmnopqrstuvwxyz", n)))
+            .collect();
+        let printing =
+            Console::with_output(&config(Verbosity::Normal), false, Box::new(io::sink()));
+        let redirected =
+            Console::with_output(&config(Verbosity::Auto), false, Box::new(io::sink()));
+        assert!(redirected.is_none());
+
+        let with_console = time_sinks(printing, &packets);
+        let without_console = time_sinks(redirected, &packets);
+        let per_packet = |d: Duration| d.as_nanos() as f64 / packets.len() as f64;
+        println!(
+            "{} packets: {:.0} ns each printing them, {:.1} ns each with the console off",
+            packets.len(),
+            per_packet(with_console),
+            per_packet(without_console)
+        );
+        assert!(without_console < with_console);
+    }
+}
@@ -0,0 +1,191 @@
+// Running in the background (`--daemon`) and recording the process ID (`--pid-file`).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The detached background process, which still has to tell the terminal it was
+/// started from whether it came up.
+///
+/// The original process waits on a pipe until the daemon calls [`ready`](Self::ready)
+/// or [`fail`](Self::fail), so startup errors such as a port already in use still show
+/// up in the terminal and in the exit status, not only in the daemon's log.
+pub struct Daemon {
+    report: File,
+}
+
+impl Daemon {
+    /// Tells the waiting terminal that startup succeeded, letting it exit with status 0.
+    pub fn ready(self) {
+        let mut report = self.report;
+        // If the terminal has already gone, there is nobody left to tell.
+        let _ = writeln!(report, "ready {}", std::process::id());
+    }
+
+    /// Passes a startup error back to the waiting terminal, which prints it and exits
+    /// with status 1.
+    pub fn fail(self, error: &io::Error) {
+        let mut report = self.report;
+        let _ = writeln!(report, "error {}", error);
+    }
+}
+
+/// Detaches from the terminal with the usual double fork, leaving this process running
+/// in the background with stdout and stderr appended to `log`.
+///
+/// Only the background process returns; the original one waits for its report and
+/// exits. This must be called before any threads are started, since only the calling
+/// thread survives a fork. The working directory is left as it is, so relative paths
+/// on the command line keep their meaning.
+#[cfg(unix)]
+pub fn daemonize(log: &Path) -> io::Result<Daemon> {
+    use std::io::Read;
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    // Open everything the daemon needs while errors can still be printed here.
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Couldn't open daemon log '{}': {}", log.display(), e),
+            )
+        })?;
+    let null = File::open("/dev/null")?;
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors `pipe` writes.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `pipe` just opened both descriptors and nothing else owns them.
+    let (mut from_daemon, to_terminal) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: the program is still single-threaded, so the child is a full copy.
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        _ => {
+            // The terminal side: wait for the daemon to say how startup went.
+            drop(to_terminal);
+            let mut report = String::new();
+            let _ = from_daemon.read_to_string(&mut report);
+            match report.trim_end().split_once(' ') {
+                Some(("ready", pid)) => {
+                    println!(
+                        "Listening in the background (PID {}); messages go to {}",
+                        pid,
+                        log.display()
+                    );
+                    std::process::exit(0);
+                }
+                Some(("error", message)) => eprintln!("error: {}", message),
+                _ => eprintln!("error: the background process exited during startup"),
+            }
+            std::process::exit(1);
+        }
+    }
+    drop(from_daemon);
+
+    // Start a new session so the terminal's hangup and Ctrl-C no longer reach us, then
+    // fork again so the daemon isn't a session leader and can never reacquire a
+    // controlling terminal.
+    // SAFETY: `setsid` has no memory-safety requirements.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: still single-threaded, as above.
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        // SAFETY: `_exit` skips the exit handlers, which belong to the daemon now.
+        _ => unsafe { libc::_exit(0) },
+    }
+
+    for (from, to) in [
+        (null.as_raw_fd(), libc::STDIN_FILENO),
+        (log_file.as_raw_fd(), libc::STDOUT_FILENO),
+        (log_file.as_raw_fd(), libc::STDERR_FILENO),
+    ] {
+        // SAFETY: both descriptors are open for the duration of the call.
+        if unsafe { libc::dup2(from, to) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(Daemon {
+        report: to_terminal,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_log: &Path) -> io::Result<Daemon> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--daemon is only supported on Unix",
+    ))
+}
+
+/// A file holding this process's ID, removed again when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the current process ID to `path`. Refuses if the file already names a
+    /// process that is still running, which usually means another listener is using
+    /// the same PID file; a file left behind by a process that has exited is replaced.
+    pub fn create(path: &Path) -> io::Result<PidFile> {
+        if let Ok(contents) = fs::read_to_string(path)
+            && let Ok(pid) = contents.trim().parse::<u32>()
+            && is_running(pid)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "PID file '{}' belongs to process {}, which is still running",
+                    path.display(),
+                    pid
+                ),
+            ));
+        }
+        fs::write(path, format!("{}\n", std::process::id())).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Couldn't write PID file '{}': {}", path.display(), e),
+            )
+        })?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Whether a process with this ID exists.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // Signal 0 only checks that the process exists; EPERM means it does but belongs to
+    // another user.
+    // SAFETY: `kill` with signal 0 sends nothing.
+    let found = unsafe { libc::kill(pid, 0) } == 0;
+    found || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a portable way to check, assume the previous owner has gone.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
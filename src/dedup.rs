@@ -0,0 +1,114 @@
+// Recognising datagrams that are repeats of one received moments before.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use ahash::RandomState;
+
+use crate::checksum::PayloadChecksum;
+
+/// How often the logger says how many duplicates it has suppressed.
+pub(crate) const DEDUP_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Remembers which payloads each source sent recently, so retransmissions can be left
+/// out of the log.
+///
+/// A packet is a duplicate if the same source sent the same payload within `window`
+/// of the copy that was logged. Later copies don't extend the window, so a payload
+/// that is resent continuously is still logged once per window. Packets are compared by
+/// a 64-bit hash of the source and payload; a collision between different packets is
+/// possible in principle but vanishingly unlikely. Packets that already have a
+/// [checksum](crate::Config::checksum) are compared by the source and the checksum
+/// instead, so the payload isn't hashed twice; with CRC-32's 32 bits a collision is
+/// likelier, though still rare within one window.
+///
+/// At most `max_entries` packets are remembered. A flood of distinct packets fills the
+/// table before the window is up, and then the oldest is forgotten to make room, so a
+/// repeat of it would be logged again.
+pub(crate) struct Deduplicator {
+    window: Duration,
+    max_entries: usize,
+    hasher: RandomState,
+    /// When each recently logged packet's hash was first seen.
+    seen: HashMap<u64, Instant>,
+    /// The same entries in the order they were seen, so expired ones can be removed
+    /// from the front without scanning the map.
+    expiry: VecDeque<(Instant, u64)>,
+    /// Packets forgotten early since [`take_evicted`](Self::take_evicted) last
+    /// returned them.
+    evicted: u64,
+    /// Duplicates suppressed since the last report.
+    suppressed: u64,
+    last_report: Instant,
+}
+
+impl Deduplicator {
+    pub(crate) fn new(window: Duration, max_entries: usize) -> Deduplicator {
+        Deduplicator {
+            window,
+            max_entries: max_entries.max(1),
+            hasher: RandomState::new(),
+            seen: HashMap::new(),
+            expiry: VecDeque::new(),
+            evicted: 0,
+            suppressed: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Returns true if `data` from `src` repeats a packet seen within the window, and
+    /// otherwise remembers it. `checksum` is the payload's, if it was computed.
+    pub(crate) fn is_duplicate(
+        &mut self,
+        src: SocketAddr,
+        data: &[u8],
+        checksum: Option<PayloadChecksum>,
+    ) -> bool {
+        let now = Instant::now();
+        self.expire(now);
+        let hash = match checksum {
+            Some(checksum) => self.hasher.hash_one((src, checksum.value)),
+            None => self.hasher.hash_one((src, data)),
+        };
+        if self.seen.contains_key(&hash) {
+            self.suppressed += 1;
+            return true;
+        }
+        if self.seen.len() >= self.max_entries
+            && let Some((_, oldest)) = self.expiry.pop_front()
+        {
+            self.seen.remove(&oldest);
+            self.evicted += 1;
+        }
+        self.seen.insert(hash, now);
+        self.expiry.push_back((now, hash));
+        false
+    }
+
+    /// How many packets were forgotten before their window was up since the last call.
+    pub(crate) fn take_evicted(&mut self) -> u64 {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Forgets packets seen longer ago than the window.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(seen_at, hash)) = self.expiry.front() {
+            if now.duration_since(seen_at) < self.window {
+                break;
+            }
+            self.expiry.pop_front();
+            self.seen.remove(&hash);
+        }
+    }
+
+    /// Once every [`DEDUP_REPORT_INTERVAL`], returns how many duplicates were
+    /// suppressed since the last time, if any were.
+    pub(crate) fn report(&mut self) -> Option<u64> {
+        if self.last_report.elapsed() < DEDUP_REPORT_INTERVAL {
+            return None;
+        }
+        self.last_report = Instant::now();
+        (self.suppressed > 0).then(|| std::mem::take(&mut self.suppressed))
+    }
+}
@@ -0,0 +1,28 @@
+// Reporting warnings and errors from the listener.
+//
+// Without the `tracing` feature these are printed on stderr, errors as they are and
+// warnings after "Warning: ". With it they become `tracing` events instead, at the
+// matching level and with the module that raised them as the target, so they reach
+// whatever subscriber the application has installed.
+
+/// Reports something that went wrong but that the listener carries on past.
+macro_rules! warning {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("Warning: {}", format_args!($($arg)*));
+    }};
+}
+
+/// Reports an error, such as a failed write, that cost a packet or an operation.
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::error!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!($($arg)*);
+    }};
+}
+
+pub(crate) use {error, warning};
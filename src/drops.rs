@@ -0,0 +1,108 @@
+// Finding out how many datagrams the kernel dropped before they could be received.
+
+use std::io;
+use std::net::UdpSocket;
+
+/// Watches the kernel's count of datagrams dropped on the listening socket.
+///
+/// When the socket's receive buffer is full, the kernel throws new datagrams away and
+/// the listener never hears of them. On Linux the count of such drops is listed for
+/// each socket in `/proc/net/udp` (and `/proc/net/udp6`); the monitor finds the socket's
+/// line by its inode and compares the count with the previous reading. On other
+/// systems, or if the count can't be read, the monitor is inactive and reports nothing.
+pub(crate) struct DropMonitor {
+    /// The socket's inode, or `None` if drops can't be monitored.
+    inode: Option<u64>,
+    /// The kernel's count when the monitor started.
+    baseline: u64,
+    /// The kernel's count at the last reading.
+    last: u64,
+}
+
+impl DropMonitor {
+    /// Starts monitoring `socket`, counting only drops from now on.
+    pub(crate) fn new(socket: &UdpSocket) -> DropMonitor {
+        let inode = socket_inode(socket);
+        let baseline = inode.and_then(|inode| read_drops(inode).ok());
+        DropMonitor {
+            // Only monitor if the first reading worked.
+            inode: baseline.and(inode),
+            baseline: baseline.unwrap_or(0),
+            last: baseline.unwrap_or(0),
+        }
+    }
+
+    /// Reads the kernel's count and returns how many datagrams were dropped since the
+    /// last call. Returns 0 if the monitor is inactive or the count can't be read.
+    pub(crate) fn poll(&mut self) -> u64 {
+        let Some(inode) = self.inode else {
+            return 0;
+        };
+        match read_drops(inode) {
+            Ok(drops) => {
+                // The count can only go down if the kernel wrapped it; start afresh.
+                let new = drops.saturating_sub(self.last);
+                self.last = drops;
+                new
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Datagrams dropped since the monitor started, as of the last `poll`, or `None`
+    /// if the monitor is inactive.
+    pub(crate) fn total(&self) -> Option<u64> {
+        self.inode.map(|_| self.last.saturating_sub(self.baseline))
+    }
+}
+
+/// The inode of the socket, which identifies it in `/proc/net/udp`.
+#[cfg(target_os = "linux")]
+fn socket_inode(socket: &UdpSocket) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+
+    // `/proc/self/fd/N` resolves to the socket itself, so its metadata has the inode.
+    let path = format!("/proc/self/fd/{}", socket.as_raw_fd());
+    std::fs::metadata(path).ok().map(|metadata| metadata.ino())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_inode(_socket: &UdpSocket) -> Option<u64> {
+    None
+}
+
+/// Finds the drop count for the socket with `inode` in `/proc/net/udp` or
+/// `/proc/net/udp6`.
+fn read_drops(inode: u64) -> io::Result<u64> {
+    for table in ["/proc/net/udp", "/proc/net/udp6"] {
+        let contents = match std::fs::read_to_string(table) {
+            Ok(contents) => contents,
+            // Without IPv6 support there's no `udp6` table.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(drops) = find_drops(&contents, inode) {
+            return Ok(drops);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "socket not found in /proc/net/udp",
+    ))
+}
+
+/// Looks up `inode` in the text of a `/proc/net/udp` table. After the header line,
+/// each line describes one socket; the inode is the tenth field and the drop count the
+/// thirteenth (and last).
+fn find_drops(table: &str, inode: u64) -> Option<u64> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match (fields.get(9), fields.get(12)) {
+            (Some(line_inode), Some(drops)) if line_inode.parse() == Ok(inode) => {
+                drops.parse().ok()
+            }
+            _ => None,
+        }
+    })
+}
@@ -0,0 +1,239 @@
+// Streaming packets as JSON lines to local processes over a Unix domain socket.
+
+use std::io;
+use std::path::Path;
+#[cfg(unix)]
+use std::{
+    io::Write,
+    net::Shutdown,
+    os::unix::fs::FileTypeExt,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+};
+
+#[cfg(unix)]
+use crossbeam_channel::{Sender, TrySendError};
+
+#[cfg(unix)]
+use crate::diag;
+use crate::format::TimeFormat;
+#[cfg(unix)]
+use crate::format::format_json;
+use crate::packet::Packet;
+use crate::sink::Sink;
+
+/// How many events may wait for one subscriber before it counts as too slow and is
+/// disconnected.
+#[cfg(unix)]
+const SUBSCRIBER_QUEUE_LEN: usize = 1024;
+
+/// A Unix domain socket that any number of local processes can connect to, each
+/// receiving every logged packet from then on as a line of JSON, in the same layout as
+/// [`Format::Json`](crate::Format::Json).
+///
+/// Subscribers connect and disconnect whenever they like; nothing is read from them.
+/// Each has a queue and a thread of its own, so the receive loop only ever hands an
+/// event over. A subscriber that falls [`SUBSCRIBER_QUEUE_LEN`] events behind is
+/// disconnected rather than holding the listener up. Dropping the socket disconnects
+/// everyone and removes the socket file.
+#[cfg(unix)]
+pub(crate) struct EventSocket {
+    path: PathBuf,
+    time_format: TimeFormat,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+struct Subscriber {
+    queue: Sender<Arc<str>>,
+    /// Another handle on the connection, to shut it down if the subscriber is dropped
+    /// while its thread is stuck in a write.
+    stream: UnixStream,
+}
+
+#[cfg(unix)]
+impl EventSocket {
+    /// Creates the socket at `path` and starts accepting subscribers.
+    ///
+    /// A socket file left behind by a listener that has exited is replaced, but one
+    /// that another process is still listening on is refused, as is any other kind of
+    /// file.
+    pub(crate) fn spawn(path: &Path, time_format: &TimeFormat) -> io::Result<EventSocket> {
+        let context = |e: io::Error| {
+            io::Error::new(
+                e.kind(),
+                format!("Couldn't create event socket '{}': {}", path.display(), e),
+            )
+        };
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(context(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "a file that isn't a socket is in the way",
+                )));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(context(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another process is listening on it",
+                )));
+            }
+            std::fs::remove_file(path).map_err(context)?;
+        }
+        let listener = UnixListener::bind(path).map_err(context)?;
+
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let subscribers = Arc::clone(&subscribers);
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name("events".to_string())
+                .spawn(move || accept_subscribers(&listener, &subscribers, &stop))?
+        };
+
+        Ok(EventSocket {
+            path: path.to_path_buf(),
+            time_format: time_format.clone(),
+            subscribers,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Sends a packet to every subscriber, disconnecting any that have fallen too far
+    /// behind. The JSON is only built if someone is connected.
+    pub(crate) fn publish(&self, pkt: &Packet) {
+        let mut subscribers = lock(&self.subscribers);
+        if subscribers.is_empty() {
+            return;
+        }
+        let line: Arc<str> = format_json(pkt, &self.time_format).into();
+        subscribers.retain(
+            |subscriber| match subscriber.queue.try_send(Arc::clone(&line)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    diag::warning!(
+                        "disconnected an event socket subscriber that fell {} events behind",
+                        SUBSCRIBER_QUEUE_LEN
+                    );
+                    let _ = subscriber.stream.shutdown(Shutdown::Both);
+                    false
+                }
+                // Its thread has finished, because the subscriber hung up.
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        );
+    }
+}
+
+/// Publishing never fails as far as the logger is concerned: a subscriber that can't
+/// keep up is dropped instead.
+#[cfg(unix)]
+impl Sink for EventSocket {
+    fn write(&mut self, pkt: &Packet) -> io::Result<()> {
+        self.publish(pkt);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("event socket '{}'", self.path.display())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EventSocket {
+    fn drop(&mut self) {
+        // Wake the accepting thread with a connection of our own, so it sees the flag.
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = UnixStream::connect(&self.path);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        for subscriber in lock(&self.subscribers).drain(..) {
+            let _ = subscriber.stream.shutdown(Shutdown::Both);
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Accepts connections until `stop` is set, starting a writing thread for each.
+#[cfg(unix)]
+fn accept_subscribers(
+    listener: &UnixListener,
+    subscribers: &Mutex<Vec<Subscriber>>,
+    stop: &AtomicBool,
+) {
+    for stream in listener.incoming() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                diag::error!("Error accepting an event socket subscriber: {}", e);
+                continue;
+            }
+        };
+        let Ok(handle) = stream.try_clone() else {
+            continue;
+        };
+        let (queue, events) = crossbeam_channel::bounded::<Arc<str>>(SUBSCRIBER_QUEUE_LEN);
+        let spawned = thread::Builder::new()
+            .name("event-subscriber".to_string())
+            .spawn(move || {
+                let mut stream = stream;
+                // Runs until the subscriber hangs up or is dropped for being slow.
+                for line in events {
+                    if stream.write_all(line.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            });
+        if spawned.is_ok() {
+            lock(subscribers).push(Subscriber {
+                queue,
+                stream: handle,
+            });
+        }
+    }
+}
+
+#[cfg(unix)]
+fn lock(subscribers: &Mutex<Vec<Subscriber>>) -> std::sync::MutexGuard<'_, Vec<Subscriber>> {
+    subscribers.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Unix domain sockets are only available on Unix.
+#[cfg(not(unix))]
+pub(crate) struct EventSocket;
+
+#[cfg(not(unix))]
+impl EventSocket {
+    pub(crate) fn spawn(_path: &Path, _time_format: &TimeFormat) -> io::Result<EventSocket> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--event-socket is only supported on Unix",
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+impl Sink for EventSocket {
+    fn write(&mut self, _pkt: &Packet) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+// Deciding which packets are worth logging.
+
+mod expr;
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use regex::bytes::Regex;
+
+pub use expr::FilterExpr;
+
+/// Allow and deny lists of source address ranges.
+///
+/// A packet is logged when its source is in none of the `deny` ranges and, if any
+/// `allow` ranges are given, in at least one of them. Deny takes precedence, so
+/// `allow 10.0.0.0/8` with `deny 10.0.0.5/32` logs all of 10/8 except that one host.
+/// IPv4 and IPv6 ranges can be mixed freely; a range only ever matches addresses of
+/// its own family.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    /// Returns true if packets from `ip` should be logged.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Returns true if no ranges are configured, so every source is allowed.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+/// Bounds on payload length, in bytes. Both ends are inclusive and either may be left
+/// open.
+///
+/// UDP allows datagrams with an empty payload, and they're often used as keepalives.
+/// With no minimum they are logged like any other packet; `min: Some(1)` is the way to
+/// drop them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LengthFilter {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+impl LengthFilter {
+    /// Returns true if a payload of `len` bytes should be logged. A zero-length datagram
+    /// has a `len` of 0, so it passes unless `min` is at least 1.
+    pub fn is_allowed(&self, len: usize) -> bool {
+        self.min.is_none_or(|min| len >= min) && self.max.is_none_or(|max| len <= max)
+    }
+}
+
+/// Patterns a payload must match to be logged.
+///
+/// A packet is logged if its payload matches at least one of the patterns, or if there
+/// are none. The patterns are matched against the raw bytes, so they work on binary
+/// payloads as well as text; build them with [`parse_payload_regex`] and
+/// [`parse_payload_hex`].
+#[derive(Debug, Clone, Default)]
+pub struct PayloadFilter {
+    pub patterns: Vec<Regex>,
+}
+
+impl PayloadFilter {
+    /// Returns true if a packet with this payload should be logged.
+    pub fn is_allowed(&self, data: &[u8]) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|regex| regex.is_match(data))
+    }
+}
+
+/// Payloads that frame a capture: nothing is logged until one matching `start` arrives,
+/// and the listener stops when one matching `stop` does.
+///
+/// The triggers are looked for before any filter is applied, so a trigger needn't pass
+/// the filters itself. Packets before the start trigger are counted in
+/// [`Stats::untriggered`](crate::Stats::untriggered) and otherwise ignored, including by
+/// echoing and forwarding. Without `start` the capture is armed from the outset; without
+/// `stop` it runs until shut down. Build the patterns with [`parse_payload_hex`].
+#[derive(Debug, Clone, Default)]
+pub struct Triggers {
+    pub start: Option<Regex>,
+    pub stop: Option<Regex>,
+    /// Also log the trigger packets, after the filters like any other. By default they
+    /// are left out.
+    pub log: bool,
+}
+
+/// Compiles a regular expression to look for anywhere in a payload, such as
+/// `^GET ` or `user=\w+`. Payloads needn't be UTF-8: `.` and character classes match
+/// UTF-8 text, and `(?-u:\xff)` matches a single raw byte.
+pub fn parse_payload_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| {
+        // Syntax errors come with a drawing of where in the pattern they are, over
+        // several lines; the last line says what's wrong, which is enough for one flag.
+        let message = e.to_string();
+        let last = message.lines().last().unwrap_or_default();
+        last.trim_start_matches("error: ").to_string()
+    })
+}
+
+/// Compiles a sequence of bytes given in hex, such as `deadbeef` or `de:ad:be:ef`, into
+/// a pattern matching it anywhere in a payload. Spaces, colons and dashes between the
+/// bytes are ignored.
+pub fn parse_payload_hex(s: &str) -> Result<Regex, String> {
+    let digits: Vec<u8> = s
+        .bytes()
+        .filter(|b| !matches!(b, b' ' | b':' | b'-'))
+        .collect();
+    if digits.is_empty()
+        || !digits.len().is_multiple_of(2)
+        || !digits.iter().all(u8::is_ascii_hexdigit)
+    {
+        return Err("expected bytes in hex, such as 'deadbeef'".to_string());
+    }
+    // Each byte becomes a `\xHH` escape, with Unicode off so it matches that one byte.
+    let mut pattern = String::from("(?-u)");
+    for pair in digits.chunks(2) {
+        pattern.push_str("\\x");
+        pattern.push(pair[0] as char);
+        pattern.push(pair[1] as char);
+    }
+    Regex::new(&pattern).map_err(|e| e.to_string())
+}
+
+/// Parses a CIDR range such as `10.0.0.0/8` or `2001:db8::/32`. A bare address is
+/// taken as a range containing just that host.
+pub fn parse_ip_net(s: &str) -> Result<IpNet, String> {
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| "expected an IP address or a CIDR range like 10.0.0.0/8".to_string())
+}
@@ -0,0 +1,682 @@
+// `--filter`: conditions on a packet's fields, combined with `and`, `or` and `not`.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use regex::bytes::Regex;
+
+use super::{parse_ip_net, parse_payload_regex};
+use crate::packet::Packet;
+
+/// A condition a packet must meet to be logged, written as an expression such as
+/// `src net 10.0.0.0/8 and len > 100 and payload ~ /ERROR/`.
+///
+/// The conditions are:
+///
+/// | Condition | True for packets |
+/// |-----------|------------------|
+/// | `src net CIDR`, `src host IP` | from an address in the range, or that one address |
+/// | `dst net CIDR`, `dst host IP` | sent to the address, if it was recorded (see [`Config::dst_ip`](crate::Config::dst_ip)) |
+/// | `src port N` | from the port |
+/// | `len N` | with a payload of that many bytes |
+/// | `ttl N`, `tos N` | with that IP header field, if it was recorded (see [`Config::ip_fields`](crate::Config::ip_fields)) |
+/// | `payload ~ /REGEX/` | whose payload matches the regular expression, as for `--match` |
+/// | `payload contains "TEXT"` | whose payload contains the text |
+/// | `truncated` | that filled the receive buffer |
+///
+/// A number can be compared with `=`, `==`, `!=`, `<`, `<=`, `>` or `>=` in front of it,
+/// as in `len >= 64` or `src port != 53`; without one it must be equal. A condition on
+/// a field that wasn't recorded is false. A `/` or `\` in a regular expression is
+/// written `\/` or `\\`, and a `"` or `\` in text `\"` or `\\`.
+///
+/// Conditions combine with `and` (or `&&`), `or` (or `||`) and `not` (or `!`), with
+/// `not` binding tightest and `or` loosest, and parentheses to group them otherwise.
+/// Parsing one with [`str::parse`] reports what was wrong and the column it was found
+/// at.
+///
+/// ```
+/// use udp_listener::FilterExpr;
+///
+/// let filter: FilterExpr = "src net 10.0.0.0/8 and not (len < 8 or payload ~ /^PING/)"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(
+///     "len > 100 and".parse::<FilterExpr>().unwrap_err(),
+///     "expected a condition at column 14, found the end"
+/// );
+/// ```
+#[derive(Clone)]
+pub struct FilterExpr {
+    source: String,
+    root: Node,
+}
+
+impl FilterExpr {
+    /// Returns true if `pkt` meets the condition.
+    pub fn matches(&self, pkt: &Packet) -> bool {
+        self.root.matches(pkt)
+    }
+
+    /// The expression as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Debug for FilterExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FilterExpr").field(&self.source).finish()
+    }
+}
+
+impl std::str::FromStr for FilterExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            next: 0,
+            end: s.chars().count() + 1,
+        };
+        let root = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(parser.unexpected("'and', 'or' or the end", token));
+        }
+        Ok(FilterExpr {
+            source: s.to_string(),
+            root,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    SrcNet(IpNet),
+    DstNet(IpNet),
+    Compare(Field, Comparison, u64),
+    Payload(Regex),
+    Truncated,
+}
+
+impl Node {
+    fn matches(&self, pkt: &Packet) -> bool {
+        match self {
+            Node::And(left, right) => left.matches(pkt) && right.matches(pkt),
+            Node::Or(left, right) => left.matches(pkt) || right.matches(pkt),
+            Node::Not(inner) => !inner.matches(pkt),
+            Node::SrcNet(net) => net.contains(&pkt.src.ip().to_canonical()),
+            Node::DstNet(net) => pkt
+                .dst_ip
+                .is_some_and(|ip| net.contains(&ip.to_canonical())),
+            Node::Compare(field, comparison, value) => field
+                .of(pkt)
+                .is_some_and(|actual| comparison.holds(actual, *value)),
+            Node::Payload(regex) => regex.is_match(&pkt.data),
+            Node::Truncated => pkt.truncated,
+        }
+    }
+}
+
+/// A number a packet has, or may have.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    SrcPort,
+    Len,
+    Ttl,
+    Tos,
+}
+
+impl Field {
+    fn of(self, pkt: &Packet) -> Option<u64> {
+        match self {
+            Field::SrcPort => Some(pkt.src.port().into()),
+            Field::Len => Some(pkt.data.len() as u64),
+            Field::Ttl => pkt.ttl.map(u64::from),
+            Field::Tos => pkt.tos.map(u64::from),
+        }
+    }
+
+    /// The largest value the field can have, and what it's called in errors.
+    fn limit(self) -> (u64, &'static str) {
+        match self {
+            Field::SrcPort => (u16::MAX.into(), "a port"),
+            Field::Len => (u64::MAX, "a length"),
+            Field::Ttl => (u8::MAX.into(), "a TTL"),
+            Field::Tos => (u8::MAX.into(), "a TOS"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, actual: u64, value: u64) -> bool {
+        match self {
+            Comparison::Eq => actual == value,
+            Comparison::Ne => actual != value,
+            Comparison::Lt => actual < value,
+            Comparison::Le => actual <= value,
+            Comparison::Gt => actual > value,
+            Comparison::Ge => actual >= value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    /// A keyword, number, address or range.
+    Word(String),
+    /// An operator, such as `>=` or `&&`.
+    Symbol(&'static str),
+    Open,
+    Close,
+    /// A regular expression between slashes, with the escaped slashes unescaped.
+    Pattern(String),
+    /// Text between double quotes, unescaped.
+    Text(String),
+}
+
+struct Token {
+    kind: Kind,
+    /// Where the token starts, counting characters from 1.
+    column: usize,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            Kind::Word(word) => write!(f, "'{}'", word),
+            Kind::Symbol(symbol) => write!(f, "'{}'", symbol),
+            Kind::Open => f.write_str("'('"),
+            Kind::Close => f.write_str("')'"),
+            Kind::Pattern(_) => f.write_str("a regular expression"),
+            Kind::Text(_) => f.write_str("quoted text"),
+        }
+    }
+}
+
+/// The operators, longest first so `>=` isn't read as `>` and then `=`.
+const SYMBOLS: [&str; 11] = ["==", "!=", "<=", ">=", "&&", "||", "=", "<", ">", "~", "!"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let kind = match c {
+            '(' => {
+                i += 1;
+                Kind::Open
+            }
+            ')' => {
+                i += 1;
+                Kind::Close
+            }
+            '/' | '"' => {
+                let (kind, what) = if c == '/' {
+                    (Kind::Pattern as fn(String) -> Kind, "regular expression")
+                } else {
+                    (Kind::Text as fn(String) -> Kind, "text")
+                };
+                let mut quoted = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => {
+                            return Err(format!(
+                                "the {} starting at column {} isn't closed with {}",
+                                what, column, c
+                            ));
+                        }
+                        Some(&end) if end == c => break,
+                        Some('\\') if chars.get(i + 1) == Some(&c) => {
+                            quoted.push(c);
+                            i += 1;
+                        }
+                        // An escaped backslash is kept escaped in a regular expression,
+                        // whose other escapes are its own.
+                        Some('\\') if chars.get(i + 1) == Some(&'\\') => {
+                            if c == '/' {
+                                quoted.push('\\');
+                            }
+                            quoted.push('\\');
+                            i += 1;
+                        }
+                        Some(&other) => quoted.push(other),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                kind(quoted)
+            }
+            _ if is_word_char(c) => {
+                let start = i;
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                Kind::Word(chars[start..i].iter().collect())
+            }
+            _ => {
+                let rest = &chars[i..];
+                let symbol = SYMBOLS
+                    .iter()
+                    .find(|symbol| symbol.chars().eq(rest.iter().copied().take(symbol.len())))
+                    .ok_or_else(|| format!("unexpected '{}' at column {}", c, column))?;
+                i += symbol.len();
+                Kind::Symbol(symbol)
+            }
+        };
+        tokens.push(Token { kind, column });
+    }
+    Ok(tokens)
+}
+
+/// Characters of keywords, numbers, addresses and ranges, such as `10.0.0.0/8` or
+/// `fe80::1`.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '.' | ':' | '/' | '-' | '_' | '%')
+}
+
+/// A recursive-descent parser over the tokens, one function for each level of
+/// precedence.
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    /// The column just past the end of the expression, for errors about it ending early.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn take(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.next);
+        self.next += 1;
+        token
+    }
+
+    /// Takes the next token if it's one of `words` or `symbols`.
+    fn take_if(&mut self, words: &[&str], symbols: &[&str]) -> bool {
+        let matched = self.peek().is_some_and(|token| match &token.kind {
+            Kind::Word(word) => words.contains(&word.as_str()),
+            Kind::Symbol(symbol) => symbols.contains(symbol),
+            _ => false,
+        });
+        if matched {
+            self.next += 1;
+        }
+        matched
+    }
+
+    fn unexpected(&self, expected: &str, found: &Token) -> String {
+        format!(
+            "expected {} at column {}, found {}",
+            expected, found.column, found
+        )
+    }
+
+    /// The error for the next token not being what was `expected`.
+    fn expected(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(token) => self.unexpected(expected, token),
+            None => format!(
+                "expected {} at column {}, found the end",
+                expected, self.end
+            ),
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.take_if(&["or"], &["||"]) {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.not()?;
+        while self.take_if(&["and"], &["&&"]) {
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.take_if(&["not"], &["!"]) {
+            Ok(Node::Not(Box::new(self.not()?)))
+        } else {
+            self.condition()
+        }
+    }
+
+    fn condition(&mut self) -> Result<Node, String> {
+        let expected = "a condition";
+        let Some(token) = self.peek() else {
+            return Err(self.expected(expected));
+        };
+        let word = match &token.kind {
+            Kind::Open => {
+                self.next += 1;
+                let node = self.or()?;
+                if !matches!(self.peek().map(|token| &token.kind), Some(Kind::Close)) {
+                    return Err(self.expected("')'"));
+                }
+                self.next += 1;
+                return Ok(node);
+            }
+            Kind::Word(word) => word.clone(),
+            _ => return Err(self.unexpected(expected, token)),
+        };
+        let node = match word.as_str() {
+            "src" | "dst" => {
+                self.next += 1;
+                let src = word == "src";
+                if src && self.take_if(&["port"], &[]) {
+                    return self.comparison(Field::SrcPort);
+                }
+                let net = if self.take_if(&["net"], &[]) {
+                    self.address("a range such as 10.0.0.0/8", parse_ip_net)?
+                } else if self.take_if(&["host"], &[]) {
+                    self.address("an IP address", |s| {
+                        s.parse::<IpAddr>()
+                            .map(IpNet::from)
+                            .map_err(|_| "expected an IP address".to_string())
+                    })?
+                } else if src {
+                    return Err(self.expected("'net', 'host' or 'port'"));
+                } else {
+                    return Err(self.expected("'net' or 'host'"));
+                };
+                if src {
+                    Node::SrcNet(net)
+                } else {
+                    Node::DstNet(net)
+                }
+            }
+            "len" | "ttl" | "tos" => {
+                self.next += 1;
+                let field = match word.as_str() {
+                    "len" => Field::Len,
+                    "ttl" => Field::Ttl,
+                    _ => Field::Tos,
+                };
+                return self.comparison(field);
+            }
+            "payload" => {
+                self.next += 1;
+                let pattern = if self.take_if(&[], &["~"]) {
+                    match self.peek() {
+                        Some(Token {
+                            kind: Kind::Pattern(pattern),
+                            column,
+                        }) => parse_payload_regex(pattern).map_err(|e| {
+                            format!("invalid regular expression at column {}: {}", column, e)
+                        })?,
+                        _ => return Err(self.expected("a regular expression such as /ERROR/")),
+                    }
+                } else if self.take_if(&["contains"], &[]) {
+                    match self.peek() {
+                        Some(Token {
+                            kind: Kind::Text(text),
+                            ..
+                        }) => Regex::new(&regex::escape(text)).map_err(|e| e.to_string())?,
+                        _ => return Err(self.expected("quoted text such as \"ERROR\"")),
+                    }
+                } else {
+                    return Err(self.expected("'~' or 'contains'"));
+                };
+                self.next += 1;
+                return Ok(Node::Payload(pattern));
+            }
+            "truncated" => {
+                self.next += 1;
+                Node::Truncated
+            }
+            _ => return Err(self.unexpected(expected, token)),
+        };
+        Ok(node)
+    }
+
+    /// Parses an address or range with `parse`, reporting where it was if it's invalid.
+    fn address(
+        &mut self,
+        expected: &str,
+        parse: impl Fn(&str) -> Result<IpNet, String>,
+    ) -> Result<IpNet, String> {
+        match self.peek() {
+            Some(Token {
+                kind: Kind::Word(word),
+                column,
+            }) => {
+                let column = *column;
+                let net = parse(word).map_err(|e| {
+                    format!("invalid address '{}' at column {}: {}", word, column, e)
+                })?;
+                self.next += 1;
+                Ok(net)
+            }
+            _ => Err(self.expected(expected)),
+        }
+    }
+
+    /// Parses the rest of a condition on a number: an optional comparison, then the
+    /// number to compare with.
+    fn comparison(&mut self, field: Field) -> Result<Node, String> {
+        let comparison = match self.peek().map(|token| &token.kind) {
+            Some(Kind::Symbol(symbol)) => {
+                let comparison = match *symbol {
+                    "=" | "==" => Comparison::Eq,
+                    "!=" => Comparison::Ne,
+                    "<" => Comparison::Lt,
+                    "<=" => Comparison::Le,
+                    ">" => Comparison::Gt,
+                    ">=" => Comparison::Ge,
+                    _ => return Err(self.expected("a comparison or a number")),
+                };
+                self.next += 1;
+                comparison
+            }
+            _ => Comparison::Eq,
+        };
+        let (max, what) = field.limit();
+        let Some(token) = self.take() else {
+            return Err(self.expected("a number"));
+        };
+        match &token.kind {
+            Kind::Word(word) => match word.parse::<u64>() {
+                Ok(value) if value <= max => Ok(Node::Compare(field, comparison, value)),
+                _ => Err(format!(
+                    "'{}' at column {} isn't {}",
+                    word, token.column, what
+                )),
+            },
+            _ => {
+                self.next -= 1;
+                Err(self.expected("a number"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use super::*;
+
+    fn packet(payload: &[u8]) -> Packet {
+        Packet {
+            data: payload.to_vec(),
+            captured_len: payload.len(),
+            original_len: payload.len(),
+            src: "192.0.2.1:5000".parse().unwrap(),
+            received_at: Local::now(),
+            monotonic: None,
+            truncated: false,
+            ttl: Some(64),
+            tos: None,
+            dst_ip: None,
+            bind: None,
+            session_id: None,
+            src_host: None,
+            src_geo: None,
+            parsed: None,
+            malformed: false,
+            checksum: None,
+            index: None,
+        }
+    }
+
+    fn matches(expr: &str, payload: &[u8]) -> bool {
+        expr.parse::<FilterExpr>()
+            .unwrap()
+            .matches(&packet(payload))
+    }
+
+    fn error(expr: &str) -> String {
+        expr.parse::<FilterExpr>().unwrap_err()
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        // (not truncated) and len 5, rather than not (truncated and len 5).
+        assert!(!matches("not truncated and len 5", b"abc"));
+        assert!(matches("not (truncated and len 5)", b"abc"));
+        assert!(matches("! truncated && len 3", b"abc"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // len 3 or (len 1 and len 2), rather than (len 3 or len 1) and len 2.
+        assert!(matches("len 3 or len 1 and len 2", b"abc"));
+        assert!(!matches("(len 3 or len 1) and len 2", b"abc"));
+        assert!(matches("len 1 and len 2 || len 3", b"abc"));
+    }
+
+    #[test]
+    fn escapes_in_patterns_and_text() {
+        assert!(matches(r"payload ~ /^GET \/index/", b"GET /index.html"));
+        assert!(matches(r"payload ~ /a\\b/", br"a\b"));
+        assert!(!matches(r"payload ~ /a\\b/", b"ab"));
+        assert!(matches(
+            r#"payload contains "say \"hi\"""#,
+            br#"they say "hi""#
+        ));
+        assert!(matches(r#"payload contains "C:\\temp""#, br"C:\temp"));
+        // Regular expression syntax in text is matched literally.
+        assert!(matches(r#"payload contains "a.b""#, b"a.b"));
+        assert!(!matches(r#"payload contains "a.b""#, b"axb"));
+    }
+
+    #[test]
+    fn numbers_must_fit_the_field() {
+        assert!(matches("ttl 64 and src port 5000", b""));
+        assert!(
+            "ttl <= 255 and src port < 65535"
+                .parse::<FilterExpr>()
+                .is_ok()
+        );
+        assert_eq!(error("ttl 300"), "'300' at column 5 isn't a TTL");
+        assert_eq!(error("tos 256"), "'256' at column 5 isn't a TOS");
+        assert_eq!(error("src port 70000"), "'70000' at column 10 isn't a port");
+        assert_eq!(error("len -1"), "'-1' at column 5 isn't a length");
+    }
+
+    #[test]
+    fn unclosed_patterns_and_text() {
+        assert_eq!(
+            error("len 1 and payload ~ /abc"),
+            "the regular expression starting at column 21 isn't closed with /"
+        );
+        assert_eq!(
+            error(r#"payload contains "abc\""#),
+            "the text starting at column 18 isn't closed with \""
+        );
+    }
+
+    #[test]
+    fn errors_say_where_they_were_found() {
+        let cases = [
+            ("len > 1 $", "unexpected '$' at column 9"),
+            (
+                "len 1 len 2",
+                "expected 'and', 'or' or the end at column 7, found 'len'",
+            ),
+            (
+                "len 1 and )",
+                "expected a condition at column 11, found ')'",
+            ),
+            (
+                "len 1 or",
+                "expected a condition at column 9, found the end",
+            ),
+            (
+                "length 1",
+                "expected a condition at column 1, found 'length'",
+            ),
+            ("(len 1", "expected ')' at column 7, found the end"),
+            (
+                "src 10.0.0.1",
+                "expected 'net', 'host' or 'port' at column 5, found '10.0.0.1'",
+            ),
+            (
+                "dst port 53",
+                "expected 'net' or 'host' at column 5, found 'port'",
+            ),
+            (
+                "src net",
+                "expected a range such as 10.0.0.0/8 at column 8, found the end",
+            ),
+            (
+                "src host 10.0.0.0/8",
+                "invalid address '10.0.0.0/8' at column 10: expected an IP address",
+            ),
+            (
+                "payload is",
+                "expected '~' or 'contains' at column 9, found 'is'",
+            ),
+            (
+                "payload ~ ERROR",
+                "expected a regular expression such as /ERROR/ at column 11, found 'ERROR'",
+            ),
+            (
+                "payload contains /x/",
+                "expected quoted text such as \"ERROR\" at column 18, found a regular expression",
+            ),
+            (
+                "len ~ 5",
+                "expected a comparison or a number at column 5, found '~'",
+            ),
+            ("len >", "expected a number at column 6, found the end"),
+            ("len > (", "expected a number at column 7, found '('"),
+        ];
+        for (expr, message) in cases {
+            assert_eq!(error(expr), message, "for {:?}", expr);
+        }
+        assert!(
+            error("src net 10.0.0.0/33").starts_with("invalid address '10.0.0.0/33' at column 9: ")
+        );
+        assert!(error("payload ~ /(/").starts_with("invalid regular expression at column 11: "));
+    }
+}
@@ -0,0 +1,462 @@
+// Rendering packets as lines of text for the log and the console.
+
+// `std::fmt::Write` lets `write!` append to a `String`.
+use std::borrow::Cow;
+use std::fmt::Write as _;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::config::{Checksum, Format, LineEnding, Timezone};
+use crate::geoip::GeoLocation;
+use crate::packet::Packet;
+use crate::parsers::{DnsMessage, DtlsRecord, NtpPacket, Parsed, SyslogMessage};
+
+/// Timestamp layout used at the start of each text log entry: date, time and milliseconds.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// How timestamps are written in log entries.
+///
+/// By default text and hex entries use [`TIMESTAMP_FORMAT`] and JSON uses RFC 3339, all
+/// on the local clock. A `pattern` (in `strftime` syntax, see [`check_time_pattern`])
+/// replaces both layouts. Pcap records store seconds since the epoch, which are the
+/// same in every timezone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeFormat {
+    pub timezone: Timezone,
+    pub pattern: Option<String>,
+}
+
+impl TimeFormat {
+    /// The timestamp for a text or hex entry.
+    pub fn text(&self, time: &DateTime<Local>) -> String {
+        let pattern = self.pattern.as_deref().unwrap_or(TIMESTAMP_FORMAT);
+        match self.timezone {
+            Timezone::Local => render(time, pattern),
+            Timezone::Utc => render(&time.with_timezone(&Utc), pattern),
+        }
+    }
+
+    /// The timestamp for a JSON record.
+    pub fn json(&self, time: &DateTime<Local>) -> String {
+        match (&self.pattern, self.timezone) {
+            (Some(pattern), Timezone::Local) => render(time, pattern),
+            (Some(pattern), Timezone::Utc) => render(&time.with_timezone(&Utc), pattern),
+            (None, Timezone::Local) => time.to_rfc3339_opts(SecondsFormat::Millis, false),
+            // UTC is written with the conventional `Z` suffix rather than `+00:00`.
+            (None, Timezone::Utc) => time
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+}
+
+/// Formats `time` with a `strftime` pattern, falling back to [`TIMESTAMP_FORMAT`] if
+/// the pattern is invalid (chrono would otherwise panic).
+fn render<Tz: TimeZone>(time: &DateTime<Tz>, pattern: &str) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let mut out = String::new();
+    if write!(out, "{}", time.format(pattern)).is_err() {
+        out.clear();
+        let _ = write!(out, "{}", time.format(TIMESTAMP_FORMAT));
+    }
+    out
+}
+
+/// Checks that `pattern` is a valid `strftime` pattern for chrono, such as
+/// `%Y-%m-%dT%H:%M:%S%.3f%:z`.
+pub fn check_time_pattern(pattern: &str) -> Result<(), String> {
+    if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+        Err(format!("'{}' is not a valid strftime pattern", pattern))
+    } else {
+        Ok(())
+    }
+}
+
+/// Formats a packet as one line of text, ending in a newline:
+/// `[2025-08-11 20:27:05.715] Received from 127.0.0.1:57879: hello`.
+///
+/// The payload is converted with `String::from_utf8_lossy`, which replaces invalid
+/// UTF-8 sequences with a Unicode replacement character. This is good for displaying
+/// potentially mixed data, but binary payloads should use [`format_hex_entry`] instead.
+/// A decoded payload is written as its fields instead (see [`payload_text`]), and a
+/// [malformed](Packet::malformed) one as a hex entry. The bind address the packet
+/// arrived on, when there are several, and a recorded destination address, TTL and ToS
+/// follow the source, as in
+/// `Received from 127.0.0.1:57879 to 127.0.0.1 [ttl=64 tos=0x00]: hello`, and then the
+/// payload's checksum, as in `[crc32=3610a686]`. A [numbered](Packet::index) packet's
+/// entry starts with its number, as in `#10542 [2024-06-01 12:00:00.123] Received ...`.
+pub fn format_text(pkt: &Packet, time_format: &TimeFormat) -> String {
+    text_entry(pkt, time_format, &LineEnding::Lf)
+}
+
+fn text_entry(pkt: &Packet, time_format: &TimeFormat, ending: &LineEnding) -> String {
+    if pkt.malformed {
+        return hex_entry(pkt, time_format, ending);
+    }
+    format!(
+        "{}[{}] Received from {}{}{}{}: {}{}",
+        index_note(pkt),
+        time_format.text(&pkt.received_at),
+        pkt.source(),
+        destination_note(pkt),
+        ip_fields_note(pkt),
+        checksum_note(pkt),
+        payload_text(pkt),
+        ending.record_end()
+    )
+}
+
+/// `#10542 ` for a [numbered](Packet::index) packet, otherwise nothing.
+pub(crate) fn index_note(pkt: &Packet) -> String {
+    match pkt.index {
+        Some(index) => format!("#{} ", index),
+        None => String::new(),
+    }
+}
+
+/// Where the packet arrived: ` on 0.0.0.0:53` when the listener has several bind
+/// addresses, then ` to 10.0.0.1` if its destination address was recorded; otherwise
+/// nothing.
+pub(crate) fn destination_note(pkt: &Packet) -> String {
+    let mut note = String::new();
+    if let Some(bind) = pkt.bind {
+        let _ = write!(note, " on {}", bind);
+    }
+    if let Some(dst) = pkt.dst_ip {
+        let _ = write!(note, " to {}", dst);
+    }
+    note
+}
+
+/// ` [ttl=64 tos=0x00]` for a packet whose IP fields were recorded, with whichever of
+/// them are known, otherwise nothing.
+pub(crate) fn ip_fields_note(pkt: &Packet) -> String {
+    let fields: Vec<String> = [
+        pkt.ttl.map(|ttl| format!("ttl={}", ttl)),
+        pkt.tos.map(|tos| format!("tos=0x{:02x}", tos)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if fields.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", fields.join(" "))
+    }
+}
+
+/// ` [crc32=3610a686]` for a packet whose checksum was computed, otherwise nothing.
+pub(crate) fn checksum_note(pkt: &Packet) -> String {
+    match pkt.checksum {
+        Some(checksum) => format!(" [{}]", checksum),
+        None => String::new(),
+    }
+}
+
+/// The payload as text: its decoded fields if it was parsed, as in
+/// `facility=daemon severity=info app=ntpd msg="synchronized"`, otherwise the raw bytes
+/// converted with `String::from_utf8_lossy`. A zero-length datagram is shown as
+/// [`EMPTY_DATAGRAM`], so it doesn't look like a line with nothing after the colon.
+pub fn payload_text(pkt: &Packet) -> Cow<'_, str> {
+    match &pkt.parsed {
+        Some(Parsed::Syslog(message)) => Cow::Owned(message.to_string()),
+        Some(Parsed::Dns(message)) => Cow::Owned(message.to_string()),
+        Some(Parsed::Dtls(records)) => {
+            let records: Vec<String> = records.iter().map(DtlsRecord::to_string).collect();
+            Cow::Owned(records.join("; "))
+        }
+        Some(Parsed::Ntp(packet)) => Cow::Owned(packet.to_string()),
+        None if pkt.original_len == 0 => Cow::Borrowed(EMPTY_DATAGRAM),
+        None => String::from_utf8_lossy(&pkt.data),
+    }
+}
+
+/// What text entries show in place of the payload of a zero-length datagram.
+pub const EMPTY_DATAGRAM: &str = "<empty datagram>";
+
+/// Formats a packet as a header line followed by a hex dump of its payload. The header
+/// gives the datagram's full length, and says how much of it is shown if the payload
+/// was cut to the snap length.
+pub fn format_hex_entry(pkt: &Packet, time_format: &TimeFormat) -> String {
+    hex_entry(pkt, time_format, &LineEnding::Lf)
+}
+
+/// A hex entry whose lines end in `ending`'s line end, except the last, which ends the
+/// record.
+fn hex_entry(pkt: &Packet, time_format: &TimeFormat, ending: &LineEnding) -> String {
+    let mut entry = format!(
+        "{}[{}] Received {} bytes from {}{}{}{}{}:{}",
+        index_note(pkt),
+        time_format.text(&pkt.received_at),
+        pkt.original_len,
+        pkt.source(),
+        destination_note(pkt),
+        ip_fields_note(pkt),
+        checksum_note(pkt),
+        snap_note(pkt),
+        ending.line_end()
+    );
+    hex_rows(&pkt.data, ending.line_end(), &mut entry);
+    entry.truncate(entry.len() - ending.line_end().len());
+    entry.push_str(ending.record_end());
+    entry
+}
+
+/// ` (first N bytes captured)` for a packet cut to the snap length, otherwise nothing.
+fn snap_note(pkt: &Packet) -> String {
+    if pkt.is_snapped() {
+        format!(" (first {} bytes captured)", pkt.captured_len)
+    } else {
+        String::new()
+    }
+}
+
+/// Formats a packet as a log entry in one of the text formats, ending in `ending`. Pcap
+/// and binary records are written by [`PcapWriter`](crate::PcapWriter) and
+/// [`encode_record`](crate::encode_record) instead; here they fall back to text.
+pub(crate) fn format_entry(
+    pkt: &Packet,
+    format: Format,
+    time_format: &TimeFormat,
+    ending: &LineEnding,
+) -> String {
+    match format {
+        Format::Hex => hex_entry(pkt, time_format, ending),
+        Format::Json => json_entry(pkt, time_format, ending),
+        Format::Base64 { prefix } => base64_entry(pkt, time_format, prefix, ending),
+        Format::Text | Format::Pcap | Format::Bin => text_entry(pkt, time_format, ending),
+    }
+}
+
+/// One JSON Lines record. Field order here is the order in the output.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    /// Only written for numbered packets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idx: Option<u64>,
+    ts: String,
+    /// Nanoseconds since the capture started, on the monotonic clock, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mono_ns: Option<u64>,
+    /// Only written when the packet's session is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<&'a str>,
+    src: String,
+    src_ip: String,
+    src_port: u16,
+    /// Only written when a host name is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src_host: Option<String>,
+    /// Only written when the sender's location is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src_geo: Option<&'a GeoLocation>,
+    /// Only written when the listener has several bind addresses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bind: Option<String>,
+    /// Only written when the destination address was recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dst_ip: Option<String>,
+    len: usize,
+    /// Only written for packets cut to the snap length.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captured_len: Option<usize>,
+    data_b64: String,
+    /// The IP header fields, only written when they were recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tos: Option<u8>,
+    /// The payload's checksum in hex, under the checksum's name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crc32: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xxhash: Option<String>,
+    /// Only written for packets that were truncated, to keep ordinary records short.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    /// The decoded syslog fields, for packets parsed as syslog.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    syslog: Option<&'a SyslogMessage>,
+    /// The decoded DNS message, for packets parsed as DNS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dns: Option<&'a DnsMessage>,
+    /// The DTLS record headers, for packets parsed as DTLS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dtls: Option<&'a [DtlsRecord]>,
+    /// The decoded NTP header, for packets parsed as NTP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ntp: Option<&'a NtpPacket>,
+}
+
+/// Formats a packet as a single-line JSON object, ending in a newline:
+///
+/// ```text
+/// {"ts":"2024-06-01T12:00:00.123+02:00","src":"1.2.3.4:5555","src_ip":"1.2.3.4","src_port":5555,"len":5,"data_b64":"aGVsbG8="}
+/// ```
+///
+/// The timestamp is RFC 3339 with milliseconds, unless `time_format` sets a pattern.
+/// A [numbered](Packet::index) packet's record starts with an `"idx"` field before it.
+/// Packets received live also have a `"mono_ns"` field right after it, the nanoseconds
+/// since the capture started on the monotonic clock, which setting the system clock
+/// doesn't move. The payload is base64-encoded so binary
+/// data survives intact (a zero-length datagram has `"len":0` and an empty
+/// `"data_b64"`), and numeric fields are JSON numbers rather than strings. A
+/// `"truncated":true` field is added for packets that didn't fit the receive buffer, a
+/// `"captured_len"` field for packets cut to the snap length (`"len"` is always the full
+/// length), `"ttl"` and `"tos"` fields when the datagram's were recorded, a `"bind"`
+/// field with the local address it arrived on when the listener has several, a
+/// `"dst_ip"` field when the address it was sent to was recorded, a `"crc32"` or `"xxhash"` field
+/// with the payload's checksum in hex, such as `"crc32":"3610a686"`, when one was
+/// computed, a `"session_id"` field (after `"ts"` and any
+/// `"mono_ns"`) when the capture session is known, and a
+/// `"src_host"` field when the sender's host name is known, and a `"src_geo"` object,
+/// such as `{"country":"DE","city":"Berlin"}`, when its location is. A parsed payload is
+/// added as an object of its fields, such as `"syslog":{"facility":3,...}` or
+/// `"dns":{"id":4660,...}` or `"ntp":{"leap":"none",...}`, or for DTLS an array of records, such as
+/// `"dtls":[{"type":"handshake",...}]`, next to the raw `data_b64`.
+pub fn format_json(pkt: &Packet, time_format: &TimeFormat) -> String {
+    json_entry(pkt, time_format, &LineEnding::Lf)
+}
+
+fn json_entry(pkt: &Packet, time_format: &TimeFormat, ending: &LineEnding) -> String {
+    let record = JsonRecord {
+        idx: pkt.index,
+        ts: time_format.json(&pkt.received_at),
+        mono_ns: pkt.monotonic.map(|since| since.as_nanos() as u64),
+        session_id: pkt.session_id.as_deref(),
+        src: pkt.src.to_string(),
+        src_ip: pkt.src.ip().to_string(),
+        src_port: pkt.src.port(),
+        src_host: pkt.src_host.clone(),
+        src_geo: pkt.src_geo.as_deref(),
+        bind: pkt.bind.map(|bind| bind.to_string()),
+        dst_ip: pkt.dst_ip.map(|dst| dst.to_string()),
+        len: pkt.original_len,
+        captured_len: pkt.is_snapped().then_some(pkt.captured_len),
+        data_b64: BASE64.encode(&pkt.data),
+        ttl: pkt.ttl,
+        tos: pkt.tos,
+        crc32: checksum_hex(pkt, Checksum::Crc32),
+        xxhash: checksum_hex(pkt, Checksum::Xxhash),
+        truncated: pkt.truncated,
+        syslog: match &pkt.parsed {
+            Some(Parsed::Syslog(message)) => Some(message),
+            _ => None,
+        },
+        dns: match &pkt.parsed {
+            Some(Parsed::Dns(message)) => Some(message),
+            _ => None,
+        },
+        dtls: match &pkt.parsed {
+            Some(Parsed::Dtls(records)) => Some(records),
+            _ => None,
+        },
+        ntp: match &pkt.parsed {
+            Some(Parsed::Ntp(packet)) => Some(packet),
+            _ => None,
+        },
+    };
+    // Serializing plain strings and numbers can't fail.
+    let mut line = serde_json::to_string(&record).expect("JSON record serializes");
+    line.push_str(ending.record_end());
+    line
+}
+
+/// The packet's checksum in hex, if it has one computed with `algorithm`.
+fn checksum_hex(pkt: &Packet, algorithm: Checksum) -> Option<String> {
+    pkt.checksum
+        .filter(|checksum| checksum.algorithm == algorithm)
+        .map(|checksum| checksum.hex())
+}
+
+/// Formats a packet as its payload in standard base64, padded, on one line, ending in
+/// a newline: `aGVsbG8=`. A zero-length datagram is an empty line. The payload is all
+/// there is, so every line decodes back to exactly the bytes logged, and no payload can
+/// break a line in two.
+///
+/// With `prefix`, the line starts with the receive time and the source, each followed
+/// by a space: `2024-06-01T12:00:00.123+02:00 192.0.2.1:5000 aGVsbG8=`. The time is
+/// RFC 3339 with milliseconds, unless `time_format` sets a pattern, which should then
+/// have no spaces of its own for the fields to split cleanly. A
+/// [numbered](Packet::index) packet's number comes first, as in `#10542 `.
+pub fn format_base64(pkt: &Packet, time_format: &TimeFormat, prefix: bool) -> String {
+    base64_entry(pkt, time_format, prefix, &LineEnding::Lf)
+}
+
+fn base64_entry(
+    pkt: &Packet,
+    time_format: &TimeFormat,
+    prefix: bool,
+    ending: &LineEnding,
+) -> String {
+    let mut line = String::new();
+    if prefix {
+        line.push_str(&index_note(pkt));
+        line.push_str(&time_format.json(&pkt.received_at));
+        line.push(' ');
+        line.push_str(&pkt.src.to_string());
+        line.push(' ');
+    }
+    BASE64.encode_string(&pkt.data, &mut line);
+    line.push_str(ending.record_end());
+    line
+}
+
+/// Renders `data` as a hex dump, 16 bytes per row:
+///
+/// ```text
+/// 00000000  68 65 6c 6c 6f 2c 20 77  6f 72 6c 64 0a           |hello, world.|
+/// ```
+///
+/// Each row has the offset, the bytes in hex (with an extra gap after the eighth), and
+/// the printable ASCII characters with everything else shown as `.`. The output is
+/// sized up front and filled character by character, so it allocates once per packet
+/// rather than once per byte.
+pub fn format_hex(data: &[u8]) -> String {
+    let mut out = String::new();
+    hex_rows(data, "\n", &mut out);
+    out
+}
+
+/// Appends the rows of `data`'s hex dump to `out`, each ending in `line_end`.
+fn hex_rows(data: &[u8], line_end: &str, out: &mut String) {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+    // 8 offset digits + 2 spaces + 16 * 3 hex columns + 1 middle gap + " |" + 16 + "|".
+    const ROW_LEN: usize = 8 + 2 + 16 * 3 + 1 + 2 + 16 + 1;
+
+    out.reserve(data.len().div_ceil(16) * (ROW_LEN + line_end.len()));
+    for (row, chunk) in data.chunks(16).enumerate() {
+        // Writing into a `String` cannot fail, so the `fmt::Result` is safe to ignore.
+        let _ = write!(out, "{:08x}  ", row * 16);
+        for column in 0..16 {
+            if column == 8 {
+                out.push(' ');
+            }
+            match chunk.get(column) {
+                Some(&byte) => {
+                    out.push(HEX_DIGITS[usize::from(byte >> 4)] as char);
+                    out.push(HEX_DIGITS[usize::from(byte & 0x0f)] as char);
+                    out.push(' ');
+                }
+                // Pad a short final row so the ASCII gutter still lines up.
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str(" |");
+        for &byte in chunk {
+            out.push(if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            });
+        }
+        out.push('|');
+        out.push_str(line_end);
+    }
+}
@@ -0,0 +1,86 @@
+// Relaying received datagrams on to other UDP endpoints.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use crate::diag;
+use crate::ip_fields;
+
+/// Sends copies of datagrams to a fixed list of targets, from sockets of its own so the
+/// copies never come from the listening port.
+///
+/// The sockets are non-blocking: if the operating system can't take a copy straight
+/// away, that copy is dropped and counted as failed rather than holding up the
+/// listener. A failure for one target doesn't affect the others.
+pub(crate) struct Forwarder {
+    targets: Vec<SocketAddr>,
+    /// Sockets for IPv4 and IPv6 targets, each opened only if there's a target of that
+    /// family.
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+}
+
+impl Forwarder {
+    pub(crate) fn new(targets: &[SocketAddr]) -> io::Result<Forwarder> {
+        let open = |local: &str| -> io::Result<UdpSocket> {
+            let local: SocketAddr = local.parse().expect("valid address");
+            let socket = UdpSocket::bind(local).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Couldn't open a socket for forwarding: {}", e),
+                )
+            })?;
+            socket.set_nonblocking(true)?;
+            Ok(socket)
+        };
+        let v4 = targets
+            .iter()
+            .any(SocketAddr::is_ipv4)
+            .then(|| open("0.0.0.0:0"))
+            .transpose()?;
+        let v6 = targets
+            .iter()
+            .any(SocketAddr::is_ipv6)
+            .then(|| open("[::]:0"))
+            .transpose()?;
+        Ok(Forwarder {
+            targets: targets.to_vec(),
+            v4,
+            v6,
+        })
+    }
+
+    /// Sends `data`, unchanged, to every target. Returns how many copies were sent and
+    /// how many failed; each failure is also reported on stderr.
+    ///
+    /// With `from`, the address the datagram was sent to, each copy is sent from that
+    /// address where possible. Where it isn't, such as from a loopback address to
+    /// another host, the copy goes out from the default address instead.
+    pub(crate) fn forward(&self, data: &[u8], from: Option<IpAddr>) -> (u64, u64) {
+        let mut sent = 0;
+        let mut failed = 0;
+        for &target in &self.targets {
+            let socket = if target.is_ipv4() {
+                self.v4.as_ref()
+            } else {
+                self.v6.as_ref()
+            };
+            let socket = socket.expect("a socket is opened for every target's family");
+            let result = match from {
+                Some(from) if from.is_ipv4() == target.is_ipv4() => {
+                    ip_fields::send_from(socket, data, target, from)
+                        .or_else(|_| socket.send_to(data, target))
+                }
+                _ => socket.send_to(data, target),
+            };
+            match result {
+                Ok(_) => sent += 1,
+                Err(e) => {
+                    failed += 1;
+                    diag::error!("Error forwarding packet to {}: {}", target, e);
+                }
+            }
+        }
+        (sent, failed)
+    }
+}
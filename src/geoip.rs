@@ -0,0 +1,125 @@
+// Looking up where source addresses are, in a MaxMind GeoIP database.
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+use crossbeam_channel::{Sender, TrySendError};
+use maxminddb::{Reader, geoip2};
+use serde::Serialize;
+
+use crate::lru::LruCache;
+
+/// How many addresses may wait to be looked up. Beyond this, new addresses are logged
+/// without a location and looked up once they send again.
+const PENDING_LOOKUPS: usize = 1024;
+
+/// Where a source address is, according to the GeoIP database, for
+/// [`Config::geoip`](crate::Config::geoip). Shown as `Berlin, DE`, or just `DE` when
+/// the database has no city for the address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GeoLocation {
+    /// The ISO 3166-1 country code, such as `DE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// The city's English name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+}
+
+impl fmt::Display for GeoLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.city, &self.country) {
+            (Some(city), Some(country)) => write!(f, "{}, {}", city, country),
+            (Some(place), None) | (None, Some(place)) => f.write_str(place),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Locations for addresses, looked up by a background thread.
+///
+/// Works like the reverse DNS [`Resolver`](crate::resolve::Resolver):
+/// [`GeoLocator::location`] answers from the cache, and an address it hasn't seen before
+/// is queued for the lookup thread and answered with `None` until the lookup finishes.
+/// An address the database doesn't know, such as a private one, is cached as `None` and
+/// isn't annotated. The cache is limited to `max_addresses` addresses in the same way.
+pub(crate) struct GeoLocator {
+    cache: Arc<Mutex<LruCache<IpAddr, Option<Arc<GeoLocation>>>>>,
+    requests: Sender<IpAddr>,
+}
+
+impl GeoLocator {
+    /// Loads the database at `path`, a GeoIP2 or GeoLite2 City or Country database, and
+    /// starts the lookup thread. It stops when the `GeoLocator` is dropped.
+    pub(crate) fn spawn(path: &Path, max_addresses: usize) -> io::Result<GeoLocator> {
+        let reader = Reader::open_readfile(path).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Couldn't load the GeoIP database '{}': {}",
+                    path.display(),
+                    e
+                ),
+            )
+        })?;
+        let cache = Arc::new(Mutex::new(LruCache::new(max_addresses)));
+        let (requests, pending) = crossbeam_channel::bounded::<IpAddr>(PENDING_LOOKUPS);
+
+        let results = Arc::clone(&cache);
+        thread::Builder::new()
+            .name("geoip".to_string())
+            .spawn(move || {
+                for ip in pending {
+                    let location = look_up(&reader, ip).map(Arc::new);
+                    lock(&results).update(ip, location);
+                }
+            })?;
+
+        Ok(GeoLocator { cache, requests })
+    }
+
+    /// The location of `ip`, if it has already been looked up and the database knows
+    /// it.
+    pub(crate) fn location(&self, ip: IpAddr) -> Option<Arc<GeoLocation>> {
+        let mut cache = lock(&self.cache);
+        if let Some(location) = cache.get(ip) {
+            return location.clone();
+        }
+        match self.requests.try_send(ip) {
+            Ok(()) => {
+                cache.insert(ip, None);
+            }
+            // Leave the address out of the cache so it's queued again next time.
+            Err(TrySendError::Full(_)) => {}
+            // The lookup thread only stops if it panicked; carry on without locations.
+            Err(TrySendError::Disconnected(_)) => {
+                cache.insert(ip, None);
+            }
+        }
+        None
+    }
+
+    /// How many addresses were forgotten to make room since the last call.
+    pub(crate) fn take_evicted(&self) -> u64 {
+        lock(&self.cache).take_evicted()
+    }
+}
+
+/// Finds `ip` in the database. Errors, like addresses it has no record for, just mean
+/// there's no location.
+fn look_up(reader: &Reader<Vec<u8>>, ip: IpAddr) -> Option<GeoLocation> {
+    let record: geoip2::City = reader.lookup(ip).ok()?.decode().ok()??;
+    let location = GeoLocation {
+        country: record.country.iso_code.map(str::to_string),
+        city: record.city.names.english.map(str::to_string),
+    };
+    (location.country.is_some() || location.city.is_some()).then_some(location)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
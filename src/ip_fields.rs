@@ -0,0 +1,287 @@
+// Reading the parts of each datagram's IP header that `recv_from` doesn't return, and
+// choosing the source address of replies.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+/// Fields of the IP header a datagram arrived with, for
+/// [`Config::ip_fields`](crate::Config::ip_fields) and
+/// [`Config::dst_ip`](crate::Config::dst_ip).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct IpFields {
+    /// The IPv4 TTL, or IPv6 hop limit.
+    pub(crate) ttl: Option<u8>,
+    /// The IPv4 type of service byte, or IPv6 traffic class: the DSCP in the top six
+    /// bits and ECN in the bottom two.
+    pub(crate) tos: Option<u8>,
+    /// The local address the datagram was sent to.
+    pub(crate) dst: Option<IpAddr>,
+}
+
+/// Asks the kernel to hand over the TTL and ToS (with `ttl_tos`) and the destination
+/// address (with `dst`) of each datagram with the datagram itself, as ancillary data
+/// for `recvmsg`.
+///
+/// An IPv6 socket asks for both the IPv6 fields and, for the IPv4 datagrams a
+/// dual-stack socket receives, the IPv4 ones. Only Linux is supported; elsewhere this
+/// returns an `Unsupported` error and the caller carries on without the fields.
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(socket: &UdpSocket, ttl_tos: bool, dst: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let set = |level: libc::c_int, name: libc::c_int| -> io::Result<()> {
+        let on: libc::c_int = 1;
+        // SAFETY: the option value is a live `c_int` of the length given.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                (&on as *const libc::c_int).cast(),
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    let mut ipv4 = Vec::new();
+    let mut ipv6 = Vec::new();
+    if ttl_tos {
+        ipv4.extend([libc::IP_RECVTTL, libc::IP_RECVTOS]);
+        ipv6.extend([libc::IPV6_RECVHOPLIMIT, libc::IPV6_RECVTCLASS]);
+    }
+    if dst {
+        ipv4.push(libc::IP_PKTINFO);
+        ipv6.push(libc::IPV6_RECVPKTINFO);
+    }
+    if socket.local_addr()?.is_ipv4() {
+        for name in ipv4 {
+            set(libc::IPPROTO_IP, name)?;
+        }
+    } else {
+        for name in ipv6 {
+            set(libc::IPPROTO_IPV6, name)?;
+        }
+        // Fails on an IPv6-only socket, which never sees IPv4 datagrams anyway.
+        for name in ipv4 {
+            let _ = set(libc::IPPROTO_IP, name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(_socket: &UdpSocket, _ttl_tos: bool, _dst: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading the IP header of received datagrams is only supported on Linux",
+    ))
+}
+
+/// Receives one datagram like `UdpSocket::recv_from`, along with whichever of its IP
+/// fields came with it. Fields that [`enable`] didn't ask for, or that the kernel
+/// didn't supply, are `None`.
+#[cfg(target_os = "linux")]
+pub(crate) fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, IpFields)> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: all-zero bytes are a valid (empty) value for these plain C structs.
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    // Room for a few control messages, aligned for `cmsghdr`.
+    let mut control = [0u64; 16];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // SAFETY: as above.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = (&mut addr as *mut libc::sockaddr_storage).cast();
+    msg.msg_namelen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = size_of_val(&control);
+
+    // SAFETY: every pointer in `msg` refers to a live buffer of the length given.
+    let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fields = IpFields::default();
+    // SAFETY: the kernel filled `msg_control` with well-formed control messages, up to
+    // `msg_controllen`, which the CMSG macros walk without going past. Each message's
+    // data has the type its level and type say.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            // IP_TOS is a single byte; the others are `int`s or structs.
+            let int = || data.cast::<libc::c_int>().read_unaligned();
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TTL) => fields.ttl = u8::try_from(int()).ok(),
+                (libc::IPPROTO_IP, libc::IP_TOS) => fields.tos = Some(*data),
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info = data.cast::<libc::in_pktinfo>().read_unaligned();
+                    let ip = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+                    fields.dst = Some(IpAddr::V4(ip));
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => fields.ttl = u8::try_from(int()).ok(),
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => fields.tos = u8::try_from(int()).ok(),
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    let info = data.cast::<libc::in6_pktinfo>().read_unaligned();
+                    let ip = Ipv6Addr::from(info.ipi6_addr.s6_addr);
+                    fields.dst = Some(IpAddr::V6(ip).to_canonical());
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    let src = match libc::c_int::from(addr.ss_family) {
+        libc::AF_INET => {
+            // SAFETY: the family says the storage holds a `sockaddr_in`.
+            let v4 =
+                unsafe { *(&addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(v4.sin_addr.s_addr)),
+                u16::from_be(v4.sin_port),
+            ))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the family says the storage holds a `sockaddr_in6`.
+            let v6 =
+                unsafe { *(&addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(v6.sin6_addr.s6_addr),
+                u16::from_be(v6.sin6_port),
+                u32::from_be(v6.sin6_flowinfo),
+                v6.sin6_scope_id,
+            ))
+        }
+        family => {
+            return Err(io::Error::other(format!(
+                "datagram from an address of unexpected family {}",
+                family
+            )));
+        }
+    };
+    Ok((received as usize, src, fields))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, IpFields)> {
+    let (len, src) = socket.recv_from(buf)?;
+    Ok((len, src, IpFields::default()))
+}
+
+/// Sends `data` to `to` like `UdpSocket::send_to`, but from the local address `from`
+/// rather than whichever one the routing table would pick. A reply to a datagram
+/// received on a socket bound to every interface then comes from the address the
+/// sender used, which is what the sender expects to hear back from.
+///
+/// `from` must be one of this host's addresses, of the family the datagram goes out
+/// as. An IPv6 link-local `from` would also need its interface, so it's left to the
+/// kernel; so is everything on systems other than Linux.
+#[cfg(target_os = "linux")]
+pub(crate) fn send_from(
+    socket: &UdpSocket,
+    data: &[u8],
+    to: SocketAddr,
+    from: IpAddr,
+) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    // A dual-stack socket sends to IPv4 addresses in their IPv4-mapped form, from an
+    // IPv4-mapped source.
+    let v6_socket = socket.local_addr()?.is_ipv6();
+    let (to, from) = match (v6_socket, to, from) {
+        (_, _, IpAddr::V6(ip)) if ip.is_unicast_link_local() => {
+            return socket.send_to(data, to);
+        }
+        (true, SocketAddr::V4(to), IpAddr::V4(from)) => (
+            SocketAddr::new(IpAddr::V6(to.ip().to_ipv6_mapped()), to.port()),
+            IpAddr::V6(from.to_ipv6_mapped()),
+        ),
+        (false, SocketAddr::V4(_), IpAddr::V4(_)) | (true, SocketAddr::V6(_), IpAddr::V6(_)) => {
+            (to, from)
+        }
+        _ => return socket.send_to(data, to),
+    };
+    let to = socket2::SockAddr::from(to);
+
+    let mut control = [0u64; 8];
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr().cast_mut().cast(),
+        iov_len: data.len(),
+    };
+    // SAFETY: all-zero bytes are a valid (empty) `msghdr`.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = to.as_ptr().cast_mut().cast();
+    msg.msg_namelen = to.len();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+
+    // SAFETY: `control` has room for one control message holding either pktinfo
+    // struct, and `msg_controllen` is set to exactly that message's space before the
+    // CMSG macros look at it. `sendmsg` only reads the buffers `msg` points to.
+    let sent = unsafe {
+        let (level, kind, len) = match from {
+            IpAddr::V4(_) => (
+                libc::IPPROTO_IP,
+                libc::IP_PKTINFO,
+                size_of::<libc::in_pktinfo>(),
+            ),
+            IpAddr::V6(_) => (
+                libc::IPPROTO_IPV6,
+                libc::IPV6_PKTINFO,
+                size_of::<libc::in6_pktinfo>(),
+            ),
+        };
+        msg.msg_controllen = libc::CMSG_SPACE(len as u32) as usize;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = kind;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(len as u32) as usize;
+        let data = libc::CMSG_DATA(cmsg);
+        match from {
+            IpAddr::V4(ip) => {
+                let mut info: libc::in_pktinfo = std::mem::zeroed();
+                info.ipi_spec_dst.s_addr = u32::from(ip).to_be();
+                data.cast::<libc::in_pktinfo>().write_unaligned(info);
+            }
+            IpAddr::V6(ip) => {
+                let mut info: libc::in6_pktinfo = std::mem::zeroed();
+                info.ipi6_addr.s6_addr = ip.octets();
+                data.cast::<libc::in6_pktinfo>().write_unaligned(info);
+            }
+        }
+        libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn send_from(
+    socket: &UdpSocket,
+    data: &[u8],
+    to: SocketAddr,
+    _from: IpAddr,
+) -> io::Result<usize> {
+    socket.send_to(data, to)
+}
@@ -0,0 +1,193 @@
+// One-way latency, from send timestamps the sender puts in its payloads.
+
+use std::fmt::Write as _;
+
+/// Buckets on each side of zero. The last one covers everything from about 39 hours
+/// up, far beyond any real latency or clock difference.
+const BUCKETS: usize = 48;
+
+/// The spread of one-way latencies seen so far: exact minimum, maximum and mean, and
+/// a histogram for percentiles.
+///
+/// A latency is the receive time minus the timestamp the sender wrote, so it depends
+/// on the two clocks agreeing. If the sender's clock is ahead, latencies come out
+/// negative; they are kept as they are rather than dropped, so the skew shows.
+///
+/// The histogram buckets double in width: below 1µs, 1-2µs, 2-4µs and so on, mirrored
+/// for negative values. That keeps it small whatever the traffic, at the cost of
+/// percentiles being accurate only to the bucket they fall in.
+#[derive(Debug, Clone)]
+pub struct LatencyStats {
+    count: u64,
+    sum: i128,
+    min: i64,
+    max: i64,
+    positive: [u64; BUCKETS],
+    negative: [u64; BUCKETS],
+}
+
+impl Default for LatencyStats {
+    fn default() -> Self {
+        LatencyStats {
+            count: 0,
+            sum: 0,
+            min: 0,
+            max: 0,
+            positive: [0; BUCKETS],
+            negative: [0; BUCKETS],
+        }
+    }
+}
+
+impl LatencyStats {
+    /// Adds one latency, in nanoseconds.
+    pub fn record(&mut self, nanos: i64) {
+        if self.count == 0 {
+            self.min = nanos;
+            self.max = nanos;
+        } else {
+            self.min = self.min.min(nanos);
+            self.max = self.max.max(nanos);
+        }
+        self.count += 1;
+        self.sum += i128::from(nanos);
+        let bucket = bucket(nanos.unsigned_abs());
+        if nanos < 0 {
+            self.negative[bucket] += 1;
+        } else {
+            self.positive[bucket] += 1;
+        }
+    }
+
+    /// Number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The smallest latency, in nanoseconds, if any were recorded.
+    pub fn min(&self) -> Option<i64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// The largest latency, in nanoseconds, if any were recorded.
+    pub fn max(&self) -> Option<i64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// The mean latency, in nanoseconds, if any were recorded.
+    pub fn mean(&self) -> Option<i64> {
+        (self.count > 0).then(|| (self.sum / i128::from(self.count)) as i64)
+    }
+
+    /// The latency that `percent` percent of packets were at or below, in nanoseconds.
+    /// This is the upper edge of the bucket it falls in, so it can overstate the true
+    /// value by up to a factor of two, but never beyond the maximum.
+    pub fn percentile(&self, percent: f64) -> Option<i64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percent / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (_, upper, count) in self.rows() {
+            seen += count;
+            if seen >= rank {
+                return Some(upper.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// A summary line followed by a bar chart of the histogram, for printing at exit.
+    /// Empty if no latencies were recorded.
+    ///
+    /// ```text
+    /// latency of 1000 packets: min 212µs, mean 498µs, max 3.1ms, p99 1.0ms
+    ///   256µs to 512µs  ######################################## 702
+    ///   512µs to 1.0ms  ############ 214
+    /// ```
+    pub fn histogram(&self) -> String {
+        let (Some(min), Some(mean), Some(max), Some(p99)) =
+            (self.min(), self.mean(), self.max(), self.percentile(99.0))
+        else {
+            return String::new();
+        };
+        let mut out = format!(
+            "latency of {} packets: min {}, mean {}, max {}, p99 {}",
+            self.count,
+            format_nanos(min),
+            format_nanos(mean),
+            format_nanos(max),
+            format_nanos(p99)
+        );
+        let rows: Vec<_> = self.rows().filter(|&(_, _, count)| count > 0).collect();
+        let widest = rows.iter().map(|&(_, _, count)| count).max().unwrap_or(1);
+        for (lower, upper, count) in rows {
+            // Labelled with the edge the bucket stops short of, so that neighbouring rows
+            // meet at round numbers: 1µs to 2µs, 2µs to 4µs.
+            let (from, to) = if upper <= 0 && lower < 0 {
+                (lower.saturating_sub(1), upper)
+            } else {
+                (lower, upper.saturating_add(1))
+            };
+            let bar = (count * 40).div_ceil(widest) as usize;
+            let _ = write!(
+                out,
+                "\n  {:>7} to {:<7} {} {}",
+                format_nanos(from),
+                format_nanos(to),
+                "#".repeat(bar),
+                count
+            );
+        }
+        out
+    }
+
+    /// Each bucket's lower and upper edges in nanoseconds and its count, from the most
+    /// negative latencies to the largest.
+    fn rows(&self) -> impl Iterator<Item = (i64, i64, u64)> + '_ {
+        let negative = (0..BUCKETS)
+            .rev()
+            .map(|b| (-upper_bound(b), -lower_bound(b), self.negative[b]));
+        let positive = (0..BUCKETS).map(|b| (lower_bound(b), upper_bound(b), self.positive[b]));
+        negative.chain(positive)
+    }
+}
+
+/// The bucket for a latency of `nanos` either side of zero.
+fn bucket(nanos: u64) -> usize {
+    let micros = nanos / 1000;
+    if micros == 0 {
+        0
+    } else {
+        ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+    }
+}
+
+/// The smallest magnitude in bucket `b`, in nanoseconds.
+fn lower_bound(b: usize) -> i64 {
+    if b == 0 { 0 } else { 1000 << (b - 1) }
+}
+
+/// The largest magnitude in bucket `b`, in nanoseconds.
+fn upper_bound(b: usize) -> i64 {
+    if b == BUCKETS - 1 {
+        i64::MAX
+    } else {
+        (1000_i64 << b) - 1
+    }
+}
+
+/// A latency in the most readable unit, such as `-12µs`, `498µs`, `3.1ms` or `2.0s`.
+pub(crate) fn format_nanos(nanos: i64) -> String {
+    let magnitude = nanos.unsigned_abs();
+    let sign = if nanos < 0 { "-" } else { "" };
+    if magnitude < 1_000 {
+        format!("{}{}ns", sign, magnitude)
+    } else if magnitude < 1_000_000 {
+        format!("{}{}µs", sign, magnitude / 1_000)
+    } else if magnitude < 1_000_000_000 {
+        format!("{}{:.1}ms", sign, magnitude as f64 / 1e6)
+    } else {
+        format!("{}{:.1}s", sign, magnitude as f64 / 1e9)
+    }
+}
@@ -0,0 +1,100 @@
+//! Listen for UDP datagrams and log them to a file.
+//!
+//! The [`UdpLogger`] binds a socket and writes each received [`Packet`] to the output
+//! described by its [`Config`], as text, a hex dump, JSON Lines or a pcap capture. The
+//! `udp_listener` binary is a thin command-line wrapper around it.
+//!
+//! With the `tokio` feature, [`run_async`] runs the same listener inside a tokio
+//! runtime.
+
+#[cfg(feature = "tokio")]
+mod async_logger;
+mod binds;
+mod checksum;
+mod clock;
+mod color;
+mod compress;
+mod config;
+mod config_file;
+mod console;
+mod dedup;
+mod diag;
+mod drops;
+mod events;
+mod filter;
+mod format;
+mod forward;
+mod geoip;
+mod ip_fields;
+mod latency;
+mod limit;
+mod logger;
+mod lru;
+mod metrics;
+mod packet;
+mod parsers;
+mod pcap;
+mod pool;
+mod rate;
+mod reassemble;
+mod records;
+mod replay;
+mod resolve;
+mod ring;
+mod rotate;
+mod sample;
+mod segment;
+mod send;
+mod sequence;
+mod session;
+mod sink;
+mod socket;
+mod split;
+mod sqlite;
+mod stats;
+mod stats_file;
+mod throttle;
+mod writer;
+
+#[cfg(feature = "tokio")]
+pub use async_logger::run_async;
+pub use checksum::PayloadChecksum;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use config::{
+    Checksum, ChunkLayout, ColorChoice, Compression, Config, DEFAULT_BIND_ADDR,
+    DEFAULT_BUFFER_SIZE, DEFAULT_DEDUP_WINDOW, DEFAULT_MAX_LOG_FILES, DEFAULT_MAX_OPEN_FILES,
+    DEFAULT_MAX_TRACKED_SOURCES, DEFAULT_OUTPUT_PATH, DEFAULT_QUEUE_SIZE,
+    DEFAULT_REASSEMBLE_TIMEOUT, DEFAULT_SQLITE_BATCH, DEFAULT_WRITE_BUFFER, ExistingLog,
+    FlushPolicy, Format, LineEnding, MulticastGroup, Output, OverflowPolicy, ParseMap, Protocol,
+    RotatePeriod, Sample, Timezone, Verbosity,
+};
+pub use config_file::load_config;
+pub use filter::{
+    FilterExpr, IpFilter, LengthFilter, PayloadFilter, Triggers, parse_ip_net, parse_payload_hex,
+    parse_payload_regex,
+};
+pub use format::{
+    EMPTY_DATAGRAM, TIMESTAMP_FORMAT, TimeFormat, check_time_pattern, format_base64, format_hex,
+    format_hex_entry, format_json, format_text, payload_text,
+};
+pub use geoip::GeoLocation;
+pub use latency::LatencyStats;
+pub use limit::check_per_source_limit;
+pub use logger::{RecvOutcome, UdpLogger};
+pub use packet::Packet;
+pub use parsers::{
+    DnsMessage, DnsQuestion, DnsRecord, DtlsAlert, DtlsRecord, NtpPacket, Parsed, RecordData,
+    SyslogMessage, parse_dns, parse_dtls, parse_dtls_record, parse_ntp, parse_syslog,
+};
+pub use pcap::{PcapWriter, global_header as pcap_global_header};
+pub use records::{RECORD_HEADER_LEN, capture_header, encode_record, read_records};
+pub use replay::{ReplayConfig, replay};
+pub use rotate::RotatingWriter;
+pub use send::{DEFAULT_SEND_SIZE, Payload, SendConfig, SendSummary, send};
+pub use session::{MAX_SESSION_ID_LEN, check_session_id};
+pub use sink::Sink;
+pub use stats::{SourceStats, Stats};
+pub use throttle::check_throttle;
+
+// `IpNet` appears in `IpFilter`, so callers need it to build a filter.
+pub use ipnet::IpNet;
@@ -0,0 +1,100 @@
+// Capping how many packets each source may have logged per second.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::lru::Recency;
+
+/// How often the logger says how many packets were held back by the limit.
+pub(crate) const LIMIT_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Checks that `pps` can be used as
+/// [`Config::per_source_limit`](crate::Config::per_source_limit): a positive number of
+/// packets per second. Fractions are allowed, such as 0.1 for one packet every ten
+/// seconds.
+pub fn check_per_source_limit(pps: f64) -> Result<(), String> {
+    if pps.is_finite() && pps > 0.0 {
+        Ok(())
+    } else {
+        Err("must be a positive number of packets per second".to_string())
+    }
+}
+
+/// A token bucket for each source address, for
+/// [`Config::per_source_limit`](crate::Config::per_source_limit).
+///
+/// Each bucket holds up to a second's worth of packets (at least one) and refills at
+/// `rate` packets a second, so a source may burst briefly but averages no more than the
+/// limit. At most `max_sources` sources have their own bucket at once; beyond that, the
+/// one heard from longest ago is forgotten, so spoofed sources can't grow the map
+/// without bound. A source that is forgotten starts again with a full bucket.
+pub(crate) struct SourceLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<SocketAddr, Bucket>,
+    recency: Recency<SocketAddr>,
+    /// Buckets forgotten since [`take_evicted`](Self::take_evicted) last returned them.
+    evicted: u64,
+    /// Packets held back since the last report.
+    limited: u64,
+    last_report: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl SourceLimiter {
+    pub(crate) fn new(rate: f64, max_sources: usize) -> SourceLimiter {
+        SourceLimiter {
+            rate,
+            burst: rate.max(1.0),
+            buckets: HashMap::new(),
+            recency: Recency::new(max_sources),
+            evicted: 0,
+            limited: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Takes a token from `src`'s bucket. Returns false, and counts the packet as held
+    /// back, if the bucket is empty.
+    pub(crate) fn allow(&mut self, src: SocketAddr) -> bool {
+        let now = Instant::now();
+        if let Some(forgotten) = self.recency.touch(src) {
+            self.buckets.remove(&forgotten);
+            self.evicted += 1;
+        }
+        let bucket = self.buckets.entry(src).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            self.limited += 1;
+            false
+        }
+    }
+
+    /// How many buckets were forgotten to make room since the last call.
+    pub(crate) fn take_evicted(&mut self) -> u64 {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Once every [`LIMIT_REPORT_INTERVAL`], returns how many packets were held back
+    /// since the last time, if any were.
+    pub(crate) fn report(&mut self) -> Option<u64> {
+        if self.last_report.elapsed() < LIMIT_REPORT_INTERVAL {
+            return None;
+        }
+        self.last_report = Instant::now();
+        (self.limited > 0).then(|| std::mem::take(&mut self.limited))
+    }
+}
@@ -0,0 +1,2026 @@
+// The receive loop: bind a socket, receive datagrams, and write them to the log.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::ops::ControlFlow;
+#[cfg(all(feature = "mio", unix))]
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, TimeDelta};
+use crossbeam_channel::TrySendError;
+
+use crate::binds::{BindReceivers, QUEUED_DATAGRAMS};
+#[cfg(not(feature = "tracing"))]
+use crate::config::Output;
+use crate::config::{Config, FlushPolicy, OverflowPolicy, Protocol, Verbosity};
+use crate::console::Console;
+use crate::dedup::{DEDUP_REPORT_INTERVAL, Deduplicator};
+use crate::diag;
+use crate::drops::DropMonitor;
+use crate::events::EventSocket;
+use crate::forward::Forwarder;
+use crate::geoip::GeoLocator;
+use crate::ip_fields::{self, IpFields};
+use crate::limit::{LIMIT_REPORT_INTERVAL, SourceLimiter};
+use crate::metrics::MetricsServer;
+use crate::packet::Packet;
+use crate::parsers::{Parsed, parse_dns, parse_dtls, parse_ntp, parse_syslog};
+use crate::pool::BufferPool;
+use crate::rate::{RATE_WINDOW, RateMeter};
+use crate::reassemble::{Chunk, Reassembler};
+use crate::resolve::Resolver;
+use crate::ring::PacketRing;
+use crate::sample::Sampler;
+use crate::sequence::{SequenceEvent, SequenceTracker};
+use crate::sink::{LogSink, Sink, Sinks, lock_sinks, lock_writer};
+use crate::socket::{
+    self, RecvErrorAction, bind_socket, classify_recv_error, connect_peer, is_icmp_error,
+    join_multicast, leave_multicast,
+};
+use crate::sqlite::SqliteSink;
+use crate::stats::Stats;
+use crate::stats_file::StatsFile;
+use crate::throttle::Throttle;
+use crate::writer::LogWriter;
+
+/// How long `recv_from` blocks before returning so the loop can notice a shutdown
+/// request. Without a timeout, a shutdown would only take effect once the next packet
+/// arrived.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often the kernel's drop count is checked.
+const DROP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A UDP listener that writes every datagram it receives to a log.
+///
+/// ```no_run
+/// use udp_listener::{Config, UdpLogger};
+///
+/// let mut logger = UdpLogger::new(Config::default())?;
+/// if let Some(packet) = logger.recv_once()?.packet() {
+///     println!("{} sent {} bytes", packet.src, packet.data.len());
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct UdpLogger {
+    config: Config,
+    socket: UdpSocket,
+    /// The sockets for `config.extra_binds`, in order, kept to echo from.
+    extra_sockets: Vec<UdpSocket>,
+    /// Receives on `socket` and `extra_sockets` together, when there are extra ones.
+    receivers: Option<BindReceivers>,
+    /// The configured output, also held by the first of `sinks`; kept here to reopen it.
+    writer: Arc<Mutex<LogWriter>>,
+    /// Everything logged packets are written to. Shared with the worker threads when
+    /// [`Config::workers`] is set, and with the flusher thread.
+    sinks: Arc<Mutex<Sinks>>,
+    /// Prints the packets on the console as they go into the ring, when
+    /// `config.ring_size` is set. Otherwise the console is the first of the sinks.
+    ring_console: Option<Console>,
+    /// Set to stop `run` (and `recv_once`) at the next opportunity.
+    shutdown: Arc<AtomicBool>,
+    /// Buffer that incoming datagrams are received into.
+    buf: Vec<u8>,
+    /// The buffers packets' payloads are copied into, reused once each packet has been
+    /// written. Shared with the worker threads and the receiving threads.
+    buffers: Arc<BufferPool>,
+    /// Set to print the current statistics without stopping.
+    report: Arc<AtomicBool>,
+    /// Running totals, reported when the listener stops.
+    stats: Stats,
+    /// Watches for datagrams the kernel dropped before they could be received, on each
+    /// socket.
+    drops: Vec<DropMonitor>,
+    last_drop_check: Instant,
+    /// When the last datagram arrived (or the logger started), for the idle timeout.
+    last_packet_at: Instant,
+    /// Set when `run` stopped because of the idle timeout.
+    idle_timed_out: bool,
+    /// The error that stopped `run`, when the socket could no longer be received from.
+    recv_failure: Option<io::Error>,
+    /// Set once the warning that a per-source table is full has been given.
+    evictions_warned: bool,
+    /// Packets that passed the filters, for the packet limit.
+    accepted: u64,
+    /// The number given to the last packet logged, when `config.index` is set.
+    last_index: u64,
+    /// Looks up source host names, when `config.resolve` is set.
+    resolver: Option<Resolver>,
+    /// Looks up source locations, when `config.geoip` is set.
+    geo_locator: Option<GeoLocator>,
+    /// The most recent packets, when `config.ring_size` is set. They are written to the
+    /// log only when `dump` is set.
+    ring: Option<PacketRing>,
+    /// Set to write the ring out to the log.
+    dump: Arc<AtomicBool>,
+    /// Set to reopen the log file, after an outside tool has moved it.
+    reopen: Arc<AtomicBool>,
+    /// Sends copies of packets on, when `config.forward` has targets.
+    forwarder: Option<Forwarder>,
+    /// Recognises repeated packets, when `config.dedup_window` is set.
+    dedup: Option<Deduplicator>,
+    /// Puts chunked messages back together, when `config.reassemble` is set.
+    reassembler: Option<Reassembler>,
+    /// Holds back packets over the limit, when `config.per_source_limit` is set.
+    limiter: Option<SourceLimiter>,
+    /// Spaces out the packets taken from the socket, when `config.throttle` is set.
+    throttle: Option<Throttle>,
+    /// Serves the statistics over HTTP, when `config.metrics_addr` is set.
+    metrics: Option<MetricsServer>,
+    /// Saves the statistics for the next run, when `config.stats_file` is set.
+    stats_file: Option<StatsFile>,
+    /// A copy of the statistics for other threads, once one has asked through
+    /// `stats_handle`.
+    shared_stats: Option<Arc<Mutex<Stats>>>,
+    /// Measures the traffic, when `config.alert_pps` or `config.alert_bps` is set.
+    rate: Option<RateMeter>,
+    /// Set while the traffic is above an alert threshold, so each spike is reported
+    /// once.
+    over_rate: bool,
+    /// Set when `run` stopped because of `config.exit_on_alert`.
+    rate_alert_stopped: bool,
+    /// Whether packets are being captured: false until the start trigger arrives, if
+    /// there is one, and again after the stop trigger.
+    armed: bool,
+    /// Set when `run` stopped because of the stop trigger.
+    trigger_stopped: bool,
+    /// Follows each source's sequence numbers, when `config.seq_offset` is set.
+    sequence: Option<SequenceTracker>,
+    /// Picks the packets to log, when `config.sample` is set.
+    sampler: Option<Sampler>,
+    /// Whether datagrams are received with their IP header fields, which needs
+    /// `config.ip_fields` or `config.dst_ip` and a system that supports it.
+    ip_fields: bool,
+    /// The address the socket is connected to, when `config.connect` is set: where
+    /// every datagram comes from and every echo goes.
+    peer: Option<SocketAddr>,
+    /// The port `socket` is bound to, which packets without a [`Packet::bind`] arrived
+    /// on.
+    local_port: u16,
+    /// Attached to every packet; the same as `config.session_id`, which is filled in
+    /// with a generated one if it wasn't set.
+    session_id: Arc<str>,
+    /// When the capture started, on the wall clock and on the monotonic clock that
+    /// [`Packet::monotonic`] counts from.
+    started_at: DateTime<Local>,
+    started: Instant,
+}
+
+impl UdpLogger {
+    /// Binds the socket and opens the output described by `config`.
+    pub fn new(mut config: Config) -> io::Result<Self> {
+        let session_id = config.session_id();
+        if config.connect.is_some() && !config.extra_binds.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "connecting to a peer needs a single bind address",
+            ));
+        }
+
+        // Create the socket, set its options and bind it to the configured address.
+        // Errors are given some context and returned to the caller with the `?` operator.
+        let socket = bind_socket(&config, config.bind_addr)?;
+        join_multicast(&socket, &config.multicast)?;
+        let peer = config
+            .connect
+            .map(|peer| connect_peer(&socket, peer))
+            .transpose()?;
+        let extra_sockets = config
+            .extra_binds
+            .iter()
+            .map(|&addr| bind_socket(&config, addr))
+            .collect::<io::Result<Vec<_>>>()?;
+        let sockets = || std::iter::once(&socket).chain(&extra_sockets);
+
+        // Make `recv_from` give up after a short wait instead of blocking forever, so a
+        // shutdown request is noticed even when no packets are arriving.
+        for socket in sockets() {
+            socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+        }
+
+        // Create or open the destination where UDP packet data will be stored.
+        // For a file, `File::create` will create a new file or truncate an existing one.
+        let writer = LogWriter::open(&config, socket.local_addr()?)?;
+
+        let writer = Arc::new(Mutex::new(writer));
+
+        let buf = vec![0; config.buffer_size.max(1)];
+        if config.ring_size.is_some() && config.workers > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a packet ring can't be combined with worker threads",
+            ));
+        }
+
+        let ip_fields = (config.ip_fields || config.dst_ip)
+            && match sockets()
+                .try_for_each(|socket| ip_fields::enable(socket, config.ip_fields, config.dst_ip))
+            {
+                Ok(()) => true,
+                Err(e) => {
+                    diag::warning!("the IP header fields won't be logged: {}", e);
+                    false
+                }
+            };
+
+        let local_port = socket.local_addr()?.port();
+        let bound_ports = sockets()
+            .map(|socket| socket.local_addr().map(|addr| addr.port()))
+            .collect::<io::Result<Vec<_>>>()?;
+        for (port, _) in config.parse_map.iter() {
+            if !bound_ports.contains(&port) {
+                diag::warning!(
+                    "port {} has a protocol in the parse map, but isn't one the listener \
+                     is bound to",
+                    port
+                );
+            }
+        }
+
+        let drops: Vec<DropMonitor> = sockets().map(DropMonitor::new).collect();
+        let buffers = Arc::new(BufferPool::new(pooled_buffers(
+            &config,
+            !extra_sockets.is_empty(),
+        )));
+        let receivers = if extra_sockets.is_empty() {
+            None
+        } else {
+            Some(BindReceivers::spawn(
+                sockets(),
+                buf.len(),
+                ip_fields,
+                Arc::clone(&buffers),
+                SHUTDOWN_POLL_INTERVAL,
+            )?)
+        };
+        let resolver = if config.resolve {
+            Some(Resolver::spawn(config.max_tracked_sources)?)
+        } else {
+            None
+        };
+        let geo_locator = config
+            .geoip
+            .as_deref()
+            .map(|path| GeoLocator::spawn(path, config.max_tracked_sources))
+            .transpose()?;
+        let mut stats = Stats::new().with_max_sources(config.max_tracked_sources);
+        stats.kernel_drops = total_drops(&drops);
+        stats.session_id = Some(Arc::clone(&session_id));
+        let stats_file = config
+            .stats_file
+            .as_deref()
+            .map(|path| StatsFile::open(path, &mut stats, config.clock.now()))
+            .transpose()?;
+        let metrics = config.metrics_addr.map(MetricsServer::spawn).transpose()?;
+        // The console comes first, then the log, the event socket and the database.
+        let mut console = Console::new(&config);
+        let mut sinks = Sinks::default();
+        if config.ring_size.is_none()
+            && let Some(console) = console.take()
+        {
+            sinks.push(Box::new(console));
+        }
+        sinks.push(Box::new(LogSink::new(Arc::clone(&writer), &config)));
+        if let Some(path) = &config.event_socket {
+            sinks.push(Box::new(EventSocket::spawn(path, &config.time_format)?));
+        }
+        if let Some(path) = &config.output_sqlite {
+            sinks.push(Box::new(SqliteSink::open(path, config.sqlite_batch)?));
+        }
+        let sinks = Arc::new(Mutex::new(sinks));
+        if let FlushPolicy::Interval(interval) = config.flush {
+            spawn_flusher(Arc::downgrade(&sinks), interval)?;
+        }
+        let forwarder = if config.forward.is_empty() {
+            None
+        } else {
+            let local_addrs = sockets()
+                .map(UdpSocket::local_addr)
+                .collect::<io::Result<Vec<_>>>()?;
+            if let Some(target) = config.forward.iter().find(|t| {
+                local_addrs
+                    .iter()
+                    .any(|&local_addr| is_self(**t, local_addr))
+            }) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "forwarding to {} would send packets back to this listener",
+                        target
+                    ),
+                ));
+            }
+            Some(Forwarder::new(&config.forward)?)
+        };
+
+        Ok(UdpLogger {
+            socket,
+            extra_sockets,
+            receivers,
+            writer,
+            sinks,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            buf,
+            buffers,
+            report: Arc::new(AtomicBool::new(false)),
+            stats,
+            drops,
+            last_drop_check: Instant::now(),
+            last_packet_at: Instant::now(),
+            idle_timed_out: false,
+            recv_failure: None,
+            evictions_warned: false,
+            accepted: 0,
+            last_index: 0,
+            resolver,
+            geo_locator,
+            ring: config.ring_size.map(PacketRing::new),
+            dump: Arc::new(AtomicBool::new(false)),
+            reopen: Arc::new(AtomicBool::new(false)),
+            metrics,
+            stats_file,
+            shared_stats: None,
+            forwarder,
+            dedup: config
+                .dedup_window
+                .map(|window| Deduplicator::new(window, config.max_tracked_sources)),
+            limiter: config
+                .per_source_limit
+                .map(|pps| SourceLimiter::new(pps, config.max_tracked_sources)),
+            throttle: config.throttle.map(Throttle::new),
+            reassembler: config
+                .reassemble
+                .map(|layout| Reassembler::new(layout, config.reassemble_timeout)),
+            ring_console: console,
+            rate: (config.alert_pps.is_some() || config.alert_bps.is_some()).then(RateMeter::new),
+            over_rate: false,
+            rate_alert_stopped: false,
+            armed: config.triggers.start.is_none(),
+            trigger_stopped: false,
+            sequence: config
+                .seq_offset
+                .map(|offset| SequenceTracker::new(offset, config.max_tracked_sources)),
+            sampler: config.sample.map(Sampler::new),
+            ip_fields,
+            peer,
+            local_port,
+            session_id,
+            started_at: config.clock.now(),
+            started: config.clock.instant(),
+            config,
+        })
+    }
+
+    /// The ID of this capture session: [`Config::session_id`], or the one generated
+    /// because it wasn't set.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// The address the socket is bound to. Useful when binding to port 0, where the
+    /// operating system picks the port.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Every address the sockets receive on: each bound address, or for a wildcard
+    /// address such as `0.0.0.0` or `[::]`, each local address it covers.
+    pub fn listening_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        let mut addrs = socket::listening_addrs(&self.socket)?;
+        for socket in &self.extra_sockets {
+            addrs.extend(socket::listening_addrs(socket)?);
+        }
+        Ok(addrs)
+    }
+
+    /// The size of the socket's kernel receive buffer, that of the first with several
+    /// bind addresses. This is what the operating system granted, which may differ from
+    /// [`Config::recv_buffer_size`].
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        socket::recv_buffer_size(&self.socket)
+    }
+
+    /// The address the metrics are served on, if they are. Like
+    /// [`local_addr`](Self::local_addr), this has the actual port when binding to port 0.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics.as_ref().and_then(MetricsServer::local_addr)
+    }
+
+    /// The configuration this logger was created with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the flag that stops the logger. Storing `true` in it (from another thread
+    /// or a signal handler) makes `run` return within a fraction of a second.
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutdown)
+    }
+
+    /// Returns the flag that asks for a statistics report. Storing `true` in it makes
+    /// the logger print its current [`Stats::summary`] and carry on listening.
+    pub fn report_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.report)
+    }
+
+    /// Whether `run` returned because no packets arrived for [`Config::idle_timeout`],
+    /// rather than because a shutdown was requested.
+    pub fn idle_timed_out(&self) -> bool {
+        self.idle_timed_out
+    }
+
+    /// Whether `run` stopped because the traffic crossed an alert threshold with
+    /// [`Config::exit_on_alert`] set.
+    pub fn rate_alert_stopped(&self) -> bool {
+        self.rate_alert_stopped
+    }
+
+    /// Whether `run` stopped because a packet matched the stop trigger in
+    /// [`Config::triggers`].
+    pub fn trigger_stopped(&self) -> bool {
+        self.trigger_stopped
+    }
+
+    /// Whether [`Config::max_packets`] packets have been captured, which makes `run`
+    /// return.
+    pub fn packet_limit_reached(&self) -> bool {
+        let counted = if self.config.count_includes_filtered {
+            self.packets_received()
+        } else {
+            self.accepted
+        };
+        self.config.max_packets.is_some_and(|max| counted >= max)
+    }
+
+    /// Returns the flag that asks for the packet ring to be written out. Storing `true`
+    /// in it makes the logger append every packet in the ring to the log and carry on
+    /// listening, with the ring left as it was. Only has an effect when
+    /// [`Config::ring_size`] is set.
+    pub fn dump_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.dump)
+    }
+
+    /// Returns the flag that asks for the log file to be reopened. Storing `true` in it
+    /// makes the logger flush the log and open the configured path again, so that once
+    /// a tool such as logrotate has renamed the file, logging carries on in a new file
+    /// under the original name rather than in the renamed one.
+    pub fn reopen_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.reopen)
+    }
+
+    /// Traffic counters for everything received so far.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Returns a copy of the statistics that other threads can read while the logger
+    /// runs. It's brought up to date about once a second, and when `run` returns.
+    pub fn stats_handle(&mut self) -> Arc<Mutex<Stats>> {
+        let stats = &self.stats;
+        Arc::clone(
+            self.shared_stats
+                .get_or_insert_with(|| Arc::new(Mutex::new(stats.clone()))),
+        )
+    }
+
+    /// Number of datagrams received so far by this run, leaving out any carried over
+    /// from [`Config::stats_file`].
+    pub fn packets_received(&self) -> u64 {
+        self.stats.packets - self.stats.carried_packets
+    }
+
+    /// Total payload bytes received so far by this run, leaving out any carried over
+    /// from [`Config::stats_file`].
+    pub fn bytes_received(&self) -> u64 {
+        self.stats.bytes - self.stats.carried_bytes
+    }
+
+    /// Number of packets that could not be written to the log.
+    pub fn write_errors(&self) -> u64 {
+        self.stats.write_errors
+    }
+
+    /// Number of packets successfully echoed back to their sender.
+    pub fn echoed(&self) -> u64 {
+        self.stats.echoed
+    }
+
+    /// Number of echo replies that could not be sent.
+    pub fn echo_failures(&self) -> u64 {
+        self.stats.echo_failures
+    }
+
+    /// Number of packets dropped because their source wasn't allowed.
+    pub fn filtered(&self) -> u64 {
+        self.stats.filtered
+    }
+
+    /// Number of packets dropped because their payload was too short or too long.
+    pub fn length_filtered(&self) -> u64 {
+        self.stats.length_filtered
+    }
+
+    /// Waits for the next datagram and returns it without logging it.
+    ///
+    /// Returns [`RecvOutcome::Shutdown`] if a shutdown is requested before a datagram
+    /// arrives, and [`RecvOutcome::Timeout`] once nothing has been received for
+    /// [`Config::idle_timeout`]. A statistics report requested while waiting is printed
+    /// straight away. ICMP errors the system reports for earlier echoes are counted in
+    /// [`Stats::icmp_errors`] and skipped, with a warning on a socket connected to
+    /// [`Config::connect`]. Other receive errors are returned as they are, apart from
+    /// the interrupted and timed-out waits that are an expected part of receiving,
+    /// which are retried.
+    pub fn recv_once(&mut self) -> io::Result<RecvOutcome> {
+        loop {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!("recv").entered();
+            self.housekeeping();
+
+            let received = match &self.receivers {
+                None => recv_with_shutdown(
+                    &self.socket,
+                    &mut self.buf,
+                    &self.shutdown,
+                    self.ip_fields,
+                    self.peer,
+                )
+                .map(|received| {
+                    received.map(|(number_of_bytes, src, fields)| {
+                        let data = self.buffers.copy_of(&self.buf[..number_of_bytes]);
+                        (data, src, fields, None)
+                    })
+                }),
+                Some(receivers) => recv_queued(receivers, &self.shutdown),
+            };
+            let received = match received {
+                Err(e) if is_icmp_error(&e) => {
+                    self.skip_icmp_error(&e);
+                    continue;
+                }
+                // The receive functions only return `Interrupted` once the shutdown flag
+                // is set.
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    return Ok(RecvOutcome::Shutdown);
+                }
+                received => received?,
+            };
+            match received {
+                // A zero-length datagram is a real packet too, and is returned like any
+                // other; only `None` means nothing arrived.
+                Some((data, src, fields, bind)) => {
+                    return Ok(RecvOutcome::Packet(self.received(data, src, fields, bind)));
+                }
+                // Nothing arrived in time. That isn't an error: unless the idle timeout
+                // has now passed, go round again. The idle timeout is therefore only
+                // checked every `SHUTDOWN_POLL_INTERVAL`, which is precise enough for
+                // whole seconds.
+                None => {
+                    if let Some(idle_timeout) = self.config.idle_timeout
+                        && self.last_packet_at.elapsed() >= idle_timeout
+                    {
+                        return Ok(RecvOutcome::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the next datagram if one is waiting, without logging it, or
+    /// [`RecvOutcome::Timeout`] if there isn't one. Never blocks once the logger is
+    /// registered with a mio [`Registry`](mio::Registry) (see the
+    /// [`Source`](mio::event::Source) implementation), so an event loop can call it until
+    /// it returns [`RecvOutcome::Timeout`] each time the socket becomes readable.
+    ///
+    /// Like [`recv_once`](Self::recv_once) it does the logger's periodic work, such as
+    /// answering a report request, and skips ICMP errors; unlike it, it leaves shutdown
+    /// and the idle timeout to the event loop, so never returns
+    /// [`RecvOutcome::Shutdown`].
+    #[cfg(all(feature = "mio", unix))]
+    pub fn poll_recv(&mut self) -> io::Result<RecvOutcome> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("poll_recv").entered();
+        self.housekeeping();
+        loop {
+            match recv_datagram(&self.socket, &mut self.buf, self.ip_fields, self.peer) {
+                Ok((number_of_bytes, src, fields)) => {
+                    let data = self.buffers.copy_of(&self.buf[..number_of_bytes]);
+                    return Ok(RecvOutcome::Packet(self.received(data, src, fields, None)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(RecvOutcome::Timeout),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if is_icmp_error(&e) => self.skip_icmp_error(&e),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Does whatever is due between receives: the periodic checks and reports, and the
+    /// reports, dumps and reopens asked for through the handles.
+    fn housekeeping(&mut self) {
+        if self.last_drop_check.elapsed() >= DROP_CHECK_INTERVAL {
+            self.check_drops();
+            self.count_evictions();
+            self.check_rates();
+            self.publish_stats();
+        }
+        if self.stats_file.as_ref().is_some_and(StatsFile::is_due) {
+            self.save_stats();
+        }
+        if self.report.swap(false, Ordering::Relaxed) {
+            self.check_drops();
+            self.count_evictions();
+            self.print_report();
+        }
+        if self.dump.swap(false, Ordering::Relaxed) {
+            self.dump_ring();
+        }
+        if self.reopen.swap(false, Ordering::Relaxed) {
+            self.reopen_log();
+        }
+        if let Some(suppressed) = self.dedup.as_mut().and_then(Deduplicator::report) {
+            self.status(format!(
+                "Suppressed {} duplicates in the last {}s",
+                suppressed,
+                DEDUP_REPORT_INTERVAL.as_secs()
+            ));
+        }
+        if let Some(limited) = self.limiter.as_mut().and_then(SourceLimiter::report) {
+            self.status(format!(
+                "Held back {} packets over the per-source limit in the last {}s",
+                limited,
+                LIMIT_REPORT_INTERVAL.as_secs()
+            ));
+        }
+        let incomplete = self
+            .reassembler
+            .as_mut()
+            .map(Reassembler::expire)
+            .unwrap_or_default();
+        for message in incomplete {
+            self.stats.reassembly_incomplete += 1;
+            diag::warning!(
+                "discarded message {} from {}: {} of its {} chunks never arrived",
+                message.id,
+                message.src,
+                message.missing,
+                message.total
+            );
+        }
+    }
+
+    /// Turns a datagram just received, with the payload `data`, into a packet, and
+    /// counts it. `bind` is the address it arrived on, given when there are several.
+    fn received(
+        &mut self,
+        data: Vec<u8>,
+        src: SocketAddr,
+        fields: IpFields,
+        bind: Option<SocketAddr>,
+    ) -> Packet {
+        self.last_packet_at = Instant::now();
+        if let Some(rate) = &mut self.rate {
+            rate.record(data.len());
+        }
+        let mut pkt = Packet::received(
+            data,
+            self.buf.len(),
+            src,
+            self.config.clock.now(),
+            &mut self.stats,
+        );
+        pkt.monotonic = Some(
+            self.config
+                .clock
+                .instant()
+                .saturating_duration_since(self.started),
+        );
+        pkt.ttl = fields.ttl;
+        pkt.tos = fields.tos;
+        pkt.dst_ip = fields.dst;
+        pkt.bind = bind;
+        pkt.session_id = Some(Arc::clone(&self.session_id));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(src = %pkt.src, len = pkt.original_len, "received a packet");
+        pkt
+    }
+
+    /// Counts an ICMP error for an earlier echo, which says nothing about the listening
+    /// socket itself. On a connected socket it can only be about the peer, so it's
+    /// reported as what it says about that.
+    fn skip_icmp_error(&mut self, e: &io::Error) {
+        self.stats.icmp_errors += 1;
+        if let Some(peer) = self.peer {
+            diag::warning!(
+                "{} answered an earlier echo with ICMP port unreachable; nothing is \
+                 listening on its port ({})",
+                peer,
+                e
+            );
+        } else if self.config.verbosity == Verbosity::Verbose {
+            eprintln!("Ignored an ICMP error for an earlier echo: {}", e);
+        }
+    }
+
+    /// Writes a packet to every [`Sink`]: the console, when it prints packets, the log,
+    /// and any others.
+    ///
+    /// A failed write (for example a full disk) is reported on stderr, counted, and
+    /// returned as an error, but leaves the logger usable; later packets are written
+    /// once the problem clears. The other sinks still get the packet.
+    pub fn log_packet(&mut self, pkt: &Packet) -> io::Result<()> {
+        let failed = write_entry(&self.sinks, pkt);
+        self.stats.write_errors += failed;
+        if failed > 0 {
+            return Err(io::Error::other(format!(
+                "the packet couldn't be written to {} of the sinks",
+                failed
+            )));
+        }
+        Ok(())
+    }
+
+    /// Adds somewhere for logged packets to go, after the configured output and the
+    /// event socket. It gets every packet logged from then on, and is flushed along
+    /// with the log.
+    pub fn add_sink(&mut self, sink: Box<dyn Sink>) {
+        lock_sinks(&self.sinks).push(sink);
+    }
+
+    /// Sends a packet's payload back to the address it came from.
+    ///
+    /// A failed send is counted and returned but doesn't affect the socket's ability to
+    /// keep receiving. One common cause is an earlier reply having drawn an ICMP "port
+    /// unreachable" from a sender that has since gone away.
+    ///
+    /// The reply comes from the address the packet was sent to, if that was recorded
+    /// (see [`Config::dst_ip`]), and from the socket it arrived on when there are
+    /// several.
+    pub fn echo_packet(&mut self, pkt: &Packet) -> io::Result<()> {
+        let socket = pkt
+            .bind
+            .and_then(|bind| {
+                self.extra_sockets
+                    .iter()
+                    .find(|socket| socket.local_addr().is_ok_and(|addr| addr == bind))
+            })
+            .unwrap_or(&self.socket);
+        let sent = match (self.peer, pkt.dst_ip) {
+            // A connected socket can only send to its peer, which is also the sender.
+            (Some(_), _) => socket.send(&pkt.data),
+            (None, Some(from)) => ip_fields::send_from(socket, &pkt.data, pkt.src, from),
+            (None, None) => socket.send_to(&pkt.data, pkt.src),
+        };
+        match sent {
+            Ok(_) => {
+                self.stats.echoed += 1;
+                Ok(())
+            }
+            Err(e) => {
+                self.stats.echo_failures += 1;
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads the kernel's drop count into the statistics, warning if anything was
+    /// dropped since the last check.
+    fn check_drops(&mut self) {
+        self.last_drop_check = Instant::now();
+        let new_drops: u64 = self.drops.iter_mut().map(DropMonitor::poll).sum();
+        self.stats.kernel_drops = total_drops(&self.drops);
+        if new_drops == 0 {
+            return;
+        }
+        match self.config.throttle {
+            // The drops are what the throttle is for, so there's nothing to fix.
+            Some(pps) => diag::warning!(
+                "the kernel dropped {} packets while --throttle held the listener to {} \
+                 packets/s",
+                new_drops,
+                pps
+            ),
+            None => diag::warning!(
+                "the kernel dropped {} packets because the socket's receive buffer \
+                 was full; try a larger --recv-buffer or --workers",
+                new_drops
+            ),
+        }
+    }
+
+    /// Adds up what the per-source tables forgot to stay within
+    /// `config.max_tracked_sources` since the last count, warning the first time any
+    /// of them is full.
+    fn count_evictions(&mut self) {
+        let evicted = self.dedup.as_mut().map_or(0, Deduplicator::take_evicted)
+            + self.limiter.as_mut().map_or(0, SourceLimiter::take_evicted)
+            + self
+                .sequence
+                .as_mut()
+                .map_or(0, SequenceTracker::take_evicted)
+            + self.resolver.as_ref().map_or(0, Resolver::take_evicted)
+            + self
+                .geo_locator
+                .as_ref()
+                .map_or(0, GeoLocator::take_evicted);
+        self.stats.sources_evicted += evicted;
+        if self.stats.sources_evicted > 0 && !self.evictions_warned {
+            self.evictions_warned = true;
+            diag::warning!(
+                "a per-source table reached its limit of {} sources, so the least \
+                 recently heard from are now being forgotten; see --max-tracked-sources",
+                self.config.max_tracked_sources
+            );
+        }
+    }
+
+    /// Warns once when the traffic goes above an alert threshold, and says when it's
+    /// back below all of them. With `config.exit_on_alert`, crossing a threshold also
+    /// asks the logger to stop.
+    fn check_rates(&mut self) {
+        let Some(rate) = &mut self.rate else {
+            return;
+        };
+        let (pps, bytes_per_sec) = rate.rates();
+        let bps = bytes_per_sec * 8.0;
+        let exceeded = match (self.config.alert_pps, self.config.alert_bps) {
+            (Some(limit), _) if pps > limit as f64 => {
+                Some(format!("{:.0} packets/s (--alert-pps {})", pps, limit))
+            }
+            (_, Some(limit)) if bps > limit as f64 => {
+                Some(format!("{:.0} bits/s (--alert-bps {})", bps, limit))
+            }
+            _ => None,
+        };
+        match (exceeded, self.over_rate) {
+            (Some(exceeded), false) => {
+                diag::warning!(
+                    "traffic over the last {}s is above the alert threshold: {}",
+                    RATE_WINDOW.as_secs(),
+                    exceeded
+                );
+                self.over_rate = true;
+                if self.config.exit_on_alert {
+                    self.rate_alert_stopped = true;
+                    self.shutdown.store(true, Ordering::Relaxed);
+                }
+            }
+            (None, true) => {
+                self.status("Traffic is back below the alert thresholds".to_string());
+                self.over_rate = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Hands the metrics server a fresh copy of the statistics.
+    fn publish_stats(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.publish(&self.stats);
+        }
+        if let Some(shared) = &self.shared_stats {
+            *shared.lock().unwrap_or_else(PoisonError::into_inner) = self.stats.clone();
+        }
+    }
+
+    /// Saves the statistics to `config.stats_file`, if one is set. Failing to is
+    /// reported, and tried again at the next save.
+    fn save_stats(&mut self) {
+        let Some(stats_file) = &mut self.stats_file else {
+            return;
+        };
+        if let Err(e) = stats_file.save(&self.stats, self.config.clock.now()) {
+            diag::error!(
+                "Couldn't save the statistics to '{}': {}",
+                stats_file.path().display(),
+                e
+            );
+        }
+    }
+
+    /// Writes every packet in the ring to the sinks, oldest first, and flushes them.
+    /// The ring itself is kept, so a later dump includes these packets again if they're
+    /// still among the most recent.
+    fn dump_ring(&mut self) {
+        let Some(ring) = &self.ring else {
+            return;
+        };
+        let mut sinks = lock_sinks(&self.sinks);
+        let mut written = 0;
+        for pkt in ring.iter() {
+            match sinks.write(pkt) {
+                0 => written += 1,
+                failed => self.stats.write_errors += failed,
+            }
+        }
+        if let Err(e) = sinks.flush() {
+            diag::error!("Error flushing {}", e);
+        }
+        drop(sinks);
+        self.status(format!(
+            "Wrote {} of {} packets in the ring to {}",
+            written,
+            ring.len(),
+            self.config.output
+        ));
+    }
+
+    /// Reopens the log file, reporting the outcome. If it can't be reopened, logging
+    /// carries on into the file that was open before.
+    fn reopen_log(&mut self) {
+        let local_addr = match self.socket.local_addr() {
+            Ok(addr) => addr,
+            Err(e) => {
+                diag::error!("Error reopening {}: {}", self.config.output, e);
+                return;
+            }
+        };
+        let reopened = lock_writer(&self.writer).reopen(&self.config, local_addr);
+        match reopened {
+            Ok(true) => self.status(format!("Reopened {}", self.config.output)),
+            Ok(false) => self.status(format!("Flushed {}", self.config.output)),
+            Err(e) => diag::error!("Error reopening {}: {}", self.config.output, e),
+        }
+    }
+
+    /// Prints the current statistics. Like the rest of the console output it goes to
+    /// stdout, or to stderr when the log itself is being written to stdout.
+    fn print_report(&self) {
+        self.status(format!("Stats: {}", self.stats.summary()));
+    }
+
+    /// Prints a status message on the console: to stdout, or to stderr when the log
+    /// itself is being written to stdout. With the `tracing` feature it's an info event
+    /// instead.
+    fn status(&self, message: String) {
+        #[cfg(feature = "tracing")]
+        tracing::info!("{}", message);
+        #[cfg(not(feature = "tracing"))]
+        if matches!(self.config.output, Output::Stdout) {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// Applies the configured triggers and filters to a received packet, and rejects it
+    /// as a duplicate if deduplication is on and it repeats a recent one. Returns true
+    /// if it should be logged.
+    ///
+    /// A packet that passes the filters is given its checksum, if one is configured,
+    /// before it's compared with recent ones. An accepted packet is also decoded, if a
+    /// protocol is configured, and given its sender's host name, if names are being
+    /// looked up and this one is already known.
+    fn accept(&mut self, pkt: &mut Packet) -> bool {
+        let mut accepted = self.check_triggers(pkt) && accept(pkt, &self.config, &mut self.stats);
+        if accepted {
+            pkt.checksum = self
+                .config
+                .checksum
+                .map(|algorithm| algorithm.of(&pkt.data));
+        }
+        if accepted
+            && let Some(dedup) = &mut self.dedup
+            && dedup.is_duplicate(pkt.src, &pkt.data, pkt.checksum)
+        {
+            self.stats.duplicates += 1;
+            accepted = false;
+        }
+        if accepted {
+            self.accepted += 1;
+            self.check_sequence(pkt);
+            if let Some(offset) = self.config.latency_offset {
+                record_latency(pkt, offset, self.started_at, &mut self.stats);
+            }
+            // With reassembly, only whole messages are decoded.
+            if self.reassembler.is_none() {
+                decode(pkt, &self.config, self.local_port, &mut self.stats);
+            }
+            if let Some(resolver) = &self.resolver {
+                pkt.src_host = resolver.name(pkt.src.ip());
+            }
+            if let Some(geo_locator) = &self.geo_locator {
+                pkt.src_geo = geo_locator.location(pkt.src.ip());
+            }
+        }
+        accepted
+    }
+
+    /// Arms the capture on the start trigger and ends it on the stop trigger. Returns
+    /// false if the packet is to be ignored: because the capture isn't armed yet, or
+    /// because it's a trigger and triggers aren't logged.
+    fn check_triggers(&mut self, pkt: &Packet) -> bool {
+        let triggers = &self.config.triggers;
+        if !self.armed {
+            if triggers
+                .start
+                .as_ref()
+                .is_some_and(|start| start.is_match(&pkt.data))
+            {
+                self.armed = true;
+                self.status(format!("Start trigger from {}; capturing", pkt.src));
+                return triggers.log;
+            }
+            self.stats.untriggered += 1;
+            return false;
+        }
+        if triggers
+            .stop
+            .as_ref()
+            .is_some_and(|stop| stop.is_match(&pkt.data))
+        {
+            // Nothing more is received once the shutdown flag is set.
+            self.armed = false;
+            self.trigger_stopped = true;
+            self.shutdown.store(true, Ordering::Relaxed);
+            return triggers.log;
+        }
+        true
+    }
+
+    /// Warns about, and counts, a gap or reordering shown by the packet's sequence
+    /// number.
+    fn check_sequence(&mut self, pkt: &Packet) {
+        let Some(sequence) = &mut self.sequence else {
+            return;
+        };
+        match sequence.check(pkt.src, &pkt.data) {
+            None => {}
+            Some(SequenceEvent::Gap { first, last }) => {
+                let missing = u64::from(last.wrapping_sub(first)) + 1;
+                self.stats.seq_missing += missing;
+                if first == last {
+                    diag::warning!("sequence number {} from {} is missing", first, pkt.src);
+                } else {
+                    diag::warning!(
+                        "sequence numbers {} to {} from {} are missing ({} packets)",
+                        first,
+                        last,
+                        pkt.src,
+                        missing
+                    );
+                }
+            }
+            Some(SequenceEvent::Reordered { seq, expected }) => {
+                // Most likely a packet already counted as missing, now turning up late.
+                self.stats.seq_missing = self.stats.seq_missing.saturating_sub(1);
+                self.stats.seq_reordered += 1;
+                diag::warning!(
+                    "sequence number {} from {} arrived out of order (expected {})",
+                    seq,
+                    pkt.src,
+                    expected
+                );
+            }
+            Some(SequenceEvent::Repeated { seq }) => {
+                self.stats.seq_reordered += 1;
+                diag::warning!("sequence number {} from {} arrived twice", seq, pkt.src);
+            }
+        }
+    }
+
+    /// Echoes a packet back to its sender and forwards it to the forward targets, as
+    /// configured, reporting failures on stderr.
+    fn relay(&mut self, pkt: &Packet) {
+        if self.config.echo
+            && let Err(e) = self.echo_packet(pkt)
+        {
+            diag::error!("Error echoing packet to {}: {}", pkt.src, e);
+        }
+        if let Some(forwarder) = &self.forwarder {
+            let (sent, failed) = forwarder.forward(&pkt.data, pkt.dst_ip);
+            self.stats.forwarded += sent;
+            self.stats.forward_failures += failed;
+        }
+    }
+
+    /// Hands an accepted packet to the reassembler, if messages are being reassembled.
+    /// Returns what is to be logged: a whole message once its last chunk arrives,
+    /// nothing for the chunks before that, and a chunk whose header doesn't fit the
+    /// layout as it is. What is returned is decoded, if a protocol is configured, and a
+    /// whole message is given the checksum of the whole message.
+    fn reassemble(&mut self, pkt: Packet) -> Option<Packet> {
+        let Some(reassembler) = &mut self.reassembler else {
+            return Some(pkt);
+        };
+        let mut pkt = match reassembler.add(pkt) {
+            Chunk::Complete(mut message) => {
+                self.stats.reassembled += 1;
+                message.checksum = self
+                    .config
+                    .checksum
+                    .map(|algorithm| algorithm.of(&message.data));
+                message
+            }
+            Chunk::Pending => return None,
+            Chunk::Malformed(pkt) => {
+                self.stats.reassembly_malformed += 1;
+                pkt
+            }
+        };
+        decode(&mut pkt, &self.config, self.local_port, &mut self.stats);
+        Some(pkt)
+    }
+
+    /// Whether the packet just accepted is to be logged: it must be in the sample and
+    /// within its source's limit, where those are configured. A packet left out is
+    /// counted under whichever stopped it. The limit is applied after sampling, so it
+    /// bounds what is actually written.
+    fn should_log(&mut self, pkt: &Packet) -> bool {
+        let keep = self.sampler.as_mut().is_none_or(Sampler::keep);
+        if !keep {
+            self.stats.unsampled += 1;
+            return false;
+        }
+        if let Some(limiter) = &mut self.limiter
+            && !limiter.allow(pkt.src)
+        {
+            self.stats.rate_limited += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Gives a packet that's to be logged the next number, when [`Config::index`] is
+    /// set, before any sink sees it.
+    fn number(&mut self, pkt: &mut Packet) {
+        if self.config.index {
+            self.last_index += 1;
+            pkt.index = Some(self.last_index);
+        }
+    }
+
+    /// Receives and logs packets until a shutdown is requested, then flushes the log.
+    ///
+    /// Packets are skipped, in this order, while the [`Config::triggers`] haven't armed
+    /// the capture, when rejected by [`Config::source_filter`],
+    /// [`Config::length_filter`], [`Config::payload_filter`] (`--match` and
+    /// `--match-hex`) or the [`Config::filter`] expression, when they repeat one seen
+    /// within [`Config::dedup_window`], when [`Config::sample`] leaves them out, and when
+    /// their source is over [`Config::per_source_limit`]. Each is counted in [`Stats`]
+    /// under whichever stopped it.
+    ///
+    /// With [`Config::echo`] set, each logged packet is also sent back to its sender, and
+    /// with [`Config::forward`] set, copied to the targets. With [`Config::workers`] set,
+    /// the writing is done on worker threads.
+    ///
+    /// Errors writing individual packets or receiving a datagram are reported on stderr
+    /// and the listener keeps going. The exception is a receive error that means the
+    /// socket itself is unusable, such as `ENOTSOCK` or `EBADF`: then the listener
+    /// stops, flushes the log as usual, and returns that error.
+    ///
+    /// Each payload is copied into a buffer that is reused once the packet has been
+    /// written, by this thread or a worker, so after a short warm-up receiving a packet
+    /// allocates nothing, however fast they arrive. With
+    /// [`Format::Pcap`](crate::Format::Pcap) and [`Format::Bin`](crate::Format::Bin)
+    /// writing one doesn't either; the text formats build each entry as a string.
+    /// Packets kept in the ring, or returned by [`recv_once`](Self::recv_once), keep
+    /// their buffers.
+    pub fn run(&mut self) -> io::Result<()> {
+        self.run_with(|_| ControlFlow::Continue(()))
+    }
+
+    /// Like [`run`](Self::run), but also calls `f` with each packet the logger logs,
+    /// once it has been written to the sinks, so an application can act on the traffic
+    /// without a receive loop of its own. Returning [`ControlFlow::Break`] stops the
+    /// listener as a shutdown request would: the packet is already logged, the log is
+    /// flushed and `run_with` returns `Ok(())`.
+    ///
+    /// `f` sees packets as they're logged, after the filters, sampling and the snap
+    /// length; with [`Config::ring_size`] it sees them as they go into the ring. With
+    /// [`Config::workers`] it's called on the receiving thread as each packet is
+    /// queued, so the packet may not have been written yet.
+    ///
+    /// ```no_run
+    /// use std::ops::ControlFlow;
+    /// use udp_listener::{Config, UdpLogger};
+    ///
+    /// let mut logger = UdpLogger::new(Config::default())?;
+    /// let mut bytes = 0;
+    /// logger.run_with(|pkt| {
+    ///     bytes += pkt.data.len();
+    ///     if bytes < 1_000_000 {
+    ///         ControlFlow::Continue(())
+    ///     } else {
+    ///         ControlFlow::Break(())
+    ///     }
+    /// })?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn run_with<F: FnMut(&Packet) -> ControlFlow<()>>(&mut self, mut f: F) -> io::Result<()> {
+        if self.config.workers > 0 {
+            return self.run_with_workers(&mut f);
+        }
+
+        while let Some(mut pkt) = self.next_packet() {
+            if !self.accept(&mut pkt) {
+                self.buffers.put(pkt.data);
+                continue;
+            }
+            // The echo and forwarded copies carry the whole payload; only what's logged is
+            // cut to the snap length.
+            self.relay(&pkt);
+            let Some(mut pkt) = self.reassemble(pkt) else {
+                continue;
+            };
+            if !self.should_log(&pkt) {
+                self.buffers.put(pkt.data);
+                continue;
+            }
+            if let Some(snaplen) = self.config.snaplen {
+                pkt.snap(snaplen);
+            }
+            self.number(&mut pkt);
+            if self.ring.is_some() {
+                // Only shown on the console; the log is written when the ring is dumped.
+                if let Some(console) = &mut self.ring_console
+                    && let Err(e) = console.print_packet(&pkt)
+                {
+                    diag::error!("Error writing to the console: {}", e);
+                }
+                let stop = f(&pkt).is_break();
+                if let Some(ring) = &mut self.ring {
+                    ring.push(pkt);
+                }
+                if stop {
+                    break;
+                }
+                continue;
+            }
+            // Failures have already been reported.
+            let _ = self.log_packet(&pkt);
+            let stop = f(&pkt).is_break();
+            self.buffers.put(pkt.data);
+            if stop {
+                break;
+            }
+        }
+
+        // Pick up any drops since the last check, and flush whatever is still buffered
+        // before handing control back.
+        self.check_drops();
+        self.count_evictions();
+        self.publish_stats();
+        self.save_stats();
+        lock_sinks(&self.sinks).sync()?;
+        self.recv_failure.take().map_or(Ok(()), Err)
+    }
+
+    /// Receives on this thread and hands each accepted packet to a pool of worker
+    /// threads through a bounded queue. The workers print and write the packets, so
+    /// the socket is read again straight away instead of after each write and flush.
+    ///
+    /// The log itself is written by one worker at a time. With more than one worker,
+    /// packets that arrive close together may be logged slightly out of order. Failed
+    /// writes are added to the statistics once the workers finish.
+    fn run_with_workers<F: FnMut(&Packet) -> ControlFlow<()>>(
+        &mut self,
+        f: &mut F,
+    ) -> io::Result<()> {
+        let (queue, jobs) = crossbeam_channel::bounded::<Packet>(self.config.queue_size.max(1));
+
+        let mut workers = Vec::with_capacity(self.config.workers);
+        for n in 0..self.config.workers {
+            let jobs = jobs.clone();
+            let sinks = Arc::clone(&self.sinks);
+            let buffers = Arc::clone(&self.buffers);
+            let worker = thread::Builder::new()
+                .name(format!("writer-{}", n))
+                .spawn(move || {
+                    // Runs until the receiver drops its end of the queue and every packet
+                    // already in it has been written. Returns the number of failed writes.
+                    let mut write_errors = 0;
+                    for pkt in jobs {
+                        write_errors += write_entry(&sinks, &pkt);
+                        buffers.put(pkt.data);
+                    }
+                    write_errors
+                })?;
+            workers.push(worker);
+        }
+        drop(jobs);
+
+        while let Some(mut pkt) = self.next_packet() {
+            if !self.accept(&mut pkt) {
+                self.buffers.put(pkt.data);
+                continue;
+            }
+            // The echo and forwarded copies go out first, since the packet itself is moved
+            // into the queue.
+            self.relay(&pkt);
+            let Some(mut pkt) = self.reassemble(pkt) else {
+                continue;
+            };
+            if !self.should_log(&pkt) {
+                self.buffers.put(pkt.data);
+                continue;
+            }
+            if let Some(snaplen) = self.config.snaplen {
+                pkt.snap(snaplen);
+            }
+            self.number(&mut pkt);
+            let stop = f(&pkt).is_break();
+            let queued = match self.config.on_overflow {
+                OverflowPolicy::Block => queue.send(pkt).is_ok(),
+                OverflowPolicy::Drop => match queue.try_send(pkt) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(pkt)) => {
+                        self.buffers.put(pkt.data);
+                        self.stats.dropped += 1;
+                        true
+                    }
+                    Err(TrySendError::Disconnected(_)) => false,
+                },
+            };
+            // The queue only disconnects if every worker has stopped, which means they
+            // panicked; joining them below passes the panic on.
+            if !queued || stop {
+                break;
+            }
+        }
+
+        // Closing the queue lets the workers finish what's left in it and exit.
+        drop(queue);
+        for worker in workers {
+            match worker.join() {
+                Ok(write_errors) => self.stats.write_errors += write_errors,
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+
+        self.check_drops();
+        self.count_evictions();
+        self.publish_stats();
+        self.save_stats();
+        lock_sinks(&self.sinks).sync()?;
+        self.recv_failure.take().map_or(Ok(()), Err)
+    }
+
+    /// Receives the next packet for `run`, reporting receive errors on stderr. Returns
+    /// `None` once a shutdown has been requested, the idle timeout has passed or the
+    /// packet limit has been reached, or after a fatal receive error, which is kept in
+    /// `recv_failure` for `run` to return.
+    fn next_packet(&mut self) -> Option<Packet> {
+        if self.packet_limit_reached() {
+            return None;
+        }
+        if let Some(throttle) = &mut self.throttle {
+            throttle.wait(&self.shutdown);
+        }
+        loop {
+            match self.recv_once() {
+                Ok(RecvOutcome::Packet(pkt)) => return Some(pkt),
+                Ok(RecvOutcome::Shutdown) => return None,
+                Ok(RecvOutcome::Timeout) => {
+                    self.idle_timed_out = true;
+                    return None;
+                }
+                Err(e) if classify_recv_error(&e) == RecvErrorAction::Fatal => {
+                    self.recv_failure = Some(io::Error::new(
+                        e.kind(),
+                        format!("Stopped receiving, as the socket can't be read: {}", e),
+                    ));
+                    return None;
+                }
+                Err(e) => {
+                    // Anything else loses at most one datagram; report it and go on.
+                    diag::error!("Error receiving packet: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// What [`UdpLogger::recv_once`] and [`UdpLogger::poll_recv`] return when receiving
+/// didn't fail: a datagram, or the reason there isn't one. Neither of those is an
+/// error, so code using `?` on the receive only has real failures to deal with.
+// Boxing the packet would cost an allocation for every one received, which receiving
+// otherwise avoids.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecvOutcome {
+    /// A datagram arrived.
+    Packet(Packet),
+    /// Nothing arrived in time: for `recv_once`, for [`Config::idle_timeout`]; for
+    /// `poll_recv`, nothing is waiting.
+    Timeout,
+    /// A shutdown was requested through [`UdpLogger::shutdown_handle`] before anything
+    /// arrived.
+    Shutdown,
+}
+
+impl RecvOutcome {
+    /// The packet, if one arrived.
+    pub fn packet(self) -> Option<Packet> {
+        match self {
+            RecvOutcome::Packet(pkt) => Some(pkt),
+            RecvOutcome::Timeout | RecvOutcome::Shutdown => None,
+        }
+    }
+}
+
+impl Drop for UdpLogger {
+    /// Leaves any multicast groups the logger joined.
+    fn drop(&mut self) {
+        leave_multicast(&self.socket, &self.config.multicast);
+    }
+}
+
+/// How many free payload buffers to keep for `config`: one for each packet that can be
+/// on its way through at once, waiting in the worker queue, being written by a worker
+/// or, with `several_binds`, queued by the receiving threads, and one on the receiving
+/// thread. Once that many have been handed out and come back, receiving allocates
+/// nothing more.
+fn pooled_buffers(config: &Config, several_binds: bool) -> usize {
+    let mut buffers = 1;
+    if config.workers > 0 {
+        buffers += config.queue_size.max(1) + config.workers;
+    }
+    if several_binds {
+        buffers += QUEUED_DATAGRAMS + config.extra_binds.len() + 1;
+    }
+    buffers
+}
+
+/// Writes a packet to every sink, the console included, returning how many failed.
+/// The log is flushed or synced as the flush policy and `fsync` say.
+fn write_entry(sinks: &Mutex<Sinks>, pkt: &Packet) -> u64 {
+    lock_sinks(sinks).write(pkt)
+}
+
+/// Syncs the sinks every `interval` from a background thread, for
+/// [`FlushPolicy::Interval`]. The thread only holds a weak reference, and stops once
+/// the logger that owns the sinks is dropped.
+fn spawn_flusher(sinks: Weak<Mutex<Sinks>>, interval: Duration) -> io::Result<()> {
+    thread::Builder::new()
+        .name("flusher".to_string())
+        .spawn(move || {
+            loop {
+                thread::sleep(interval);
+                let Some(sinks) = sinks.upgrade() else {
+                    break;
+                };
+                if let Err(e) = lock_sinks(&sinks).sync() {
+                    diag::error!("Error flushing {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Records the one-way latency of a packet whose payload holds its send time at
+/// `offset`. Payloads too short to hold one are skipped.
+fn record_latency(pkt: &Packet, offset: usize, started_at: DateTime<Local>, stats: &mut Stats) {
+    let Some(bytes) = offset
+        .checked_add(8)
+        .and_then(|end| pkt.data.get(offset..end))
+    else {
+        return;
+    };
+    let sent = i64::from_be_bytes(bytes.try_into().expect("eight bytes"));
+    // Counted on from the start of the capture where possible, so that the local wall
+    // clock being corrected part-way through doesn't show up as a change in latency.
+    let received_at = match pkt
+        .monotonic
+        .and_then(|since| TimeDelta::from_std(since).ok())
+    {
+        Some(since) => started_at + since,
+        None => pkt.received_at,
+    };
+    // Only out of range some 290 years from 1970.
+    let Some(received) = received_at.timestamp_nanos_opt() else {
+        return;
+    };
+    stats.latency.record(received.saturating_sub(sent));
+}
+
+/// Applies the configured filters to a received packet, counting it in `stats` if it's
+/// rejected. Returns true if the packet should be logged.
+///
+/// The first packet that may have been truncated also prints a warning; the summary
+/// reports how many were affected in total.
+pub(crate) fn accept(pkt: &Packet, config: &Config, stats: &mut Stats) -> bool {
+    if pkt.truncated && stats.truncated == 1 {
+        diag::warning!(
+            "a datagram from {} filled the {}-byte receive buffer and may have been \
+             truncated; use --buffer-size to receive larger datagrams",
+            pkt.src,
+            config.buffer_size
+        );
+    }
+    if !config.source_filter.is_allowed(pkt.src.ip()) {
+        stats.filtered += 1;
+        return false;
+    }
+    if !config.length_filter.is_allowed(pkt.data.len()) {
+        stats.length_filtered += 1;
+        return false;
+    }
+    if !config.payload_filter.is_allowed(&pkt.data) {
+        stats.payload_filtered += 1;
+        return false;
+    }
+    if config
+        .filter
+        .as_ref()
+        .is_some_and(|filter| !filter.matches(pkt))
+    {
+        stats.expression_filtered += 1;
+        return false;
+    }
+    true
+}
+
+/// Decodes the payload of an accepted packet as the protocol [`Config::parse_map`] gives
+/// for the port it arrived on, `local_port` unless it came to one of the extra binds,
+/// or else [`Config::parse`]. Counts it in `stats` if it can't be decoded.
+pub(crate) fn decode(pkt: &mut Packet, config: &Config, local_port: u16, stats: &mut Stats) {
+    let port = pkt.bind.map_or(local_port, |bind| bind.port());
+    let Some(protocol) = config.parse_map.protocol_for(port).or(config.parse) else {
+        return;
+    };
+    pkt.parsed = match protocol {
+        Protocol::Syslog => parse_syslog(&pkt.data).map(Parsed::Syslog),
+        Protocol::Dns => parse_dns(&pkt.data).map(Parsed::Dns),
+        Protocol::Dtls => parse_dtls(&pkt.data).map(Parsed::Dtls),
+        Protocol::Ntp => parse_ntp(&pkt.data).map(Parsed::Ntp),
+    };
+    if pkt.parsed.is_none() {
+        stats.unparsed += 1;
+        pkt.malformed = protocol.is_binary();
+    }
+}
+
+/// The kernel's drops on every socket that can be monitored, or `None` if none can.
+fn total_drops(drops: &[DropMonitor]) -> Option<u64> {
+    drops
+        .iter()
+        .filter_map(DropMonitor::total)
+        .reduce(|a, b| a + b)
+}
+
+/// Receives one datagram into `buf`. With `with_ip_fields` set it's read with `recvmsg`
+/// instead, to get its IP header fields too (see [`ip_fields::recv_from`]); otherwise
+/// they are left empty. A socket connected to `peer` is read with `recv`, every
+/// datagram being from the peer.
+pub(crate) fn recv_datagram(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    with_ip_fields: bool,
+    peer: Option<SocketAddr>,
+) -> io::Result<(usize, SocketAddr, IpFields)> {
+    match peer {
+        _ if with_ip_fields => ip_fields::recv_from(socket, buf),
+        Some(peer) => socket.recv(buf).map(|len| (len, peer, IpFields::default())),
+        None => socket
+            .recv_from(buf)
+            .map(|(len, src)| (len, src, IpFields::default())),
+    }
+}
+
+/// Lets the logger's socket be polled by a mio event loop, alongside the application's
+/// other event sources. Registering puts the socket in non-blocking mode, for
+/// [`UdpLogger::poll_recv`]; deregistering puts it back, so [`UdpLogger::run`] and
+/// [`UdpLogger::recv_once`] can be used again.
+///
+/// ```no_run
+/// use mio::{Events, Interest, Poll, Token};
+/// use udp_listener::{Config, RecvOutcome, UdpLogger};
+///
+/// let mut logger = UdpLogger::new(Config::default())?;
+/// let mut poll = Poll::new()?;
+/// poll.registry().register(&mut logger, Token(0), Interest::READABLE)?;
+/// let mut events = Events::with_capacity(16);
+/// loop {
+///     poll.poll(&mut events, None)?;
+///     while let RecvOutcome::Packet(packet) = logger.poll_recv()? {
+///         logger.log_packet(&packet)?;
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(all(feature = "mio", unix))]
+impl mio::event::Source for UdpLogger {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        if self.receivers.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an event loop can only wait on a listener with one bind address",
+            ));
+        }
+        self.socket.set_nonblocking(true)?;
+        mio::unix::SourceFd(&self.socket.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        mio::unix::SourceFd(&self.socket.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        mio::unix::SourceFd(&self.socket.as_raw_fd()).deregister(registry)?;
+        // The read timeout is still set, so receiving goes back to waking up regularly.
+        self.socket.set_nonblocking(false)
+    }
+}
+
+/// Returns true if `target` is the listener's own address, so a forwarded packet would
+/// be received again. A listener bound to every interface is also reached through
+/// loopback.
+fn is_self(target: SocketAddr, local_addr: SocketAddr) -> bool {
+    target == local_addr
+        || (local_addr.ip().is_unspecified()
+            && target.port() == local_addr.port()
+            && (target.ip().is_loopback() || target.ip().is_unspecified()))
+}
+
+/// Receives one datagram into `buf`, or returns `Ok(None)` if none arrived within the
+/// socket's read timeout ([`SHUTDOWN_POLL_INTERVAL`]), so the caller can do its
+/// periodic work and call again. Once `shutdown` is set, returns an `Interrupted`
+/// error instead of waiting.
+///
+/// This is where the platforms differ. On Unix, Ctrl-C and SIGTERM run their handler
+/// on the receiving thread and usually cut `recv_from` short with `EINTR`; the timeout
+/// shows up as `WouldBlock`. On Windows, the console's Ctrl-C handler runs on a thread
+/// of its own and never interrupts `recv_from`, so the read timeout is what wakes the
+/// loop to see the flag; the timeout shows up as `TimedOut`. Either way a shutdown
+/// takes effect within one poll interval. ICMP errors for earlier echoes are returned
+/// for the caller to skip (see [`is_icmp_error`]).
+fn recv_with_shutdown(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    shutdown: &AtomicBool,
+    with_ip_fields: bool,
+    peer: Option<SocketAddr>,
+) -> io::Result<Option<(usize, SocketAddr, IpFields)>> {
+    if shutdown.load(Ordering::Relaxed) {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "shutdown requested",
+        ));
+    }
+    match recv_datagram(socket, buf, with_ip_fields, peer) {
+        Ok(received) => Ok(Some(received)),
+        Err(e) if classify_recv_error(&e) == RecvErrorAction::Retry => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// A datagram the receiving threads queued: its payload, sender and IP header fields,
+/// and the address it arrived on.
+type Queued = (Vec<u8>, SocketAddr, IpFields, Option<SocketAddr>);
+
+/// Like [`recv_with_shutdown`], with several sockets: takes the next datagram the
+/// receiving threads queued, returning the address it arrived on as well.
+fn recv_queued(receivers: &BindReceivers, shutdown: &AtomicBool) -> io::Result<Option<Queued>> {
+    if shutdown.load(Ordering::Relaxed) {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "shutdown requested",
+        ));
+    }
+    Ok(receivers.recv(SHUTDOWN_POLL_INTERVAL)?.map(|datagram| {
+        (
+            datagram.data,
+            datagram.src,
+            datagram.fields,
+            Some(datagram.bind),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use chrono::{Local, TimeZone, Utc};
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::config::{ExistingLog, Format, Output, RotatePeriod, Timezone};
+    use crate::format::{TimeFormat, format_hex_entry, format_text, payload_text};
+
+    /// A clock stopped at 2024-06-01 12:00:00 UTC.
+    fn fixed_clock() -> Arc<MockClock> {
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        Arc::new(MockClock::new(start.with_timezone(&Local)))
+    }
+
+    /// Sends each payload to a logger set up by `config` from a socket of its own,
+    /// logging one and then moving `clock` on by `step`, and returns the log and the
+    /// sender's address.
+    fn log_payloads(
+        mut config: Config,
+        clock: Arc<MockClock>,
+        step: Duration,
+        payloads: &[&[u8]],
+    ) -> (String, SocketAddr) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("udp_packets.log");
+        config.bind_addr = "127.0.0.1:0".parse().unwrap();
+        config.output = Output::File(path.clone());
+        config.verbosity = Verbosity::Quiet;
+        config.time_format.timezone = Timezone::Utc;
+        config.clock = clock.clone();
+
+        let mut logger = UdpLogger::new(config).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for payload in payloads {
+            sender
+                .send_to(payload, logger.local_addr().unwrap())
+                .unwrap();
+            let pkt = logger.recv_once().unwrap().packet().unwrap();
+            logger.log_packet(&pkt).unwrap();
+            clock.advance(step);
+        }
+        drop(logger);
+        (
+            fs::read_to_string(&path).unwrap(),
+            sender.local_addr().unwrap(),
+        )
+    }
+
+    #[test]
+    fn text_lines_use_the_clock() {
+        let (log, src) = log_payloads(
+            Config::default(),
+            fixed_clock(),
+            Duration::from_millis(1500),
+            &[b"hello", b"world"],
+        );
+        assert_eq!(
+            log,
+            format!(
+                "[2024-06-01 12:00:00.000] Received from {src}: hello\n\
+                 [2024-06-01 12:00:01.500] Received from {src}: world\n"
+            )
+        );
+    }
+
+    #[test]
+    fn json_records_use_the_clock() {
+        let config = Config {
+            format: Format::Json,
+            session_id: Some("test".to_string()),
+            ..Config::default()
+        };
+        let (log, src) = log_payloads(config, fixed_clock(), Duration::ZERO, &[b"hello"]);
+        assert_eq!(
+            log,
+            format!(
+                "{{\"ts\":\"2024-06-01T12:00:00.000Z\",\"mono_ns\":0,\"session_id\":\"test\",\
+                 \"src\":\"{src}\",\"src_ip\":\"127.0.0.1\",\"src_port\":{},\
+                 \"len\":5,\"data_b64\":\"aGVsbG8=\"}}\n",
+                src.port()
+            )
+        );
+    }
+
+    #[test]
+    fn monotonic_times_ignore_the_clock_being_set() {
+        let clock = fixed_clock();
+        let config = Config {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            output: Output::Stdout,
+            verbosity: Verbosity::Quiet,
+            clock: clock.clone(),
+            ..Config::default()
+        };
+        let mut logger = UdpLogger::new(config).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = logger.local_addr().unwrap();
+
+        clock.advance(Duration::from_secs(1));
+        sender.send_to(b"first", addr).unwrap();
+        let first = logger.recv_once().unwrap().packet().unwrap();
+        // The wall clock is put back an hour, and two seconds pass.
+        clock.set(first.received_at - TimeDelta::hours(1));
+        clock.advance(Duration::from_secs(2));
+        sender.send_to(b"second", addr).unwrap();
+        let second = logger.recv_once().unwrap().packet().unwrap();
+
+        assert_eq!(first.monotonic, Some(Duration::from_secs(1)));
+        assert_eq!(second.monotonic, Some(Duration::from_secs(3)));
+        assert!(second.received_at < first.received_at);
+    }
+
+    #[test]
+    fn waits_that_end_without_a_packet_are_not_errors() {
+        let config = Config {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            output: Output::Stdout,
+            verbosity: Verbosity::Quiet,
+            idle_timeout: Some(Duration::from_millis(1)),
+            ..Config::default()
+        };
+        let mut logger = UdpLogger::new(config).unwrap();
+        assert_eq!(logger.recv_once().unwrap(), RecvOutcome::Timeout);
+        logger.shutdown_handle().store(true, Ordering::Relaxed);
+        assert_eq!(logger.recv_once().unwrap(), RecvOutcome::Shutdown);
+    }
+
+    #[test]
+    fn hex_entries_use_the_clock() {
+        let config = Config {
+            format: Format::Hex,
+            ..Config::default()
+        };
+        let (log, src) = log_payloads(config, fixed_clock(), Duration::ZERO, &[b"hi\n"]);
+        assert_eq!(
+            log,
+            format!(
+                "[2024-06-01 12:00:00.000] Received 3 bytes from {src}:\n\
+                 00000000  68 69 0a                                          |hi.|\n"
+            )
+        );
+    }
+
+    #[test]
+    fn time_patterns_apply_to_the_clock() {
+        let mut config = Config::default();
+        config.time_format.pattern = Some("%H:%M:%S".to_string());
+        let (log, src) = log_payloads(config, fixed_clock(), Duration::ZERO, &[b"hello"]);
+        assert_eq!(log, format!("[12:00:00] Received from {src}: hello\n"));
+    }
+
+    #[test]
+    fn daily_rotation_follows_the_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = fixed_clock();
+        clock.set(
+            Utc.with_ymd_and_hms(2024, 6, 1, 23, 59, 59)
+                .unwrap()
+                .with_timezone(&Local),
+        );
+        let mut config = Config {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            output: Output::File(dir.path().join("udp_packets.log")),
+            verbosity: Verbosity::Quiet,
+            rotate: Some(RotatePeriod::Daily),
+            clock: clock.clone(),
+            ..Config::default()
+        };
+        config.time_format.timezone = Timezone::Utc;
+
+        let mut logger = UdpLogger::new(config).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for payload in ["before midnight", "after midnight"] {
+            sender
+                .send_to(payload.as_bytes(), logger.local_addr().unwrap())
+                .unwrap();
+            let pkt = logger.recv_once().unwrap().packet().unwrap();
+            logger.log_packet(&pkt).unwrap();
+            clock.advance(Duration::from_secs(2));
+        }
+        drop(logger);
+
+        let src = sender.local_addr().unwrap();
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(
+            read("udp_packets-2024-06-01.log"),
+            format!("[2024-06-01 23:59:59.000] Received from {src}: before midnight\n")
+        );
+        assert_eq!(
+            read("udp_packets-2024-06-02.log"),
+            format!("[2024-06-02 00:00:01.000] Received from {src}: after midnight\n")
+        );
+    }
+
+    #[test]
+    fn the_parse_map_picks_the_protocol_by_port() {
+        let config = Config {
+            parse_map: "53=dns,514=syslog".parse().unwrap(),
+            ..Config::default()
+        };
+        let mut stats = Stats::new();
+        let src = "192.0.2.1:40000".parse().unwrap();
+        let mut decoded = |data: &[u8], bind: Option<&str>| {
+            let mut pkt = Packet::received(data.to_vec(), 1500, src, Local::now(), &mut stats);
+            pkt.bind = bind.map(|bind| bind.parse().unwrap());
+            decode(&mut pkt, &config, 53, &mut stats);
+            pkt.parsed
+        };
+        // A query for example.com, A record.
+        let query = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+                      \x07example\x03com\x00\x00\x01\x00\x01";
+        let message = b"<13>Jun  1 12:00:00 host app: hello";
+
+        assert!(matches!(decoded(query, None), Some(Parsed::Dns(_))));
+        assert!(matches!(
+            decoded(message, Some("127.0.0.1:514")),
+            Some(Parsed::Syslog(_))
+        ));
+        // Neither the map nor `parse` has a protocol for this port.
+        assert!(decoded(query, Some("127.0.0.1:9999")).is_none());
+        assert_eq!(stats.unparsed, 0);
+    }
+
+    #[test]
+    fn ntp_headers_are_decoded_and_short_ones_left_raw() {
+        let config = Config {
+            parse: Some(Protocol::Ntp),
+            ..Config::default()
+        };
+        let mut stats = Stats::new();
+        let src = "192.0.2.1:123".parse().unwrap();
+        let mut decoded = |data: Vec<u8>| {
+            let mut pkt = Packet::received(data, 1500, src, Local::now(), &mut stats);
+            decode(&mut pkt, &config, 123, &mut stats);
+            pkt
+        };
+        // A version 4 server reply from stratum 2, sent at 2024-06-01 12:00:00.5 UTC.
+        let mut reply = vec![0; 48];
+        reply[0] = 0x24;
+        reply[1] = 2;
+        reply[40..48].copy_from_slice(&0xea05_8bc0_8000_0000_u64.to_be_bytes());
+
+        let pkt = decoded(reply.clone());
+        assert_eq!(
+            payload_text(&pkt),
+            "server v4 stratum=2 leap=none transmit=2024-06-01T12:00:00.500000000Z"
+        );
+        let pkt = decoded(reply[..47].to_vec());
+        assert!(pkt.parsed.is_none() && pkt.malformed);
+        assert_eq!(stats.unparsed, 1);
+    }
+
+    #[test]
+    fn dtls_records_are_decoded_and_tls_ones_dumped_as_hex() {
+        let config = Config {
+            parse: Some(Protocol::Dtls),
+            ..Config::default()
+        };
+        let mut stats = Stats::new();
+        let src = "192.0.2.1:4433".parse().unwrap();
+        let mut decoded = |data: Vec<u8>| {
+            let mut pkt = Packet::received(data, 1500, src, Local::now(), &mut stats);
+            decode(&mut pkt, &config, 4433, &mut stats);
+            pkt
+        };
+        // A fatal handshake_failure alert from a DTLS 1.2 peer.
+        let mut alert = vec![21, 0xfe, 0xfd, 0, 0, 0, 0, 0, 0, 0, 7, 0, 2, 2, 40];
+
+        let pkt = decoded(alert.clone());
+        assert_eq!(
+            payload_text(&pkt),
+            "alert DTLS1.2 epoch=0 seq=7 len=2 fatal handshake_failure"
+        );
+        // The same alert from TLS 1.2, which isn't DTLS.
+        alert[1..3].copy_from_slice(&[0x03, 0x03]);
+        let pkt = decoded(alert);
+        assert!(pkt.parsed.is_none() && pkt.malformed);
+        let time_format = TimeFormat::default();
+        assert_eq!(
+            format_text(&pkt, &time_format),
+            format_hex_entry(&pkt, &time_format)
+        );
+        assert_eq!(stats.unparsed, 1);
+    }
+
+    #[test]
+    fn an_existing_log_is_appended_to_or_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("udp_packets.pcap");
+        let capture = |existing_log: ExistingLog| -> io::Result<()> {
+            let config = Config {
+                bind_addr: "127.0.0.1:0".parse().unwrap(),
+                output: Output::File(path.clone()),
+                format: Format::Pcap,
+                existing_log,
+                verbosity: Verbosity::Quiet,
+                ..Config::default()
+            };
+            let mut logger = UdpLogger::new(config)?;
+            UdpSocket::bind("127.0.0.1:0")?.send_to(b"ping", logger.local_addr()?)?;
+            let pkt = logger.recv_once()?.packet().unwrap();
+            logger.log_packet(&pkt)
+        };
+        // A 24-byte global header, then for each packet a 16-byte record header and the
+        // payload behind an IPv4 and a UDP header.
+        let record = 16 + 20 + 8 + 4;
+        capture(ExistingLog::Truncate).unwrap();
+        capture(ExistingLog::Append).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 24 + 2 * record);
+
+        let refused = capture(ExistingLog::Refuse).unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&path).unwrap().len(), 24 + 2 * record);
+
+        capture(ExistingLog::Truncate).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 24 + record);
+    }
+}
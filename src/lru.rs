@@ -0,0 +1,120 @@
+// Keeping the per-source tables to a fixed size by forgetting the least recently used.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// How far the queue of uses may run ahead of the keys before the stale uses in it are
+/// cleared out, on top of twice the number of keys.
+const COMPACT_SLACK: usize = 64;
+
+/// The order a table's keys were last used in, kept beside the table so that it can
+/// hold at most `capacity` keys, forgetting the least recently used one to make room,
+/// as with [`Config::max_tracked_sources`](crate::Config::max_tracked_sources).
+///
+/// Each use is stamped with a counter and queued, and a key's queued uses other than its
+/// latest are skipped when they reach the front, so finding the oldest key doesn't mean
+/// scanning the table. The stale uses are cleared out once they outnumber the keys, so
+/// the queue stays within about twice the number of keys.
+#[derive(Debug, Clone)]
+pub(crate) struct Recency<K> {
+    capacity: usize,
+    /// Each key's latest use.
+    last_used: HashMap<K, u64>,
+    /// Every use, oldest first, including ones a later use of the same key superseded.
+    uses: VecDeque<(u64, K)>,
+    counter: u64,
+}
+
+impl<K: Copy + Eq + Hash> Recency<K> {
+    /// Tracks up to `capacity` keys, at least one.
+    pub(crate) fn new(capacity: usize) -> Recency<K> {
+        Recency {
+            capacity: capacity.max(1),
+            last_used: HashMap::new(),
+            uses: VecDeque::new(),
+            counter: 0,
+        }
+    }
+
+    /// Marks `key` as just used. If it's new and there's no room for it, the least
+    /// recently used key is forgotten and returned, for the caller to remove from its
+    /// table.
+    pub(crate) fn touch(&mut self, key: K) -> Option<K> {
+        self.counter += 1;
+        let is_new = self.last_used.insert(key, self.counter).is_none();
+        self.uses.push_back((self.counter, key));
+        let forgotten = if is_new && self.last_used.len() > self.capacity {
+            self.forget_oldest()
+        } else {
+            None
+        };
+        if self.uses.len() > 2 * self.last_used.len() + COMPACT_SLACK {
+            let last_used = &self.last_used;
+            self.uses
+                .retain(|(stamp, key)| last_used.get(key) == Some(stamp));
+        }
+        forgotten
+    }
+
+    fn forget_oldest(&mut self) -> Option<K> {
+        while let Some((stamp, key)) = self.uses.pop_front() {
+            if self.last_used.get(&key) == Some(&stamp) {
+                self.last_used.remove(&key);
+                return Some(key);
+            }
+        }
+        None
+    }
+}
+
+/// A map of at most `capacity` entries that forgets the least recently used to make
+/// room, for caches such as the host names of sources.
+#[derive(Debug)]
+pub(crate) struct LruCache<K, V> {
+    entries: HashMap<K, V>,
+    recency: Recency<K>,
+    /// Entries forgotten since [`take_evicted`](Self::take_evicted) last returned them.
+    evicted: u64,
+}
+
+impl<K: Copy + Eq + Hash, V> LruCache<K, V> {
+    pub(crate) fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            entries: HashMap::new(),
+            recency: Recency::new(capacity),
+            evicted: 0,
+        }
+    }
+
+    /// The entry for `key`, which counts as a use of it.
+    pub(crate) fn get(&mut self, key: K) -> Option<&V> {
+        if self.entries.contains_key(&key) {
+            self.recency.touch(key);
+        }
+        self.entries.get(&key)
+    }
+
+    /// Adds or replaces the entry for `key`, forgetting the least recently used entry
+    /// if there's no room.
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        if let Some(forgotten) = self.recency.touch(key) {
+            self.entries.remove(&forgotten);
+            self.evicted += 1;
+        }
+        self.entries.insert(key, value);
+    }
+
+    /// Replaces the entry for `key` if there still is one, without counting it as a
+    /// use. A background lookup finishing for an entry already forgotten doesn't bring
+    /// it back.
+    pub(crate) fn update(&mut self, key: K, value: V) {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            *entry = value;
+        }
+    }
+
+    /// How many entries were forgotten to make room since the last call.
+    pub(crate) fn take_evicted(&mut self) -> u64 {
+        std::mem::take(&mut self.evicted)
+    }
+}
@@ -1,95 +1,403 @@
-// Import necessary modules from the standard library.
-// `std::net::UdpSocket` is used for UDP network communication.
-use std::net::UdpSocket;
-// `std::fs::File` is used for file system operations, specifically creating and opening files.
+// The `udp_listener` command-line program: parse the arguments, then hand over to the
+// `UdpLogger` from the library, which does the actual receiving and logging.
+
+// `File` and `BufWriter` write the per-source table for `--summary-csv`.
 use std::fs::File;
-// `std::io::Write` trait provides the `write_all` method for writing data to a file.
-use std::io::Write;
+use std::io::BufWriter;
 // `std::io::Result` is a type alias for `Result<T, std::io::Error>`, used for error handling in I/O operations.
 use std::io;
+// `IsTerminal` tells whether stdout is a terminal the `--tui` dashboard can be drawn on.
+use std::io::IsTerminal;
+// `SocketAddr` is an IP address and port, for listing where the listener receives.
+use std::net::SocketAddr;
+// `Path` is a borrowed file-system path, like `&str` is to `String`.
+use std::path::Path;
+// `Arc` shares the logger's shutdown flag with the signal handlers.
+use std::sync::Arc;
+// `AtomicBool` is the flag the signal handlers set to stop a replay.
+use std::sync::atomic::AtomicBool;
+
+use udp_listener::{Config, Output, Stats, UdpLogger, Verbosity};
+
+// Checking the settings for `--check`.
+mod check;
+// Command-line argument parsing.
+mod cli;
+use cli::{Command, Process};
+// Running in the background and writing a PID file.
+mod daemon;
+use daemon::{Daemon, PidFile};
+// The `--tui` dashboard.
+#[cfg(feature = "tui")]
+mod tui;
 
 /// The main function is the entry point of the Rust program.
-fn main() -> io::Result<()> {
-    // Define the address and port to which the UDP socket will bind.
-    // "127.0.0.1:8080" means it will listen on the local loopback interface (your computer)
-    // on port 8080. You can change this to "0.0.0.0:8080" to listen on all available
-    // network interfaces.
-    let bind_address = "127.0.0.1:8080";
-
-    // Attempt to bind the `UdpSocket` to the specified address.
-    // `UdpSocket::bind` returns a `Result`.
-    // `.expect()` is used here for simplicity; in a production application, you'd use
-    // more robust error handling (e.g., a `match` statement or `?` operator).
-    let socket = UdpSocket::bind(bind_address)
-        .expect(&format!("Couldn't bind to address {}", bind_address));
-
-    // Print a message indicating that the server is listening.
-    println!("UDP Listener started on {}", bind_address);
-    println!("Incoming packets will be logged to 'udp_packets.log'");
-
-    // Create or open the file where UDP packet data will be stored.
-    // `File::create` will create a new file or truncate an existing one.
-    let mut file = File::create("udp_packets.log")
-        .expect("Couldn't create or open 'udp_packets.log'");
-
-    // Define a buffer to hold incoming data.
-    // A buffer of 1500 bytes is common, as it's a typical Ethernet MTU (Maximum Transmission Unit)
-    // size, meaning most single UDP packets won't exceed this.
-    let mut buf = [0; 1500];
-
-    // Start an infinite loop to continuously receive UDP packets.
-    // `loop {}` creates an infinite loop.
-    loop {
-        // Attempt to receive a datagram into the buffer.
-        // `socket.recv_from(&mut buf)` returns a `Result` containing the number of bytes
-        // received and the source address (`SocketAddr`).
-        match socket.recv_from(&mut buf) {
-            Ok((number_of_bytes, src_addr)) => {
-                // If reception is successful:
-                // Extract the actual data from the buffer based on the `number_of_bytes`.
-                // borrowed slice `&buf[..number_of_bytes]` contains only the received data.
-                // scope for borrowed slice is limited to this block.
-
-                let received_data = &buf[..number_of_bytes];
-
-                // Convert the received data to a string for logging (if it's valid UTF-8).
-                // `String::from_utf8_lossy` converts bytes to a string, replacing invalid
-                // UTF-8 sequences with a Unicode replacement character. This is good for
-                // displaying potentially mixed data.
-                // JAA: Remove this one, because ours is not a string!
-                // whats cow for the data type? 
-                
-                let data_str = String::from_utf8_lossy(received_data);
-
-                // Print information about the received packet to the console.
-                println!("Received {} bytes from {}: {}", number_of_bytes, src_addr, data_str);
-
-                // Prepare the log entry string.
-                // It includes the timestamp, source address, and the received data.
-                // Change log entry, we only want the data
-                let log_entry = format!(
-                    "[{}] Received from {}: {}\n",
-                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), // Add a timestamp with milliseconds
-                    src_addr,
-                    data_str
-                );
-
-                // Write the log entry to the file.
-                // `file.write_all()` attempts to write the entire byte slice to the file.
-                // `.expect()` is used for basic error handling here.
-                file.write_all(log_entry.as_bytes())
-                    .expect("Couldn't write to file");
-
-                // Ensure the data is immediately written to disk, not just buffered.
-                // This is important for real-time logging and crash recovery.
-                file.flush().expect("Couldn't flush file buffer");
-            },
+fn main() {
+    // With the `tracing` feature the library's messages are events, printed here on
+    // stderr. `RUST_LOG` picks which: `RUST_LOG=debug` adds one per packet.
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+
+    // Read the settings from the command line. `skip(1)` drops the program name.
+    // A bad argument prints a short error and exits with status 2, the usual code for
+    // command-line usage errors.
+    let (config, process) = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Listen(config, process)) => (*config, process),
+        Ok(Command::Send(send_config)) => {
+            match udp_listener::send(&send_config) {
+                Ok(summary) => {
+                    println!(
+                        "Sent {} datagrams ({} bytes) to {}",
+                        summary.sent, summary.bytes, send_config.to
+                    );
+                    if summary.failed > 0 {
+                        eprintln!("{} datagrams could not be sent", summary.failed);
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Ok(Command::Replay(replay_config)) => {
+            if let Err(e) = replay(&replay_config) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(Command::Help(usage)) => {
+            print!("{}", usage);
+            return;
+        }
+        Ok(Command::Version) => {
+            print!("{}", cli::VERSION);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("Run with --help to see the supported options.");
+            std::process::exit(2);
+        }
+    };
+
+    // With `--check`, stop at showing what the listener would have run with, and
+    // whether it could have.
+    if process.check {
+        println!("{:#?}", config);
+        let problems = check::check(&config, &process);
+        if problems.is_empty() {
+            println!("The configuration is valid");
+            return;
+        }
+        for problem in &problems {
+            eprintln!("error: {}", problem);
+        }
+        std::process::exit(1);
+    }
+
+    // With `--daemon`, detach before anything starts a thread. From here on this is the
+    // background process; the terminal's process exits once startup has been reported.
+    let mut daemon = match &process.daemon_log {
+        Some(log) => match daemon::daemonize(log) {
+            Ok(daemon) => Some(daemon),
             Err(e) => {
-                // If an error occurs during reception, print an error message.
-                eprintln!("Error receiving packet: {}", e);
-                // In a production scenario, you might want to handle specific errors
-                // differently or decide whether to continue the loop.
+                eprintln!("error: {}", e);
+                std::process::exit(1);
             }
+        },
+        None => None,
+    };
+
+    // Any error that stops the listener is printed in a readable form (rather than
+    // the `Debug` output `main` would give) and the process exits with status 1.
+    if let Err(e) = run(config, &process, &mut daemon) {
+        // An error during startup is also passed back to the terminal.
+        if let Some(daemon) = daemon.take() {
+            daemon.fail(&e);
+        }
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Replays a capture until it ends or Ctrl-C or SIGTERM stops it, then prints what was
+/// sent.
+fn replay(config: &udp_listener::ReplayConfig) -> io::Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&stop))?;
+    }
+    let summary = udp_listener::replay(config, &stop)?;
+    println!(
+        "Replayed {} datagrams ({} bytes) to {}",
+        summary.sent, summary.bytes, config.to
+    );
+    if summary.failed > 0 {
+        return Err(io::Error::other(format!(
+            "{} datagrams could not be sent",
+            summary.failed
+        )));
+    }
+    Ok(())
+}
+
+/// Writes the per-source table for `--summary-csv`, replacing any existing file.
+fn write_summary_csv(path: &Path, stats: &Stats) -> io::Result<()> {
+    let file = File::create(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Couldn't create '{}': {}", path.display(), e),
+        )
+    })?;
+    stats.write_sources_csv(BufWriter::new(file))
+}
+
+/// Whether to show the `--tui` dashboard. Without a terminal to draw it on, or in a
+/// build that can't draw it, the listener carries on as if it hadn't been asked for.
+fn use_dashboard(process: &Process, config: &Config) -> bool {
+    if !process.tui {
+        return false;
+    }
+    let problem = if !cfg!(feature = "tui") {
+        "--tui needs a build with the 'tui' feature"
+    } else if matches!(config.output, Output::Stdout) {
+        "--tui can't draw on stdout while the log is written there"
+    } else if !io::stdout().is_terminal() {
+        "--tui needs stdout to be a terminal"
+    } else {
+        return true;
+    };
+    eprintln!("Warning: {}; carrying on without the dashboard", problem);
+    false
+}
+
+/// Starts the logger, runs it until Ctrl-C or SIGTERM, then prints a summary.
+/// A daemon reports that it started once the logger is listening.
+fn run(mut config: Config, process: &Process, daemon: &mut Option<Daemon>) -> io::Result<()> {
+    // Held until `run` returns, when dropping it removes the file.
+    let _pid_file = process
+        .pid_file
+        .as_deref()
+        .map(PidFile::create)
+        .transpose()?;
+
+    // When the log itself goes to stdout, keep stdout clean for the log lines and send
+    // the human-readable messages to stderr instead.
+    let log_to_stdout = matches!(config.output, Output::Stdout);
+    let status = |message: String| {
+        if log_to_stdout {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    };
+
+    // The dashboard takes the place of the packets printed on the console.
+    let show_dashboard = use_dashboard(process, &config);
+    if show_dashboard {
+        config.verbosity = Verbosity::Quiet;
+    }
+
+    let mut logger = UdpLogger::new(config)?;
+
+    // Print a message indicating that the server is listening, and where: with port 0
+    // the system picks the port, and a wildcard address covers every local address.
+    let local_addr = logger.local_addr()?;
+    match dns_lookup::get_hostname() {
+        Ok(host) => status(format!(
+            "UDP Listener started on {} on host {}",
+            local_addr, host
+        )),
+        Err(_) => status(format!("UDP Listener started on {}", local_addr)),
+    }
+    if logger.config().bind_addr.port() == 0 {
+        status(format!(
+            "Port {} was assigned by the system",
+            local_addr.port()
+        ));
+    }
+    let listening = logger.listening_addrs()?;
+    if listening != [local_addr] {
+        let listening: Vec<String> = listening.iter().map(SocketAddr::to_string).collect();
+        status(format!("Listening on {}", listening.join(", ")));
+    }
+    status(format!("Session ID: {}", logger.session_id()));
+    let config = logger.config();
+    match (&config.split_by_source, &config.output_dir) {
+        (Some(dir), _) => status(format!(
+            "Incoming packets will be logged to one file per source in '{}'",
+            dir.display()
+        )),
+        (None, Some(dir)) => status(format!(
+            "Incoming packets will be logged to numbered segments in '{}'",
+            dir.display()
+        )),
+        (None, None) => status(format!(
+            "Incoming packets will be logged to {}",
+            logger.config().output
+        )),
+    }
+    // The kernel may grant more or less than was asked for, so show what it gave.
+    if let Some(requested) = logger.config().recv_buffer_size {
+        let granted = logger.recv_buffer_size()?;
+        status(format!(
+            "Socket receive buffer: asked for {} bytes, got {}",
+            requested, granted
+        ));
+        if granted < requested {
+            eprintln!(
+                "Warning: the receive buffer was capped by the system limit \
+                 (net.core.rmem_max on Linux)"
+            );
         }
     }
+    if let Some(peer) = logger.config().connect {
+        status(format!(
+            "Receiving only from {}, over a connected socket",
+            peer
+        ));
+    }
+    if let Some(pps) = logger.config().throttle {
+        status(format!(
+            "Throttled to {} packets/s; faster traffic queues in the receive buffer",
+            pps
+        ));
+        if logger.stats().kernel_drops.is_none() {
+            eprintln!(
+                "Warning: this system doesn't report the kernel's drops, so what \
+                 --throttle drops won't be counted"
+            );
+        }
+    }
+    for group in &logger.config().multicast {
+        status(format!("Joined multicast group {}", group));
+    }
+    for target in &logger.config().forward {
+        status(format!("Forwarding packets to {}", target));
+    }
+    if let Some(path) = &logger.config().stats_file {
+        match logger.stats().carried_packets {
+            0 => status(format!("Keeping the statistics in '{}'", path.display())),
+            carried => status(format!(
+                "Keeping the statistics in '{}', carrying on from {} packets counted \
+                 in earlier runs",
+                path.display(),
+                carried
+            )),
+        }
+    }
+    if let Some(addr) = logger.metrics_addr() {
+        status(format!("Serving metrics at http://{}/metrics", addr));
+    }
+    if let Some(path) = &logger.config().event_socket {
+        status(format!(
+            "Streaming packets to subscribers of '{}'",
+            path.display()
+        ));
+    }
+    if let Some(path) = &logger.config().output_sqlite {
+        status(format!(
+            "Storing packets in the database '{}'",
+            path.display()
+        ));
+    }
+
+    // Install handlers for Ctrl-C (SIGINT) and SIGTERM. Each one simply sets the
+    // logger's shutdown flag, which the receive loop checks on every pass. On Windows,
+    // Ctrl-C in the console is delivered as SIGINT too.
+    let shutdown = logger.shutdown_handle();
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
+    // SIGUSR1 (`kill -USR1 <pid>`) prints the running totals and keeps listening.
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, logger.report_handle())?;
+    // SIGHUP reopens the log file, as logrotate expects after renaming it.
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, logger.reopen_handle())?;
+    // SIGUSR2 writes the packet ring to the log, again without stopping.
+    #[cfg(unix)]
+    if let Some(size) = logger.config().ring_size {
+        signal_hook::flag::register(signal_hook::consts::SIGUSR2, logger.dump_handle())?;
+        status(format!(
+            "Keeping the last {} packets in memory; send SIGUSR2 (kill -USR2 {}) to write them out",
+            size,
+            std::process::id()
+        ));
+    }
+
+    if let Some(daemon) = daemon.take() {
+        daemon.ready();
+    }
+
+    // The dashboard draws over the startup messages until the logger stops, and then
+    // gives them back with the terminal.
+    #[cfg(feature = "tui")]
+    let dashboard = if show_dashboard {
+        let title = format!("UDP Listener on {}", local_addr);
+        Some(tui::Dashboard::start(&mut logger, title)?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "tui"))]
+    let _ = show_dashboard;
+
+    // A fatal receive error still leaves the summary to print before it's reported.
+    let ran = logger.run();
+    #[cfg(feature = "tui")]
+    let drawn = dashboard.map(tui::Dashboard::finish).transpose();
+    #[cfg(not(feature = "tui"))]
+    let drawn: io::Result<()> = Ok(());
+    if logger.packet_limit_reached() {
+        status(format!(
+            "Reached the limit of {} packets",
+            logger.config().max_packets.unwrap_or_default()
+        ));
+    }
+    if logger.trigger_stopped() {
+        status("Stopped by the stop trigger".to_string());
+    }
+    let rate_alert_stopped = logger.rate_alert_stopped();
+    if logger.idle_timed_out()
+        && let Some(timeout) = logger.config().idle_timeout
+    {
+        status(format!("No packets for {}s", timeout.as_secs_f64()));
+    }
+
+    // Dropping the logger closes the file; `run` has already flushed it.
+    let summary = format!("Shutting down: {}", logger.stats().summary());
+    let latency = logger.stats().latency.histogram();
+    // Written now, while the stats are still here, but any error is only reported
+    // after the summary so a failed write doesn't lose that too.
+    let csv = process
+        .summary_csv
+        .as_deref()
+        .map(|path| write_summary_csv(path, logger.stats()));
+    drop(logger);
+    status(summary);
+    if !latency.is_empty() {
+        status(latency);
+    }
+    ran?;
+    drawn?;
+    csv.transpose()?;
+
+    if rate_alert_stopped {
+        return Err(io::Error::other(
+            "stopped because the traffic crossed an alert threshold",
+        ));
+    }
+    Ok(())
 }
@@ -0,0 +1,222 @@
+// Serving the statistics to Prometheus over HTTP.
+
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::stats::Stats;
+
+/// An HTTP server answering `GET /metrics` with the listener's counters in the
+/// Prometheus text format, on a thread of its own.
+///
+/// The server reads a copy of the statistics that the receive loop refreshes about
+/// once a second, so the receive loop never waits for a scrape. Dropping the server
+/// stops its thread.
+pub(crate) struct MetricsServer {
+    server: Arc<Server>,
+    stats: Arc<Mutex<Stats>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Listens on `addr` and starts answering requests.
+    pub(crate) fn spawn(addr: SocketAddr) -> io::Result<MetricsServer> {
+        let server = Server::http(addr)
+            .map_err(|e| io::Error::other(format!("Couldn't serve metrics on {}: {}", addr, e)))?;
+        let server = Arc::new(server);
+        let stats = Arc::new(Mutex::new(Stats::new()));
+
+        let thread = {
+            let server = Arc::clone(&server);
+            let stats = Arc::clone(&stats);
+            thread::Builder::new()
+                .name("metrics".to_string())
+                .spawn(move || {
+                    for request in server.incoming_requests() {
+                        // A client that hangs up before the answer is sent is no concern of
+                        // the listener's.
+                        let _ = respond(request, &stats);
+                    }
+                })?
+        };
+
+        Ok(MetricsServer {
+            server,
+            stats,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address being served, with the actual port if port 0 was asked for.
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Replaces the statistics being served with a copy of `stats`.
+    pub(crate) fn publish(&self, stats: &Stats) {
+        *lock(&self.stats) = stats.clone();
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn respond(request: Request, stats: &Mutex<Stats>) -> io::Result<()> {
+    // Ignore any query string, as Prometheus itself sends none.
+    let path = request.url().split('?').next().unwrap_or_default();
+    if path != "/metrics" {
+        return request.respond(Response::from_string("Not found\n").with_status_code(404));
+    }
+    let body = render(&lock(stats));
+    let content_type =
+        Header::from_bytes("Content-Type", "text/plain; version=0.0.4").expect("valid header");
+    request.respond(Response::from_string(body).with_header(content_type))
+}
+
+/// Formats the counters in the Prometheus text exposition format.
+fn render(stats: &Stats) -> String {
+    let mut out = String::new();
+    // Writing into a `String` cannot fail, so the `fmt::Result`s are safe to ignore.
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{}{} {}", name, labels, value);
+        }
+    };
+
+    metric(
+        "udp_packets_total",
+        "counter",
+        "Datagrams received.",
+        &[("", stats.packets)],
+    );
+    metric(
+        "udp_bytes_total",
+        "counter",
+        "Payload bytes received.",
+        &[("", stats.bytes)],
+    );
+    let mut dropped = vec![("{reason=\"queue_full\"}", stats.dropped)];
+    if let Some(kernel_drops) = stats.kernel_drops {
+        dropped.push(("{reason=\"kernel\"}", kernel_drops));
+    }
+    metric(
+        "udp_dropped_total",
+        "counter",
+        "Datagrams lost before they could be logged.",
+        &dropped,
+    );
+    metric(
+        "udp_filtered_total",
+        "counter",
+        "Datagrams dropped by a filter.",
+        &[
+            ("{filter=\"source\"}", stats.filtered),
+            ("{filter=\"length\"}", stats.length_filtered),
+            ("{filter=\"payload\"}", stats.payload_filtered),
+            ("{filter=\"expression\"}", stats.expression_filtered),
+            ("{filter=\"trigger\"}", stats.untriggered),
+        ],
+    );
+    metric(
+        "udp_duplicates_total",
+        "counter",
+        "Datagrams left out as duplicates of a recent one.",
+        &[("", stats.duplicates)],
+    );
+    metric(
+        "udp_unsampled_total",
+        "counter",
+        "Datagrams left out of the log by sampling.",
+        &[("", stats.unsampled)],
+    );
+    metric(
+        "udp_rate_limited_total",
+        "counter",
+        "Datagrams left out of the log for going over the per-source limit.",
+        &[("", stats.rate_limited)],
+    );
+    metric(
+        "udp_sequence_missing_total",
+        "counter",
+        "Sequence numbers that never arrived.",
+        &[("", stats.seq_missing)],
+    );
+    metric(
+        "udp_sequence_reordered_total",
+        "counter",
+        "Datagrams that arrived after a later-numbered one.",
+        &[("", stats.seq_reordered)],
+    );
+    metric(
+        "udp_unparsed_total",
+        "counter",
+        "Datagrams logged raw because they couldn't be decoded.",
+        &[("", stats.unparsed)],
+    );
+    metric(
+        "udp_truncated_total",
+        "counter",
+        "Datagrams that filled the receive buffer and may have been cut short.",
+        &[("", stats.truncated)],
+    );
+    metric(
+        "udp_write_errors_total",
+        "counter",
+        "Datagrams that could not be written to the log.",
+        &[("", stats.write_errors)],
+    );
+    metric(
+        "udp_icmp_errors_total",
+        "counter",
+        "ICMP errors, such as port unreachable, reported for echoes and ignored.",
+        &[("", stats.icmp_errors)],
+    );
+    metric(
+        "udp_reassembly_total",
+        "counter",
+        "Chunked messages by outcome: reassembled, given up on, or with a malformed header.",
+        &[
+            ("{outcome=\"complete\"}", stats.reassembled),
+            ("{outcome=\"incomplete\"}", stats.reassembly_incomplete),
+            ("{outcome=\"malformed\"}", stats.reassembly_malformed),
+        ],
+    );
+    metric(
+        "udp_forwarded_total",
+        "counter",
+        "Copies sent to forward targets, by outcome.",
+        &[
+            ("{result=\"sent\"}", stats.forwarded),
+            ("{result=\"failed\"}", stats.forward_failures),
+        ],
+    );
+    metric(
+        "udp_sources",
+        "gauge",
+        "Distinct source addresses tracked.",
+        &[("", stats.per_source.len() as u64)],
+    );
+    metric(
+        "udp_sources_evicted_total",
+        "counter",
+        "Entries forgotten from the per-source tables to stay within --max-tracked-sources.",
+        &[("", stats.sources_evicted)],
+    );
+    out
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,149 @@
+// A single received datagram and where it came from.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+
+use crate::checksum::PayloadChecksum;
+use crate::geoip::GeoLocation;
+use crate::parsers::Parsed;
+use crate::stats::Stats;
+
+/// One UDP datagram as received by the listener.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// The datagram's payload, exactly as received, or its first
+    /// [`captured_len`](Self::captured_len) bytes if it was cut to
+    /// [`Config::snaplen`](crate::Config::snaplen).
+    pub data: Vec<u8>,
+    /// How many payload bytes were kept in `data`.
+    pub captured_len: usize,
+    /// How many payload bytes the datagram had when it was received. Larger than
+    /// `captured_len` only if the payload was cut to the snap length.
+    pub original_len: usize,
+    /// The address and port the datagram was sent from.
+    pub src: SocketAddr,
+    /// When the datagram was received, on the local clock.
+    pub received_at: DateTime<Local>,
+    /// When the datagram was received, as the time since the capture started on the
+    /// monotonic clock. Unlike `received_at` it can't jump when the wall clock is
+    /// corrected, so the difference between two packets' values is always the time
+    /// between them. `None` for packets read from captures that didn't record it.
+    pub monotonic: Option<Duration>,
+    /// The datagram filled the whole receive buffer, so part of it may have been cut
+    /// off. See [`Config::buffer_size`](crate::Config::buffer_size).
+    pub truncated: bool,
+    /// The TTL (IPv6 hop limit) the datagram arrived with, when
+    /// [`Config::ip_fields`](crate::Config::ip_fields) is set and the system reports it.
+    /// Senders usually start from 64, 128 or 255, so it hints at how many routers the
+    /// datagram passed.
+    pub ttl: Option<u8>,
+    /// The type of service byte (IPv6 traffic class) the datagram arrived with, under
+    /// the same conditions: the DSCP in the top six bits and ECN in the bottom two.
+    pub tos: Option<u8>,
+    /// The local address the datagram was sent to, when
+    /// [`Config::dst_ip`](crate::Config::dst_ip) is set and the system reports it.
+    pub dst_ip: Option<IpAddr>,
+    /// The local address the datagram arrived on, when the listener has several (see
+    /// [`Config::extra_binds`](crate::Config::extra_binds)).
+    pub bind: Option<SocketAddr>,
+    /// The [session](crate::Config::session_id) the packet was captured in, shared by
+    /// every packet of the run.
+    pub session_id: Option<Arc<str>>,
+    /// The sender's host name, from a reverse DNS lookup, when
+    /// [`Config::resolve`](crate::Config::resolve) is set and a name was found.
+    pub src_host: Option<String>,
+    /// Where the sender is, from the GeoIP database, when
+    /// [`Config::geoip`](crate::Config::geoip) is set and the database knows the
+    /// address. Shared by every packet from the same address.
+    pub src_geo: Option<Arc<GeoLocation>>,
+    /// The payload's decoded fields, when [`Config::parse`](crate::Config::parse) is
+    /// set and the payload could be decoded.
+    pub parsed: Option<Parsed>,
+    /// The payload should have been in the binary protocol set by
+    /// [`Config::parse`](crate::Config::parse), such as DNS, but didn't decode. Text
+    /// logs show it as a hex dump instead of garbled text.
+    pub malformed: bool,
+    /// The checksum of the whole payload, when [`Config::checksum`](crate::Config::checksum)
+    /// is set.
+    pub checksum: Option<PayloadChecksum>,
+    /// The packet's place among those logged this run, counting from 1, when
+    /// [`Config::index`](crate::Config::index) is set. Every log and sink shows the same
+    /// number for it; pcap, binary and SQLite logs don't record it.
+    pub index: Option<u64>,
+}
+
+impl Packet {
+    /// The sender for display: the address, followed by its host name in parentheses
+    /// if one is known and its location in brackets if that is, as in
+    /// `192.0.2.1:5000 (host.example.com) [Berlin, DE]`.
+    pub fn source(&self) -> String {
+        let mut source = match &self.src_host {
+            Some(host) => format!("{} ({})", self.src, host),
+            None => self.src.to_string(),
+        };
+        if let Some(geo) = &self.src_geo {
+            source.push_str(&format!(" [{}]", geo));
+        }
+        source
+    }
+
+    /// The payload was cut to the snap length, so `data` holds only part of it.
+    pub fn is_snapped(&self) -> bool {
+        self.captured_len < self.original_len
+    }
+
+    /// Keeps only the first `snaplen` bytes of the payload, still recording its full
+    /// length.
+    pub(crate) fn snap(&mut self, snaplen: usize) {
+        self.data.truncate(snaplen);
+        self.captured_len = self.data.len();
+    }
+
+    /// Builds the packet for a datagram with the payload `data`, just received into a
+    /// buffer of `buffer_size` bytes from `src` at `received_at`, and counts it in
+    /// `stats`.
+    pub(crate) fn received(
+        data: Vec<u8>,
+        buffer_size: usize,
+        src: SocketAddr,
+        received_at: DateTime<Local>,
+        stats: &mut Stats,
+    ) -> Packet {
+        // On a dual-stack socket IPv4 senders show up as IPv4-mapped IPv6 addresses
+        // (`::ffff:192.0.2.1`); report them as plain IPv4.
+        let src = SocketAddr::new(src.ip().to_canonical(), src.port());
+        let len = data.len();
+        stats.record(src, len, received_at);
+        // The operating system silently drops whatever doesn't fit in the buffer, so a
+        // completely full buffer is the only sign that the datagram may have been
+        // bigger. (Windows instead reports such a datagram as an error, which is printed
+        // like any receive error.)
+        let truncated = len == buffer_size;
+        if truncated {
+            stats.truncated += 1;
+        }
+        Packet {
+            data,
+            captured_len: len,
+            original_len: len,
+            src,
+            received_at,
+            monotonic: None,
+            truncated,
+            ttl: None,
+            tos: None,
+            dst_ip: None,
+            bind: None,
+            session_id: None,
+            src_host: None,
+            src_geo: None,
+            parsed: None,
+            malformed: false,
+            checksum: None,
+            index: None,
+        }
+    }
+}
@@ -0,0 +1,22 @@
+// Decoding the payloads of well-known protocols into structured fields.
+
+mod dns;
+mod dtls;
+mod ntp;
+mod syslog;
+
+pub use dns::{DnsMessage, DnsQuestion, DnsRecord, RecordData, parse_dns};
+pub use dtls::{DtlsAlert, DtlsRecord, parse_dtls, parse_dtls_record};
+pub use ntp::{NtpPacket, parse_ntp};
+pub use syslog::{SyslogMessage, parse_syslog};
+
+/// A payload decoded by one of the parsers, attached to the packet as
+/// [`Packet::parsed`](crate::Packet::parsed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed {
+    Syslog(SyslogMessage),
+    Dns(DnsMessage),
+    /// The records of a DTLS datagram, in order.
+    Dtls(Vec<DtlsRecord>),
+    Ntp(NtpPacket),
+}
@@ -0,0 +1,730 @@
+// DNS queries and responses (RFC 1035), as sent to and from UDP port 53.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::{Serialize, Serializer};
+
+/// The most compression pointers followed while reading one name. Real names need a
+/// handful at most; the limit stops a pointer loop from spinning forever.
+const MAX_POINTER_JUMPS: usize = 64;
+
+/// The longest a name can be in wire format, including the length bytes.
+const MAX_NAME_LEN: usize = 255;
+
+/// A DNS message: the header, the questions and the answer records. The authority and
+/// additional sections are only counted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DnsMessage {
+    pub id: u16,
+    /// A response rather than a query (the QR bit).
+    pub response: bool,
+    #[serde(serialize_with = "opcode_name")]
+    pub opcode: u8,
+    /// The answer is from a server that's authoritative for the name (AA).
+    pub authoritative: bool,
+    /// The response didn't fit in the datagram and was cut short (TC).
+    pub truncated: bool,
+    /// The client asked the server to resolve the name recursively (RD).
+    pub recursion_desired: bool,
+    /// The server offers recursive resolution (RA).
+    pub recursion_available: bool,
+    /// The response code, such as 3 for `NXDOMAIN`.
+    #[serde(serialize_with = "rcode_name")]
+    pub rcode: u8,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    /// How many records the authority section holds.
+    pub authority_count: u16,
+    /// How many records the additional section holds, including any EDNS `OPT` record.
+    pub additional_count: u16,
+}
+
+/// An entry in the question section: what is being asked for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DnsQuestion {
+    /// The name in presentation format, with a trailing dot: `example.com.`.
+    pub name: String,
+    #[serde(rename = "type", serialize_with = "type_name")]
+    pub qtype: u16,
+    #[serde(serialize_with = "class_name")]
+    pub class: u16,
+}
+
+/// A resource record from the answer section.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DnsRecord {
+    pub name: String,
+    #[serde(rename = "type", serialize_with = "type_name")]
+    pub rtype: u16,
+    #[serde(serialize_with = "class_name")]
+    pub class: u16,
+    pub ttl: u32,
+    #[serde(serialize_with = "display")]
+    pub data: RecordData,
+}
+
+/// The data of a resource record, decoded for the common types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    /// A name, for `CNAME`, `NS`, `PTR` and `DNAME` records.
+    Name(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Txt(Vec<String>),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    /// The raw bytes of any other type.
+    Other(Vec<u8>),
+}
+
+/// Parses a DNS message, or returns `None` if it is malformed.
+///
+/// Names may be compressed: a name can end with a pointer to a name earlier in the
+/// message, and that one may end in a pointer again. Pointers are followed wherever
+/// they appear, including inside record data, but a name that points outside the
+/// message, grows past 255 bytes or follows too many pointers makes the whole message
+/// malformed rather than being cut short. So does a record whose data doesn't match
+/// its length or type. Nothing in a malformed message is returned, as it can't be
+/// trusted.
+pub fn parse_dns(data: &[u8]) -> Option<DnsMessage> {
+    let mut reader = Reader { msg: data, pos: 0 };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    let question_count = reader.u16()?;
+    let answer_count = reader.u16()?;
+    let authority_count = reader.u16()?;
+    let additional_count = reader.u16()?;
+
+    // Each question takes at least five bytes, so a bogus count fails quickly.
+    let mut questions = Vec::new();
+    for _ in 0..question_count {
+        questions.push(DnsQuestion {
+            name: reader.name()?,
+            qtype: reader.u16()?,
+            class: reader.u16()?,
+        });
+    }
+    let mut answers = Vec::new();
+    for _ in 0..answer_count {
+        answers.push(reader.record()?);
+    }
+
+    Some(DnsMessage {
+        id,
+        response: flags & 0x8000 != 0,
+        opcode: ((flags >> 11) & 0x0f) as u8,
+        authoritative: flags & 0x0400 != 0,
+        truncated: flags & 0x0200 != 0,
+        recursion_desired: flags & 0x0100 != 0,
+        recursion_available: flags & 0x0080 != 0,
+        rcode: (flags & 0x000f) as u8,
+        questions,
+        answers,
+        authority_count,
+        additional_count,
+    })
+}
+
+/// Reads big-endian fields and names from a message, keeping the whole message at hand
+/// for compression pointers.
+struct Reader<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.msg.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads a possibly compressed name. Afterwards the reader is just past the name's
+    /// first pointer (or its terminating zero byte if it has none), not wherever the
+    /// pointers led.
+    fn name(&mut self) -> Option<String> {
+        let mut name = String::new();
+        let mut pos = self.pos;
+        // Where reading resumes: set at the first pointer.
+        let mut resume = None;
+        let mut jumps = 0;
+        let mut wire_len = 0;
+        loop {
+            let len = *self.msg.get(pos)?;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    self.pos = resume.unwrap_or(pos + 1);
+                    if name.is_empty() {
+                        name.push('.');
+                    }
+                    return Some(name);
+                }
+                0x00 => {
+                    let label = self.msg.get(pos + 1..pos + 1 + usize::from(len))?;
+                    wire_len += 1 + label.len();
+                    if wire_len + 1 > MAX_NAME_LEN {
+                        return None;
+                    }
+                    push_label(&mut name, label);
+                    pos += 1 + label.len();
+                }
+                0xc0 => {
+                    let low = *self.msg.get(pos + 1)?;
+                    jumps += 1;
+                    if jumps > MAX_POINTER_JUMPS {
+                        return None;
+                    }
+                    resume.get_or_insert(pos + 2);
+                    pos = (usize::from(len & 0x3f) << 8) | usize::from(low);
+                }
+                // 0x40 and 0x80 are reserved label types.
+                _ => return None,
+            }
+        }
+    }
+
+    fn record(&mut self) -> Option<DnsRecord> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = usize::from(self.u16()?);
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.msg.len())?;
+
+        let data = match rtype {
+            TYPE_A => RecordData::A(<[u8; 4]>::try_from(self.bytes(len)?).ok()?.into()),
+            TYPE_AAAA => RecordData::Aaaa(<[u8; 16]>::try_from(self.bytes(len)?).ok()?.into()),
+            TYPE_NS | TYPE_CNAME | TYPE_PTR | TYPE_DNAME => RecordData::Name(self.name()?),
+            TYPE_MX => RecordData::Mx {
+                preference: self.u16()?,
+                exchange: self.name()?,
+            },
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                while self.pos < end {
+                    let len = usize::from(self.u8()?);
+                    strings.push(String::from_utf8_lossy(self.bytes(len)?).into_owned());
+                }
+                RecordData::Txt(strings)
+            }
+            TYPE_SRV => RecordData::Srv {
+                priority: self.u16()?,
+                weight: self.u16()?,
+                port: self.u16()?,
+                target: self.name()?,
+            },
+            TYPE_SOA => RecordData::Soa {
+                mname: self.name()?,
+                rname: self.name()?,
+                serial: self.u32()?,
+                refresh: self.u32()?,
+                retry: self.u32()?,
+                expire: self.u32()?,
+                minimum: self.u32()?,
+            },
+            _ => RecordData::Other(self.bytes(len)?.to_vec()),
+        };
+        // The data must fill exactly the length the record gave.
+        if self.pos != end {
+            return None;
+        }
+        Some(DnsRecord {
+            name,
+            rtype,
+            class,
+            ttl,
+            data,
+        })
+    }
+}
+
+/// Appends a label and a dot to `name`, escaping dots, backslashes and unprintable
+/// bytes as `dig` does (`\.`, `\\`, `\DDD`).
+fn push_label(name: &mut String, label: &[u8]) {
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                name.push('\\');
+                name.push(byte as char);
+            }
+            b'!'..=b'~' => name.push(byte as char),
+            _ => name.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    name.push('.');
+}
+
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_MX: u16 = 15;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_DNAME: u16 = 39;
+
+/// A record type's mnemonic, or `TYPE<n>` for ones without a well-known name.
+struct TypeName(u16);
+
+impl fmt::Display for TypeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            TYPE_A => "A",
+            TYPE_NS => "NS",
+            TYPE_CNAME => "CNAME",
+            TYPE_SOA => "SOA",
+            TYPE_PTR => "PTR",
+            TYPE_MX => "MX",
+            TYPE_TXT => "TXT",
+            TYPE_AAAA => "AAAA",
+            TYPE_SRV => "SRV",
+            TYPE_DNAME => "DNAME",
+            41 => "OPT",
+            43 => "DS",
+            46 => "RRSIG",
+            48 => "DNSKEY",
+            64 => "SVCB",
+            65 => "HTTPS",
+            252 => "AXFR",
+            255 => "ANY",
+            257 => "CAA",
+            other => return write!(f, "TYPE{}", other),
+        };
+        f.write_str(name)
+    }
+}
+
+/// A class's mnemonic, or `CLASS<n>`.
+struct ClassName(u16);
+
+impl fmt::Display for ClassName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => f.write_str("IN"),
+            3 => f.write_str("CH"),
+            4 => f.write_str("HS"),
+            255 => f.write_str("ANY"),
+            other => write!(f, "CLASS{}", other),
+        }
+    }
+}
+
+struct OpcodeName(u8);
+
+impl fmt::Display for OpcodeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => f.write_str("QUERY"),
+            1 => f.write_str("IQUERY"),
+            2 => f.write_str("STATUS"),
+            4 => f.write_str("NOTIFY"),
+            5 => f.write_str("UPDATE"),
+            other => write!(f, "OPCODE{}", other),
+        }
+    }
+}
+
+struct RcodeName(u8);
+
+impl fmt::Display for RcodeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            0 => "NOERROR",
+            1 => "FORMERR",
+            2 => "SERVFAIL",
+            3 => "NXDOMAIN",
+            4 => "NOTIMP",
+            5 => "REFUSED",
+            6 => "YXDOMAIN",
+            7 => "YXRRSET",
+            8 => "NXRRSET",
+            9 => "NOTAUTH",
+            10 => "NOTZONE",
+            other => return write!(f, "RCODE{}", other),
+        };
+        f.write_str(name)
+    }
+}
+
+/// A one-line summary in the spirit of `dig`: the header, then each question and
+/// answer, separated by semicolons.
+///
+/// ```text
+/// response id=4660 QUERY NOERROR flags=qr,rd,ra; question example.com. IN A; answer example.com. 300 IN A 93.184.216.34; 0 authority, 1 additional
+/// ```
+impl fmt::Display for DnsMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} id={} {} {}",
+            if self.response { "response" } else { "query" },
+            self.id,
+            OpcodeName(self.opcode),
+            RcodeName(self.rcode)
+        )?;
+        let flags: Vec<&str> = [
+            (self.response, "qr"),
+            (self.authoritative, "aa"),
+            (self.truncated, "tc"),
+            (self.recursion_desired, "rd"),
+            (self.recursion_available, "ra"),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect();
+        if !flags.is_empty() {
+            write!(f, " flags={}", flags.join(","))?;
+        }
+        for question in &self.questions {
+            write!(
+                f,
+                "; question {} {} {}",
+                question.name,
+                ClassName(question.class),
+                TypeName(question.qtype)
+            )?;
+        }
+        for answer in &self.answers {
+            write!(
+                f,
+                "; answer {} {} {} {} {}",
+                answer.name,
+                answer.ttl,
+                ClassName(answer.class),
+                TypeName(answer.rtype),
+                answer.data
+            )?;
+        }
+        write!(
+            f,
+            "; {} authority, {} additional",
+            self.authority_count, self.additional_count
+        )
+    }
+}
+
+/// Record data in zone-file presentation format, such as `10 mail.example.com.` for an
+/// MX record. Unknown types use the generic `\# <length> <hex>` form.
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordData::A(addr) => write!(f, "{}", addr),
+            RecordData::Aaaa(addr) => write!(f, "{}", addr),
+            RecordData::Name(name) => f.write_str(name),
+            RecordData::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RecordData::Txt(strings) => {
+                let quoted: Vec<String> = strings.iter().map(|s| format!("{:?}", s)).collect();
+                f.write_str(&quoted.join(" "))
+            }
+            RecordData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target),
+            RecordData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            RecordData::Other(bytes) => {
+                write!(f, "\\# {}", bytes.len())?;
+                if !bytes.is_empty() {
+                    f.write_str(" ")?;
+                    for byte in bytes {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// JSON records use the same mnemonics and presentation format as the text log.
+
+fn type_name<S: Serializer>(rtype: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&TypeName(*rtype))
+}
+
+fn class_name<S: Serializer>(class: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&ClassName(*class))
+}
+
+fn opcode_name<S: Serializer>(opcode: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&OpcodeName(*opcode))
+}
+
+fn rcode_name<S: Serializer>(rcode: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&RcodeName(*rcode))
+}
+
+fn display<S: Serializer>(data: &RecordData, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header with the given ID and flags, and question, answer, authority and
+    /// additional counts.
+    fn header(id: u16, flags: u16, counts: [u16; 4]) -> Vec<u8> {
+        let mut msg = Vec::new();
+        for field in [id, flags].into_iter().chain(counts) {
+            msg.extend_from_slice(&field.to_be_bytes());
+        }
+        msg
+    }
+
+    /// `labels` in wire format, ending with the root label.
+    fn wire_name(labels: &[&str]) -> Vec<u8> {
+        let mut name = Vec::new();
+        for label in labels {
+            name.push(label.len() as u8);
+            name.extend_from_slice(label.as_bytes());
+        }
+        name.push(0);
+        name
+    }
+
+    /// The question `example.com. IN A`, which starts at offset 12 in a message.
+    fn example_question() -> Vec<u8> {
+        let mut question = wire_name(&["example", "com"]);
+        question.extend_from_slice(&[0, 1, 0, 1]);
+        question
+    }
+
+    /// An answer record with its name given as a pointer to the question's.
+    fn answer(rtype: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+        let mut record = vec![0xc0, 12];
+        record.extend_from_slice(&rtype.to_be_bytes());
+        record.extend_from_slice(&[0, 1]);
+        record.extend_from_slice(&ttl.to_be_bytes());
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    /// The answer to `example.com. IN A` from a recursive resolver, with an EDNS `OPT`
+    /// record after it. Returns the message and where the answer ends.
+    fn example_response() -> (Vec<u8>, usize) {
+        let mut msg = header(0x1234, 0x8180, [1, 1, 0, 1]);
+        msg.extend(example_question());
+        msg.extend(answer(TYPE_A, 300, &[93, 184, 216, 34]));
+        let answer_end = msg.len();
+        msg.extend_from_slice(&[0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, 0]);
+        (msg, answer_end)
+    }
+
+    #[test]
+    fn a_query_is_parsed() {
+        let mut msg = header(0xabcd, 0x0100, [1, 0, 0, 0]);
+        msg.extend(example_question());
+
+        let query = parse_dns(&msg).unwrap();
+        assert_eq!(query.id, 0xabcd);
+        assert!(!query.response && query.recursion_desired);
+        assert_eq!(
+            query.questions,
+            [DnsQuestion {
+                name: "example.com.".to_string(),
+                qtype: TYPE_A,
+                class: 1,
+            }]
+        );
+        assert!(query.answers.is_empty());
+        assert_eq!(
+            query.to_string(),
+            "query id=43981 QUERY NOERROR flags=rd; question example.com. IN A; \
+             0 authority, 0 additional"
+        );
+    }
+
+    #[test]
+    fn a_response_is_parsed() {
+        let (msg, _) = example_response();
+
+        let response = parse_dns(&msg).unwrap();
+        assert!(response.response && response.recursion_available);
+        assert_eq!(response.answers[0].name, "example.com.");
+        assert_eq!(
+            response.answers[0].data,
+            RecordData::A(Ipv4Addr::new(93, 184, 216, 34))
+        );
+        assert_eq!(response.additional_count, 1);
+        assert_eq!(
+            response.to_string(),
+            "response id=4660 QUERY NOERROR flags=qr,rd,ra; question example.com. IN A; \
+             answer example.com. 300 IN A 93.184.216.34; 0 authority, 1 additional"
+        );
+    }
+
+    #[test]
+    fn compressed_names_are_followed_inside_record_data() {
+        let mut msg = header(1, 0x8180, [1, 2, 0, 0]);
+        msg.extend(example_question());
+        // www.example.com., with the suffix a pointer to the question's name.
+        let mut cname = vec![3];
+        cname.extend_from_slice(b"www");
+        cname.extend_from_slice(&[0xc0, 12]);
+        msg.extend(answer(TYPE_CNAME, 60, &cname));
+        // mail.www.example.com., pointing at the CNAME target, which points on again.
+        let target = msg.len() - cname.len();
+        let mut mx = vec![0, 10, 4];
+        mx.extend_from_slice(b"mail");
+        mx.extend_from_slice(&[0xc0, target as u8]);
+        msg.extend(answer(TYPE_MX, 60, &mx));
+
+        let response = parse_dns(&msg).unwrap();
+        assert_eq!(
+            response.answers[0].data,
+            RecordData::Name("www.example.com.".to_string())
+        );
+        assert_eq!(
+            response.answers[1].data,
+            RecordData::Mx {
+                preference: 10,
+                exchange: "mail.www.example.com.".to_string(),
+            }
+        );
+    }
+
+    /// A query whose name is a chain of `jumps` pointers, each to the next, ending at
+    /// the root.
+    fn pointer_chain(jumps: usize) -> Vec<u8> {
+        let mut msg = header(1, 0, [1, 0, 0, 0]);
+        for _ in 0..jumps {
+            let next = msg.len() + 2;
+            msg.extend_from_slice(&[0xc0 | (next >> 8) as u8, next as u8]);
+        }
+        msg.extend_from_slice(&[0, 0, 1, 0, 1]);
+        msg
+    }
+
+    #[test]
+    fn pointers_are_followed_up_to_the_jump_limit() {
+        let msg = pointer_chain(MAX_POINTER_JUMPS);
+        assert_eq!(parse_dns(&msg).unwrap().questions[0].name, ".");
+        assert_eq!(parse_dns(&pointer_chain(MAX_POINTER_JUMPS + 1)), None);
+    }
+
+    #[test]
+    fn a_pointer_loop_is_malformed() {
+        let mut msg = header(1, 0, [1, 0, 0, 0]);
+        msg.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+        assert_eq!(parse_dns(&msg), None);
+
+        // Two names pointing at each other.
+        let mut msg = header(1, 0, [1, 0, 0, 0]);
+        msg.extend_from_slice(&[1, b'a', 0xc0, 16, 1, b'b', 0xc0, 12, 0, 1, 0, 1]);
+        assert_eq!(parse_dns(&msg), None);
+    }
+
+    #[test]
+    fn a_pointer_outside_the_message_is_malformed() {
+        let mut msg = header(1, 0, [1, 0, 0, 0]);
+        msg.extend_from_slice(&[0xc0, 200, 0, 1, 0, 1]);
+        assert_eq!(parse_dns(&msg), None);
+    }
+
+    #[test]
+    fn names_are_limited_to_255_bytes() {
+        let long = "x".repeat(63);
+        let query = |last: &str| {
+            let mut msg = header(1, 0, [1, 0, 0, 0]);
+            msg.extend(wire_name(&[&long, &long, &long, last]));
+            msg.extend_from_slice(&[0, 1, 0, 1]);
+            parse_dns(&msg)
+        };
+        // Three 64-byte labels, one of 62 and the root make 255 bytes.
+        assert!(query(&"y".repeat(61)).is_some());
+        assert_eq!(query(&"y".repeat(62)), None);
+    }
+
+    #[test]
+    fn truncated_messages_are_malformed() {
+        let (msg, answer_end) = example_response();
+        for len in 0..answer_end {
+            assert_eq!(parse_dns(&msg[..len]), None, "cut to {} bytes", len);
+        }
+        // The additional section is only counted, so cutting it short goes unnoticed.
+        assert!(parse_dns(&msg[..answer_end]).is_some());
+    }
+
+    #[test]
+    fn record_data_must_match_its_length() {
+        let response = |record: Vec<u8>| {
+            let mut msg = header(1, 0x8180, [1, 1, 0, 0]);
+            msg.extend(example_question());
+            msg.extend(record);
+            parse_dns(&msg)
+        };
+        // An address one byte too long.
+        assert_eq!(response(answer(TYPE_A, 60, &[192, 0, 2, 1, 0])), None);
+        // A name shorter than the length says, and one running past it.
+        let mut name = wire_name(&["www"]);
+        name.push(0);
+        assert_eq!(response(answer(TYPE_CNAME, 60, &name)), None);
+        let mut record = answer(TYPE_CNAME, 60, &wire_name(&["www"]));
+        let len_at = record.len() - 7;
+        record[len_at + 1] = 3;
+        assert_eq!(response(record), None);
+        // A length past the end of the message.
+        let mut record = answer(TYPE_TXT, 60, b"\x02hi");
+        let len_at = record.len() - 5;
+        record[len_at + 1] = 9;
+        assert_eq!(response(record), None);
+        // MX data with nothing after the preference.
+        assert_eq!(response(answer(TYPE_MX, 60, &[0, 10])), None);
+        // While data that fills its length exactly is fine.
+        assert!(response(answer(TYPE_TXT, 60, b"\x02hi\x00")).is_some());
+    }
+}
@@ -0,0 +1,424 @@
+// DTLS record headers (RFC 6347, RFC 9147), as seen in the handshakes of VPNs, WebRTC
+// and CoAP over UDP. Nothing is decrypted.
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+/// The length of a DTLS record header: type, version, epoch, sequence number and length.
+const HEADER_LEN: usize = 13;
+
+/// The length of a DTLS handshake message header, which a plaintext handshake record
+/// starts with.
+const HANDSHAKE_HEADER_LEN: usize = 12;
+
+const CONTENT_ALERT: u8 = 21;
+const CONTENT_HANDSHAKE: u8 = 22;
+
+/// One DTLS record: what its header says, and for the plaintext records of the first
+/// epoch, which handshake message or alert it carries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DtlsRecord {
+    /// The content type, such as 22 for a handshake record.
+    #[serde(rename = "type", serialize_with = "content_type_name")]
+    pub content_type: u8,
+    /// The version on the record, such as `0xfefd` for DTLS 1.2. DTLS 1.3 records that
+    /// use this header still carry the DTLS 1.2 version.
+    #[serde(serialize_with = "version_name")]
+    pub version: u16,
+    /// Counts the cipher state changes; records of epoch 0 are unencrypted.
+    pub epoch: u16,
+    /// The 48-bit record sequence number within the epoch.
+    pub sequence: u64,
+    /// The length of the record's fragment, after the header.
+    pub length: u16,
+    /// The type of the handshake message a plaintext handshake record starts with, such
+    /// as 1 for ClientHello.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "handshake_type_name"
+    )]
+    pub handshake_type: Option<u8>,
+    /// The alert a plaintext alert record carries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<DtlsAlert>,
+}
+
+/// A DTLS (or TLS) alert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DtlsAlert {
+    /// 1 for a warning, 2 for a fatal alert.
+    #[serde(serialize_with = "alert_level_name")]
+    pub level: u8,
+    /// What went wrong, such as 40 for a handshake failure.
+    #[serde(serialize_with = "alert_description_name")]
+    pub description: u8,
+}
+
+/// Parses the DTLS record at the start of `data`, or returns `None` if it doesn't start
+/// with one: the header must have a known content type and a DTLS version, and the
+/// fragment must fit in `data`.
+///
+/// Only the plaintext record header is understood. The encrypted records of DTLS 1.3,
+/// with its shortened header, aren't, nor is the content of anything encrypted; a
+/// handshake record past epoch 0 shows its header alone.
+pub fn parse_dtls_record(data: &[u8]) -> Option<DtlsRecord> {
+    let header = data.get(..HEADER_LEN)?;
+    let content_type = header[0];
+    let version = u16::from_be_bytes([header[1], header[2]]);
+    if !matches!(content_type, 20..=26) || !matches!(version, 0xfeff | 0xfefd | 0xfefc) {
+        return None;
+    }
+    let epoch = u16::from_be_bytes([header[3], header[4]]);
+    let sequence = header[5..11]
+        .iter()
+        .fold(0, |sequence, &byte| (sequence << 8) | u64::from(byte));
+    let length = u16::from_be_bytes([header[11], header[12]]);
+    let fragment = data.get(HEADER_LEN..HEADER_LEN + usize::from(length))?;
+
+    let plaintext = epoch == 0;
+    let handshake_type =
+        (plaintext && content_type == CONTENT_HANDSHAKE && fragment.len() >= HANDSHAKE_HEADER_LEN)
+            .then(|| fragment[0]);
+    let alert = match fragment {
+        [level, description] if plaintext && content_type == CONTENT_ALERT => Some(DtlsAlert {
+            level: *level,
+            description: *description,
+        }),
+        _ => None,
+    };
+    Some(DtlsRecord {
+        content_type,
+        version,
+        epoch,
+        sequence,
+        length,
+        handshake_type,
+        alert,
+    })
+}
+
+/// Parses every DTLS record in a datagram, which may carry several, such as a server's
+/// whole first flight. Returns `None` if the datagram isn't made of DTLS records from
+/// start to end.
+pub fn parse_dtls(data: &[u8]) -> Option<Vec<DtlsRecord>> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let record = parse_dtls_record(rest)?;
+        rest = &rest[HEADER_LEN + usize::from(record.length)..];
+        records.push(record);
+    }
+    (!records.is_empty()).then_some(records)
+}
+
+/// `handshake DTLS1.2 epoch=0 seq=1 len=120 client_hello`, with the handshake message or
+/// alert at the end when the record is plaintext.
+impl fmt::Display for DtlsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} epoch={} seq={} len={}",
+            ContentTypeName(self.content_type),
+            VersionName(self.version),
+            self.epoch,
+            self.sequence,
+            self.length
+        )?;
+        if let Some(handshake_type) = self.handshake_type {
+            write!(f, " {}", HandshakeTypeName(handshake_type))?;
+        }
+        if let Some(alert) = self.alert {
+            write!(
+                f,
+                " {} {}",
+                AlertLevelName(alert.level),
+                AlertDescriptionName(alert.description)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct ContentTypeName(u8);
+
+impl fmt::Display for ContentTypeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            20 => "change_cipher_spec",
+            CONTENT_ALERT => "alert",
+            CONTENT_HANDSHAKE => "handshake",
+            23 => "application_data",
+            24 => "heartbeat",
+            25 => "tls12_cid",
+            26 => "ack",
+            other => return write!(f, "type{}", other),
+        };
+        f.write_str(name)
+    }
+}
+
+struct VersionName(u16);
+
+impl fmt::Display for VersionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0xfeff => f.write_str("DTLS1.0"),
+            0xfefd => f.write_str("DTLS1.2"),
+            0xfefc => f.write_str("DTLS1.3"),
+            other => write!(f, "0x{:04x}", other),
+        }
+    }
+}
+
+struct HandshakeTypeName(u8);
+
+impl fmt::Display for HandshakeTypeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            0 => "hello_request",
+            1 => "client_hello",
+            2 => "server_hello",
+            3 => "hello_verify_request",
+            4 => "new_session_ticket",
+            5 => "end_of_early_data",
+            8 => "encrypted_extensions",
+            11 => "certificate",
+            12 => "server_key_exchange",
+            13 => "certificate_request",
+            14 => "server_hello_done",
+            15 => "certificate_verify",
+            16 => "client_key_exchange",
+            20 => "finished",
+            24 => "key_update",
+            254 => "message_hash",
+            other => return write!(f, "handshake{}", other),
+        };
+        f.write_str(name)
+    }
+}
+
+struct AlertLevelName(u8);
+
+impl fmt::Display for AlertLevelName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => f.write_str("warning"),
+            2 => f.write_str("fatal"),
+            other => write!(f, "level{}", other),
+        }
+    }
+}
+
+struct AlertDescriptionName(u8);
+
+impl fmt::Display for AlertDescriptionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            0 => "close_notify",
+            10 => "unexpected_message",
+            20 => "bad_record_mac",
+            22 => "record_overflow",
+            40 => "handshake_failure",
+            42 => "bad_certificate",
+            43 => "unsupported_certificate",
+            44 => "certificate_revoked",
+            45 => "certificate_expired",
+            46 => "certificate_unknown",
+            47 => "illegal_parameter",
+            48 => "unknown_ca",
+            49 => "access_denied",
+            50 => "decode_error",
+            51 => "decrypt_error",
+            70 => "protocol_version",
+            71 => "insufficient_security",
+            80 => "internal_error",
+            86 => "inappropriate_fallback",
+            90 => "user_canceled",
+            100 => "no_renegotiation",
+            109 => "missing_extension",
+            110 => "unsupported_extension",
+            112 => "unrecognized_name",
+            116 => "no_application_protocol",
+            other => return write!(f, "alert{}", other),
+        };
+        f.write_str(name)
+    }
+}
+
+// JSON records use the same names as the text log.
+
+fn content_type_name<S: Serializer>(content_type: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&ContentTypeName(*content_type))
+}
+
+fn version_name<S: Serializer>(version: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&VersionName(*version))
+}
+
+fn handshake_type_name<S: Serializer>(
+    handshake_type: &Option<u8>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match handshake_type {
+        Some(handshake_type) => serializer.collect_str(&HandshakeTypeName(*handshake_type)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn alert_level_name<S: Serializer>(level: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&AlertLevelName(*level))
+}
+
+fn alert_description_name<S: Serializer>(
+    description: &u8,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&AlertDescriptionName(*description))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DTLS_1_2: u16 = 0xfefd;
+
+    fn record(content_type: u8, epoch: u16, sequence: u64, fragment: &[u8]) -> Vec<u8> {
+        let mut record = vec![content_type];
+        record.extend_from_slice(&DTLS_1_2.to_be_bytes());
+        record.extend_from_slice(&epoch.to_be_bytes());
+        record.extend_from_slice(&sequence.to_be_bytes()[2..]);
+        record.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+        record.extend_from_slice(fragment);
+        record
+    }
+
+    /// A whole handshake message of type `msg_type` with a `body_len`-byte body, as the
+    /// fragment of a handshake record.
+    fn handshake(msg_type: u8, message_seq: u16, body_len: usize) -> Vec<u8> {
+        let len = (body_len as u32).to_be_bytes();
+        let mut message = vec![msg_type, len[1], len[2], len[3]];
+        message.extend_from_slice(&message_seq.to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, len[1], len[2], len[3]]);
+        message.resize(HANDSHAKE_HEADER_LEN + body_len, 0xab);
+        message
+    }
+
+    #[test]
+    fn a_client_hello_is_decoded() {
+        let data = record(CONTENT_HANDSHAKE, 0, 0, &handshake(1, 0, 100));
+
+        let records = parse_dtls(&data).unwrap();
+        assert_eq!(
+            records,
+            [DtlsRecord {
+                content_type: CONTENT_HANDSHAKE,
+                version: DTLS_1_2,
+                epoch: 0,
+                sequence: 0,
+                length: 112,
+                handshake_type: Some(1),
+                alert: None,
+            }]
+        );
+        assert_eq!(
+            records[0].to_string(),
+            "handshake DTLS1.2 epoch=0 seq=0 len=112 client_hello"
+        );
+    }
+
+    #[test]
+    fn every_record_of_a_flight_is_decoded() {
+        let mut data = record(CONTENT_HANDSHAKE, 0, 1, &handshake(2, 1, 70));
+        data.extend(record(CONTENT_HANDSHAKE, 0, 2, &handshake(11, 2, 300)));
+        data.extend(record(
+            CONTENT_HANDSHAKE,
+            0,
+            0x0102_0304_0506,
+            &handshake(14, 3, 0),
+        ));
+
+        let records = parse_dtls(&data).unwrap();
+        let summary: Vec<(u64, Option<u8>)> = records
+            .iter()
+            .map(|record| (record.sequence, record.handshake_type))
+            .collect();
+        assert_eq!(
+            summary,
+            [(1, Some(2)), (2, Some(11)), (0x0102_0304_0506, Some(14))]
+        );
+        assert_eq!(
+            records[2].to_string(),
+            "handshake DTLS1.2 epoch=0 seq=1108152157446 len=12 server_hello_done"
+        );
+    }
+
+    #[test]
+    fn a_plaintext_alert_is_decoded() {
+        let records = parse_dtls(&record(CONTENT_ALERT, 0, 5, &[2, 40])).unwrap();
+        assert_eq!(
+            records[0].alert,
+            Some(DtlsAlert {
+                level: 2,
+                description: 40,
+            })
+        );
+        assert_eq!(
+            records[0].to_string(),
+            "alert DTLS1.2 epoch=0 seq=5 len=2 fatal handshake_failure"
+        );
+    }
+
+    #[test]
+    fn encrypted_records_show_their_header_alone() {
+        // A Finished message after ChangeCipherSpec, and an encrypted alert.
+        let mut data = record(20, 0, 3, &[1]);
+        data.extend(record(CONTENT_HANDSHAKE, 1, 0, &handshake(20, 4, 12)));
+        data.extend(record(CONTENT_ALERT, 1, 1, &[1, 0]));
+
+        let records = parse_dtls(&data).unwrap();
+        assert_eq!(records[1].epoch, 1);
+        assert_eq!(records[1].handshake_type, None);
+        assert_eq!(records[2].alert, None);
+        assert_eq!(
+            records[1].to_string(),
+            "handshake DTLS1.2 epoch=1 seq=0 len=24"
+        );
+    }
+
+    #[test]
+    fn a_fragment_too_short_for_a_handshake_header_has_no_type() {
+        let records = parse_dtls(&record(CONTENT_HANDSHAKE, 0, 0, &[1, 0, 0])).unwrap();
+        assert_eq!(records[0].handshake_type, None);
+    }
+
+    #[test]
+    fn records_running_past_the_datagram_are_malformed() {
+        let data = record(CONTENT_HANDSHAKE, 0, 0, &handshake(1, 0, 100));
+        for len in 0..data.len() {
+            assert_eq!(parse_dtls(&data[..len]), None, "cut to {} bytes", len);
+        }
+
+        // A first record that's whole, with a second cut short.
+        let mut flight = record(CONTENT_ALERT, 0, 0, &[1, 0]);
+        flight.extend(&data[..20]);
+        assert_eq!(parse_dtls(&flight), None);
+
+        // A length bigger than what follows.
+        let mut data = record(CONTENT_ALERT, 0, 0, &[2, 40]);
+        data[12] = 0xff;
+        assert_eq!(parse_dtls_record(&data), None);
+    }
+
+    #[test]
+    fn other_versions_and_content_types_arent_dtls() {
+        let mut tls = record(CONTENT_HANDSHAKE, 0, 0, &handshake(1, 0, 10));
+        tls[1..3].copy_from_slice(&0x0303_u16.to_be_bytes());
+        assert_eq!(parse_dtls(&tls), None);
+
+        let mut unknown = record(CONTENT_ALERT, 0, 0, &[2, 40]);
+        unknown[0] = 19;
+        assert_eq!(parse_dtls(&unknown), None);
+        assert_eq!(parse_dtls(&[]), None);
+    }
+}
@@ -0,0 +1,156 @@
+// NTP packet headers (RFC 5905), as sent between clients and time servers on UDP port
+// 123. Extension fields and MACs after the header are ignored.
+
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Serialize, Serializer};
+
+/// The length of the NTP header, up to the end of the transmit timestamp.
+const HEADER_LEN: usize = 48;
+
+/// Seconds from the start of the NTP era, 1900-01-01, to the Unix epoch.
+const UNIX_EPOCH_SECONDS: i64 = 2_208_988_800;
+
+/// Seconds in an NTP era, after which the 32-bit seconds field wraps.
+const ERA_SECONDS: i64 = 1 << 32;
+
+/// The fields of an NTP header that tell which time a server handed out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NtpPacket {
+    /// The leap indicator: 1 or 2 when a leap second is due at the end of the day, 3
+    /// when the server's clock isn't synchronised.
+    #[serde(serialize_with = "leap_name")]
+    pub leap: u8,
+    /// The NTP version, 4 for current clients and servers.
+    pub version: u8,
+    /// Which side sent the packet, such as 3 for a client or 4 for a server.
+    #[serde(serialize_with = "mode_name")]
+    pub mode: u8,
+    /// How far the server is from a reference clock, 1 for a server attached to one.
+    /// 0 is unspecified, as in a client's request or a kiss-o'-death reply.
+    pub stratum: u8,
+    /// When the packet left the sender, in NTP's 64-bit format: seconds since 1900 and
+    /// a binary fraction of a second. 0 if the sender didn't say.
+    #[serde(serialize_with = "timestamp_text")]
+    pub transmit: u64,
+}
+
+impl NtpPacket {
+    /// The transmit timestamp as a time, or `None` if it's 0. A timestamp with the top
+    /// bit clear is taken to be in the era that starts in 2036, as RFC 4330 suggests,
+    /// since none of the first era's is that early.
+    pub fn transmit_time(&self) -> Option<DateTime<Utc>> {
+        ntp_time(self.transmit)
+    }
+}
+
+/// Parses the header of an NTP packet, or returns `None` if `data` is shorter than one
+/// or isn't one: the version must be 1 to 4, and the mode one of the modes that use
+/// this header, 1 to 5. Control and private messages, modes 6 and 7, have headers of
+/// their own and aren't parsed.
+pub fn parse_ntp(data: &[u8]) -> Option<NtpPacket> {
+    let header = data.get(..HEADER_LEN)?;
+    let leap = header[0] >> 6;
+    let version = (header[0] >> 3) & 0x07;
+    let mode = header[0] & 0x07;
+    if !matches!(version, 1..=4) || !matches!(mode, 1..=5) {
+        return None;
+    }
+    let transmit = u64::from_be_bytes(header[40..48].try_into().ok()?);
+    Some(NtpPacket {
+        leap,
+        version,
+        mode,
+        stratum: header[1],
+        transmit,
+    })
+}
+
+/// `server v4 stratum=2 leap=none transmit=2024-06-01T12:00:00.123456789Z`.
+impl fmt::Display for NtpPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} v{} stratum={} leap={} transmit={}",
+            ModeName(self.mode),
+            self.version,
+            self.stratum,
+            LeapName(self.leap),
+            TimestampText(self.transmit)
+        )
+    }
+}
+
+fn ntp_time(timestamp: u64) -> Option<DateTime<Utc>> {
+    if timestamp == 0 {
+        return None;
+    }
+    let mut seconds = (timestamp >> 32) as i64;
+    if seconds < 1 << 31 {
+        seconds += ERA_SECONDS;
+    }
+    let nanos = ((timestamp & 0xffff_ffff) * 1_000_000_000) >> 32;
+    DateTime::from_timestamp(seconds - UNIX_EPOCH_SECONDS, nanos as u32)
+}
+
+struct LeapName(u8);
+
+impl fmt::Display for LeapName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            0 => "none",
+            1 => "insert_second",
+            2 => "delete_second",
+            _ => "unsynchronised",
+        };
+        f.write_str(name)
+    }
+}
+
+struct ModeName(u8);
+
+impl fmt::Display for ModeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            1 => "symmetric_active",
+            2 => "symmetric_passive",
+            3 => "client",
+            4 => "server",
+            5 => "broadcast",
+            6 => "control",
+            7 => "private",
+            other => return write!(f, "mode{}", other),
+        };
+        f.write_str(name)
+    }
+}
+
+/// The timestamp in RFC 3339 with nanoseconds, or `0` if it's unset.
+struct TimestampText(u64);
+
+impl fmt::Display for TimestampText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match ntp_time(self.0) {
+            Some(time) => f.write_str(&time.to_rfc3339_opts(SecondsFormat::Nanos, true)),
+            None => f.write_str("0"),
+        }
+    }
+}
+
+// JSON records use the same names as the text log, and an unset timestamp is null.
+
+fn leap_name<S: Serializer>(leap: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&LeapName(*leap))
+}
+
+fn mode_name<S: Serializer>(mode: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&ModeName(*mode))
+}
+
+fn timestamp_text<S: Serializer>(timestamp: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    match *timestamp {
+        0 => serializer.serialize_none(),
+        timestamp => serializer.collect_str(&TimestampText(timestamp)),
+    }
+}
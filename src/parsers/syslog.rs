@@ -0,0 +1,403 @@
+// Syslog messages, as sent by network devices and servers to UDP port 514.
+
+use serde::Serialize;
+
+/// A syslog message, in either the modern (RFC 5424) or the traditional BSD
+/// (RFC 3164) format. Fields the sender left out, or that the format doesn't have, are
+/// `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyslogMessage {
+    /// The facility code, 0 to 23. See [`facility_name`](Self::facility_name).
+    pub facility: u8,
+    /// The severity code, 0 (emergency) to 7 (debug). See
+    /// [`severity_name`](Self::severity_name).
+    pub severity: u8,
+    /// The protocol version: 1 for RFC 5424, `None` for RFC 3164.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u8>,
+    /// The timestamp exactly as the sender wrote it. RFC 3164 timestamps have no year
+    /// or timezone, so they are not converted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// The program that sent the message (the "tag" in RFC 3164).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_name: Option<String>,
+    /// The sending process, usually its PID.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proc_id: Option<String>,
+    /// The message type, RFC 5424 only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<String>,
+    /// The structured data elements (`[id key="value" ...]`) unchanged, RFC 5424 only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structured_data: Option<String>,
+    /// The free-form message text, without a trailing newline.
+    pub message: String,
+}
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "security", "console", "clock", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+impl SyslogMessage {
+    /// The facility's conventional name, such as `daemon` or `local0`.
+    pub fn facility_name(&self) -> &'static str {
+        FACILITIES
+            .get(usize::from(self.facility))
+            .copied()
+            .unwrap_or("unknown")
+    }
+
+    /// The severity's conventional name, such as `err` or `info`.
+    pub fn severity_name(&self) -> &'static str {
+        SEVERITIES
+            .get(usize::from(self.severity))
+            .copied()
+            .unwrap_or("unknown")
+    }
+}
+
+/// The fields of a syslog message as `key=value` pairs, ending with the quoted
+/// message: `facility=auth severity=info host=web1 app=sshd pid=812 msg="Accepted key"`.
+/// Values containing spaces or quotes, such as RFC 3164 timestamps, are quoted too.
+impl std::fmt::Display for SyslogMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "facility={} severity={}",
+            self.facility_name(),
+            self.severity_name()
+        )?;
+        let fields = [
+            ("time", &self.timestamp),
+            ("host", &self.hostname),
+            ("app", &self.app_name),
+            ("pid", &self.proc_id),
+            ("msgid", &self.msg_id),
+            ("sd", &self.structured_data),
+        ];
+        for (key, value) in fields {
+            match value {
+                Some(value) if value.contains([' ', '"']) => write!(f, " {}={:?}", key, value)?,
+                Some(value) => write!(f, " {}={}", key, value)?,
+                None => {}
+            }
+        }
+        write!(f, " msg={:?}", self.message)
+    }
+}
+
+/// Parses a syslog datagram, or returns `None` if it doesn't start with a valid
+/// priority (`<PRI>`).
+///
+/// A version number after the priority marks an RFC 5424 message, whose header fields
+/// are all required (with `-` for a missing one). Anything else is read as RFC 3164,
+/// which is loosely defined: the timestamp and tag are picked out when they look right,
+/// and whatever can't be recognised is left in the message. Invalid UTF-8 is replaced,
+/// as in text logs.
+pub fn parse_syslog(data: &[u8]) -> Option<SyslogMessage> {
+    let text = String::from_utf8_lossy(data);
+    let rest = text.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let priority = &rest[..end];
+    if priority.is_empty() || priority.len() > 3 || !priority.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let priority: u8 = priority.parse().ok()?;
+    if priority > 191 {
+        return None;
+    }
+    let mut message = SyslogMessage {
+        facility: priority / 8,
+        severity: priority % 8,
+        version: None,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+        proc_id: None,
+        msg_id: None,
+        structured_data: None,
+        message: String::new(),
+    };
+
+    let rest = &rest[end + 1..];
+    // Something that starts like RFC 5424 but doesn't parse as it is read as RFC 3164.
+    let rfc5424 = match rest.split_once(' ') {
+        Some((version, header)) if !version.is_empty() && version.len() <= 2 => {
+            version.parse().ok().and_then(|version| {
+                let mut candidate = message.clone();
+                parse_rfc5424(&mut candidate, version, header).map(|()| candidate)
+            })
+        }
+        _ => None,
+    };
+    match rfc5424 {
+        Some(parsed) => message = parsed,
+        None => parse_rfc3164(&mut message, rest),
+    }
+    message.message = message
+        .message
+        .trim_end_matches(['\n', '\r', '\0'])
+        .to_string();
+    Some(message)
+}
+
+/// Reads the fields after `<PRI>VERSION ` in an RFC 5424 message.
+fn parse_rfc5424(message: &mut SyslogMessage, version: u8, header: &str) -> Option<()> {
+    // TIMESTAMP HOSTNAME APP-NAME PROCID MSGID, each `-` when absent.
+    let mut fields = header.splitn(6, ' ');
+    let mut field = || -> Option<Option<String>> {
+        let value = fields.next()?;
+        Some((value != "-").then(|| value.to_string()))
+    };
+    message.version = Some(version);
+    message.timestamp = field()?;
+    message.hostname = field()?;
+    message.app_name = field()?;
+    message.proc_id = field()?;
+    message.msg_id = field()?;
+    let rest = fields.next().unwrap_or_default();
+
+    let (structured_data, text) = split_structured_data(rest)?;
+    message.structured_data = structured_data.map(str::to_string);
+    // The message may start with a byte order mark to say it's UTF-8.
+    message.message = text.trim_start_matches('\u{feff}').to_string();
+    Some(())
+}
+
+/// Splits RFC 5424 structured data (`-` or one or more `[...]` elements) from the
+/// message after it. Inside an element a quoted value may contain `]`, and `\` escapes
+/// the next character.
+fn split_structured_data(rest: &str) -> Option<(Option<&str>, &str)> {
+    if let Some(text) = rest.strip_prefix('-') {
+        return Some((None, text.strip_prefix(' ').unwrap_or(text)));
+    }
+    if !rest.starts_with('[') {
+        return None;
+    }
+    let mut in_element = false;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut end = None;
+    for (i, c) in rest.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' if in_element => in_quotes = !in_quotes,
+            ']' if in_element && !in_quotes => {
+                in_element = false;
+                end = Some(i + 1);
+            }
+            '[' if !in_element => in_element = true,
+            // Anything other than another element after one ends the structured data.
+            _ if !in_element => break,
+            _ => {}
+        }
+    }
+    let end = end?;
+    let text = &rest[end..];
+    Some((Some(&rest[..end]), text.strip_prefix(' ').unwrap_or(text)))
+}
+
+/// Reads what follows `<PRI>` in an RFC 3164 message: `Mmm dd hh:mm:ss HOSTNAME TAG: MSG`,
+/// where senders often leave out the hostname and sometimes everything but the message.
+fn parse_rfc3164(message: &mut SyslogMessage, rest: &str) {
+    let Some(after_time) = rfc3164_timestamp(rest) else {
+        message.message = rest.to_string();
+        return;
+    };
+    message.timestamp = Some(rest[..after_time].to_string());
+    let mut rest = rest[after_time..].trim_start_matches(' ');
+
+    // The hostname is the next word, unless that word is already the tag.
+    if let Some((word, after)) = rest.split_once(' ')
+        && !word.ends_with(':')
+        && !word.contains('[')
+    {
+        message.hostname = Some(word.to_string());
+        rest = after;
+    }
+
+    // TAG[PID]: or TAG: names the program. The tag is alphanumeric, plus a few
+    // punctuation characters programs commonly use.
+    let tag_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c)))
+        .unwrap_or(rest.len());
+    let (tag, after_tag) = rest.split_at(tag_len);
+    let (proc_id, after_pid) = match after_tag.strip_prefix('[').and_then(|s| s.split_once(']')) {
+        Some((pid, after)) => (Some(pid), after),
+        None => (None, after_tag),
+    };
+    match after_pid.strip_prefix(':') {
+        Some(text) if !tag.is_empty() => {
+            message.app_name = Some(tag.to_string());
+            message.proc_id = proc_id.map(str::to_string);
+            message.message = text.strip_prefix(' ').unwrap_or(text).to_string();
+        }
+        _ => message.message = rest.to_string(),
+    }
+}
+
+/// If `s` starts with an RFC 3164 timestamp such as `Oct  9 22:14:15`, returns its
+/// length.
+fn rfc3164_timestamp(s: &str) -> Option<usize> {
+    let stamp = s.get(..15).filter(|stamp| stamp.is_ascii())?;
+    let bytes = stamp.as_bytes();
+    let month_ok = MONTHS.contains(&&stamp[..3]);
+    let day_ok = bytes[3] == b' '
+        && (bytes[4] == b' ' || bytes[4].is_ascii_digit())
+        && bytes[5].is_ascii_digit();
+    let time_ok = bytes[6] == b' '
+        && [7, 8, 10, 11, 13, 14]
+            .iter()
+            .all(|&i| bytes[i].is_ascii_digit())
+        && bytes[9] == b':'
+        && bytes[12] == b':';
+    (month_ok && day_ok && time_ok).then_some(15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc5424_without_structured_data() {
+        let message = parse_syslog(
+            b"<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - 'su root' failed\n",
+        )
+        .unwrap();
+        assert_eq!(
+            (message.facility_name(), message.severity_name()),
+            ("auth", "crit")
+        );
+        assert_eq!(message.version, Some(1));
+        assert_eq!(
+            message.timestamp.as_deref(),
+            Some("2003-10-11T22:14:15.003Z")
+        );
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app_name.as_deref(), Some("su"));
+        assert_eq!(message.proc_id, None);
+        assert_eq!(message.msg_id.as_deref(), Some("ID47"));
+        assert_eq!(message.structured_data, None);
+        assert_eq!(message.message, "'su root' failed");
+    }
+
+    #[test]
+    fn rfc5424_with_structured_data() {
+        let message = parse_syslog(
+            br#"<165>1 2003-10-11T22:14:15Z host app 42 - [exampleSDID@32473 iut="3" note="a \"b\" ]c"][origin ip="192.0.2.1"] An event"#,
+        )
+        .unwrap();
+        assert_eq!(message.proc_id.as_deref(), Some("42"));
+        assert_eq!(
+            message.structured_data.as_deref(),
+            Some(r#"[exampleSDID@32473 iut="3" note="a \"b\" ]c"][origin ip="192.0.2.1"]"#)
+        );
+        assert_eq!(message.message, "An event");
+
+        // With nothing after the elements, and a byte order mark before the text.
+        let message = parse_syslog(b"<14>1 - - - - - [id k=\"v\\]\"]").unwrap();
+        assert_eq!(message.structured_data.as_deref(), Some("[id k=\"v\\]\"]"));
+        assert_eq!(message.message, "");
+        let message = parse_syslog("<14>1 - - - - - - \u{feff}text".as_bytes()).unwrap();
+        assert_eq!(message.message, "text");
+    }
+
+    #[test]
+    fn unterminated_structured_data_is_read_as_rfc3164() {
+        let message = parse_syslog(br#"<14>1 - host app - - [id k="v] text"#).unwrap();
+        assert_eq!(message.version, None);
+        assert_eq!(message.message, r#"1 - host app - - [id k="v] text"#);
+    }
+
+    #[test]
+    fn rfc3164_with_a_hostname_and_pid() {
+        let message =
+            parse_syslog(b"<86>Oct  9 22:14:15 web1 sshd[812]: Accepted publickey\r\n").unwrap();
+        assert_eq!(message.version, None);
+        assert_eq!(message.timestamp.as_deref(), Some("Oct  9 22:14:15"));
+        assert_eq!(message.hostname.as_deref(), Some("web1"));
+        assert_eq!(message.app_name.as_deref(), Some("sshd"));
+        assert_eq!(message.proc_id.as_deref(), Some("812"));
+        assert_eq!(message.message, "Accepted publickey");
+        assert_eq!(
+            message.to_string(),
+            "facility=authpriv severity=info time=\"Oct  9 22:14:15\" host=web1 app=sshd \
+             pid=812 msg=\"Accepted publickey\""
+        );
+    }
+
+    #[test]
+    fn rfc3164_without_a_hostname() {
+        let message = parse_syslog(b"<13>Feb 28 01:02:03 cron: job done").unwrap();
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name.as_deref(), Some("cron"));
+        assert_eq!(message.proc_id, None);
+        assert_eq!(message.message, "job done");
+
+        let message = parse_syslog(b"<13>Feb 28 01:02:03 kernel[0]: oops").unwrap();
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.proc_id.as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn rfc3164_keeps_what_it_cant_recognise_in_the_message() {
+        let message = parse_syslog(b"<13>just some text").unwrap();
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.message, "just some text");
+
+        let message = parse_syslog(b"<13>Feb 28 01:02:03 host no tag here").unwrap();
+        assert_eq!(message.hostname.as_deref(), Some("host"));
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.message, "no tag here");
+    }
+
+    #[test]
+    fn an_invalid_priority_isnt_syslog() {
+        assert!(parse_syslog(b"<191>x").is_some());
+        for data in [
+            &b"<192>1 - - - - - - too high"[..],
+            b"<999>x",
+            b"<34 no closing bracket",
+            b"<>x",
+            b"<1a>x",
+            b"<0034>x",
+            b"no priority",
+            b"",
+        ] {
+            assert_eq!(
+                parse_syslog(data),
+                None,
+                "{:?}",
+                String::from_utf8_lossy(data)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let message = parse_syslog(b"<13>Feb 28 01:02:03 app: caf\xe9 \xff\xfe").unwrap();
+        assert_eq!(message.app_name.as_deref(), Some("app"));
+        assert_eq!(message.message, "caf\u{fffd} \u{fffd}\u{fffd}");
+        // Even in the priority and the timestamp, without panicking on char boundaries.
+        assert_eq!(parse_syslog(b"<1\xff>x"), None);
+        let message = parse_syslog(b"<13>Feb 28 01:02:\xe93 app: x").unwrap();
+        assert_eq!(message.timestamp, None);
+    }
+}
@@ -0,0 +1,286 @@
+//! Writing captured datagrams in the classic libpcap file format, so a capture can be
+//! opened directly in Wireshark or tcpdump.
+//!
+//! A UDP socket only hands us the payload, not the packet that carried it, but pcap
+//! readers expect whole packets. Each record is therefore written with link type
+//! `LINKTYPE_RAW` (raw IP, no Ethernet header) and a synthetic IPv4 or IPv6 header plus
+//! UDP header built from the source address and the listener's local address. This
+//! lets Wireshark show the real ports and hand the payload to the right dissector
+//! (DNS, syslog, ...). Fields the socket can't tell us, such as the IP ID, are filled
+//! with fixed values, as are the TTL and ToS unless they were recorded (see
+//! [`Config::ip_fields`](crate::Config::ip_fields)).
+
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+use chrono::{DateTime, TimeZone};
+
+use crate::ip_fields::IpFields;
+use crate::packet::Packet;
+
+/// Magic number at the start of every pcap file. Readers use its byte order to work out
+/// the endianness of the rest of the file; it also marks timestamps as microseconds.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+/// `LINKTYPE_RAW`: each record begins with an IPv4 or IPv6 header.
+const LINKTYPE_RAW: u32 = 101;
+/// Largest record we claim to capture. A UDP payload plus its headers always fits.
+const SNAPLEN: u32 = 65_535;
+/// IP protocol number for UDP.
+const IPPROTO_UDP: u8 = 17;
+/// TTL / hop limit written into the synthetic IP header when the real one isn't known.
+const SYNTHETIC_TTL: u8 = 64;
+
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+
+/// The 24-byte pcap global header that starts every capture file.
+pub fn global_header() -> [u8; 24] {
+    // All fields are written little-endian, matching the byte order of the magic.
+    let mut header = [0u8; 24];
+    header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&2u16.to_le_bytes()); // major version
+    header[6..8].copy_from_slice(&4u16.to_le_bytes()); // minor version
+    // Bytes 8..16 are the timezone offset and timestamp accuracy, both always zero.
+    header[16..20].copy_from_slice(&SNAPLEN.to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    header
+}
+
+/// Writes a pcap global header once, then one record per packet.
+///
+/// Each record is assembled in memory and handed to the underlying writer in a single
+/// `write_all`, so a writer that rotates between writes never splits a record.
+pub struct PcapWriter<W: Write> {
+    inner: W,
+    /// The listener's own address, used as the destination in the synthetic headers.
+    local_addr: SocketAddr,
+    /// Reused buffer for assembling each record.
+    record: Vec<u8>,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the pcap global header to `inner` and returns a writer ready for packets.
+    pub fn new(mut inner: W, local_addr: SocketAddr) -> io::Result<Self> {
+        inner.write_all(&global_header())?;
+        Ok(PcapWriter::headerless(inner, local_addr))
+    }
+
+    /// Returns a writer that writes packet records only, for a destination that already
+    /// has (or writes its own) [`global_header`].
+    pub fn headerless(inner: W, local_addr: SocketAddr) -> Self {
+        PcapWriter {
+            inner,
+            local_addr,
+            record: Vec::new(),
+        }
+    }
+
+    /// Writes one datagram received from `src` at time `ts`.
+    pub fn write_packet<Tz: TimeZone>(
+        &mut self,
+        ts: &DateTime<Tz>,
+        src: SocketAddr,
+        data: &[u8],
+    ) -> io::Result<()> {
+        self.write_snapped(ts, src, data, data.len())
+    }
+
+    /// Writes one datagram of `original_len` payload bytes, of which only `data` was
+    /// kept, as a record whose captured length is shorter than its original length.
+    /// The headers give the full lengths, as in a capture taken with tcpdump's `-s`. The
+    /// UDP checksum can't be computed over bytes that weren't kept, so a cut datagram's
+    /// checksum is left at zero, which means "not computed".
+    pub fn write_snapped<Tz: TimeZone>(
+        &mut self,
+        ts: &DateTime<Tz>,
+        src: SocketAddr,
+        data: &[u8],
+        original_len: usize,
+    ) -> io::Result<()> {
+        self.write_datagram(
+            ts,
+            src,
+            self.local_addr,
+            data,
+            original_len,
+            IpFields::default(),
+        )
+    }
+
+    /// Writes a logged packet, with its real destination address, TTL and ToS in the IP
+    /// header if they were recorded, and sent to the port of the bind address it arrived
+    /// on.
+    pub(crate) fn write_logged(&mut self, pkt: &Packet) -> io::Result<()> {
+        self.write_datagram(
+            &pkt.received_at,
+            pkt.src,
+            pkt.bind.unwrap_or(self.local_addr),
+            &pkt.data,
+            pkt.original_len,
+            IpFields {
+                ttl: pkt.ttl,
+                tos: pkt.tos,
+                dst: pkt.dst_ip,
+            },
+        )
+    }
+
+    fn write_datagram<Tz: TimeZone>(
+        &mut self,
+        ts: &DateTime<Tz>,
+        src: SocketAddr,
+        local_addr: SocketAddr,
+        data: &[u8],
+        original_len: usize,
+        fields: IpFields,
+    ) -> io::Result<()> {
+        let original_len = original_len.max(data.len());
+        let ttl = fields.ttl.unwrap_or(SYNTHETIC_TTL);
+        let tos = fields.tos.unwrap_or_default();
+        // Both ends of the synthetic packet must be the same IP version. On a dual-stack
+        // socket an IPv4 sender can arrive as an IPv6 address or vice versa, so map IPv4
+        // into IPv6 whenever the two differ.
+        let dst = fields.dst.unwrap_or(local_addr.ip());
+        let (src_ip, dst_ip) = match (src.ip(), dst) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => (IpAddr::V4(s), IpAddr::V4(d)),
+            (s, d) => (IpAddr::V6(to_ipv6(s)), IpAddr::V6(to_ipv6(d))),
+        };
+
+        let udp_len = UDP_HEADER_LEN + original_len;
+        let udp_len_u16 = u16::try_from(udp_len).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} byte payload is too large for a pcap record",
+                    original_len
+                ),
+            )
+        })?;
+
+        let mut udp = [0u8; UDP_HEADER_LEN];
+        udp[0..2].copy_from_slice(&src.port().to_be_bytes());
+        udp[2..4].copy_from_slice(&local_addr.port().to_be_bytes());
+        udp[4..6].copy_from_slice(&udp_len_u16.to_be_bytes());
+
+        // Build the IP header into a buffer big enough for either version.
+        let mut ip = [0u8; IPV6_HEADER_LEN];
+        let ip_len = match (src_ip, dst_ip) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                let total_len = u16::try_from(IPV4_HEADER_LEN + udp_len).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} byte payload is too large for IPv4", original_len),
+                    )
+                })?;
+                ip[0] = 0x45; // version 4, header length 5 words
+                ip[1] = tos;
+                ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+                ip[8] = ttl;
+                ip[9] = IPPROTO_UDP;
+                ip[12..16].copy_from_slice(&s.octets());
+                ip[16..20].copy_from_slice(&d.octets());
+                let checksum = fold_checksum(checksum_add(0, &ip[..IPV4_HEADER_LEN]));
+                ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+                IPV4_HEADER_LEN
+            }
+            (IpAddr::V6(s), IpAddr::V6(d)) => {
+                // Version 6, then the traffic class across a nibble boundary and a zero
+                // flow label.
+                ip[0] = 0x60 | (tos >> 4);
+                ip[1] = tos << 4;
+                ip[4..6].copy_from_slice(&udp_len_u16.to_be_bytes());
+                ip[6] = IPPROTO_UDP;
+                ip[7] = ttl;
+                ip[8..24].copy_from_slice(&s.octets());
+                ip[24..40].copy_from_slice(&d.octets());
+                IPV6_HEADER_LEN
+            }
+            _ => unreachable!("addresses were mapped to the same IP version above"),
+        };
+
+        // The UDP checksum covers a pseudo-header of the addresses, protocol and length.
+        if data.len() == original_len {
+            let pseudo_header_sum = add_address(add_address(0, src_ip), dst_ip)
+                + u32::from(IPPROTO_UDP)
+                + u32::from(udp_len_u16);
+            let checksum = udp_checksum(pseudo_header_sum, &udp, data);
+            udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        // Per-record header: timestamp seconds, microseconds, captured and original length.
+        let original_record_len = (ip_len + udp_len) as u32;
+        let record_len = (ip_len + UDP_HEADER_LEN + data.len()) as u32;
+        self.record.clear();
+        self.record
+            .extend_from_slice(&(ts.timestamp() as u32).to_le_bytes());
+        self.record
+            .extend_from_slice(&ts.timestamp_subsec_micros().to_le_bytes());
+        self.record.extend_from_slice(&record_len.to_le_bytes());
+        self.record
+            .extend_from_slice(&original_record_len.to_le_bytes());
+        self.record.extend_from_slice(&ip[..ip_len]);
+        self.record.extend_from_slice(&udp);
+        self.record.extend_from_slice(data);
+        self.inner.write_all(&self.record)
+    }
+
+    /// The underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Flushes buffered records through to the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Converts an address to IPv6, mapping IPv4 into the `::ffff:a.b.c.d` range.
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+/// Adds an IP address to a running ones'-complement sum.
+fn add_address(sum: u32, ip: IpAddr) -> u32 {
+    match ip {
+        IpAddr::V4(v4) => checksum_add(sum, &v4.octets()),
+        IpAddr::V6(v6) => checksum_add(sum, &v6.octets()),
+    }
+}
+
+/// Adds `bytes` to a running ones'-complement sum as big-endian 16-bit words.
+/// An odd trailing byte is padded with zero, so only the last slice may have odd length.
+fn checksum_add(mut sum: u32, bytes: &[u8]) -> u32 {
+    let mut words = bytes.chunks_exact(2);
+    for word in &mut words {
+        sum += u32::from(u16::from_be_bytes([word[0], word[1]]));
+    }
+    if let [last] = words.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    // Fold carries as we go so very large payloads can't overflow the `u32`.
+    (sum & 0xffff) + (sum >> 16)
+}
+
+/// Folds a running sum into the final 16-bit ones'-complement checksum.
+fn fold_checksum(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Finishes a UDP checksum given the pseudo-header sum, the UDP header (with a zero
+/// checksum field) and the payload. A computed value of zero is sent as `0xffff`,
+/// because zero means "no checksum" in UDP.
+fn udp_checksum(pseudo_header_sum: u32, udp_header: &[u8], data: &[u8]) -> u16 {
+    let sum = checksum_add(checksum_add(pseudo_header_sum, udp_header), data);
+    match fold_checksum(sum) {
+        0 => 0xffff,
+        checksum => checksum,
+    }
+}
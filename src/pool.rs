@@ -0,0 +1,43 @@
+// Reusing payload buffers, so receiving a packet doesn't allocate.
+
+use crossbeam_channel::{Receiver, Sender};
+
+/// Buffers for packet payloads, handed back once a packet has been written so the next
+/// one can be received into the same memory.
+///
+/// A buffer keeps the capacity it grew to, so after the first few packets, jumbo
+/// datagrams included, copying a payload in allocates nothing. A buffer taken when
+/// the pool is empty is a new, empty one, and one handed back to a full pool is freed,
+/// so the pool never holds more than `capacity` buffers however many packets are
+/// outstanding. Taking and handing back are safe from any thread and never block.
+pub(crate) struct BufferPool {
+    returned: Sender<Vec<u8>>,
+    free: Receiver<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// A pool holding at most `capacity` free buffers.
+    pub(crate) fn new(capacity: usize) -> BufferPool {
+        let (returned, free) = crossbeam_channel::bounded(capacity.max(1));
+        BufferPool { returned, free }
+    }
+
+    /// An empty buffer, with room from earlier use if one is free.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.free.try_recv().unwrap_or_default()
+    }
+
+    /// A buffer holding a copy of `data`.
+    pub(crate) fn copy_of(&self, data: &[u8]) -> Vec<u8> {
+        let mut buf = self.take();
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    /// Hands back a packet's buffer once the packet is done with.
+    pub(crate) fn put(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        // A full pool has enough spare buffers already.
+        let _ = self.returned.try_send(buf);
+    }
+}
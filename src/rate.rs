@@ -0,0 +1,67 @@
+// Measuring the recent packet and byte rate, for the traffic alerts.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The span of traffic the rates are averaged over.
+pub(crate) const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How finely the window is divided. The window slides forward one slot at a time.
+const SLOT: Duration = Duration::from_millis(250);
+
+/// Counts packets and bytes over the last [`RATE_WINDOW`], so short bursts average out
+/// but a sustained spike shows up within a few seconds.
+///
+/// Arrivals are added to fixed slots rather than remembered one by one, so the memory
+/// used doesn't grow with the rate.
+pub(crate) struct RateMeter {
+    /// When each slot started, with the packets and bytes counted in it, oldest first.
+    slots: VecDeque<(Instant, u64, u64)>,
+}
+
+impl RateMeter {
+    pub(crate) fn new() -> RateMeter {
+        RateMeter {
+            slots: VecDeque::new(),
+        }
+    }
+
+    /// Counts one packet of `bytes` bytes, received now.
+    pub(crate) fn record(&mut self, bytes: usize) {
+        let now = Instant::now();
+        match self.slots.back_mut() {
+            Some((start, packets, total)) if now.duration_since(*start) < SLOT => {
+                *packets += 1;
+                *total += bytes as u64;
+            }
+            _ => {
+                self.expire(now);
+                self.slots.push_back((now, 1, bytes as u64));
+            }
+        }
+    }
+
+    /// The average packets and bytes per second over the window. Right after startup
+    /// the window isn't full yet, which makes the rates come out low rather than high.
+    pub(crate) fn rates(&mut self) -> (f64, f64) {
+        self.expire(Instant::now());
+        let (packets, bytes) = self
+            .slots
+            .iter()
+            .fold((0, 0), |(packets, bytes), &(_, p, b)| {
+                (packets + p, bytes + b)
+            });
+        let secs = RATE_WINDOW.as_secs_f64();
+        (packets as f64 / secs, bytes as f64 / secs)
+    }
+
+    /// Forgets slots that started longer ago than the window.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(start, _, _)) = self.slots.front() {
+            if now.duration_since(start) < RATE_WINDOW {
+                break;
+            }
+            self.slots.pop_front();
+        }
+    }
+}
@@ -0,0 +1,191 @@
+// Putting messages that were split across several datagrams back together.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::ChunkLayout;
+use crate::packet::Packet;
+
+/// How many messages may be waiting for chunks at once. Beyond this the oldest is given
+/// up on early, so a sender that never finishes its messages can't use up the memory.
+pub(crate) const MAX_PENDING_MESSAGES: usize = 1024;
+
+/// The most chunks a message may be split into. A header claiming more is treated as
+/// malformed rather than reserving room for them all.
+const MAX_CHUNKS: u64 = 65536;
+
+/// What became of a chunk handed to [`Reassembler::add`].
+pub(crate) enum Chunk {
+    /// The chunk completed its message, which is returned whole: the first chunk's
+    /// packet with every chunk's share of the payload, in order, as its data.
+    Complete(Packet),
+    /// The message is still waiting for other chunks.
+    Pending,
+    /// The header didn't fit the layout, or made no sense; the packet is returned as
+    /// it was.
+    Malformed(Packet),
+}
+
+/// A message that was given up on with chunks still missing.
+pub(crate) struct Incomplete {
+    pub(crate) src: SocketAddr,
+    pub(crate) id: u64,
+    pub(crate) missing: usize,
+    pub(crate) total: usize,
+}
+
+/// Collects chunks per source and message ID until each message is complete.
+///
+/// A chunk that repeats one already held is ignored. A message is given up on once
+/// `timeout` has passed since its first chunk, or if [`MAX_PENDING_MESSAGES`] newer
+/// messages are waiting.
+pub(crate) struct Reassembler {
+    layout: ChunkLayout,
+    timeout: Duration,
+    pending: HashMap<(SocketAddr, u64), Pending>,
+    /// The same messages in the order they started, so the oldest can be found from
+    /// the front. Entries for messages already completed are skipped when they come
+    /// up.
+    started: VecDeque<(Instant, (SocketAddr, u64))>,
+    /// Messages given up on since [`expire`](Self::expire) last returned them.
+    given_up: Vec<Incomplete>,
+}
+
+struct Pending {
+    started: Instant,
+    /// The first chunk to arrive, whose details the whole message is logged with.
+    first: Packet,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+impl Reassembler {
+    pub(crate) fn new(layout: ChunkLayout, timeout: Duration) -> Reassembler {
+        Reassembler {
+            layout,
+            timeout,
+            pending: HashMap::new(),
+            started: VecDeque::new(),
+            given_up: Vec::new(),
+        }
+    }
+
+    /// Adds a chunk, returning the message if that was its last missing chunk.
+    pub(crate) fn add(&mut self, pkt: Packet) -> Chunk {
+        let Some((id, index, total)) = self.header(&pkt.data) else {
+            return Chunk::Malformed(pkt);
+        };
+        let key = (pkt.src, id);
+        let data = pkt.data[self.layout.header_len()..].to_vec();
+        if total == 1 {
+            return Chunk::Complete(whole(pkt, data));
+        }
+        let now = Instant::now();
+        if !self.pending.contains_key(&key) {
+            if self.pending.len() >= MAX_PENDING_MESSAGES {
+                self.give_up_oldest();
+            }
+            self.started.push_back((now, key));
+            self.pending.insert(
+                key,
+                Pending {
+                    started: now,
+                    first: pkt,
+                    chunks: vec![None; total],
+                    received: 0,
+                },
+            );
+        }
+        let message = self.pending.get_mut(&key).expect("just inserted");
+        if message.chunks.len() != total {
+            // Another message under the same ID, or a broken sender; keep the first.
+            return Chunk::Pending;
+        }
+        if message.chunks[index].is_none() {
+            message.chunks[index] = Some(data);
+            message.received += 1;
+        }
+        if message.received < total {
+            return Chunk::Pending;
+        }
+        let message = self.pending.remove(&key).expect("still pending");
+        let data = message.chunks.into_iter().flatten().flatten().collect();
+        Chunk::Complete(whole(message.first, data))
+    }
+
+    /// Gives up on messages whose time is up, and returns them with any given up on
+    /// early to make room.
+    pub(crate) fn expire(&mut self) -> Vec<Incomplete> {
+        let now = Instant::now();
+        while let Some(&(started, key)) = self.started.front() {
+            let waiting = self.is_waiting(started, key);
+            if waiting && now.duration_since(started) < self.timeout {
+                break;
+            }
+            self.started.pop_front();
+            if waiting {
+                self.give_up(key);
+            }
+        }
+        std::mem::take(&mut self.given_up)
+    }
+
+    fn give_up_oldest(&mut self) {
+        while let Some((started, key)) = self.started.pop_front() {
+            if self.is_waiting(started, key) {
+                self.give_up(key);
+                return;
+            }
+        }
+    }
+
+    /// Whether an entry in `started` is for a message still waiting for chunks. It
+    /// isn't once the message is complete, and a message completed and then started
+    /// again under the same ID has a newer entry of its own further back.
+    fn is_waiting(&self, started: Instant, key: (SocketAddr, u64)) -> bool {
+        self.pending
+            .get(&key)
+            .is_some_and(|message| message.started == started)
+    }
+
+    fn give_up(&mut self, key: (SocketAddr, u64)) {
+        if let Some(message) = self.pending.remove(&key) {
+            self.given_up.push(Incomplete {
+                src: key.0,
+                id: key.1,
+                missing: message.chunks.len() - message.received,
+                total: message.chunks.len(),
+            });
+        }
+    }
+
+    /// Reads the message ID, chunk index and chunk count from the start of `data`.
+    fn header(&self, data: &[u8]) -> Option<(u64, usize, usize)> {
+        let layout = self.layout;
+        if data.len() < layout.header_len() {
+            return None;
+        }
+        let (id, rest) = data.split_at(layout.id_len);
+        let (index, rest) = rest.split_at(layout.index_len);
+        let total = &rest[..layout.total_len];
+        let (id, index, total) = (be(id), be(index), be(total));
+        if total == 0 || total > MAX_CHUNKS || index >= total {
+            return None;
+        }
+        Some((id, index as usize, total as usize))
+    }
+}
+
+/// A big-endian unsigned integer of up to 8 bytes.
+fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |n, &b| (n << 8) | u64::from(b))
+}
+
+/// The packet `pkt` with its payload replaced by the whole message.
+fn whole(mut pkt: Packet, data: Vec<u8>) -> Packet {
+    pkt.captured_len = data.len();
+    pkt.original_len = data.len();
+    pkt.data = data;
+    pkt
+}
@@ -0,0 +1,319 @@
+// A compact binary capture format, and reading it back.
+
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{Local, TimeZone};
+
+use crate::checksum::PayloadChecksum;
+use crate::config::Checksum;
+use crate::packet::Packet;
+use crate::session::MAX_SESSION_ID_LEN;
+
+/// The first bytes of every binary capture: a name and the format version.
+const CAPTURE_MAGIC: [u8; 8] = *b"UDPLREC3";
+
+/// The magic of the second version of the format, whose records had no monotonic time.
+/// Captures in it can still be read.
+const CAPTURE_MAGIC_V2: [u8; 8] = *b"UDPLREC2";
+
+/// The magic of the first version of the format, whose records had no checksum.
+/// Captures in it can still be read.
+const CAPTURE_MAGIC_V1: [u8; 8] = *b"UDPLREC1";
+
+/// The header a [`Format::Bin`](crate::Format::Bin) capture starts with, naming the
+/// session that wrote it, so captures from several runs can be told apart.
+///
+/// | Bytes | Field |
+/// |-------|-------|
+/// | 0..8   | `UDPLREC3`: the format and its version |
+/// | 8      | length of the session ID, N |
+/// | 9..9+N | the session ID, in UTF-8 |
+///
+/// An ID longer than [`MAX_SESSION_ID_LEN`] bytes is cut to fit.
+pub fn capture_header(session_id: &str) -> Vec<u8> {
+    let id = &session_id.as_bytes()[..session_id.len().min(MAX_SESSION_ID_LEN)];
+    let mut header = Vec::with_capacity(CAPTURE_MAGIC.len() + 1 + id.len());
+    header.extend_from_slice(&CAPTURE_MAGIC);
+    header.push(id.len() as u8);
+    header.extend_from_slice(id);
+    header
+}
+
+/// Size of the header in front of every payload.
+pub const RECORD_HEADER_LEN: usize = 51;
+
+/// Size of the record header in `UDPLREC2` captures, which ends before the monotonic
+/// time.
+const V2_RECORD_HEADER_LEN: usize = 43;
+
+/// Size of the record header in `UDPLREC1` captures, which ends before the checksum.
+const V1_RECORD_HEADER_LEN: usize = 34;
+
+/// The largest payload a record may claim. Nothing bigger fits in a UDP datagram, so a
+/// larger length means the file is corrupt, and is refused rather than allocated.
+const MAX_RECORD_PAYLOAD: usize = 65_535;
+
+/// Encodes a packet as one record of [`Format::Bin`](crate::Format::Bin): a fixed header
+/// followed by the payload as logged.
+///
+/// | Bytes | Field |
+/// |-------|-------|
+/// | 0..8   | receive time, nanoseconds since the Unix epoch (signed) |
+/// | 8..10  | source port |
+/// | 10..26 | source address, IPv4 as an IPv4-mapped IPv6 address, in network order |
+/// | 26..30 | payload length in the record |
+/// | 30..34 | the datagram's full payload length, larger if it was cut to the snap length |
+/// | 34     | the checksum's algorithm: 0 for none, 1 for CRC-32, 2 for XXH64 |
+/// | 35..43 | the checksum of the full payload, or zero; CRC-32 in the low four bytes |
+/// | 43..51 | nanoseconds from the start of the capture on the monotonic clock, or all ones if unknown |
+///
+/// The numbers are little-endian. A capture is a [`capture_header`] followed by the
+/// records, with nothing in between, so it can be read from the start by taking each
+/// record header's length in turn.
+pub fn encode_record(pkt: &Packet) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + pkt.data.len());
+    encode_record_into(pkt, &mut record);
+    record
+}
+
+/// Like [`encode_record`], appending the record to `record`, so a buffer can be reused
+/// from one packet to the next.
+pub(crate) fn encode_record_into(pkt: &Packet, record: &mut Vec<u8>) {
+    let nanos = pkt.received_at.timestamp_nanos_opt().unwrap_or_default();
+    record.extend_from_slice(&nanos.to_le_bytes());
+    record.extend_from_slice(&pkt.src.port().to_le_bytes());
+    let ip = match pkt.src.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    record.extend_from_slice(&ip.octets());
+    record.extend_from_slice(&(pkt.data.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(pkt.original_len.max(pkt.data.len()) as u32).to_le_bytes());
+    let (algorithm, checksum) = match pkt.checksum {
+        Some(checksum) => (algorithm_code(checksum.algorithm), checksum.value),
+        None => (0, 0),
+    };
+    record.push(algorithm);
+    record.extend_from_slice(&checksum.to_le_bytes());
+    let monotonic = pkt.monotonic.map_or(u64::MAX, |since| {
+        since.as_nanos().min(u64::MAX as u128 - 1) as u64
+    });
+    record.extend_from_slice(&monotonic.to_le_bytes());
+    record.extend_from_slice(&pkt.data);
+}
+
+/// Reads the packets back out of a capture written with [`Format::Bin`](crate::Format::Bin),
+/// oldest first, for example to replay or convert it.
+///
+/// The packets carry their source, receive time, monotonic time, payload, full length,
+/// checksum and the session ID from the capture header; the rest of the [`Packet`]
+/// fields aren't stored and come back empty. Captures from before checksums or
+/// monotonic times were stored are read too, without them. A read error, a file that
+/// doesn't start with a capture header, or one that ends part-way through a record, is
+/// returned as the last item.
+pub fn read_records<R: Read>(reader: R) -> impl Iterator<Item = io::Result<Packet>> {
+    Records {
+        reader,
+        session_id: None,
+        header_len: RECORD_HEADER_LEN,
+        header_read: false,
+        done: false,
+    }
+}
+
+struct Records<R> {
+    reader: R,
+    /// From the capture header, shared by every packet.
+    session_id: Option<Arc<str>>,
+    /// The record header's size in this capture's version of the format.
+    header_len: usize,
+    header_read: bool,
+    /// Set after the end of the file or an error, so nothing more is read.
+    done: bool,
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = io::Result<Packet>;
+
+    fn next(&mut self) -> Option<io::Result<Packet>> {
+        if self.done {
+            return None;
+        }
+        match self.read_record() {
+            Ok(Some(pkt)) => Some(Ok(pkt)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<R: Read> Records<R> {
+    /// Reads the capture header. Returns false for an empty file, which holds no
+    /// records.
+    fn read_header(&mut self) -> io::Result<bool> {
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        let filled = read_full(&mut self.reader, &mut magic)?;
+        if filled == 0 {
+            return Ok(false);
+        }
+        if filled < magic.len()
+            || (magic != CAPTURE_MAGIC && magic != CAPTURE_MAGIC_V2 && magic != CAPTURE_MAGIC_V1)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a binary capture from this listener: it doesn't start with a UDPLREC header",
+            ));
+        }
+        if magic == CAPTURE_MAGIC_V2 {
+            self.header_len = V2_RECORD_HEADER_LEN;
+        } else if magic == CAPTURE_MAGIC_V1 {
+            self.header_len = V1_RECORD_HEADER_LEN;
+        }
+        let truncated = || {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the capture ends part-way through its header",
+            )
+        };
+        let mut len = [0];
+        if read_full(&mut self.reader, &mut len)? < 1 {
+            return Err(truncated());
+        }
+        let mut id = vec![0; len[0].into()];
+        if read_full(&mut self.reader, &mut id)? < id.len() {
+            return Err(truncated());
+        }
+        self.session_id = Some(String::from_utf8_lossy(&id).into());
+        Ok(true)
+    }
+
+    /// Reads one record, or returns `None` at a clean end of the file.
+    fn read_record(&mut self) -> io::Result<Option<Packet>> {
+        if !self.header_read {
+            self.header_read = true;
+            if !self.read_header()? {
+                return Ok(None);
+            }
+        }
+        let mut header = [0; RECORD_HEADER_LEN];
+        let header = &mut header[..self.header_len];
+        let filled = read_full(&mut self.reader, header)?;
+        if filled == 0 {
+            return Ok(None);
+        }
+        if filled < header.len() {
+            return Err(truncated_record());
+        }
+
+        let nanos = i64::from_le_bytes(header[0..8].try_into().expect("eight bytes"));
+        let port = u16::from_le_bytes(header[8..10].try_into().expect("two bytes"));
+        let ip: [u8; 16] = header[10..26].try_into().expect("sixteen bytes");
+        let ip = IpAddr::V6(Ipv6Addr::from(ip)).to_canonical();
+        let len = u32::from_le_bytes(header[26..30].try_into().expect("four bytes")) as usize;
+        let original_len =
+            u32::from_le_bytes(header[30..34].try_into().expect("four bytes")) as usize;
+        // Version 1 records end before the checksum, and read as having none.
+        let code = header.get(34).copied().unwrap_or(0);
+        let checksum = if code == 0 {
+            None
+        } else {
+            let algorithm = algorithm_from_code(code).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("record has an unknown checksum algorithm, {}", code),
+                )
+            })?;
+            Some(PayloadChecksum {
+                algorithm,
+                value: u64::from_le_bytes(header[35..43].try_into().expect("eight bytes")),
+            })
+        };
+        // Earlier versions end before the monotonic time, and read as not knowing it.
+        let monotonic = header
+            .get(43..51)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().expect("eight bytes")))
+            .filter(|&nanos| nanos != u64::MAX)
+            .map(Duration::from_nanos);
+        if len > MAX_RECORD_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "record claims a {}-byte payload, more than a UDP datagram can hold",
+                    len
+                ),
+            ));
+        }
+
+        let mut data = vec![0; len];
+        if read_full(&mut self.reader, &mut data)? < len {
+            return Err(truncated_record());
+        }
+        Ok(Some(Packet {
+            data,
+            captured_len: len,
+            original_len: original_len.max(len),
+            src: SocketAddr::new(ip, port),
+            received_at: Local.timestamp_nanos(nanos),
+            monotonic,
+            truncated: false,
+            ttl: None,
+            tos: None,
+            dst_ip: None,
+            bind: None,
+            session_id: self.session_id.clone(),
+            src_host: None,
+            src_geo: None,
+            parsed: None,
+            malformed: false,
+            checksum,
+            index: None,
+        }))
+    }
+}
+
+/// How a checksum's algorithm is stored in a record.
+fn algorithm_code(algorithm: Checksum) -> u8 {
+    match algorithm {
+        Checksum::Crc32 => 1,
+        Checksum::Xxhash => 2,
+    }
+}
+
+fn algorithm_from_code(code: u8) -> Option<Checksum> {
+    match code {
+        1 => Some(Checksum::Crc32),
+        2 => Some(Checksum::Xxhash),
+        _ => None,
+    }
+}
+
+/// Fills as much of `buf` as the reader has, returning how much that was. Unlike
+/// `read_exact`, this tells a clean end of the file apart from one mid-record.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn truncated_record() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the capture ends part-way through a record",
+    )
+}
@@ -0,0 +1,154 @@
+// Sending a binary capture back out, to reproduce the traffic it recorded.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::records::read_records;
+use crate::send::{SendSummary, sending_socket};
+
+/// The longest single sleep while waiting for a datagram's time, so that `stop` is
+/// noticed during long gaps in a capture.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Settings for [`replay`].
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// A capture written with [`Format::Bin`](crate::Format::Bin).
+    pub path: PathBuf,
+    /// Where the payloads are sent.
+    pub to: SocketAddr,
+    /// Space the datagrams out as they were when captured, using the recorded receive
+    /// times.
+    pub preserve_timing: bool,
+    /// With `preserve_timing`, how much faster than recorded to play: 2.0 halves every
+    /// gap, 0.5 doubles them.
+    pub speed: f64,
+    /// Datagrams per second, instead of the recorded timing. `None` (and
+    /// `preserve_timing` off) sends as fast as possible.
+    pub rate: Option<f64>,
+    /// Start again from the beginning after the last packet, until stopped.
+    pub repeat: bool,
+}
+
+impl ReplayConfig {
+    /// Settings for sending every payload in the capture at `path` to `to` once, as
+    /// fast as possible.
+    pub fn new(path: PathBuf, to: SocketAddr) -> ReplayConfig {
+        ReplayConfig {
+            path,
+            to,
+            preserve_timing: false,
+            speed: 1.0,
+            rate: None,
+            repeat: false,
+        }
+    }
+}
+
+/// Sends the payload of every packet in a capture to `config.to`, unchanged and in
+/// order, paced as `config` says. Only the payloads are sent; they come from this
+/// host, not from the addresses they were captured from.
+///
+/// The capture is read as it's sent, so it can be larger than memory. Returns early,
+/// with the totals so far, once `stop` is set, for example by a Ctrl-C handler; with
+/// `config.repeat` that's the only way it returns. As with [`send`](crate::send), a
+/// datagram that fails to send is counted and the rest are still sent, but a capture
+/// that can't be read is an error, as is a speed or rate so low that the next datagram
+/// would be due too far off to wait for.
+pub fn replay(config: &ReplayConfig, stop: &AtomicBool) -> io::Result<SendSummary> {
+    let socket = sending_socket(config.to)?;
+    let mut summary = SendSummary {
+        sent: 0,
+        bytes: 0,
+        failed: 0,
+    };
+    loop {
+        let file = File::open(&config.path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Couldn't open capture '{}': {}", config.path.display(), e),
+            )
+        })?;
+        let start = Instant::now();
+        let mut first_recorded = None;
+        let mut sent_this_pass = 0;
+
+        for pkt in read_records(BufReader::new(file)) {
+            let pkt = pkt.map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Couldn't read capture '{}': {}", config.path.display(), e),
+                )
+            })?;
+            if stop.load(Ordering::Relaxed) {
+                return Ok(summary);
+            }
+
+            // Each datagram is due at a time measured from the start of the pass, so
+            // a slow send is caught up on rather than delaying everything after it.
+            let due = if config.preserve_timing {
+                let (first_at, first_monotonic) =
+                    *first_recorded.get_or_insert((pkt.received_at, pkt.monotonic));
+                // The monotonic times aren't thrown by the clock being set mid-capture.
+                let offset = match (first_monotonic, pkt.monotonic) {
+                    (Some(first), Some(now)) => now.saturating_sub(first),
+                    _ => (pkt.received_at - first_at).to_std().unwrap_or_default(),
+                };
+                Some(due_at(start, offset.as_secs_f64() / config.speed)?)
+            } else {
+                match config.rate {
+                    Some(rate) => Some(due_at(start, sent_this_pass as f64 / rate)?),
+                    None => None,
+                }
+            };
+            if let Some(due) = due
+                && !sleep_until(due, stop)
+            {
+                return Ok(summary);
+            }
+
+            match socket.send_to(&pkt.data, config.to) {
+                Ok(n) => {
+                    summary.sent += 1;
+                    summary.bytes += n as u64;
+                }
+                Err(_) => summary.failed += 1,
+            }
+            sent_this_pass += 1;
+        }
+
+        // An empty capture would otherwise spin forever with `repeat`.
+        if !config.repeat || sent_this_pass == 0 || stop.load(Ordering::Relaxed) {
+            return Ok(summary);
+        }
+    }
+}
+
+/// The time `secs` seconds after `start`, or an error if that can't be represented.
+fn due_at(start: Instant, secs: f64) -> io::Result<Instant> {
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .and_then(|offset| start.checked_add(offset))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The speed or rate is too low: the next datagram would be due too far off",
+            )
+        })
+}
+
+/// Sleeps until `due`, waking regularly to check `stop`. Returns false if it was set.
+fn sleep_until(due: Instant, stop: &AtomicBool) -> bool {
+    while let Some(wait) = due.checked_duration_since(Instant::now()) {
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(wait.min(STOP_POLL_INTERVAL));
+    }
+    !stop.load(Ordering::Relaxed)
+}
@@ -0,0 +1,83 @@
+// Reverse DNS lookups of source addresses, done in the background.
+
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+use crossbeam_channel::{Sender, TrySendError};
+
+use crate::lru::LruCache;
+
+/// How many addresses may wait to be looked up. Beyond this, new addresses are logged
+/// without a name and looked up once they send again.
+const PENDING_LOOKUPS: usize = 1024;
+
+/// Names for addresses, looked up by a background thread.
+///
+/// [`Resolver::name`] never waits for DNS: it answers from the cache, and an address
+/// it hasn't seen before is queued for the lookup thread and answered with `None` until
+/// the lookup finishes. A failed lookup is cached as `None` too, so each address is
+/// looked up at most once. The operating system's resolver has no timeout of its own,
+/// but a slow lookup only holds up other lookups, never the listener.
+///
+/// The cache holds at most `max_addresses` addresses, forgetting the one seen longest
+/// ago to make room; a forgotten address is looked up again if it comes back.
+pub(crate) struct Resolver {
+    /// The addresses seen recently, with their names once found. `None` means the
+    /// lookup failed or hasn't finished yet.
+    cache: Arc<Mutex<LruCache<IpAddr, Option<String>>>>,
+    requests: Sender<IpAddr>,
+}
+
+impl Resolver {
+    /// Starts the lookup thread. It stops when the `Resolver` is dropped.
+    pub(crate) fn spawn(max_addresses: usize) -> io::Result<Resolver> {
+        let cache = Arc::new(Mutex::new(LruCache::new(max_addresses)));
+        let (requests, pending) = crossbeam_channel::bounded::<IpAddr>(PENDING_LOOKUPS);
+
+        let results = Arc::clone(&cache);
+        thread::Builder::new()
+            .name("resolver".to_string())
+            .spawn(move || {
+                for ip in pending {
+                    // A name that's just the address again means there's no PTR record.
+                    let name = dns_lookup::lookup_addr(&ip)
+                        .ok()
+                        .filter(|name| name.parse::<IpAddr>().is_err());
+                    lock(&results).update(ip, name);
+                }
+            })?;
+
+        Ok(Resolver { cache, requests })
+    }
+
+    /// The name for `ip`, if it has already been looked up and has one.
+    pub(crate) fn name(&self, ip: IpAddr) -> Option<String> {
+        let mut cache = lock(&self.cache);
+        if let Some(name) = cache.get(ip) {
+            return name.clone();
+        }
+        match self.requests.try_send(ip) {
+            Ok(()) => {
+                cache.insert(ip, None);
+            }
+            // Leave the address out of the cache so it's queued again next time.
+            Err(TrySendError::Full(_)) => {}
+            // The lookup thread only stops if it panicked; carry on without names.
+            Err(TrySendError::Disconnected(_)) => {
+                cache.insert(ip, None);
+            }
+        }
+        None
+    }
+
+    /// How many addresses were forgotten to make room since the last call.
+    pub(crate) fn take_evicted(&self) -> u64 {
+        lock(&self.cache).take_evicted()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
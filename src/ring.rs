@@ -0,0 +1,40 @@
+// A fixed-size buffer of the most recent packets, for flight-recorder captures.
+
+use std::collections::VecDeque;
+
+use crate::packet::Packet;
+
+/// Holds the last `capacity` packets, oldest first. Pushing onto a full ring drops the
+/// oldest packet.
+pub(crate) struct PacketRing {
+    packets: VecDeque<Packet>,
+    capacity: usize,
+}
+
+impl PacketRing {
+    /// Creates an empty ring for up to `capacity` (at least one) packets.
+    pub(crate) fn new(capacity: usize) -> PacketRing {
+        let capacity = capacity.max(1);
+        PacketRing {
+            packets: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds the newest packet, dropping the oldest if the ring is full.
+    pub(crate) fn push(&mut self, pkt: Packet) {
+        if self.packets.len() == self.capacity {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(pkt);
+    }
+
+    /// The packets in the order they arrived.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Packet> {
+        self.packets.iter()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.packets.len()
+    }
+}
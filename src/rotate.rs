@@ -0,0 +1,654 @@
+// Log rotation: a writer that moves a full log file aside and carries on in a new one.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+
+use crate::clock::{Clock, SystemClock};
+use crate::compress::{Compressor, gz_path, gzip_file};
+use crate::config::{Compression, ExistingLog, RotatePeriod, Timezone, create_log_file};
+use crate::diag;
+use crate::writer::SyncWrite;
+
+/// A log file that rotates itself by size, by time, or both.
+///
+/// - **By size:** when the next write would take the file over `max_size` bytes, the
+///   file is closed and renamed to `<path>.1`, any older `<path>.1`, `<path>.2`, ... are
+///   shifted up by one (the oldest beyond `max_files` is deleted), and a fresh file is
+///   created at `path`.
+/// - **By time:** each file is named after the period it covers, so `udp_packets.log`
+///   becomes `udp_packets-2024-06-01.log` (daily) or `udp_packets-2024-06-01-13.log`
+///   (hourly). The period is checked on every write against the same clock (local or
+///   UTC) used for log timestamps, so a listener that was idle across midnight still
+///   starts a new file with the first packet after it.
+///
+/// With compression (see [`with_compression`](Self::with_compression)) the size limit
+/// applies to the uncompressed bytes, so every file holds about the same number of
+/// records. Alternatively, with
+/// [`with_rotated_compression`](Self::with_rotated_compression), each file is written
+/// plainly and gzipped in the background once it's been rotated away from.
+///
+/// `<path>.current`, such as `udp_packets.log.current`, is kept as a symbolic link to
+/// the file being written, so `tail -F` on it follows the log from one file to the
+/// next. It's re-pointed by renaming a new link over it, so it's never missing. Where
+/// there are no symbolic links it's a one-line file with the current file's name
+/// instead.
+///
+/// Rotation only ever happens between calls to `write`, and each call is written in
+/// full to a single file. Writing each log record with one `write_all` therefore keeps
+/// every record whole, with none split across two files.
+pub struct RotatingWriter {
+    /// The configured path, before any date is added to it.
+    base_path: PathBuf,
+    /// The file currently being written.
+    path: PathBuf,
+    /// `None` only while the file is closed partway through a rotation.
+    file: Option<Compressor<File>>,
+    compression: Option<Compression>,
+    /// Bytes written to the current file.
+    written: u64,
+    max_size: Option<u64>,
+    max_files: usize,
+    period: Option<RotatePeriod>,
+    /// The timezone that decides when a period starts.
+    timezone: Timezone,
+    clock: Arc<dyn Clock>,
+    /// Identifies the period the current file belongs to.
+    period_key: PeriodKey,
+    /// Bytes written at the start of every new file, such as a pcap global header.
+    header: Vec<u8>,
+    /// Gzip each file once it's been rotated away from.
+    compress_rotated: bool,
+    /// The thread compressing the last file rotated away from, if it may not be done.
+    compressing: Option<JoinHandle<()>>,
+}
+
+/// Year, day of the year and (for hourly rotation) hour: enough to tell whether two
+/// times fall in the same period, without formatting a string on every write.
+pub(crate) type PeriodKey = (i32, u32, u32);
+
+impl RotatingWriter {
+    /// Creates (or truncates) the log at `path`.
+    ///
+    /// With `max_size` the file rotates once it would grow past that many bytes, keeping
+    /// at most `max_files` (at least one) rotated files. With `period` a new dated file
+    /// is started whenever the day or hour changes in `timezone`.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        max_size: Option<u64>,
+        max_files: usize,
+        period: Option<RotatePeriod>,
+        timezone: Timezone,
+    ) -> io::Result<Self> {
+        Self::new_with_clock(
+            path,
+            max_size,
+            max_files,
+            period,
+            timezone,
+            Arc::new(SystemClock),
+        )
+    }
+
+    /// Like [`new`](Self::new), but takes the time that decides the period from `clock`
+    /// rather than the system clock.
+    pub fn new_with_clock(
+        path: impl Into<PathBuf>,
+        max_size: Option<u64>,
+        max_files: usize,
+        period: Option<RotatePeriod>,
+        timezone: Timezone,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<Self> {
+        Self::with_existing_log(
+            path,
+            max_size,
+            max_files,
+            period,
+            timezone,
+            clock,
+            ExistingLog::Truncate,
+        )
+    }
+
+    /// Like [`new_with_clock`](Self::new_with_clock), with a first file that already
+    /// exists dealt with as `existing` says. One that's appended to counts towards
+    /// `max_size` from what it already holds. Files started by rotation are always
+    /// created afresh.
+    pub(crate) fn with_existing_log(
+        path: impl Into<PathBuf>,
+        max_size: Option<u64>,
+        max_files: usize,
+        period: Option<RotatePeriod>,
+        timezone: Timezone,
+        clock: Arc<dyn Clock>,
+        existing: ExistingLog,
+    ) -> io::Result<Self> {
+        let base_path = path.into();
+        let now = now(clock.as_ref(), timezone);
+        let path = match period {
+            Some(period) => dated_path(&base_path, period, &now),
+            None => base_path.clone(),
+        };
+        let file = create_log_file(&path, existing)?;
+        let written = file.metadata()?.len();
+        let writer = RotatingWriter {
+            base_path,
+            path,
+            file: Some(Compressor::new(file, None)),
+            compression: None,
+            written,
+            max_size,
+            max_files: max_files.max(1),
+            period,
+            timezone,
+            clock,
+            period_key: period.map_or((0, 0, 0), |period| period_key(period, &now)),
+            header: Vec::new(),
+            compress_rotated: false,
+            compressing: None,
+        };
+        writer.point_current_link();
+        Ok(writer)
+    }
+
+    /// Starts the current file and every file created by rotation with `header`. This
+    /// keeps formats that need a file header, like pcap, readable in every segment.
+    pub fn with_header(mut self, header: Vec<u8>) -> io::Result<Self> {
+        if self.written == 0 {
+            self.write_to_file(&header)?;
+        }
+        self.header = header;
+        Ok(self)
+    }
+
+    /// Compresses the current file and every file created by rotation, each as a
+    /// complete compressed file of its own. The current file is started afresh (with
+    /// the header, if one is set), so this should be called before anything is written.
+    /// A file carried on from an earlier run is kept, and gets a compressed stream of
+    /// its own after what's there.
+    pub fn with_compression(mut self, compression: Compression) -> io::Result<Self> {
+        self.compression = Some(compression);
+        self.file = None;
+        if self.written > 0 {
+            self.file = Some(self.append_file()?);
+        } else {
+            self.file = Some(self.create_file()?);
+            self.start_file()?;
+        }
+        Ok(self)
+    }
+
+    /// Gzips each file once it's been rotated away from, to `<name>.gz`, in a thread of
+    /// its own while writing carries on in the next file. The plain file is only
+    /// deleted once the compressed one is complete and synced to disk; if compressing
+    /// fails, the error is reported and the plain file kept.
+    ///
+    /// Only one file is compressed at a time: a rotation that comes while the last file
+    /// is still being compressed waits for it to finish, and so does dropping the
+    /// writer, so no file is left half-compressed at shutdown.
+    pub fn with_rotated_compression(mut self) -> Self {
+        self.compress_rotated = true;
+        self
+    }
+
+    /// Creates (or truncates) the file at the current path.
+    fn create_file(&self) -> io::Result<Compressor<File>> {
+        Ok(Compressor::new(
+            create_log_file(&self.path, ExistingLog::Truncate)?,
+            self.compression,
+        ))
+    }
+
+    /// Opens the file at the current path to carry on at its end.
+    fn append_file(&self) -> io::Result<Compressor<File>> {
+        OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map(|file| Compressor::new(file, self.compression))
+    }
+
+    /// Flushes and closes the current file, completing its compressed stream.
+    fn close(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(file) => file.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves on to the dated file for a new period.
+    fn start_period(&mut self, period: RotatePeriod, now: &NaiveDateTime) -> io::Result<()> {
+        self.close()?;
+        let finished = std::mem::replace(&mut self.path, dated_path(&self.base_path, period, now));
+        self.period_key = period_key(period, now);
+        self.file = Some(self.create_file()?);
+        self.point_current_link();
+        if self.compress_rotated {
+            self.compress_in_background(finished);
+        }
+        self.start_file()
+    }
+
+    /// Points `<path>.current` at the file now being written. Failing to doesn't stop
+    /// the log, and is only warned about.
+    fn point_current_link(&self) {
+        let link = suffixed_path(&self.base_path, ".current");
+        if let Err(e) = replace_current_link(&link, &self.path) {
+            diag::warning!(
+                "Couldn't point '{}' at '{}': {}",
+                link.display(),
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Closes the current file, shifts the older files up, and opens a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        // Everything already written is flushed into the file that's being moved aside.
+        // The file is closed before renaming because Windows can't rename an open file.
+        self.close()?;
+        // The files can't be renamed while one of them is still being compressed.
+        self.wait_for_compression();
+
+        let shifted = self.shift_files();
+        let reopened = match shifted {
+            Ok(()) => self.create_file(),
+            // If the renames failed, keep appending to the original file rather than
+            // truncating it, so nothing already written is lost. (A compressed file gets
+            // a second stream appended, which still decompresses as one.)
+            Err(_) => self.append_file(),
+        };
+        self.file = Some(reopened?);
+        shifted?;
+        if self.compress_rotated {
+            self.compress_in_background(numbered_path(&self.path, 1));
+        }
+        self.start_file()
+    }
+
+    /// Starts gzipping `path`, after any earlier file has been done.
+    fn compress_in_background(&mut self, path: PathBuf) {
+        self.wait_for_compression();
+        let compress = move || {
+            if let Err(e) = gzip_file(&path) {
+                diag::error!("Couldn't compress '{}': {}", path.display(), e);
+            }
+        };
+        // Without a thread, the file is compressed before writing carries on.
+        let spawned = thread::Builder::new()
+            .name("compress".to_string())
+            .spawn(compress.clone());
+        match spawned {
+            Ok(thread) => self.compressing = Some(thread),
+            Err(_) => compress(),
+        }
+    }
+
+    /// Waits for the file being compressed, if there is one, to be done.
+    fn wait_for_compression(&mut self) {
+        if let Some(thread) = self.compressing.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Resets the size count for a newly opened file and writes the header to it.
+    fn start_file(&mut self) -> io::Result<()> {
+        self.written = 0;
+        let header = std::mem::take(&mut self.header);
+        let result = self.write_to_file(&header);
+        self.header = header;
+        result
+    }
+
+    /// Renames `path.(n-1)` to `path.n` and so on down to `path` -> `path.1`, deleting
+    /// the oldest file so that at most `max_files` remain. Files already compressed
+    /// after rotating, `path.n.gz`, are shifted the same way.
+    fn shift_files(&self) -> io::Result<()> {
+        let oldest = numbered_path(&self.path, self.max_files);
+        for oldest in [gz_path(&oldest), oldest] {
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+        }
+        for n in (1..self.max_files).rev() {
+            let from = numbered_path(&self.path, n);
+            let to = numbered_path(&self.path, n + 1);
+            for (from, to) in [(gz_path(&from), gz_path(&to)), (from, to)] {
+                if from.exists() {
+                    fs::rename(&from, to)?;
+                }
+            }
+        }
+        fs::rename(&self.path, numbered_path(&self.path, 1))
+    }
+
+    /// Writes `buf` in full to the current file and counts it towards the size limit.
+    fn write_to_file(&mut self, buf: &[u8]) -> io::Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::other("log file is not open"))?;
+        file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl Write for RotatingWriter {
+    /// Writes all of `buf` to one file, first starting a new file if the period has
+    /// changed or `buf` wouldn't fit in the current one. A record bigger than the size
+    /// limit still goes into a file of its own.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(period) = self.period {
+            let now = now(self.clock.as_ref(), self.timezone);
+            if period_key(period, &now) != self.period_key {
+                self.start_period(period, &now)?;
+            }
+        }
+
+        let has_records = self.written > self.header.len() as u64;
+        if let Some(max_size) = self.max_size
+            && has_records
+            && self.written + buf.len() as u64 > max_size
+        {
+            self.rotate()?;
+        }
+
+        self.write_to_file(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RotatingWriter {
+    fn drop(&mut self) {
+        self.wait_for_compression();
+    }
+}
+
+impl SyncWrite for RotatingWriter {
+    fn sync(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.sync(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The current date and time on `clock`, in `timezone`.
+pub(crate) fn now(clock: &dyn Clock, timezone: Timezone) -> NaiveDateTime {
+    let now = clock.now();
+    match timezone {
+        Timezone::Local => now.naive_local(),
+        Timezone::Utc => now.naive_utc(),
+    }
+}
+
+/// Which period `time` falls in.
+pub(crate) fn period_key(period: RotatePeriod, time: &NaiveDateTime) -> PeriodKey {
+    match period {
+        RotatePeriod::Daily => (time.year(), time.ordinal(), 0),
+        RotatePeriod::Hourly => (time.year(), time.ordinal(), time.hour()),
+    }
+}
+
+/// Adds the period's date to the file name, before the extension:
+/// `logs/udp_packets.log` becomes `logs/udp_packets-2024-06-01.log`.
+fn dated_path(path: &Path, period: RotatePeriod, time: &NaiveDateTime) -> PathBuf {
+    let date = match period {
+        RotatePeriod::Daily => time.format("%Y-%m-%d"),
+        RotatePeriod::Hourly => time.format("%Y-%m-%d-%H"),
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, date, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, date),
+    };
+    path.with_file_name(name)
+}
+
+/// `udp_packets.log` with `n` = 2 becomes `udp_packets.log.2`.
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    suffixed_path(path, &format!(".{}", n))
+}
+
+/// `udp_packets.log` with `suffix` = `.current` becomes `udp_packets.log.current`.
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Makes `link` a symbolic link to `target`, which is in the same directory, by its
+/// name alone, so the two can be moved together.
+#[cfg(unix)]
+fn replace_current_link(link: &Path, target: &Path) -> io::Result<()> {
+    let temp = suffixed_path(link, ".tmp");
+    if let Err(e) = fs::remove_file(&temp)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        return Err(e);
+    }
+    std::os::unix::fs::symlink(target.file_name().unwrap_or_default(), &temp)?;
+    fs::rename(&temp, link)
+}
+
+/// Without symbolic links, `link` holds the name of `target` on a line of its own.
+#[cfg(not(unix))]
+fn replace_current_link(link: &Path, target: &Path) -> io::Result<()> {
+    let temp = suffixed_path(link, ".tmp");
+    let mut name = target
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    name.push('\n');
+    fs::write(&temp, name)?;
+    fs::rename(&temp, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::Duration;
+
+    use chrono::{Local, TimeZone, Utc};
+
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn clock_at(hour: u32, min: u32, sec: u32) -> Arc<MockClock> {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, hour, min, sec).unwrap();
+        Arc::new(MockClock::new(now.with_timezone(&Local)))
+    }
+
+    #[test]
+    fn hourly_files_are_named_after_the_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = clock_at(13, 59, 0);
+        let mut writer = RotatingWriter::new_with_clock(
+            dir.path().join("udp.log"),
+            None,
+            1,
+            Some(RotatePeriod::Hourly),
+            Timezone::Utc,
+            clock.clone(),
+        )
+        .unwrap();
+        writer.write_all(b"one\n").unwrap();
+        clock.advance(Duration::from_secs(30));
+        writer.write_all(b"two\n").unwrap();
+        clock.advance(Duration::from_secs(60));
+        writer.write_all(b"three\n").unwrap();
+        drop(writer);
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("udp-2024-06-01-13.log"), "one\ntwo\n");
+        assert_eq!(read("udp-2024-06-01-14.log"), "three\n");
+    }
+
+    #[test]
+    fn an_idle_period_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = clock_at(10, 0, 0);
+        let mut writer = RotatingWriter::new_with_clock(
+            dir.path().join("udp.log"),
+            None,
+            1,
+            Some(RotatePeriod::Daily),
+            Timezone::Utc,
+            clock.clone(),
+        )
+        .unwrap();
+        writer.write_all(b"saturday\n").unwrap();
+        clock.advance(Duration::from_secs(2 * 24 * 3600));
+        writer.write_all(b"monday\n").unwrap();
+        drop(writer);
+
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "udp-2024-06-01.log",
+                "udp-2024-06-03.log",
+                "udp.log.current"
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn the_current_link_follows_the_dated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = clock_at(13, 59, 30);
+        let mut writer = RotatingWriter::new_with_clock(
+            dir.path().join("udp.log"),
+            None,
+            1,
+            Some(RotatePeriod::Hourly),
+            Timezone::Utc,
+            clock.clone(),
+        )
+        .unwrap();
+        let link = dir.path().join("udp.log.current");
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            Path::new("udp-2024-06-01-13.log")
+        );
+
+        clock.advance(Duration::from_secs(60));
+        writer.write_all(b"two\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            Path::new("udp-2024-06-01-14.log")
+        );
+        assert_eq!(fs::read_to_string(&link).unwrap(), "two\n");
+        assert!(!dir.path().join("udp.log.current.tmp").exists());
+    }
+
+    #[test]
+    fn size_rotation_keeps_the_newest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("udp.log");
+        let mut writer = RotatingWriter::new_with_clock(
+            &path,
+            Some(8),
+            2,
+            None,
+            Timezone::Utc,
+            clock_at(0, 0, 0),
+        )
+        .unwrap();
+        for record in ["aaaa\n", "bbbb\n", "cccc\n", "dddd\n"] {
+            writer.write_all(record.as_bytes()).unwrap();
+        }
+        drop(writer);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddd\n");
+        assert_eq!(
+            fs::read_to_string(numbered_path(&path, 1)).unwrap(),
+            "cccc\n"
+        );
+        assert_eq!(
+            fs::read_to_string(numbered_path(&path, 2)).unwrap(),
+            "bbbb\n"
+        );
+        assert!(!numbered_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn an_appended_file_counts_what_it_already_holds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("udp.log");
+        fs::write(&path, "aaaa\n").unwrap();
+        let mut writer = RotatingWriter::with_existing_log(
+            &path,
+            Some(8),
+            2,
+            None,
+            Timezone::Utc,
+            clock_at(0, 0, 0),
+            ExistingLog::Append,
+        )
+        .unwrap();
+        writer.write_all(b"bbbb\n").unwrap();
+        drop(writer);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "bbbb\n");
+        assert_eq!(
+            fs::read_to_string(numbered_path(&path, 1)).unwrap(),
+            "aaaa\n"
+        );
+    }
+
+    #[test]
+    fn rotated_files_are_compressed_in_the_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("udp.log");
+        let mut writer = RotatingWriter::new_with_clock(
+            &path,
+            Some(8),
+            2,
+            None,
+            Timezone::Utc,
+            clock_at(0, 0, 0),
+        )
+        .unwrap()
+        .with_rotated_compression();
+        for record in ["aaaa\n", "bbbb\n", "cccc\n", "dddd\n"] {
+            writer.write_all(record.as_bytes()).unwrap();
+        }
+        // Dropping the writer waits for the last rotated file to be compressed.
+        drop(writer);
+
+        let gunzip = |path: PathBuf| {
+            let mut text = String::new();
+            flate2::read::GzDecoder::new(File::open(path).unwrap())
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddd\n");
+        assert_eq!(gunzip(gz_path(&numbered_path(&path, 1))), "cccc\n");
+        assert_eq!(gunzip(gz_path(&numbered_path(&path, 2))), "bbbb\n");
+        assert!(!numbered_path(&path, 1).exists());
+        assert!(!numbered_path(&path, 2).exists());
+        assert!(!gz_path(&numbered_path(&path, 3)).exists());
+    }
+}
@@ -0,0 +1,50 @@
+// Deciding which packets make it into a sampled log.
+
+use ahash::RandomState;
+
+use crate::config::Sample;
+
+/// Makes the keep-or-skip decision for [`Config::sample`](crate::Config::sample), one
+/// packet at a time.
+///
+/// Both kinds of sampling take a few arithmetic operations per packet. The random one
+/// uses xorshift64*, which is plenty for picking packets and needs no state beyond one
+/// number; its seed comes from ahash's per-process random keys, so separate runs pick
+/// different packets.
+pub(crate) struct Sampler {
+    sample: Sample,
+    /// Packets seen so far, for [`Sample::Every`].
+    seen: u64,
+    /// The generator's state, for [`Sample::Rate`]. Never zero.
+    state: u64,
+}
+
+impl Sampler {
+    pub(crate) fn new(sample: Sample) -> Sampler {
+        Sampler {
+            sample,
+            seen: 0,
+            state: RandomState::new().hash_one(0u64) | 1,
+        }
+    }
+
+    /// Returns true if the next packet should be logged.
+    pub(crate) fn keep(&mut self) -> bool {
+        match self.sample {
+            Sample::Every(n) => {
+                let keep = self.seen.is_multiple_of(n.max(1));
+                self.seen += 1;
+                keep
+            }
+            Sample::Rate(rate) => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                let random = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+                // The top 53 bits, as a fraction in [0, 1).
+                let fraction = (random >> 11) as f64 / (1u64 << 53) as f64;
+                fraction < rate
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+// Writing the log as numbered segment files in a directory, indexed by a manifest.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
+use crate::compress::Compressor;
+use crate::config::{Compression, Config, Format, LineEnding, RotatePeriod, Timezone};
+use crate::diag;
+use crate::format::TimeFormat;
+use crate::packet::Packet;
+use crate::rotate::{PeriodKey, now, period_key};
+use crate::writer::{AfterWrite, LogWriter, SyncWrite, buffer, buffer_size};
+
+/// The index of the segments, in the output directory.
+pub(crate) const MANIFEST_NAME: &str = "manifest.json";
+
+/// Writes the log as a series of files, `segment-000001.log`, `segment-000002.log`, ...,
+/// in one directory, with a `manifest.json` listing them:
+///
+/// ```text
+/// {
+///   "segments": [
+///     {
+///       "file": "segment-000001.log",
+///       "session_id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+///       "first_packet": "2024-06-01T12:00:00.123+02:00",
+///       "last_packet": "2024-06-01T12:59:59.870+02:00",
+///       "packets": 1520,
+///       "bytes": 60800
+///     }
+///   ]
+/// }
+/// ```
+///
+/// so a reader can find the segments covering a time window without opening any of
+/// them. `bytes` counts payload bytes, as in the [`Stats`](crate::Stats), and the
+/// times are RFC 3339 with milliseconds; they're `null` for a segment with no packets
+/// yet.
+///
+/// A new segment is started for the first packet after the current one has reached
+/// `max_size` bytes (so it may go over by one entry) or the day or hour has changed
+/// with a rotation period, and when the log is reopened. Each
+/// segment is a complete file of its format, with its own pcap or binary header, and
+/// compressed ones end in `.gz`. The manifest is rewritten whenever a segment starts,
+/// whenever the log is synced (by the flush interval, for instance) and at shutdown,
+/// each time to a temporary file that then replaces it, so it's never seen half
+/// written. A directory that already has a manifest is added to, numbering on from
+/// its segments.
+pub(crate) struct SegmentWriter {
+    dir: PathBuf,
+    format: Format,
+    local_addr: SocketAddr,
+    buffer_size: Option<usize>,
+    session_id: String,
+    compression: Option<Compression>,
+    max_size: Option<u64>,
+    period: Option<RotatePeriod>,
+    timezone: Timezone,
+    clock: Arc<dyn Clock>,
+    /// The current segment, always the last in the manifest.
+    current: Box<LogWriter>,
+    /// Bytes written to the current segment, before compression.
+    written: Arc<AtomicU64>,
+    period_key: PeriodKey,
+    manifest: Manifest,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    segments: Vec<Segment>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Segment {
+    file: String,
+    session_id: String,
+    first_packet: Option<String>,
+    last_packet: Option<String>,
+    packets: u64,
+    bytes: u64,
+}
+
+impl SegmentWriter {
+    /// Reads the manifest in `dir`, which must already exist, if there is one, and
+    /// starts the first segment of this run.
+    pub(crate) fn new(
+        dir: &Path,
+        config: &Config,
+        local_addr: SocketAddr,
+    ) -> io::Result<SegmentWriter> {
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' is not a directory", dir.display()),
+            ));
+        }
+        let manifest = read_manifest(&dir.join(MANIFEST_NAME))?;
+        let now = now(config.clock.as_ref(), config.time_format.timezone);
+        let mut writer = SegmentWriter {
+            dir: dir.to_path_buf(),
+            format: config.format,
+            local_addr,
+            buffer_size: buffer_size(config),
+            session_id: config.session_id.clone().unwrap_or_default(),
+            compression: config.compress,
+            max_size: config.max_log_size,
+            period: config.rotate,
+            timezone: config.time_format.timezone,
+            clock: Arc::clone(&config.clock),
+            // Replaced by the first segment straight away.
+            current: Box::new(LogWriter::Lines(Box::new(io::sink()))),
+            written: Arc::default(),
+            period_key: config
+                .rotate
+                .map_or((0, 0, 0), |period| period_key(period, &now)),
+            manifest,
+        };
+        writer.start_segment()?;
+        Ok(writer)
+    }
+
+    /// Writes a packet to the current segment, first starting a new one if it's full
+    /// or its period is over.
+    pub(crate) fn write_packet(
+        &mut self,
+        pkt: &Packet,
+        time_format: &TimeFormat,
+        line_ending: &LineEnding,
+        after: AfterWrite,
+    ) -> io::Result<()> {
+        if self.is_due() {
+            self.next_segment()?;
+        }
+        self.current
+            .write_packet(pkt, self.format, time_format, line_ending, after)?;
+        let segment = self
+            .manifest
+            .segments
+            .last_mut()
+            .expect("a segment is open");
+        let time = timestamp(&pkt.received_at);
+        segment.first_packet.get_or_insert_with(|| time.clone());
+        segment.last_packet = Some(time);
+        segment.packets += 1;
+        segment.bytes += pkt.original_len as u64;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.current.flush()
+    }
+
+    /// Syncs the current segment, then brings the manifest up to date.
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        self.current.sync()?;
+        self.write_manifest()
+    }
+
+    /// Finishes the current segment and starts the next, for a reopen.
+    pub(crate) fn next_segment(&mut self) -> io::Result<()> {
+        self.current.sync()?;
+        self.start_segment()
+    }
+
+    /// Whether the current segment has had its share: it's reached the size limit, or
+    /// the period changed since it started. An empty segment is never left behind.
+    fn is_due(&mut self) -> bool {
+        let has_packets = self
+            .manifest
+            .segments
+            .last()
+            .is_some_and(|segment| segment.packets > 0);
+        let full = self
+            .max_size
+            .is_some_and(|max_size| self.written.load(Ordering::Relaxed) >= max_size);
+        let new_period = self.period.is_some_and(|period| {
+            let key = period_key(period, &now(self.clock.as_ref(), self.timezone));
+            let changed = key != self.period_key;
+            self.period_key = key;
+            changed
+        });
+        has_packets && (full || new_period)
+    }
+
+    /// Creates the next numbered file, adds it to the manifest and writes the manifest.
+    fn start_segment(&mut self) -> io::Result<()> {
+        let extension = match self.format {
+            Format::Pcap => "pcap",
+            Format::Bin => "bin",
+            Format::Json => "jsonl",
+            _ => "log",
+        };
+        let compressed = if self.compression.is_some() {
+            ".gz"
+        } else {
+            ""
+        };
+        // Numbered on from the manifest, skipping any file left without an entry in it.
+        let mut number = self.manifest.segments.len() + 1;
+        let (name, file) = loop {
+            let name = format!("segment-{:06}.{}{}", number, extension, compressed);
+            let path = self.dir.join(&name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (name, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => number += 1,
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("Couldn't create '{}': {}", path.display(), e),
+                    ));
+                }
+            }
+        };
+
+        self.written = Arc::default();
+        let out = Counted {
+            inner: Compressor::new(file, self.compression),
+            written: Arc::clone(&self.written),
+        };
+        let segment = LogWriter::wrap(
+            buffer(Box::new(out), self.buffer_size),
+            self.format,
+            self.local_addr,
+            &self.session_id,
+            false,
+        )?;
+        // Dropping the old segment finishes its compressed stream.
+        *self.current = segment;
+        self.manifest.segments.push(Segment {
+            file: name,
+            session_id: self.session_id.clone(),
+            first_packet: None,
+            last_packet: None,
+            packets: 0,
+            bytes: 0,
+        });
+        self.write_manifest()
+    }
+
+    /// Replaces the manifest with the current one, through a temporary file so a
+    /// crash leaves either the old manifest or the new one.
+    fn write_manifest(&self) -> io::Result<()> {
+        let path = self.dir.join(MANIFEST_NAME);
+        let temp = self.dir.join(format!("{}.tmp", MANIFEST_NAME));
+        let fail = |e: io::Error| {
+            io::Error::new(
+                e.kind(),
+                format!("Couldn't write the manifest '{}': {}", path.display(), e),
+            )
+        };
+        let mut json = serde_json::to_vec_pretty(&self.manifest).map_err(io::Error::other)?;
+        json.push(b'\n');
+        let mut file = File::create(&temp).map_err(fail)?;
+        file.write_all(&json).map_err(fail)?;
+        file.sync_data().map_err(fail)?;
+        fs::rename(&temp, &path).map_err(fail)
+    }
+}
+
+/// Finishes the last segment and records its final counts.
+impl Drop for SegmentWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            diag::error!("Error writing to '{}': {}", self.dir.display(), e);
+        }
+    }
+}
+
+/// The manifest at `path`, or an empty one if there isn't one yet. One that can't be
+/// read is an error rather than being replaced, which would lose the index.
+fn read_manifest(path: &Path) -> io::Result<Manifest> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Manifest::default()),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_slice(&json).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Couldn't read the manifest '{}': {}", path.display(), e),
+        )
+    })
+}
+
+fn timestamp(time: &DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+/// A segment file that counts the bytes written to it, for the size limit.
+struct Counted {
+    inner: Compressor<File>,
+    written: Arc<AtomicU64>,
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl SyncWrite for Counted {
+    fn sync(&mut self) -> io::Result<()> {
+        self.inner.sync()
+    }
+}
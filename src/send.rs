@@ -0,0 +1,329 @@
+// Sending test traffic, so the listener can be exercised without any other tools.
+
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+/// The payload size used when no other size is configured.
+pub const DEFAULT_SEND_SIZE: usize = 64;
+
+/// Settings for [`send`].
+#[derive(Debug, Clone)]
+pub struct SendConfig {
+    /// Where the datagrams are sent.
+    pub to: SocketAddr,
+    /// How many datagrams to send.
+    pub count: u64,
+    /// Payload size in bytes, for generated payloads.
+    pub size: usize,
+    /// Datagrams per second. `None` sends as fast as possible.
+    pub rate: Option<f64>,
+    /// What each datagram contains.
+    pub payload: Payload,
+    /// Write the send time into each payload at this byte offset, as a 64-bit
+    /// big-endian count of nanoseconds since the Unix epoch, for the listener's
+    /// [`Config::latency_offset`](crate::Config::latency_offset). A payload too short
+    /// for it is lengthened with zeros.
+    pub timestamp_offset: Option<usize>,
+}
+
+impl SendConfig {
+    /// Settings for sending `count` datagrams of the default size to `to`, unpaced.
+    pub fn new(to: SocketAddr, count: u64) -> SendConfig {
+        SendConfig {
+            to,
+            count,
+            size: DEFAULT_SEND_SIZE,
+            rate: None,
+            payload: Payload::Sequence,
+            timestamp_offset: None,
+        }
+    }
+}
+
+/// The contents of each datagram sent by [`send`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// Readable text starting with the datagram's sequence number (`42 abcdef...`),
+    /// padded to the configured size, so gaps in the log are easy to spot.
+    Sequence,
+    /// Fresh pseudo-random bytes of the configured size for every datagram.
+    Random,
+    /// The contents of this file, sent unchanged in every datagram.
+    File(PathBuf),
+    /// The contents of this file, with placeholders filled in afresh for every datagram:
+    ///
+    /// | Placeholder | Replaced with |
+    /// |-------------|---------------|
+    /// | `{{seq}}` | the datagram's sequence number, counting from 0, as 4 bytes big-endian |
+    /// | `{{timestamp}}` | the send time, nanoseconds since the Unix epoch, as 8 bytes big-endian (signed) |
+    /// | `{{uuid}}` | a new random UUID, as 36 bytes of text such as `67e55044-10b1-426f-9247-bb680e5fe0c8` |
+    ///
+    /// Everything else is sent as it is. A field's offset in the datagram is the
+    /// length of everything before it once substituted, so for a file starting
+    /// `{{seq}}{{timestamp}}` the sequence number is at offset 0 and the time at 4,
+    /// which is what the listener's [`Config::seq_offset`](crate::Config::seq_offset) and
+    /// [`Config::latency_offset`](crate::Config::latency_offset) are then set to. Any
+    /// other `{{name}}` is an error.
+    Template(PathBuf),
+}
+
+impl Payload {
+    /// Interprets a `--payload` argument: `random`, or else a file name.
+    pub fn from_arg(value: &str) -> Payload {
+        if value == "random" {
+            Payload::Random
+        } else {
+            Payload::File(PathBuf::from(value))
+        }
+    }
+}
+
+/// Totals for a finished [`send`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendSummary {
+    /// Datagrams sent successfully.
+    pub sent: u64,
+    /// Payload bytes sent.
+    pub bytes: u64,
+    /// Datagrams the operating system refused to send.
+    pub failed: u64,
+}
+
+/// Sends `config.count` datagrams to `config.to`, pacing them to `config.rate`.
+///
+/// Pacing is against the start time rather than the previous datagram, so a slow send
+/// is caught up on instead of lowering the overall rate. A datagram that fails to send
+/// (for example because an earlier one drew an ICMP "port unreachable") is counted and
+/// the rest are still sent; only failing to set up the socket or read the payload file
+/// is an error, or a rate so low that the last datagram would be due too far off to
+/// wait for.
+pub fn send(config: &SendConfig) -> io::Result<SendSummary> {
+    let fixed = match &config.payload {
+        Payload::File(path) => Some(read_payload_file(path)?),
+        _ => None,
+    };
+    let template = match &config.payload {
+        Payload::Template(path) => {
+            Some(Template::parse(&read_payload_file(path)?).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Couldn't use template '{}': {}", path.display(), e),
+                )
+            })?)
+        }
+        _ => None,
+    };
+
+    let socket = sending_socket(config.to)?;
+
+    let interval = config
+        .rate
+        .filter(|rate| *rate > 0.0)
+        .map(|rate| 1.0 / rate);
+    let mut random = Xorshift::seeded();
+    let mut buf = Vec::with_capacity(config.size);
+    let mut stamped = Vec::new();
+    let mut summary = SendSummary {
+        sent: 0,
+        bytes: 0,
+        failed: 0,
+    };
+    let start = Instant::now();
+    if let Some(interval) = interval.filter(|_| config.count > 1) {
+        let last = interval * (config.count - 1) as f64;
+        if Duration::try_from_secs_f64(last)
+            .ok()
+            .and_then(|last| start.checked_add(last))
+            .is_none()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The rate is too low to send {} datagrams: the last would be due too far off",
+                    config.count
+                ),
+            ));
+        }
+    }
+
+    for seq in 0..config.count {
+        if let Some(interval) = interval.filter(|_| seq > 0) {
+            let due = start + Duration::from_secs_f64(interval * seq as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+
+        let mut payload: &[u8] = match &fixed {
+            Some(data) => data,
+            None => {
+                buf.clear();
+                match (&template, &config.payload) {
+                    (Some(template), _) => template.fill(&mut buf, seq),
+                    (None, Payload::Random) => {
+                        buf.extend((0..config.size).map(|_| random.next_byte()))
+                    }
+                    _ => fill_sequence(&mut buf, seq, config.size),
+                }
+                &buf
+            }
+        };
+        if let Some(offset) = config.timestamp_offset {
+            stamped.clear();
+            stamped.extend_from_slice(payload);
+            if stamped.len() < offset + 8 {
+                stamped.resize(offset + 8, 0);
+            }
+            stamped[offset..offset + 8].copy_from_slice(&now_nanos().to_be_bytes());
+            payload = &stamped;
+        }
+        match socket.send_to(payload, config.to) {
+            Ok(n) => {
+                summary.sent += 1;
+                summary.bytes += n as u64;
+            }
+            Err(_) => summary.failed += 1,
+        }
+    }
+    Ok(summary)
+}
+
+fn read_payload_file(path: &Path) -> io::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Couldn't read payload file '{}': {}", path.display(), e),
+        )
+    })
+}
+
+/// The time now in nanoseconds since the Unix epoch, as written into payloads.
+fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as i64)
+}
+
+/// Opens a socket to send to `to` from, on an ephemeral port of the same address
+/// family.
+pub(crate) fn sending_socket(to: SocketAddr) -> io::Result<UdpSocket> {
+    let local: SocketAddr = if to.is_ipv4() {
+        "0.0.0.0:0".parse().expect("valid address")
+    } else {
+        "[::]:0".parse().expect("valid address")
+    };
+    UdpSocket::bind(local)
+}
+
+/// Writes `seq` followed by a space and the alphabet, repeated, cut to `size` bytes.
+fn fill_sequence(buf: &mut Vec<u8>, seq: u64, size: usize) {
+    buf.extend_from_slice(format!("{} ", seq).as_bytes());
+    buf.extend((b'a'..=b'z').cycle().take(size.saturating_sub(buf.len())));
+    buf.truncate(size);
+}
+
+/// A [`Payload::Template`], split into its text and its placeholders.
+struct Template {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(Vec<u8>),
+    Seq,
+    Timestamp,
+    Uuid,
+}
+
+impl Template {
+    fn parse(mut rest: &[u8]) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut text = Vec::new();
+        while !rest.is_empty() {
+            if let Some((name, after)) = placeholder(rest) {
+                let part = match name {
+                    b"seq" => Part::Seq,
+                    b"timestamp" => Part::Timestamp,
+                    b"uuid" => Part::Uuid,
+                    _ => {
+                        return Err(format!(
+                            "unknown placeholder '{{{{{}}}}}'; expected {{{{seq}}}}, \
+                             {{{{timestamp}}}} or {{{{uuid}}}}",
+                            String::from_utf8_lossy(name)
+                        ));
+                    }
+                };
+                if !text.is_empty() {
+                    parts.push(Part::Text(mem::take(&mut text)));
+                }
+                parts.push(part);
+                rest = after;
+            } else {
+                text.push(rest[0]);
+                rest = &rest[1..];
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Writes the payload for the datagram numbered `seq`.
+    fn fill(&self, buf: &mut Vec<u8>, seq: u64) {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => buf.extend_from_slice(text),
+                // Wraps after 2^32 datagrams, as the listener's sequence numbers do.
+                Part::Seq => buf.extend_from_slice(&(seq as u32).to_be_bytes()),
+                Part::Timestamp => buf.extend_from_slice(&now_nanos().to_be_bytes()),
+                Part::Uuid => {
+                    let mut text = Uuid::encode_buffer();
+                    buf.extend_from_slice(
+                        Uuid::new_v4()
+                            .hyphenated()
+                            .encode_lower(&mut text)
+                            .as_bytes(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// If `data` starts with a placeholder, `{{` and a name of letters, digits and
+/// underscores followed by `}}`, returns the name and what comes after it.
+fn placeholder(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let inner = data.strip_prefix(b"{{")?;
+    let len = inner
+        .iter()
+        .position(|b| !(b.is_ascii_alphanumeric() || *b == b'_'))?;
+    let after = inner[len..].strip_prefix(b"}}")?;
+    (len > 0).then_some((&inner[..len], after))
+}
+
+/// A tiny xorshift generator. Test payloads only need to look random, so this avoids
+/// a dependency on a full random number crate.
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn seeded() -> Xorshift {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        // The state must never be zero.
+        Xorshift(nanos | 1)
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 56) as u8
+    }
+}
@@ -0,0 +1,79 @@
+// Finding lost and reordered packets from sequence numbers in the payload.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::lru::Recency;
+
+/// What a packet's sequence number says about the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SequenceEvent {
+    /// The numbers from `first` to `last` were skipped.
+    Gap { first: u32, last: u32 },
+    /// The packet came after a later-numbered one, most likely overtaken on the way.
+    Reordered { seq: u32, expected: u32 },
+    /// The packet has the same number as the one before it.
+    Repeated { seq: u32 },
+}
+
+/// Follows the sequence number each source puts in its payloads: a 32-bit big-endian
+/// number at a fixed offset, one higher in every packet, wrapping after `u32::MAX`.
+///
+/// A number more than half the range ahead of the expected one is taken to be an old
+/// packet arriving late rather than a gap of billions. At most `max_sources` sources
+/// are followed at once; a source forgotten to make room for another starts afresh
+/// with its next packet, as if it were its first.
+pub(crate) struct SequenceTracker {
+    offset: usize,
+    /// The number each source is expected to send next.
+    expected: HashMap<SocketAddr, u32>,
+    recency: Recency<SocketAddr>,
+    /// Sources forgotten since [`take_evicted`](Self::take_evicted) last returned them.
+    evicted: u64,
+}
+
+impl SequenceTracker {
+    pub(crate) fn new(offset: usize, max_sources: usize) -> SequenceTracker {
+        SequenceTracker {
+            offset,
+            expected: HashMap::new(),
+            recency: Recency::new(max_sources),
+            evicted: 0,
+        }
+    }
+
+    /// How many sources were forgotten to make room since the last call.
+    pub(crate) fn take_evicted(&mut self) -> u64 {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Checks the sequence number in `data` from `src`. Returns `None` for the next
+    /// number in order, for a source's first packet and for a payload too short to hold
+    /// a number at the offset.
+    pub(crate) fn check(&mut self, src: SocketAddr, data: &[u8]) -> Option<SequenceEvent> {
+        let bytes = data.get(self.offset..self.offset.checked_add(4)?)?;
+        let seq = u32::from_be_bytes(bytes.try_into().expect("four bytes"));
+        if let Some(forgotten) = self.recency.touch(src) {
+            self.expected.remove(&forgotten);
+            self.evicted += 1;
+        }
+        // A source's first packet only sets what to expect next.
+        let expected = self.expected.insert(src, seq.wrapping_add(1))?;
+        match seq.wrapping_sub(expected) {
+            0 => None,
+            u32::MAX => {
+                self.expected.insert(src, expected);
+                Some(SequenceEvent::Repeated { seq })
+            }
+            ahead if ahead <= u32::MAX / 2 => Some(SequenceEvent::Gap {
+                first: expected,
+                last: seq.wrapping_sub(1),
+            }),
+            _ => {
+                // A late packet doesn't move the expected number back.
+                self.expected.insert(src, expected);
+                Some(SequenceEvent::Reordered { seq, expected })
+            }
+        }
+    }
+}
@@ -0,0 +1,32 @@
+// Identifying a capture run, so records from several runs can be told apart.
+
+use uuid::Uuid;
+
+/// The longest session ID allowed, in bytes, so it fits the one-byte length in a
+/// binary capture header.
+pub const MAX_SESSION_ID_LEN: usize = 255;
+
+/// A new random session ID: a version 4 UUID such as
+/// `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+pub(crate) fn new_session_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Checks that `id` can be used as a session ID: between 1 and [`MAX_SESSION_ID_LEN`]
+/// bytes, with no control characters, so it stays on one line in text output.
+pub fn check_session_id(id: &str) -> Result<(), String> {
+    if id.is_empty() {
+        return Err("a session ID can't be empty".to_string());
+    }
+    if id.len() > MAX_SESSION_ID_LEN {
+        return Err(format!(
+            "a session ID can be at most {} bytes, not {}",
+            MAX_SESSION_ID_LEN,
+            id.len()
+        ));
+    }
+    if id.chars().any(char::is_control) {
+        return Err("a session ID can't contain control characters".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// The destinations logged packets are written to.
+
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::config::{Config, Format, LineEnding};
+use crate::diag;
+use crate::format::TimeFormat;
+use crate::packet::Packet;
+use crate::writer::{AfterWrite, LogWriter};
+
+/// Somewhere logged packets go.
+///
+/// A [`UdpLogger`](crate::UdpLogger) writes every packet it logs to each of its sinks in
+/// turn: the console when it prints packets, the configured output, the event socket
+/// and database if there are, and any added with
+/// [`add_sink`](crate::UdpLogger::add_sink). A sink that fails doesn't stop the others
+/// from getting the packet. Packets reach the sinks after sampling and after being cut
+/// to the snap length; echoes and forwarded copies are sent before either, with the
+/// whole datagram, so they aren't sinks.
+///
+/// With [`Config::workers`](crate::Config::workers) the sinks are written from the
+/// worker threads, one packet at a time, which is why they must be `Send`.
+pub trait Sink: Send {
+    /// Writes one packet.
+    fn write(&mut self, pkt: &Packet) -> io::Result<()>;
+
+    /// Pushes anything buffered on to the destination.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Flushes and, where the destination can, waits until what was written is stored
+    /// durably. Called for the flushes forced by the flush interval, a reopen or
+    /// shutdown (see [`FlushPolicy`](crate::FlushPolicy)). Only flushes unless
+    /// overridden.
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    /// What the sink is called in error messages, such as `'udp_packets.log'`.
+    fn name(&self) -> String {
+        "a custom sink".to_string()
+    }
+}
+
+/// The configured output, written in the configured format.
+///
+/// The writer is shared with the logger, which reopens it on request.
+pub(crate) struct LogSink {
+    writer: Arc<Mutex<LogWriter>>,
+    format: Format,
+    time_format: TimeFormat,
+    line_ending: LineEnding,
+    after: AfterWrite,
+    name: String,
+}
+
+impl LogSink {
+    pub(crate) fn new(writer: Arc<Mutex<LogWriter>>, config: &Config) -> LogSink {
+        LogSink {
+            writer,
+            format: config.format,
+            time_format: config.time_format.clone(),
+            line_ending: config.line_ending.clone(),
+            after: AfterWrite::for_config(config),
+            name: config.output.to_string(),
+        }
+    }
+}
+
+impl Sink for LogSink {
+    fn write(&mut self, pkt: &Packet) -> io::Result<()> {
+        lock_writer(&self.writer).write_packet(
+            pkt,
+            self.format,
+            &self.time_format,
+            &self.line_ending,
+            self.after,
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock_writer(&self.writer).flush()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        lock_writer(&self.writer).sync()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Locks the shared writer. A worker that panicked while holding the lock leaves the
+/// writer itself intact, so the lock is taken over rather than treated as an error.
+pub(crate) fn lock_writer(writer: &Mutex<LogWriter>) -> std::sync::MutexGuard<'_, LogWriter> {
+    writer.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Every sink a logger writes to, in the order they were added.
+#[derive(Default)]
+pub(crate) struct Sinks(Vec<Box<dyn Sink>>);
+
+impl Sinks {
+    pub(crate) fn push(&mut self, sink: Box<dyn Sink>) {
+        self.0.push(sink);
+    }
+
+    /// Writes a packet to every sink, carrying on past any that fail. Each failure is
+    /// reported on stderr; returns how many there were.
+    pub(crate) fn write(&mut self, pkt: &Packet) -> u64 {
+        let mut failed = 0;
+        for sink in &mut self.0 {
+            if let Err(e) = sink.write(pkt) {
+                failed += 1;
+                diag::error!("Error writing to {}: {}", sink.name(), e);
+            }
+        }
+        failed
+    }
+
+    /// Flushes every sink, returning the first error, named after its sink.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.each(|sink| sink.flush())
+    }
+
+    /// Syncs every sink, returning the first error, named after its sink.
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        self.each(|sink| sink.sync())
+    }
+
+    fn each(&mut self, mut f: impl FnMut(&mut dyn Sink) -> io::Result<()>) -> io::Result<()> {
+        let mut first_error = None;
+        for sink in &mut self.0 {
+            if let Err(e) = f(sink.as_mut()) {
+                first_error.get_or_insert_with(|| {
+                    io::Error::new(e.kind(), format!("{}: {}", sink.name(), e))
+                });
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+/// Locks the shared sinks, taking over the lock from a worker that panicked like
+/// [`lock_writer`] does.
+pub(crate) fn lock_sinks(sinks: &Mutex<Sinks>) -> std::sync::MutexGuard<'_, Sinks> {
+    sinks.lock().unwrap_or_else(PoisonError::into_inner)
+}
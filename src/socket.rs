@@ -0,0 +1,322 @@
+//! Creating and configuring the listening socket.
+//!
+//! `std::net::UdpSocket::bind` creates and binds the socket in one step, leaving no
+//! chance to set options that must be in place before binding. The socket is therefore
+//! built with `socket2`, configured, bound, and then converted into a standard
+//! `UdpSocket` for the rest of the program.
+//!
+//! # Dual-stack IPv6
+//!
+//! When bound to an IPv6 address such as `[::]:8080`, a socket can also receive IPv4
+//! traffic (seen as IPv4-mapped addresses like `::ffff:192.0.2.1`) unless the
+//! `IPV6_V6ONLY` option is set. Operating systems disagree on the default:
+//!
+//! - Linux defaults to dual-stack (`IPV6_V6ONLY` off), unless the
+//!   `net.ipv6.bindv6only` sysctl has been changed.
+//! - Windows defaults to IPv6 only (`IPV6_V6ONLY` on).
+//!
+//! To behave the same everywhere, the option is always set explicitly from
+//! [`Config::ipv6_only`](crate::Config::ipv6_only). Some systems (OpenBSD, for example)
+//! don't support dual-stack sockets at all; there a warning is printed and the socket
+//! stays IPv6 only.
+//!
+//! # Receive buffer
+//!
+//! Datagrams wait in the kernel's receive buffer (`SO_RCVBUF`) until `recv_from` picks
+//! them up, and any that arrive while it's full are dropped. A burst bigger than the
+//! default buffer is lost before the listener ever sees it, so
+//! [`Config::recv_buffer_size`](crate::Config::recv_buffer_size) can ask for a larger
+//! one. The kernel treats the size as a request: Linux doubles it to allow for its own
+//! bookkeeping and caps it at the `net.core.rmem_max` sysctl, so
+//! [`recv_buffer_size`] reports what was actually granted.
+//!
+//! # Sharing a port
+//!
+//! With [`Config::reuse_port`](crate::Config::reuse_port), `SO_REUSEPORT` is set before
+//! binding, so several listeners (separate processes, or several loggers in one) can
+//! bind the same address and port. Linux then spreads incoming datagrams across them
+//! by a hash of the source address and port, so each sender sticks to one listener.
+//! Other systems either lack the option or give it different semantics (on the BSDs
+//! and macOS only the most recent socket receives unicast traffic), so it's refused
+//! there rather than silently doing something else.
+//!
+//! # Binding to an interface
+//!
+//! With [`Config::interface`](crate::Config::interface), `SO_BINDTODEVICE` ties the
+//! socket to one network interface before it's bound, so datagrams arriving on any other
+//! are never delivered to it, even when they're for the same address. With a wildcard
+//! bind address this captures everything on that interface. It's a Linux option, so
+//! it's refused elsewhere.
+//!
+//! # Connecting to one peer
+//!
+//! With [`Config::connect`](crate::Config::connect), the bound socket is connected to a
+//! single peer. The kernel then drops datagrams from every other address before they're
+//! received, and the socket is read with `recv` and written with `send`, the peer
+//! being the only address either could mean. A connected socket is also the one kind
+//! that Linux tells about ICMP errors without `IP_RECVERR`, as described next; each is
+//! reported as a warning, since it says why the peer isn't answering.
+//!
+//! # Errors from earlier sends
+//!
+//! When an echo reaches a host where nothing is listening, that host may answer with an
+//! ICMP "port unreachable". Some systems hand this to the next receive on the socket as
+//! an error: Windows reports it as `WSAECONNRESET` on any UDP socket, and Linux as
+//! `ECONNREFUSED` on a connected one or with `IP_RECVERR`. The error says nothing about
+//! the datagram being received, so [`is_icmp_error`] picks them out for the receive
+//! loops to count and skip rather than report.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use crate::config::{Config, MulticastGroup};
+use crate::diag;
+
+/// Creates a UDP socket for `addr`, one of the configured bind addresses, applies the
+/// configured socket options, and binds it.
+pub(crate) fn bind_socket(config: &Config, addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if addr.is_ipv6()
+        && let Err(e) = socket.set_only_v6(config.ipv6_only)
+    {
+        if config.ipv6_only {
+            return Err(e);
+        }
+        diag::warning!(
+            "dual-stack sockets are not supported here, listening for IPv6 only: {}",
+            e
+        );
+    }
+
+    if let Some(size) = config.recv_buffer_size {
+        socket.set_recv_buffer_size(size).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Couldn't set the receive buffer to {} bytes: {}", size, e),
+            )
+        })?;
+    }
+
+    // Let other listeners bind the same port, which is the norm for multicast receivers.
+    if !config.multicast.is_empty() {
+        socket.set_reuse_address(true)?;
+    }
+
+    if config.reuse_port {
+        set_reuse_port(&socket)?;
+    }
+
+    if let Some(interface) = &config.interface {
+        bind_device(&socket, interface)?;
+    }
+
+    socket.bind(&addr.into()).map_err(|e| bind_error(addr, e))?;
+    Ok(socket.into())
+}
+
+/// Sets `SO_REUSEPORT`, to share the port with other listeners.
+#[cfg(target_os = "linux")]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket
+        .set_reuse_port(true)
+        .map_err(|e| io::Error::new(e.kind(), format!("Couldn't set SO_REUSEPORT: {}", e)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sharing a port between listeners (SO_REUSEPORT) is only supported on Linux",
+    ))
+}
+
+/// Sets `SO_BINDTODEVICE`, to receive only on the interface called `interface`.
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes())).map_err(|e| {
+        let reason = match e.raw_os_error() {
+            Some(libc::ENODEV) => "there's no interface by that name".to_string(),
+            Some(libc::EPERM) => {
+                "this kernel needs CAP_NET_RAW (or root) to bind to an interface".to_string()
+            }
+            _ => e.to_string(),
+        };
+        io::Error::new(
+            e.kind(),
+            format!("Couldn't bind to interface '{}': {}", interface, reason),
+        )
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &Socket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface (SO_BINDTODEVICE) is only supported on Linux",
+    ))
+}
+
+/// Connects the bound socket to `peer`, returning the address it was connected to. An
+/// IPv4 peer of an IPv6 socket is connected to as an IPv4-mapped address, which is how a
+/// dual-stack socket sees it.
+pub(crate) fn connect_peer(socket: &UdpSocket, peer: SocketAddr) -> io::Result<SocketAddr> {
+    let to = match peer {
+        SocketAddr::V4(v4) if socket.local_addr()?.is_ipv6() => {
+            SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port())
+        }
+        _ => peer,
+    };
+    socket
+        .connect(to)
+        .map_err(|e| io::Error::new(e.kind(), format!("Couldn't connect to {}: {}", peer, e)))?;
+    socket.peer_addr()
+}
+
+/// The interface the socket was bound to with `SO_BINDTODEVICE`, if any.
+#[cfg(target_os = "linux")]
+fn bound_device(socket: &UdpSocket) -> Option<Vec<u8>> {
+    SockRef::from(socket).device().ok().flatten()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bound_device(_socket: &UdpSocket) -> Option<Vec<u8>> {
+    None
+}
+
+/// The size of the socket's kernel receive buffer, as granted by the operating system.
+pub(crate) fn recv_buffer_size(socket: &UdpSocket) -> io::Result<usize> {
+    SockRef::from(socket).recv_buffer_size()
+}
+
+/// Joins each multicast group on the bound socket.
+pub(crate) fn join_multicast(socket: &UdpSocket, groups: &[MulticastGroup]) -> io::Result<()> {
+    for group in groups {
+        let joined = match group {
+            MulticastGroup::V4 { group, interface } => socket.join_multicast_v4(group, interface),
+            MulticastGroup::V6 { group, interface } => socket.join_multicast_v6(group, *interface),
+        };
+        joined.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Couldn't join multicast group {}: {}", group, e),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Leaves each multicast group. Failures are ignored: the groups are left anyway once
+/// the socket is closed, so this only makes the departure prompt and explicit.
+pub(crate) fn leave_multicast(socket: &UdpSocket, groups: &[MulticastGroup]) {
+    for group in groups {
+        let _ = match group {
+            MulticastGroup::V4 { group, interface } => socket.leave_multicast_v4(group, interface),
+            MulticastGroup::V6 { group, interface } => socket.leave_multicast_v6(group, *interface),
+        };
+    }
+}
+
+/// The addresses the bound socket receives on. A socket bound to a wildcard address
+/// such as `0.0.0.0` receives on every local address of its family, and a dual-stack
+/// IPv6 socket on the IPv4 ones too, so those are listed with the socket's port. A
+/// socket bound to an interface only lists that interface's. Only the bound address is
+/// returned if the interfaces can't be listed.
+pub(crate) fn listening_addrs(socket: &UdpSocket) -> io::Result<Vec<SocketAddr>> {
+    let local = socket.local_addr()?;
+    if !local.ip().is_unspecified() {
+        return Ok(vec![local]);
+    }
+    // Whether the socket really is dual-stack, now that it's bound.
+    let with_v4 = local.is_ipv4() || !SockRef::from(socket).only_v6().unwrap_or(true);
+    let Ok(interfaces) = if_addrs::get_if_addrs() else {
+        return Ok(vec![local]);
+    };
+    let device = bound_device(socket);
+    let mut addrs: Vec<SocketAddr> = interfaces
+        .iter()
+        .filter(|interface| {
+            device
+                .as_deref()
+                .is_none_or(|device| interface.name.as_bytes() == device)
+        })
+        .map(|interface| interface.ip())
+        .filter(|ip| {
+            if ip.is_ipv4() {
+                with_v4
+            } else {
+                local.is_ipv6()
+            }
+        })
+        .map(|ip| SocketAddr::new(ip, local.port()))
+        .collect();
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        addrs.push(local);
+    }
+    Ok(addrs)
+}
+
+/// Whether a receive error is really an ICMP error for a datagram sent earlier from
+/// the socket, such as port unreachable, rather than a problem receiving.
+pub(crate) fn is_icmp_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// What a receive loop does about an error from receiving a datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RecvErrorAction {
+    /// Expected, and not a failure: a signal interrupted the call (`EINTR`), or nothing
+    /// arrived before the read timeout (`EAGAIN`). Receive again without a word.
+    Retry,
+    /// One receive failed, or an ICMP error came back for an earlier echo (see
+    /// [`is_icmp_error`]), but the socket still works. Report it, or just count it for
+    /// ICMP errors, and carry on.
+    Ignore,
+    /// The socket can't be received from at all, as with `ENOTSOCK` or `EBADF`, so
+    /// every later call would fail the same way. Stop receiving.
+    Fatal,
+}
+
+/// Sorts a receive error into what to do about it. Errors that aren't known to be
+/// expected or fatal are [`RecvErrorAction::Ignore`], so an unfamiliar one is reported
+/// rather than either hidden or taken as a reason to stop.
+pub(crate) fn classify_recv_error(e: &io::Error) -> RecvErrorAction {
+    match e.kind() {
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            return RecvErrorAction::Retry;
+        }
+        _ => {}
+    }
+    match e.raw_os_error() {
+        Some(code) if FATAL_RECV_ERRORS.contains(&code) => RecvErrorAction::Fatal,
+        _ => RecvErrorAction::Ignore,
+    }
+}
+
+/// Receive errors that mean the socket itself is unusable: not a socket, a closed
+/// descriptor, a bad buffer, or a socket in a state it can't receive in.
+#[cfg(unix)]
+const FATAL_RECV_ERRORS: &[i32] = &[libc::ENOTSOCK, libc::EBADF, libc::EFAULT, libc::EINVAL];
+
+/// The same on Windows: `WSAENOTSOCK`, `WSAEBADF`, `WSAEFAULT`, `WSAEINVAL` and
+/// `WSAESHUTDOWN`.
+#[cfg(windows)]
+const FATAL_RECV_ERRORS: &[i32] = &[10038, 10009, 10014, 10022, 10058];
+
+#[cfg(not(any(unix, windows)))]
+const FATAL_RECV_ERRORS: &[i32] = &[];
+
+/// Adds the address to a bind error so the user can see what was attempted.
+fn bind_error(addr: SocketAddr, e: io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!("Couldn't bind to address {}: {}", addr, e),
+    )
+}
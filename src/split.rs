@@ -0,0 +1,193 @@
+// Writing each source address to its own log file.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use crate::compress::Compressor;
+use crate::config::{Compression, Format, LineEnding};
+use crate::format::TimeFormat;
+use crate::packet::Packet;
+use crate::writer::{AfterWrite, LogWriter, buffer};
+
+/// Writes packets to one file per source, such as `logs/192.168.1.5_5000.log`.
+///
+/// Files are opened the first time a source is seen. To avoid running out of file
+/// descriptors when many sources (possibly spoofed) send traffic, at most
+/// `max_open_files` are kept open; opening another one first flushes and closes the
+/// least recently used. Files are always opened for appending, so a source that comes
+/// back after its file was closed carries on where it left off. Compressed files are
+/// named `192.168.1.5_5000.log.gz`.
+pub(crate) struct SplitWriter {
+    dir: PathBuf,
+    format: Format,
+    local_addr: SocketAddr,
+    max_open_files: usize,
+    /// The size of the buffer each file collects entries in between flushes, if any.
+    buffer_size: Option<usize>,
+    /// Named in the header of each binary file.
+    session_id: String,
+    compression: Option<Compression>,
+    open: HashMap<SocketAddr, OpenFile>,
+    /// Increases with every write; used to find the least recently used file.
+    use_counter: u64,
+}
+
+/// One open per-source file.
+struct OpenFile {
+    writer: LogWriter,
+    last_used: u64,
+}
+
+impl SplitWriter {
+    /// Prepares to write per-source files into `dir`, which must already exist.
+    pub(crate) fn new(
+        dir: &Path,
+        format: Format,
+        local_addr: SocketAddr,
+        max_open_files: usize,
+        buffer_size: Option<usize>,
+        session_id: &str,
+        compression: Option<Compression>,
+    ) -> io::Result<Self> {
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' is not a directory", dir.display()),
+            ));
+        }
+        Ok(SplitWriter {
+            dir: dir.to_path_buf(),
+            format,
+            local_addr,
+            max_open_files: max_open_files.max(1),
+            buffer_size,
+            session_id: session_id.to_string(),
+            compression,
+            open: HashMap::new(),
+            use_counter: 0,
+        })
+    }
+
+    /// Writes a packet to its source's file, opening the file if needed, then flushes
+    /// or syncs that file as `after` says.
+    pub(crate) fn write_packet(
+        &mut self,
+        pkt: &Packet,
+        time_format: &TimeFormat,
+        line_ending: &LineEnding,
+        after: AfterWrite,
+    ) -> io::Result<()> {
+        self.use_counter += 1;
+        if !self.open.contains_key(&pkt.src) {
+            if self.open.len() >= self.max_open_files {
+                self.evict_least_recently_used()?;
+            }
+            let writer = self.open_file(pkt.src)?;
+            self.open.insert(
+                pkt.src,
+                OpenFile {
+                    writer,
+                    last_used: 0,
+                },
+            );
+        }
+
+        let file = self.open.get_mut(&pkt.src).expect("file was just opened");
+        file.last_used = self.use_counter;
+        file.writer
+            .write_packet(pkt, self.format, time_format, line_ending, after)
+    }
+
+    /// Flushes every open file.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        for file in self.open.values_mut() {
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes every open file and waits for them to reach the disk.
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        for file in self.open.values_mut() {
+            file.writer.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes and closes every open file. Each is opened again the next time its source
+    /// sends.
+    pub(crate) fn close_all(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.open.clear();
+        Ok(())
+    }
+
+    /// Flushes and closes the file that was written to longest ago.
+    fn evict_least_recently_used(&mut self) -> io::Result<()> {
+        let oldest = self
+            .open
+            .iter()
+            .min_by_key(|(_, file)| file.last_used)
+            .map(|(src, _)| *src);
+        if let Some(src) = oldest
+            && let Some(mut file) = self.open.remove(&src)
+        {
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Opens (or creates) the file for `src` in append mode.
+    fn open_file(&self, src: SocketAddr) -> io::Result<LogWriter> {
+        let mut path = self.dir.join(file_name(src));
+        if self.compression.is_some() {
+            path.as_mut_os_string().push(".gz");
+        }
+        let file: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Couldn't create or open '{}': {}", path.display(), e),
+                )
+            })?;
+        // A pcap or binary file only gets its header when it's new. A compressed file that's
+        // reopened gets a new gzip member appended, which decompresses as a continuation
+        // of the earlier ones.
+        let has_header = file.metadata()?.len() > 0;
+        LogWriter::wrap(
+            buffer(
+                Box::new(Compressor::new(file, self.compression)),
+                self.buffer_size,
+            ),
+            self.format,
+            self.local_addr,
+            &self.session_id,
+            has_header,
+        )
+    }
+}
+
+/// The file name for a source: `192.168.1.5_5000.log`.
+///
+/// IPv6 addresses are written out in full with dashes instead of colons, because
+/// Windows doesn't allow colons in file names: `::1` port 5000 becomes
+/// `0-0-0-0-0-0-0-1_5000.log`. Writing every group also avoids names that start with a
+/// dash, which would look like an option on the command line.
+fn file_name(src: SocketAddr) -> String {
+    let ip = match src.ip() {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => v6
+            .segments()
+            .iter()
+            .map(|segment| format!("{:x}", segment))
+            .collect::<Vec<_>>()
+            .join("-"),
+    };
+    format!("{}_{}.log", ip, src.port())
+}
@@ -0,0 +1,156 @@
+// Storing logged packets in an SQLite database, for querying with SQL.
+
+use std::io;
+use std::path::Path;
+#[cfg(feature = "sqlite")]
+use std::path::PathBuf;
+
+#[cfg(feature = "sqlite")]
+use rusqlite::{Connection, params};
+
+#[cfg(feature = "sqlite")]
+use crate::diag;
+use crate::packet::Packet;
+use crate::sink::Sink;
+
+#[cfg(feature = "sqlite")]
+const CREATE_TABLE: &str = "\
+CREATE TABLE IF NOT EXISTS packets (
+    id INTEGER PRIMARY KEY,
+    ts TEXT NOT NULL,
+    src_ip TEXT NOT NULL,
+    src_port INTEGER NOT NULL,
+    len INTEGER NOT NULL,
+    data BLOB NOT NULL
+)";
+
+#[cfg(feature = "sqlite")]
+const INSERT: &str =
+    "INSERT INTO packets (ts, src_ip, src_port, len, data) VALUES (?1, ?2, ?3, ?4, ?5)";
+
+/// A database with a `packets` table, one row per logged packet.
+///
+/// `ts` is the receive time in UTC, as ISO 8601 with microseconds, which SQLite's date
+/// functions understand. `len` is the datagram's full length and `data` the payload as
+/// captured, so the two differ only for packets cut to the snap length. An existing
+/// database is added to rather than replaced.
+///
+/// Inserting a row at a time would wait for the disk on every packet, so rows are
+/// inserted in transactions of `batch` packets. A transaction is also committed
+/// whenever the sinks are flushed, including by the flush interval and at shutdown, so
+/// no more than a batch is lost if the listener is killed. The database is in WAL mode,
+/// so it can be queried while the capture is running.
+#[cfg(feature = "sqlite")]
+pub(crate) struct SqliteSink {
+    path: PathBuf,
+    conn: Connection,
+    batch: usize,
+    /// Rows inserted in the open transaction, if one is open.
+    pending: usize,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSink {
+    /// Opens, or creates, the database at `path` and its `packets` table.
+    pub(crate) fn open(path: &Path, batch: usize) -> io::Result<SqliteSink> {
+        let fail = |e: rusqlite::Error| {
+            io::Error::other(format!(
+                "Couldn't open the database '{}': {}",
+                path.display(),
+                e
+            ))
+        };
+        let conn = Connection::open(path).map_err(fail)?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(fail)?;
+        conn.execute_batch(CREATE_TABLE).map_err(fail)?;
+        Ok(SqliteSink {
+            path: path.to_path_buf(),
+            conn,
+            batch: batch.max(1),
+            pending: 0,
+        })
+    }
+
+    fn insert(&mut self, pkt: &Packet) -> rusqlite::Result<()> {
+        if self.pending == 0 {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        let ts = pkt
+            .received_at
+            .to_utc()
+            .format("%Y-%m-%dT%H:%M:%S%.6fZ")
+            .to_string();
+        self.conn.prepare_cached(INSERT)?.execute(params![
+            ts,
+            pkt.src.ip().to_string(),
+            pkt.src.port(),
+            pkt.original_len as i64,
+            pkt.data,
+        ])?;
+        self.pending += 1;
+        if self.pending >= self.batch {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> rusqlite::Result<()> {
+        if self.pending > 0 {
+            // Whatever happens, the transaction is over: a failed COMMIT rolls it back.
+            self.pending = 0;
+            self.conn.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Sink for SqliteSink {
+    fn write(&mut self, pkt: &Packet) -> io::Result<()> {
+        self.insert(pkt).map_err(io::Error::other)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.commit().map_err(io::Error::other)
+    }
+
+    fn name(&self) -> String {
+        format!("database '{}'", self.path.display())
+    }
+}
+
+/// Commits the final, partial batch if nothing flushed it.
+#[cfg(feature = "sqlite")]
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.commit() {
+            diag::error!("Error writing to {}: {}", self.name(), e);
+        }
+    }
+}
+
+/// Without the `sqlite` feature there's no database support.
+#[cfg(not(feature = "sqlite"))]
+pub(crate) struct SqliteSink;
+
+#[cfg(not(feature = "sqlite"))]
+impl SqliteSink {
+    pub(crate) fn open(_path: &Path, _batch: usize) -> io::Result<SqliteSink> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--output-sqlite needs udp_listener to be built with the 'sqlite' feature",
+        ))
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl Sink for SqliteSink {
+    fn write(&mut self, _pkt: &Packet) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,447 @@
+// Running totals of what the listener has seen, and the summary printed from them.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, SecondsFormat};
+
+use crate::format::TIMESTAMP_FORMAT;
+use crate::latency::LatencyStats;
+use crate::lru::Recency;
+
+/// How many of the busiest sources the summary lists by name.
+const SUMMARY_TOP_SOURCES: usize = 10;
+
+/// Traffic counters for one run of the listener.
+///
+/// Every received datagram counts towards `packets`, `bytes` and its source's entry,
+/// including ones that are then filtered out. The other counters record what happened
+/// to the packet afterwards.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// Datagrams received, including any carried over from
+    /// [`Config::stats_file`](crate::Config::stats_file) (see `carried_packets`).
+    pub packets: u64,
+    /// Payload bytes received, including any carried over like `packets` (see
+    /// `carried_bytes`).
+    pub bytes: u64,
+    /// Packets that filled the receive buffer and may have been cut short.
+    pub truncated: u64,
+    /// Datagrams the kernel dropped because the socket's receive buffer was full, or
+    /// `None` where the operating system doesn't report this.
+    pub kernel_drops: Option<u64>,
+    /// Packets dropped because the worker queue was full.
+    pub dropped: u64,
+    /// Packets that could not be written to the log.
+    pub write_errors: u64,
+    /// Packets dropped by the source address filter.
+    pub filtered: u64,
+    /// Packets dropped by the payload length filter.
+    pub length_filtered: u64,
+    /// Packets whose payload matched none of the payload patterns.
+    pub payload_filtered: u64,
+    /// Packets that didn't meet [`Config::filter`](crate::Config::filter).
+    pub expression_filtered: u64,
+    /// Packets ignored because they arrived before the start trigger, with
+    /// [`Config::triggers`](crate::Config::triggers) set.
+    pub untriggered: u64,
+    /// Packets left out as duplicates of a recent one.
+    pub duplicates: u64,
+    /// Packets left out of the log by [`Config::sample`](crate::Config::sample).
+    pub unsampled: u64,
+    /// Packets left out of the log for going over
+    /// [`Config::per_source_limit`](crate::Config::per_source_limit).
+    pub rate_limited: u64,
+    /// Packets whose payload couldn't be decoded as the configured protocol, and were
+    /// logged raw instead.
+    pub unparsed: u64,
+    /// Sequence numbers that never arrived, with [`Config::seq_offset`] set. A packet
+    /// that turns up late is taken off again.
+    ///
+    /// [`Config::seq_offset`]: crate::Config::seq_offset
+    pub seq_missing: u64,
+    /// Packets that arrived after a later-numbered one from the same source, or
+    /// repeated one.
+    pub seq_reordered: u64,
+    /// One-way latencies, with [`Config::latency_offset`] set.
+    ///
+    /// [`Config::latency_offset`]: crate::Config::latency_offset
+    pub latency: LatencyStats,
+    /// Packets echoed back to their sender.
+    pub echoed: u64,
+    /// Echo replies that could not be sent.
+    pub echo_failures: u64,
+    /// ICMP errors, such as port unreachable, that the system reported on the socket for
+    /// echoes sent earlier. They're skipped, not counted as receive errors.
+    pub icmp_errors: u64,
+    /// Messages put back together from their chunks, with
+    /// [`Config::reassemble`](crate::Config::reassemble) set.
+    pub reassembled: u64,
+    /// Messages given up on because some of their chunks never arrived.
+    pub reassembly_incomplete: u64,
+    /// Packets whose chunk header didn't fit the layout, and were logged as they were.
+    pub reassembly_malformed: u64,
+    /// Copies sent to forward targets. A packet forwarded to two targets counts twice.
+    pub forwarded: u64,
+    /// Copies that could not be sent to a forward target.
+    pub forward_failures: u64,
+    /// Entries forgotten from the per-source tables to stay within
+    /// [`Config::max_tracked_sources`](crate::Config::max_tracked_sources), all the
+    /// tables together. Sources forgotten from `per_source` are missing from its
+    /// totals, though their packets still count towards `packets` and `bytes`.
+    pub sources_evicted: u64,
+    /// Of `packets`, how many were counted by earlier runs and carried over from
+    /// [`Config::stats_file`](crate::Config::stats_file). They aren't part of this run's
+    /// rate.
+    pub carried_packets: u64,
+    /// Of `bytes`, how many were counted by earlier runs and carried over from
+    /// [`Config::stats_file`](crate::Config::stats_file).
+    pub carried_bytes: u64,
+    /// The [session](crate::Config::session_id) these counts belong to, once the
+    /// listener has started.
+    pub session_id: Option<Arc<str>>,
+    /// Totals for each source address. This grows by one entry per distinct sender, up
+    /// to the limit set by [`with_max_sources`](Self::with_max_sources); beyond that,
+    /// the source heard from longest ago is forgotten to make room.
+    pub per_source: HashMap<SocketAddr, SourceStats>,
+    /// The order the sources in `per_source` were last heard from in.
+    source_recency: Recency<SocketAddr>,
+    started: Instant,
+}
+
+/// Traffic counters for a single source address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceStats {
+    pub packets: u64,
+    pub bytes: u64,
+    /// When the source's first datagram arrived.
+    pub first_seen: Option<DateTime<Local>>,
+    /// When its latest datagram arrived.
+    pub last_seen: Option<DateTime<Local>>,
+}
+
+impl Stats {
+    /// Starts a new set of counters, timing the run from now.
+    pub fn new() -> Self {
+        Stats {
+            packets: 0,
+            bytes: 0,
+            truncated: 0,
+            kernel_drops: None,
+            dropped: 0,
+            write_errors: 0,
+            filtered: 0,
+            length_filtered: 0,
+            payload_filtered: 0,
+            expression_filtered: 0,
+            untriggered: 0,
+            duplicates: 0,
+            unsampled: 0,
+            rate_limited: 0,
+            unparsed: 0,
+            seq_missing: 0,
+            seq_reordered: 0,
+            latency: LatencyStats::default(),
+            echoed: 0,
+            echo_failures: 0,
+            icmp_errors: 0,
+            reassembled: 0,
+            reassembly_incomplete: 0,
+            reassembly_malformed: 0,
+            forwarded: 0,
+            forward_failures: 0,
+            sources_evicted: 0,
+            carried_packets: 0,
+            carried_bytes: 0,
+            session_id: None,
+            per_source: HashMap::new(),
+            source_recency: Recency::new(usize::MAX),
+            started: Instant::now(),
+        }
+    }
+
+    /// Keeps totals for at most `max` sources, at least one, rather than every source
+    /// ever heard from.
+    pub fn with_max_sources(mut self, max: usize) -> Self {
+        self.source_recency = Recency::new(max);
+        self
+    }
+
+    /// Counts a datagram of `len` bytes from `src`, received at `at`.
+    pub fn record(&mut self, src: SocketAddr, len: usize, at: DateTime<Local>) {
+        self.packets += 1;
+        self.bytes += len as u64;
+        if let Some(forgotten) = self.source_recency.touch(src) {
+            self.per_source.remove(&forgotten);
+            self.sources_evicted += 1;
+        }
+        let source = self.per_source.entry(src).or_default();
+        source.packets += 1;
+        source.bytes += len as u64;
+        source.first_seen.get_or_insert(at);
+        source.last_seen = Some(at);
+    }
+
+    /// Puts back the totals an earlier run kept for `src`, as the source heard from
+    /// most recently.
+    pub(crate) fn restore_source(&mut self, src: SocketAddr, source: SourceStats) {
+        if let Some(forgotten) = self.source_recency.touch(src) {
+            self.per_source.remove(&forgotten);
+            self.sources_evicted += 1;
+        }
+        self.per_source.insert(src, source);
+    }
+
+    /// The sources, the one that sent the most bytes first. Ties go to the one with
+    /// more packets, then the lower address, so the order is stable.
+    pub fn sources_by_volume(&self) -> Vec<(&SocketAddr, &SourceStats)> {
+        let mut sources: Vec<_> = self.per_source.iter().collect();
+        sources.sort_by(|(a_addr, a), (b_addr, b)| {
+            (b.bytes, b.packets)
+                .cmp(&(a.bytes, a.packets))
+                .then_with(|| a_addr.cmp(b_addr))
+        });
+        sources
+    }
+
+    /// Writes every source as CSV, busiest first, under a header line:
+    ///
+    /// ```text
+    /// src_ip,src_port,packets,bytes,first_seen,last_seen
+    /// 127.0.0.1,5000,10,400,2024-06-01T12:00:00.123+02:00,2024-06-01T12:00:03.201+02:00
+    /// ```
+    ///
+    /// The times are RFC 3339 with milliseconds, like the JSON log's.
+    pub fn write_sources_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let time = |t: Option<DateTime<Local>>| {
+            t.map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, false))
+                .unwrap_or_default()
+        };
+        writeln!(out, "src_ip,src_port,packets,bytes,first_seen,last_seen")?;
+        for (addr, source) in self.sources_by_volume() {
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                addr.ip(),
+                addr.port(),
+                source.packets,
+                source.bytes,
+                time(source.first_seen),
+                time(source.last_seen)
+            )?;
+        }
+        out.flush()
+    }
+
+    /// Time since the counters were started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Average packets per second over the whole run, leaving out any carried over
+    /// from earlier runs.
+    pub fn packets_per_second(&self) -> f64 {
+        let secs = self.elapsed().as_secs_f64();
+        if secs > 0.0 {
+            (self.packets - self.carried_packets) as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// A multi-line, human-readable summary: the totals on the first line, followed by
+    /// a table of the sources that sent the most bytes.
+    ///
+    /// ```text
+    /// received 12 packets (480 bytes) in 3.2s, 3.75 packets/s, from 2 sources, in session 67e55044-10b1-426f-9247-bb680e5fe0c8
+    ///   SOURCE          PACKETS  BYTES  FIRST SEEN               LAST SEEN
+    ///   127.0.0.1:5000       10    400  2024-06-01 12:00:00.123  2024-06-01 12:00:03.201
+    ///   127.0.0.1:5001        2     80  2024-06-01 12:00:01.002  2024-06-01 12:00:01.950
+    /// ```
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "received {} packets ({} bytes) in {:.1}s, {:.2} packets/s, from {} sources",
+            self.packets,
+            self.bytes,
+            self.elapsed().as_secs_f64(),
+            self.packets_per_second(),
+            self.per_source.len()
+        );
+        // Writing into a `String` cannot fail, so the `fmt::Result`s are safe to ignore.
+        if self.carried_packets > 0 {
+            let _ = write!(
+                out,
+                ", {} of them carried over from earlier runs",
+                self.carried_packets
+            );
+        }
+        if self.truncated > 0 {
+            let _ = write!(out, ", {} possibly truncated", self.truncated);
+        }
+        if let Some(drops) = self.kernel_drops
+            && drops > 0
+        {
+            let _ = write!(out, ", {} dropped by the kernel", drops);
+        }
+        if self.dropped > 0 {
+            let _ = write!(out, ", {} dropped (queue full)", self.dropped);
+        }
+        if self.write_errors > 0 {
+            let _ = write!(out, ", {} could not be written", self.write_errors);
+        }
+        if self.filtered > 0 {
+            let _ = write!(out, ", {} filtered", self.filtered);
+        }
+        if self.length_filtered > 0 {
+            let _ = write!(out, ", {} outside the length limits", self.length_filtered);
+        }
+        if self.payload_filtered > 0 {
+            let _ = write!(
+                out,
+                ", {} not matching the payload patterns",
+                self.payload_filtered
+            );
+        }
+        if self.expression_filtered > 0 {
+            let _ = write!(
+                out,
+                ", {} not matching the filter",
+                self.expression_filtered
+            );
+        }
+        if self.untriggered > 0 {
+            let _ = write!(out, ", {} before the start trigger", self.untriggered);
+        }
+        if self.duplicates > 0 {
+            let _ = write!(out, ", {} duplicates suppressed", self.duplicates);
+        }
+        if self.unsampled > 0 {
+            let _ = write!(out, ", {} left out by sampling", self.unsampled);
+        }
+        if self.rate_limited > 0 {
+            let _ = write!(out, ", {} over the per-source limit", self.rate_limited);
+        }
+        if self.unparsed > 0 {
+            let _ = write!(out, ", {} could not be parsed", self.unparsed);
+        }
+        if self.seq_missing > 0 || self.seq_reordered > 0 {
+            let _ = write!(
+                out,
+                ", {} missing and {} out of order by sequence number",
+                self.seq_missing, self.seq_reordered
+            );
+        }
+        if self.echoed > 0 || self.echo_failures > 0 {
+            let _ = write!(
+                out,
+                ", echoed {} ({} failed)",
+                self.echoed, self.echo_failures
+            );
+        }
+        if self.icmp_errors > 0 {
+            let _ = write!(out, ", {} ICMP errors for echoes ignored", self.icmp_errors);
+        }
+        if self.reassembled > 0 || self.reassembly_incomplete > 0 {
+            let _ = write!(
+                out,
+                ", {} messages reassembled, {} incomplete",
+                self.reassembled, self.reassembly_incomplete
+            );
+        }
+        if self.reassembly_malformed > 0 {
+            let _ = write!(
+                out,
+                ", {} with a malformed chunk header",
+                self.reassembly_malformed
+            );
+        }
+        if self.forwarded > 0 || self.forward_failures > 0 {
+            let _ = write!(
+                out,
+                ", forwarded {} ({} failed)",
+                self.forwarded, self.forward_failures
+            );
+        }
+        if self.sources_evicted > 0 {
+            let _ = write!(
+                out,
+                ", {} per-source entries forgotten over the limit",
+                self.sources_evicted
+            );
+        }
+        if let Some(session_id) = &self.session_id {
+            let _ = write!(out, ", in session {}", session_id);
+        }
+
+        let sources = self.sources_by_volume();
+        if !sources.is_empty() {
+            source_table(&mut out, &sources[..sources.len().min(SUMMARY_TOP_SOURCES)]);
+        }
+        if sources.len() > SUMMARY_TOP_SOURCES {
+            let _ = write!(
+                out,
+                "\n  ... and {} more",
+                sources.len() - SUMMARY_TOP_SOURCES
+            );
+        }
+        out
+    }
+}
+
+/// Appends `sources` to `out` as a table, one line each under a heading, with the
+/// columns padded to line up.
+fn source_table(out: &mut String, sources: &[(&SocketAddr, &SourceStats)]) {
+    let time = |t: Option<DateTime<Local>>| {
+        t.map(|t| t.format(TIMESTAMP_FORMAT).to_string())
+            .unwrap_or_default()
+    };
+    let rows: Vec<[String; 5]> = sources
+        .iter()
+        .map(|(addr, source)| {
+            [
+                addr.to_string(),
+                source.packets.to_string(),
+                source.bytes.to_string(),
+                time(source.first_seen),
+                time(source.last_seen),
+            ]
+        })
+        .collect();
+    let heading = ["SOURCE", "PACKETS", "BYTES", "FIRST SEEN", "LAST SEEN"];
+    let mut widths = heading.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    // Counts are right-aligned so their digits line up; the rest are left-aligned.
+    let mut line = |cells: [&str; 5]| {
+        let _ = write!(
+            out,
+            "\n  {:<w0$}  {:>w1$}  {:>w2$}  {:<w3$}  {}",
+            cells[0],
+            cells[1],
+            cells[2],
+            cells[3],
+            cells[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    };
+    line(heading);
+    for row in &rows {
+        line(row.each_ref().map(String::as_str));
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
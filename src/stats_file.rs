@@ -0,0 +1,178 @@
+// Keeping the traffic totals in a file, so they carry on across restarts.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::stats::{SourceStats, Stats};
+
+/// How often the statistics are saved while the listener runs.
+pub(crate) const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The statistics file of [`Config::stats_file`](crate::Config::stats_file):
+///
+/// ```text
+/// {
+///   "since": "2024-06-01T12:00:00.123+02:00",
+///   "saved_at": "2024-06-15T08:30:10.004+02:00",
+///   "packets": 1520,
+///   "bytes": 60800,
+///   "sources": [
+///     {
+///       "src": "192.0.2.1:5000",
+///       "packets": 1500,
+///       "bytes": 60000,
+///       "first_seen": "2024-06-01T12:00:00.123+02:00",
+///       "last_seen": "2024-06-15T08:30:09.870+02:00"
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `since` is when the first run that saved to the file started, and `sources` holds
+/// the sources busiest first. Every save goes to a temporary file that then replaces
+/// it, so a crash leaves either the last save or the one before.
+pub(crate) struct StatsFile {
+    path: PathBuf,
+    since: String,
+    last_saved: Instant,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedStats {
+    since: String,
+    saved_at: String,
+    packets: u64,
+    bytes: u64,
+    sources: Vec<SavedSource>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedSource {
+    src: SocketAddr,
+    packets: u64,
+    bytes: u64,
+    first_seen: Option<String>,
+    last_seen: Option<String>,
+}
+
+impl StatsFile {
+    /// Adds the totals saved at `path` by earlier runs, if there are any, to `stats`.
+    /// A file that can't be read is an error rather than being started over, which
+    /// would lose the counts. A missing one is started at `now`.
+    pub(crate) fn open(path: &Path, stats: &mut Stats, now: DateTime<Local>) -> io::Result<Self> {
+        let since = match read_saved(path)? {
+            Some(saved) => {
+                restore(&saved, stats);
+                saved.since
+            }
+            None => timestamp(&now),
+        };
+        Ok(StatsFile {
+            path: path.to_path_buf(),
+            since,
+            last_saved: Instant::now(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the statistics haven't been saved for [`STATS_SAVE_INTERVAL`].
+    pub(crate) fn is_due(&self) -> bool {
+        self.last_saved.elapsed() >= STATS_SAVE_INTERVAL
+    }
+
+    /// Replaces the file with `stats`, saved at `now`.
+    pub(crate) fn save(&mut self, stats: &Stats, now: DateTime<Local>) -> io::Result<()> {
+        self.last_saved = Instant::now();
+        let saved = SavedStats {
+            since: self.since.clone(),
+            saved_at: timestamp(&now),
+            packets: stats.packets,
+            bytes: stats.bytes,
+            sources: stats
+                .sources_by_volume()
+                .into_iter()
+                .map(|(&src, source)| SavedSource {
+                    src,
+                    packets: source.packets,
+                    bytes: source.bytes,
+                    first_seen: source.first_seen.as_ref().map(timestamp),
+                    last_seen: source.last_seen.as_ref().map(timestamp),
+                })
+                .collect(),
+        };
+        let mut temp_name = self.path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp = PathBuf::from(temp_name);
+        let mut json = serde_json::to_vec_pretty(&saved).map_err(io::Error::other)?;
+        json.push(b'\n');
+        let mut file = File::create(&temp)?;
+        file.write_all(&json)?;
+        file.sync_data()?;
+        fs::rename(&temp, &self.path)
+    }
+}
+
+/// The statistics saved at `path`, or `None` if there's no file there yet.
+fn read_saved(path: &Path) -> io::Result<Option<SavedStats>> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Couldn't read the stats file '{}': {}", path.display(), e),
+            ));
+        }
+    };
+    serde_json::from_slice(&json).map(Some).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Couldn't read the stats file '{}': {}", path.display(), e),
+        )
+    })
+}
+
+/// Adds the saved totals to `stats`. Sources are restored the least recently heard
+/// from first, so if there are more than `stats` keeps, the ones it forgets are those
+/// it would have forgotten first anyway.
+fn restore(saved: &SavedStats, stats: &mut Stats) {
+    stats.packets += saved.packets;
+    stats.bytes += saved.bytes;
+    stats.carried_packets += saved.packets;
+    stats.carried_bytes += saved.bytes;
+    let time = |time: &Option<String>| {
+        time.as_deref()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Local))
+    };
+    let mut sources: Vec<(SocketAddr, SourceStats)> = saved
+        .sources
+        .iter()
+        .map(|source| {
+            let stats = SourceStats {
+                packets: source.packets,
+                bytes: source.bytes,
+                first_seen: time(&source.first_seen),
+                last_seen: time(&source.last_seen),
+            };
+            (source.src, stats)
+        })
+        .collect();
+    sources.sort_by_key(|(_, source)| source.last_seen);
+    for (src, source) in sources {
+        stats.restore_source(src, source);
+    }
+}
+
+fn timestamp(time: &DateTime<Local>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
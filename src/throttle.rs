@@ -0,0 +1,71 @@
+// Slowing the listener down on purpose, to see how a capture pipeline copes with a
+// consumer that can't keep up.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The longest the throttle sleeps at a time before looking for a shutdown request.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Checks that `pps` can be used as [`Config::throttle`](crate::Config::throttle): a
+/// positive number of packets per second. Fractions are allowed, such as 0.5 for one
+/// packet every two seconds, as long as the time between packets can be represented.
+pub fn check_throttle(pps: f64) -> Result<(), String> {
+    if !(pps.is_finite() && pps > 0.0) {
+        return Err("must be a positive number of packets per second".to_string());
+    }
+    if interval(pps).is_none() {
+        return Err("is too small: the time between packets would be too long".to_string());
+    }
+    Ok(())
+}
+
+/// The time between packets at `pps` packets a second, if it can be represented.
+fn interval(pps: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(1.0 / pps).ok()
+}
+
+/// Spaces out the packets the logger takes from the socket, for
+/// [`Config::throttle`](crate::Config::throttle), so that whatever arrives faster waits
+/// in the receive buffer until it fills and the kernel drops the rest.
+///
+/// Each packet is due one interval after the last, or straight away if the last was
+/// longer ago than that, so time spent idle doesn't let a burst through afterwards.
+pub(crate) struct Throttle {
+    /// `None` for a rate so low that no interval can be represented, which
+    /// [`check_throttle`] rejects, but a `Config` built in code may still hold.
+    interval: Option<Duration>,
+    /// When the next packet may be taken, or `None` if that's too far off to be
+    /// represented, so never.
+    next: Option<Instant>,
+}
+
+impl Throttle {
+    pub(crate) fn new(pps: f64) -> Throttle {
+        Throttle {
+            interval: interval(pps),
+            next: Some(Instant::now()),
+        }
+    }
+
+    /// Sleeps until the next packet is due, or `shutdown` is set, and moves the time
+    /// after that on by one interval.
+    pub(crate) fn wait(&mut self, shutdown: &AtomicBool) {
+        loop {
+            let now = Instant::now();
+            let left = match self.next {
+                Some(next) => next.checked_duration_since(now),
+                None => Some(SHUTDOWN_CHECK_INTERVAL),
+            };
+            let Some(left) = left else {
+                self.next = self.interval.and_then(|interval| now.checked_add(interval));
+                return;
+            };
+            if shutdown.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::sleep(left.min(SHUTDOWN_CHECK_INTERVAL));
+        }
+    }
+}
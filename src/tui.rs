@@ -0,0 +1,366 @@
+// `--tui`: a live dashboard of the capture, drawn on the terminal while the logger
+// carries on receiving and writing the log underneath.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use udp_listener::{Packet, Sink, Stats, UdpLogger, payload_text};
+
+/// How often the screen is redrawn.
+const FRAME_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Frames between redraws of the whole screen, which clear away anything the library
+/// printed over the dashboard, such as a warning.
+const FRAMES_PER_CLEAR: u32 = 8;
+
+/// How many of the latest packets are kept for the recent packets list, enough to fill
+/// a tall terminal.
+const RECENT_PACKETS: usize = 200;
+
+/// How much of each payload the recent packets list keeps.
+const PREVIEW_CHARS: usize = 160;
+
+/// How many sources the top sources table shows.
+const TOP_SOURCES: usize = 10;
+
+/// The span of traffic the rates are averaged over.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How long the statistics may stay the same before the traffic counts as stopped.
+/// The logger brings them up to date about once a second.
+const IDLE_AFTER: Duration = Duration::from_millis(1500);
+
+/// The dashboard, drawn by a thread of its own from the logger's
+/// [`stats_handle`](UdpLogger::stats_handle) and the packets it logs.
+///
+/// Pressing q, Esc or Ctrl-C sets the logger's shutdown flag, as Ctrl-C would without
+/// the dashboard. Dropping the `Dashboard` stops the thread and gives the terminal back
+/// as it was.
+pub struct Dashboard {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Dashboard {
+    /// Takes over the terminal and starts drawing `logger`'s traffic beneath `title`.
+    pub fn start(logger: &mut UdpLogger, title: String) -> io::Result<Dashboard> {
+        let recent = Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_PACKETS)));
+        logger.add_sink(Box::new(RecentPackets {
+            lines: Arc::clone(&recent),
+        }));
+        let screen = Screen {
+            title,
+            stats: logger.stats_handle(),
+            recent,
+            shutdown: logger.shutdown_handle(),
+            rates: Rates::default(),
+        };
+        let terminal = ratatui::try_init()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name("tui".to_string())
+                .spawn(move || screen.run(terminal, &stop))
+        };
+        match thread {
+            Ok(thread) => Ok(Dashboard {
+                stop,
+                thread: Some(thread),
+            }),
+            Err(e) => {
+                ratatui::restore();
+                Err(e)
+            }
+        }
+    }
+
+    /// Stops drawing and restores the terminal, returning the error that stopped the
+    /// dashboard early, if one did.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.stop_thread()
+    }
+
+    fn stop_thread(&mut self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        let drawn = thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("the dashboard thread panicked")));
+        ratatui::restore();
+        drawn
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = self.stop_thread();
+    }
+}
+
+/// What the drawing thread works from.
+struct Screen {
+    title: String,
+    stats: Arc<Mutex<Stats>>,
+    recent: Arc<Mutex<VecDeque<String>>>,
+    shutdown: Arc<AtomicBool>,
+    rates: Rates,
+}
+
+impl Screen {
+    /// Redraws every [`FRAME_INTERVAL`], and handles key presses in between, until
+    /// `stop` is set.
+    fn run(mut self, mut terminal: DefaultTerminal, stop: &AtomicBool) -> io::Result<()> {
+        let mut frames: u32 = 0;
+        while !stop.load(Ordering::Relaxed) {
+            if event::poll(FRAME_INTERVAL)? {
+                if let Event::Key(key) = event::read()?
+                    && key.kind == KeyEventKind::Press
+                    && quits(key.code, key.modifiers)
+                {
+                    self.shutdown.store(true, Ordering::Relaxed);
+                }
+                continue;
+            }
+            let view = View::of(&lock(&self.stats));
+            self.rates.update(Instant::now(), view.packets, view.bytes);
+            if frames.is_multiple_of(FRAMES_PER_CLEAR) {
+                terminal.clear()?;
+            }
+            frames = frames.wrapping_add(1);
+            terminal.draw(|frame| self.draw(frame, &view))?;
+        }
+        Ok(())
+    }
+
+    fn draw(&self, frame: &mut Frame, view: &View) {
+        let [header, traffic, sources, recent] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(8),
+            Constraint::Length(TOP_SOURCES as u16 + 3),
+            Constraint::Min(3),
+        ])
+        .areas(frame.area());
+
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        frame.render_widget(
+            Paragraph::new(Line::styled(format!("{}    (q to quit)", self.title), bold)),
+            header,
+        );
+
+        let (pps, bps) = self.rates.per_second();
+        let kernel_drops = match view.kernel_drops {
+            Some(drops) => drops.to_string(),
+            None => "not reported".to_string(),
+        };
+        let lines = vec![
+            Line::from(format!(
+                "Rate          {:.1} packets/s, {}",
+                pps,
+                bits_per_second(bps * 8.0)
+            )),
+            Line::from(format!(
+                "Received      {} packets, {} bytes, from {} sources",
+                view.packets, view.bytes, view.sources
+            )),
+            Line::from(format!("Truncated     {}", view.truncated)),
+            Line::from(format!(
+                "Dropped       {} by the kernel, {} with the queue full",
+                kernel_drops, view.dropped
+            )),
+            Line::from(format!("Filtered      {}", view.filtered)),
+            Line::from(format!("Write errors  {}", view.write_errors)),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Traffic ")),
+            traffic,
+        );
+
+        let rows = view.top_sources.iter().map(|(source, packets, bytes)| {
+            Row::new([source.clone(), packets.to_string(), bytes.to_string()])
+        });
+        let widths = [
+            Constraint::Min(24),
+            Constraint::Length(12),
+            Constraint::Length(14),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(Row::new(["SOURCE", "PACKETS", "BYTES"]).style(bold))
+                .block(Block::bordered().title(" Top sources ")),
+            sources,
+        );
+
+        // The newest packets at the bottom, as many as fit inside the borders.
+        let fits = usize::from(recent.height.saturating_sub(2));
+        let lines = lock(&self.recent);
+        let items: Vec<ListItem> = lines
+            .iter()
+            .skip(lines.len().saturating_sub(fits))
+            .map(|line| ListItem::new(line.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" Recent packets ")),
+            recent,
+        );
+    }
+}
+
+/// Whether a key press stops the listener. In raw mode Ctrl-C arrives as a key rather
+/// than as SIGINT.
+fn quits(code: KeyCode, modifiers: KeyModifiers) -> bool {
+    match code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// `bits` a second, scaled to the nearest unit, as in `12.3 Mbit/s`.
+fn bits_per_second(bits: f64) -> String {
+    let units = ["bit/s", "kbit/s", "Mbit/s", "Gbit/s"];
+    let mut value = bits;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+/// The parts of the statistics the dashboard shows, copied out so the lock isn't held
+/// while drawing.
+struct View {
+    packets: u64,
+    bytes: u64,
+    sources: usize,
+    truncated: u64,
+    kernel_drops: Option<u64>,
+    dropped: u64,
+    filtered: u64,
+    write_errors: u64,
+    /// The sources that sent the most bytes, with their packets and bytes.
+    top_sources: Vec<(String, u64, u64)>,
+}
+
+impl View {
+    fn of(stats: &Stats) -> View {
+        View {
+            packets: stats.packets,
+            bytes: stats.bytes,
+            sources: stats.per_source.len(),
+            truncated: stats.truncated,
+            kernel_drops: stats.kernel_drops,
+            dropped: stats.dropped,
+            filtered: stats.filtered
+                + stats.length_filtered
+                + stats.payload_filtered
+                + stats.expression_filtered,
+            write_errors: stats.write_errors,
+            top_sources: stats
+                .sources_by_volume()
+                .into_iter()
+                .take(TOP_SOURCES)
+                .map(|(addr, source)| (addr.to_string(), source.packets, source.bytes))
+                .collect(),
+        }
+    }
+}
+
+/// The recent packet and byte rates, from how the totals grew over the last
+/// [`RATE_WINDOW`].
+///
+/// The totals come from a copy of the statistics that changes about once a second, so
+/// each is timed from when it was first seen, and a rate is only worked out over
+/// several of them for that timing to even out.
+#[derive(Default)]
+struct Rates {
+    /// When each new pair of totals was first seen, with the packets and bytes.
+    samples: VecDeque<(Instant, u64, u64)>,
+}
+
+impl Rates {
+    fn update(&mut self, now: Instant, packets: u64, bytes: u64) {
+        let latest = self.samples.back();
+        let changed = latest.is_none_or(|&(_, p, b)| (p, b) != (packets, bytes));
+        let idle = latest.is_some_and(|&(at, ..)| now.duration_since(at) >= IDLE_AFTER);
+        if changed || idle {
+            self.samples.push_back((now, packets, bytes));
+        }
+        while self.samples.len() > 2
+            && self
+                .samples
+                .front()
+                .is_some_and(|&(at, ..)| now.duration_since(at) > RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Packets and bytes a second.
+    fn per_second(&self) -> (f64, f64) {
+        let (Some(&(first, p0, b0)), Some(&(last, p1, b1))) =
+            (self.samples.front(), self.samples.back())
+        else {
+            return (0.0, 0.0);
+        };
+        let secs = last.duration_since(first).as_secs_f64();
+        if secs <= 0.0 {
+            return (0.0, 0.0);
+        }
+        ((p1 - p0) as f64 / secs, (b1 - b0) as f64 / secs)
+    }
+}
+
+/// Keeps a line for each of the latest packets logged, for the recent packets list.
+struct RecentPackets {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Sink for RecentPackets {
+    fn write(&mut self, pkt: &Packet) -> io::Result<()> {
+        // Control characters would move the cursor about the dashboard.
+        let preview: String = payload_text(pkt)
+            .chars()
+            .take(PREVIEW_CHARS)
+            .map(|c| if c.is_control() { '.' } else { c })
+            .collect();
+        let line = format!(
+            "{}  {:<21}  {:>5}  {}",
+            pkt.received_at.format("%H:%M:%S%.3f"),
+            pkt.source(),
+            pkt.original_len,
+            preview
+        );
+        let mut lines = lock(&self.lines);
+        if lines.len() == RECENT_PACKETS {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        "the dashboard".to_string()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
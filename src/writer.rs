@@ -0,0 +1,364 @@
+// The open log destination and how each packet is encoded into it.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::compress::Compressor;
+use crate::config::{Config, FlushPolicy, Format, LineEnding, Output};
+use crate::format::{TimeFormat, format_entry};
+use crate::packet::Packet;
+use crate::pcap::{PcapWriter, global_header as pcap_global_header};
+use crate::records::{capture_header, encode_record_into};
+use crate::rotate::RotatingWriter;
+use crate::segment::SegmentWriter;
+use crate::split::SplitWriter;
+
+/// Somewhere log entries can be written, which can also be made to put what it has
+/// been given on disk.
+pub(crate) trait SyncWrite: Write + Send {
+    /// Flushes everything through to the operating system and, for a file, waits until
+    /// the system has written it to the storage device, so it survives a crash or power
+    /// loss. Other destinations are only flushed.
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl SyncWrite for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.sync_data()
+    }
+}
+
+impl SyncWrite for io::Stdout {}
+
+/// A placeholder for a destination that's about to be opened.
+impl SyncWrite for io::Sink {}
+
+impl<W: SyncWrite> SyncWrite for Compressor<W> {
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().sync()
+    }
+}
+
+impl<W: SyncWrite> SyncWrite for BufWriter<W> {
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().sync()
+    }
+}
+
+impl SyncWrite for Box<dyn SyncWrite> {
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+/// What [`LogWriter::write_packet`] does once an entry is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AfterWrite {
+    /// Leave the entry in the buffer, if there is one.
+    Nothing,
+    /// Flush it through to the operating system.
+    Flush,
+    /// Flush it and wait for it to reach the disk, for [`Config::fsync`].
+    Sync,
+}
+
+impl AfterWrite {
+    /// What the flush policy and `fsync` setting ask for after each packet.
+    pub(crate) fn for_config(config: &Config) -> AfterWrite {
+        match (config.flush, config.fsync) {
+            (FlushPolicy::Every, true) => AfterWrite::Sync,
+            (FlushPolicy::Every, false) => AfterWrite::Flush,
+            _ => AfterWrite::Nothing,
+        }
+    }
+}
+
+/// The open log destination, wrapped in whatever encoding the format needs.
+pub(crate) enum LogWriter {
+    /// Text, hex and JSON formats, written as lines of text.
+    Lines(Box<dyn SyncWrite>),
+    /// Binary pcap records.
+    Pcap(PcapWriter<Box<dyn SyncWrite>>),
+    /// The listener's own binary records, for [`Format::Bin`], with a buffer reused for
+    /// assembling each record.
+    Records(Box<dyn SyncWrite>, Vec<u8>),
+    /// A separate file for each source address.
+    Split(SplitWriter),
+    /// Numbered segment files in a directory, listed in its manifest.
+    Segments(SegmentWriter),
+}
+
+impl LogWriter {
+    /// Opens the configured output and wraps it in the encoding for the configured format.
+    pub(crate) fn open(config: &Config, local_addr: SocketAddr) -> io::Result<LogWriter> {
+        let rotation = config.max_log_size.is_some() || config.rotate.is_some();
+        let buffer_size = buffer_size(config);
+        let session_id = config.session_id.as_deref().unwrap_or_default();
+
+        if config.compress_rotated {
+            let problem = if !rotation {
+                Some("compressing rotated files needs log rotation")
+            } else if config.compress.is_some() {
+                Some("rotated files are already compressed when the whole log is")
+            } else if config.output_dir.is_some() {
+                Some("compressing rotated files can't be combined with an output directory")
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, problem));
+            }
+        }
+
+        if let Some(dir) = &config.split_by_source {
+            if config.output_dir.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "an output directory can't be combined with splitting by source",
+                ));
+            }
+            if rotation {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "log rotation can't be combined with splitting by source",
+                ));
+            }
+            return Ok(LogWriter::Split(SplitWriter::new(
+                dir,
+                config.format,
+                local_addr,
+                config.max_open_files,
+                buffer_size,
+                session_id,
+                config.compress,
+            )?));
+        }
+
+        if let Some(dir) = &config.output_dir {
+            return Ok(LogWriter::Segments(SegmentWriter::new(
+                dir, config, local_addr,
+            )?));
+        }
+
+        // A pcap file starts with a global header and a binary capture with its own.
+        // `wrap` writes them straight away, unless the file is an earlier log being
+        // added to, but with rotation the `RotatingWriter` writes them at the top of
+        // every file instead.
+        let (out, has_header): (Box<dyn SyncWrite>, bool) = match (&config.output, rotation) {
+            (Output::File(path), true) => {
+                let mut rotating = RotatingWriter::with_existing_log(
+                    path,
+                    config.max_log_size,
+                    config.max_log_files,
+                    config.rotate,
+                    config.time_format.timezone,
+                    Arc::clone(&config.clock),
+                    config.existing_log,
+                )?;
+                if let Some(compression) = config.compress {
+                    rotating = rotating.with_compression(compression)?;
+                }
+                if config.compress_rotated {
+                    rotating = rotating.with_rotated_compression();
+                }
+                match config.format {
+                    Format::Pcap => {
+                        rotating = rotating.with_header(pcap_global_header().to_vec())?;
+                    }
+                    Format::Bin => rotating = rotating.with_header(capture_header(session_id))?,
+                    _ => {}
+                }
+                (Box::new(rotating), true)
+            }
+            (Output::Stdout, true) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "log rotation needs a file output, not stdout",
+                ));
+            }
+            (_, false) => config.output.open(config.compress, config.existing_log)?,
+        };
+
+        LogWriter::wrap(
+            buffer(out, buffer_size),
+            config.format,
+            local_addr,
+            session_id,
+            has_header,
+        )
+    }
+
+    /// Wraps an open destination in the encoding for `format`. `has_header` says whether
+    /// a pcap or binary destination already starts with (or writes its own) header; if
+    /// not, one is written, a binary one naming `session_id`.
+    pub(crate) fn wrap(
+        mut out: Box<dyn SyncWrite>,
+        format: Format,
+        local_addr: SocketAddr,
+        session_id: &str,
+        has_header: bool,
+    ) -> io::Result<LogWriter> {
+        Ok(match (format, has_header) {
+            (Format::Pcap, false) => LogWriter::Pcap(PcapWriter::new(out, local_addr)?),
+            (Format::Pcap, true) => LogWriter::Pcap(PcapWriter::headerless(out, local_addr)),
+            (Format::Bin, false) => {
+                out.write_all(&capture_header(session_id))?;
+                LogWriter::Records(out, Vec::new())
+            }
+            (Format::Bin, true) => LogWriter::Records(out, Vec::new()),
+            _ => LogWriter::Lines(out),
+        })
+    }
+
+    /// Flushes the log and opens its file again by name, for when it has been renamed
+    /// or deleted by an outside tool such as logrotate. Returns whether a file was
+    /// reopened.
+    ///
+    /// The file is opened for appending, so nothing is lost if it wasn't moved after
+    /// all; a pcap or binary file only gets a new header if it's empty. Per-source files
+    /// are closed and reopened as their sources next send, and an output directory
+    /// moves on to a new segment. Stdout, and a log the
+    /// listener rotates itself, are only synced (see [`sync`](Self::sync)). If opening
+    /// the file fails, the old one is kept.
+    pub(crate) fn reopen(&mut self, config: &Config, local_addr: SocketAddr) -> io::Result<bool> {
+        self.sync()?;
+        let rotation = config.max_log_size.is_some() || config.rotate.is_some();
+        match (&mut *self, &config.output) {
+            (LogWriter::Split(split), _) => {
+                split.close_all()?;
+                Ok(true)
+            }
+            (LogWriter::Segments(segments), _) => {
+                segments.next_segment()?;
+                Ok(true)
+            }
+            (_, Output::File(path)) if !rotation => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!("Couldn't create or open '{}': {}", path.display(), e),
+                        )
+                    })?;
+                let has_header = file.metadata()?.len() > 0;
+                let out = Box::new(Compressor::new(file, config.compress));
+                *self = LogWriter::wrap(
+                    buffer(out, buffer_size(config)),
+                    config.format,
+                    local_addr,
+                    config.session_id.as_deref().unwrap_or_default(),
+                    has_header,
+                )?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Writes one packet in `format` with timestamps in `time_format` and, in a text
+    /// format, ending in `line_ending`, then flushes or syncs it as `after` says.
+    ///
+    /// For text formats `write_all()` writes the whole entry and a flush hands it to
+    /// the operating system straight away, not just to a buffer, so it's in the file
+    /// even if the listener is killed. Only a sync also survives the operating system
+    /// crashing or the power going.
+    pub(crate) fn write_packet(
+        &mut self,
+        pkt: &Packet,
+        format: Format,
+        time_format: &TimeFormat,
+        line_ending: &LineEnding,
+        after: AfterWrite,
+    ) -> io::Result<()> {
+        match self {
+            LogWriter::Lines(out) => {
+                let entry = format_entry(pkt, format, time_format, line_ending);
+                out.write_all(entry.as_bytes())?;
+                finish_write(out, after)?;
+            }
+            LogWriter::Pcap(pcap) => {
+                pcap.write_logged(pkt)?;
+                if after != AfterWrite::Nothing {
+                    pcap.flush()?;
+                }
+                finish_write(pcap.get_mut(), after)?;
+            }
+            // Written in one piece, like pcap records, so rotation never splits one.
+            LogWriter::Records(out, record) => {
+                record.clear();
+                encode_record_into(pkt, record);
+                out.write_all(record)?;
+                finish_write(out, after)?;
+            }
+            // Only the file the packet went to is flushed.
+            LogWriter::Split(split) => split.write_packet(pkt, time_format, line_ending, after)?,
+            LogWriter::Segments(segments) => {
+                segments.write_packet(pkt, time_format, line_ending, after)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes anything buffered through to the destination.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogWriter::Lines(out) => out.flush(),
+            LogWriter::Pcap(pcap) => pcap.flush(),
+            LogWriter::Records(out, _) => out.flush(),
+            LogWriter::Split(split) => split.flush(),
+            LogWriter::Segments(segments) => segments.flush(),
+        }
+    }
+
+    /// Flushes anything buffered and waits for it to reach the disk, for flushes forced
+    /// by the flush interval, a reopen or shutdown rather than by a full buffer.
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        match self {
+            LogWriter::Lines(out) => out.sync(),
+            LogWriter::Pcap(pcap) => {
+                pcap.flush()?;
+                pcap.get_mut().sync()
+            }
+            LogWriter::Records(out, _) => out.sync(),
+            LogWriter::Split(split) => split.sync(),
+            LogWriter::Segments(segments) => segments.sync(),
+        }
+    }
+}
+
+fn finish_write(out: &mut Box<dyn SyncWrite>, after: AfterWrite) -> io::Result<()> {
+    match after {
+        AfterWrite::Nothing => Ok(()),
+        AfterWrite::Flush => out.flush(),
+        AfterWrite::Sync => out.sync(),
+    }
+}
+
+/// How big a buffer collects entries between flushes, or `None` if every packet is
+/// flushed anyway.
+pub(crate) fn buffer_size(config: &Config) -> Option<usize> {
+    (config.flush != FlushPolicy::Every).then_some(config.write_buffer)
+}
+
+/// Puts a buffer of `size` bytes in front of `out`, if entries are to be collected
+/// between flushes.
+///
+/// `BufWriter` never splits a single write across two flushes, so every log entry
+/// still reaches the destination in one piece, as a `RotatingWriter` needs. An entry
+/// bigger than the whole buffer is written straight through.
+pub(crate) fn buffer(out: Box<dyn SyncWrite>, size: Option<usize>) -> Box<dyn SyncWrite> {
+    match size {
+        Some(size) => Box::new(BufWriter::with_capacity(size, out)),
+        None => out,
+    }
+}
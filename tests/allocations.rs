@@ -0,0 +1,135 @@
+// Steady-state capture doesn't touch the heap for each packet: a counting allocator
+// watches every allocation in the process while a listener logs a stream of packets.
+//
+// This is the only test in its binary, so nothing else allocates while it measures.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::UdpSocket;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use udp_listener::{Config, Format, Output, UdpLogger, Verbosity};
+
+/// Counts allocations, reallocations included, on the way to the system allocator.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: passed on unchanged from the caller.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: passed on unchanged from the caller.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: passed on unchanged from the caller.
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Packets logged before counting starts, for the buffer pool, the per-source table and
+/// the output's buffers to reach their working size.
+const WARM_UP: u64 = 2_000;
+
+/// Packets counted over.
+const MEASURED: u64 = 20_000;
+
+/// Allocations allowed for each second of measuring, for the work the listener does
+/// once a second whatever the traffic, such as reading the kernel's drop count.
+const PER_SECOND: u64 = 32;
+
+/// Logs packets from a sender on loopback with a listener set up by `config`, and
+/// returns how many allocations the whole process made while `MEASURED` of them were
+/// logged after the warm-up, and how long that took.
+fn allocations_while_logging(config: Config) -> (u64, Duration) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        bind_addr: "127.0.0.1:0".parse().unwrap(),
+        output: Output::File(dir.path().join("capture")),
+        verbosity: Verbosity::Quiet,
+        ..config
+    };
+    let mut logger = UdpLogger::new(config).unwrap();
+    let addr = logger.local_addr().unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let sender = {
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let payload = [0x5a; 1400];
+            let mut n: usize = 0;
+            while !done.load(Ordering::Relaxed) {
+                // Different lengths, so buffers are reused across sizes.
+                let len = 1 + n * 7919 % payload.len();
+                let _ = socket.send_to(&payload[..len], addr);
+                n = n.wrapping_add(1);
+            }
+        })
+    };
+
+    let mut logged = 0;
+    let mut start = (0, Instant::now());
+    let mut measured = (0, Duration::ZERO);
+    logger
+        .run_with(|_| {
+            logged += 1;
+            if logged == WARM_UP {
+                start = (ALLOCATIONS.load(Ordering::Relaxed), Instant::now());
+            }
+            if logged < WARM_UP + MEASURED {
+                return ControlFlow::Continue(());
+            }
+            measured = (
+                ALLOCATIONS.load(Ordering::Relaxed) - start.0,
+                start.1.elapsed(),
+            );
+            ControlFlow::Break(())
+        })
+        .unwrap();
+
+    done.store(true, Ordering::Relaxed);
+    sender.join().unwrap();
+    measured
+}
+
+#[test]
+fn steady_state_capture_allocates_nothing_per_packet() {
+    // The binary formats are written without building a line of text for each packet.
+    // A short queue fills during the warm-up, so by the time counting starts the pool
+    // has a buffer for every packet that can be on its way to the workers.
+    for format in [Format::Pcap, Format::Bin] {
+        for workers in [0, 2] {
+            let config = Config {
+                format,
+                workers,
+                queue_size: 16,
+                ..Config::default()
+            };
+            let (allocations, took) = allocations_while_logging(config);
+            let allowed = (took.as_secs() + 1) * PER_SECOND;
+            assert!(
+                allocations <= allowed,
+                "{:?} with {} workers: {} allocations for {} packets in {:?}",
+                format,
+                workers,
+                allocations,
+                MEASURED,
+                took
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// Running a listener on a loopback socket for the integration tests.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use chrono::{Local, TimeZone, Utc};
+use tempfile::TempDir;
+use udp_listener::{Config, MockClock, Output, Stats, Timezone, UdpLogger, Verbosity};
+
+/// A listener running [`UdpLogger::run`] on a thread of its own.
+pub struct Handle {
+    /// The address it's bound to, for sending to.
+    pub addr: SocketAddr,
+    /// The log file, in a directory that lasts as long as the handle.
+    log: PathBuf,
+    shutdown: Arc<AtomicBool>,
+    thread: JoinHandle<io::Result<Stats>>,
+    _dir: TempDir,
+}
+
+impl Handle {
+    /// Asks the listener to shut down, as Ctrl-C would, and waits for it to finish.
+    pub fn stop(self) -> Finished {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.wait()
+    }
+
+    /// Waits for the listener to stop by itself, as it does after
+    /// [`Config::max_packets`] packets.
+    pub fn wait(self) -> Finished {
+        let stats = self
+            .thread
+            .join()
+            .expect("the listener thread panicked")
+            .expect("the listener failed");
+        // Read before the directory goes with the handle.
+        let log = std::fs::read(&self.log).unwrap();
+        Finished { stats, log }
+    }
+}
+
+/// What a stopped listener left behind.
+pub struct Finished {
+    pub stats: Stats,
+    /// The whole log file.
+    pub log: Vec<u8>,
+}
+
+impl Finished {
+    /// The log, for one of the text formats.
+    pub fn text(&self) -> &str {
+        std::str::from_utf8(&self.log).unwrap()
+    }
+}
+
+/// Starts a listener set up by `config`, bound to a free port on 127.0.0.1 and logging
+/// to a file in a new temporary directory, quietly and with the time fixed by
+/// [`fixed_clock`]. It stops by itself after `config.max_packets` packets, if that's
+/// set.
+pub fn spawn_listener(mut config: Config) -> Handle {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("udp_packets.log");
+    config.bind_addr = "127.0.0.1:0".parse().unwrap();
+    config.output = Output::File(log.clone());
+    config.verbosity = Verbosity::Quiet;
+    config.time_format.timezone = Timezone::Utc;
+    config.clock = fixed_clock();
+
+    // The logger is made on its own thread, which then hands back where it's listening.
+    let (ready, started) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        let mut logger = match UdpLogger::new(config) {
+            Ok(logger) => logger,
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                return Err(io::Error::other("the listener didn't start"));
+            }
+        };
+        let _ = ready.send(Ok((logger.local_addr().unwrap(), logger.shutdown_handle())));
+        logger.run()?;
+        Ok(logger.stats().clone())
+    });
+    let (addr, shutdown) = started
+        .recv()
+        .expect("the listener thread panicked")
+        .unwrap();
+    Handle {
+        addr,
+        log,
+        shutdown,
+        thread,
+        _dir: dir,
+    }
+}
+
+/// A clock stopped at 2024-06-01 12:00:00 UTC.
+pub fn fixed_clock() -> Arc<MockClock> {
+    let start = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+    Arc::new(MockClock::new(start.with_timezone(&Local)))
+}
+
+/// Sends each payload to `to` from a new socket, returning the socket's address.
+pub fn send_from_new_socket(to: SocketAddr, payloads: &[&[u8]]) -> SocketAddr {
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for payload in payloads {
+        sender.send_to(payload, to).unwrap();
+    }
+    sender.local_addr().unwrap()
+}
@@ -0,0 +1,427 @@
+// The listener end to end: datagrams sent over loopback to a running logger, and the
+// log it writes.
+
+mod common;
+
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{send_from_new_socket, spawn_listener};
+use udp_listener::{Config, FlushPolicy, Format, LengthFilter, Payload, SendConfig, read_records};
+
+/// A config that stops the listener after `n` packets, in `format`.
+fn capture(format: Format, n: u64) -> Config {
+    Config {
+        format,
+        max_packets: Some(n),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn text_log() {
+    let listener = spawn_listener(capture(Format::Text, 2));
+    let src = send_from_new_socket(listener.addr, &[b"hello", b"world"]);
+    let done = listener.wait();
+    assert_eq!(done.stats.packets, 2);
+    assert_eq!(
+        done.text(),
+        format!(
+            "[2024-06-01 12:00:00.000] Received from {src}: hello\n\
+             [2024-06-01 12:00:00.000] Received from {src}: world\n"
+        )
+    );
+}
+
+#[test]
+fn hex_log() {
+    let listener = spawn_listener(capture(Format::Hex, 1));
+    let src = send_from_new_socket(listener.addr, &[b"\x00\x01hi"]);
+    let done = listener.wait();
+    assert_eq!(done.stats.packets, 1);
+    assert_eq!(
+        done.text(),
+        format!(
+            "[2024-06-01 12:00:00.000] Received 4 bytes from {src}:\n\
+             00000000  00 01 68 69                                       |..hi|\n"
+        )
+    );
+}
+
+#[test]
+fn json_log() {
+    let config = Config {
+        session_id: Some("loopback".to_string()),
+        ..capture(Format::Json, 1)
+    };
+    let listener = spawn_listener(config);
+    let src = send_from_new_socket(listener.addr, &[b"hello"]);
+    let done = listener.wait();
+    assert_eq!(done.stats.packets, 1);
+    assert_eq!(
+        done.text(),
+        format!(
+            "{{\"ts\":\"2024-06-01T12:00:00.000Z\",\"mono_ns\":0,\"session_id\":\"loopback\",\
+             \"src\":\"{src}\",\"src_ip\":\"127.0.0.1\",\"src_port\":{},\
+             \"len\":5,\"data_b64\":\"aGVsbG8=\"}}\n",
+            src.port()
+        )
+    );
+}
+
+#[test]
+fn base64_log() {
+    let listener = spawn_listener(capture(Format::Base64 { prefix: true }, 2));
+    let src = send_from_new_socket(listener.addr, &[b"hello", b"\xff\n"]);
+    let done = listener.wait();
+    assert_eq!(done.stats.packets, 2);
+    assert_eq!(
+        done.text(),
+        format!(
+            "2024-06-01T12:00:00.000Z {src} aGVsbG8=\n\
+             2024-06-01T12:00:00.000Z {src} /wo=\n"
+        )
+    );
+}
+
+#[test]
+fn indexed_logs_number_only_what_they_log() {
+    // The short datagram is filtered out, so it doesn't use up a number.
+    let config = Config {
+        index: true,
+        length_filter: LengthFilter {
+            min: Some(2),
+            max: None,
+        },
+        ..capture(Format::Text, 2)
+    };
+    let listener = spawn_listener(config);
+    let src = send_from_new_socket(listener.addr, &[b"hello", b"x", b"world"]);
+    let done = listener.wait();
+    assert_eq!(
+        done.text(),
+        format!(
+            "#1 [2024-06-01 12:00:00.000] Received from {src}: hello\n\
+             #2 [2024-06-01 12:00:00.000] Received from {src}: world\n"
+        )
+    );
+
+    let config = Config {
+        index: true,
+        ..capture(Format::Json, 1)
+    };
+    let listener = spawn_listener(config);
+    send_from_new_socket(listener.addr, &[b"hello"]);
+    assert!(listener.wait().text().starts_with("{\"idx\":1,\"ts\":"));
+}
+
+#[test]
+fn throttling_spaces_out_the_packets_taken() {
+    let config = Config {
+        throttle: Some(20.0),
+        ..capture(Format::Text, 5)
+    };
+    let listener = spawn_listener(config);
+    let started = Instant::now();
+    send_from_new_socket(listener.addr, &[b"1", b"2", b"3", b"4", b"5"]);
+    let done = listener.wait();
+    // The first packet is taken straight away, and each of the others 50ms after it.
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert_eq!(done.stats.packets, 5);
+}
+
+#[test]
+fn a_stats_file_carries_the_counts_on() {
+    let dir = tempfile::tempdir().unwrap();
+    let stats_file = dir.path().join("stats.json");
+    let run = |payloads: &[&[u8]]| {
+        let config = Config {
+            stats_file: Some(stats_file.clone()),
+            ..capture(Format::Text, payloads.len() as u64)
+        };
+        let listener = spawn_listener(config);
+        let src = send_from_new_socket(listener.addr, payloads);
+        (src, listener.wait().stats)
+    };
+    let (first, _) = run(&[b"hello", b"world"]);
+    let (_, stats) = run(&[b"again"]);
+
+    assert_eq!((stats.packets, stats.bytes), (3, 15));
+    assert_eq!((stats.carried_packets, stats.carried_bytes), (2, 10));
+    assert!(stats.per_source[&first].packets >= 2);
+    let per_source: u64 = stats.per_source.values().map(|source| source.packets).sum();
+    assert_eq!(per_source, 3);
+    assert!(!dir.path().join("stats.json.tmp").exists());
+}
+
+#[test]
+fn carried_over_packets_dont_count_towards_the_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let stats_file = dir.path().join("stats.json");
+    let run = |payloads: &[&[u8]]| {
+        let config = Config {
+            stats_file: Some(stats_file.clone()),
+            count_includes_filtered: true,
+            ..capture(Format::Text, payloads.len() as u64)
+        };
+        let listener = spawn_listener(config);
+        send_from_new_socket(listener.addr, payloads);
+        listener.wait()
+    };
+    run(&[b"hello", b"world"]);
+    let done = run(&[b"one", b"two"]);
+
+    assert_eq!(done.stats.packets, 4);
+    let text = done.text();
+    assert!(text.contains("one") && text.contains("two"), "{}", text);
+}
+
+#[test]
+fn pcap_log() {
+    let listener = spawn_listener(capture(Format::Pcap, 2));
+    let addr = listener.addr;
+    let src = send_from_new_socket(addr, &[b"hello", b"hi"]);
+    let log = listener.wait().log;
+
+    // The global header: the magic, and raw IP as the link type.
+    assert_eq!(log[0..4], 0xa1b2_c3d4u32.to_le_bytes());
+    assert_eq!(log[20..24], 101u32.to_le_bytes());
+    // Then each record: its header, an IPv4 header, a UDP header and the payload.
+    let mut rest = &log[24..];
+    for payload in [&b"hello"[..], b"hi"] {
+        let len = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+        assert_eq!(len, 20 + 8 + payload.len());
+        let (record, next) = rest[16..].split_at(len);
+        assert_eq!(record[12..16], [127, 0, 0, 1]);
+        assert_eq!(record[20..22], src.port().to_be_bytes());
+        assert_eq!(record[22..24], addr.port().to_be_bytes());
+        assert_eq!(&record[28..], payload);
+        rest = next;
+    }
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn binary_log_reads_back() {
+    let listener = spawn_listener(capture(Format::Bin, 3));
+    let src = send_from_new_socket(listener.addr, &[b"one", b"", b"three"]);
+    let log = listener.wait().log;
+    let packets: Vec<_> = read_records(&log[..]).map(Result::unwrap).collect();
+    let payloads: Vec<&[u8]> = packets.iter().map(|pkt| &pkt.data[..]).collect();
+    assert_eq!(payloads, [&b"one"[..], b"", b"three"]);
+    assert!(packets.iter().all(|pkt| pkt.src == src));
+    // The test clock stands still, on the monotonic clock too.
+    assert!(
+        packets
+            .iter()
+            .all(|pkt| pkt.monotonic == Some(Duration::ZERO))
+    );
+}
+
+#[test]
+fn filter_expression() {
+    let config = Config {
+        filter: Some(
+            "len > 3 and not payload ~ /^skip/ or payload contains \"ok\""
+                .parse()
+                .unwrap(),
+        ),
+        ..capture(Format::Text, 2)
+    };
+    let listener = spawn_listener(config);
+    let src = send_from_new_socket(listener.addr, &[b"abc", b"skip me", b"ok", b"long enough"]);
+    let done = listener.wait();
+    assert_eq!(done.stats.expression_filtered, 2);
+    assert_eq!(
+        done.text(),
+        format!(
+            "[2024-06-01 12:00:00.000] Received from {src}: ok\n\
+             [2024-06-01 12:00:00.000] Received from {src}: long enough\n"
+        )
+    );
+}
+
+#[test]
+fn send_templates_line_up_with_the_detectors() {
+    let dir = tempfile::tempdir().unwrap();
+    let template = dir.path().join("template");
+    std::fs::write(&template, "{{seq}}{{timestamp}} id={{uuid}}\n").unwrap();
+    let config = Config {
+        seq_offset: Some(0),
+        latency_offset: Some(4),
+        ..capture(Format::Bin, 3)
+    };
+    let listener = spawn_listener(config);
+    let send = SendConfig {
+        payload: Payload::Template(template),
+        ..SendConfig::new(listener.addr, 3)
+    };
+    udp_listener::send(&send).unwrap();
+    let done = listener.wait();
+
+    assert_eq!(done.stats.seq_missing, 0);
+    assert_eq!(done.stats.latency.count(), 3);
+    let packets: Vec<_> = read_records(&done.log[..]).map(Result::unwrap).collect();
+    for (seq, pkt) in packets.iter().enumerate() {
+        assert_eq!(pkt.data.len(), 4 + 8 + 4 + 36 + 1);
+        assert_eq!(pkt.data[0..4], (seq as u32).to_be_bytes());
+        assert_eq!(&pkt.data[12..16], b" id=");
+        let uuid = std::str::from_utf8(&pkt.data[16..52]).unwrap();
+        assert_eq!(
+            uuid.split('-').map(str::len).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+    }
+}
+
+#[test]
+fn zero_length_datagrams() {
+    let listener = spawn_listener(capture(Format::Text, 1));
+    let src = send_from_new_socket(listener.addr, &[b""]);
+    let done = listener.wait();
+    assert_eq!(done.stats.packets, 1);
+    assert_eq!(
+        done.text(),
+        format!("[2024-06-01 12:00:00.000] Received from {src}: <empty datagram>\n")
+    );
+
+    let listener = spawn_listener(capture(Format::Json, 1));
+    send_from_new_socket(listener.addr, &[b""]);
+    let done = listener.wait();
+    let log = done.text();
+    assert!(log.contains("\"len\":0,\"data_b64\":\"\""), "{}", log);
+}
+
+#[test]
+fn truncated_datagrams() {
+    let config = Config {
+        buffer_size: 8,
+        ..capture(Format::Json, 2)
+    };
+    let listener = spawn_listener(config);
+    send_from_new_socket(listener.addr, &[b"0123456789abcdef", b"short"]);
+    let done = listener.wait();
+    assert_eq!(done.stats.truncated, 1);
+    let lines: Vec<&str> = done.text().lines().collect();
+    assert_eq!(lines.len(), 2);
+    // Only what fitted in the buffer is logged, and the record says so.
+    assert!(
+        lines[0].contains("\"len\":8,\"data_b64\":\"MDEyMzQ1Njc=\",\"truncated\":true"),
+        "{}",
+        lines[0]
+    );
+    assert!(!lines[1].contains("truncated"), "{}", lines[1]);
+}
+
+#[test]
+fn multiple_sources() {
+    let listener = spawn_listener(capture(Format::Text, 3));
+    let first = send_from_new_socket(listener.addr, &[b"from the first"]);
+    let second = send_from_new_socket(listener.addr, &[b"from the second", b"again"]);
+    let done = listener.wait();
+    assert_eq!(done.stats.packets, 3);
+    assert_eq!(
+        done.text(),
+        format!(
+            "[2024-06-01 12:00:00.000] Received from {first}: from the first\n\
+             [2024-06-01 12:00:00.000] Received from {second}: from the second\n\
+             [2024-06-01 12:00:00.000] Received from {second}: again\n"
+        )
+    );
+}
+
+#[test]
+fn per_source_totals() {
+    let listener = spawn_listener(capture(Format::Text, 3));
+    let first = send_from_new_socket(listener.addr, &[b"a"]);
+    let second = send_from_new_socket(listener.addr, &[b"bb", b"ccc"]);
+    let stats = listener.wait().stats;
+    assert_eq!((stats.packets, stats.bytes), (3, 6));
+    assert_eq!(stats.per_source[&first].packets, 1);
+    assert_eq!(stats.per_source[&second].packets, 2);
+    assert_eq!(stats.per_source[&second].bytes, 5);
+}
+
+#[test]
+fn clean_shutdown_flushes_the_log() {
+    // Nothing is written before shutdown with this policy, so whatever is in the log
+    // afterwards was flushed by stopping.
+    let config = Config {
+        flush: FlushPolicy::Never,
+        ..Config::default()
+    };
+    let listener = spawn_listener(config);
+    let src = send_from_new_socket(listener.addr, &[b"one", b"two"]);
+    // Give the listener time to take both packets before it's asked to stop.
+    thread::sleep(Duration::from_millis(300));
+    let done = listener.stop();
+    assert_eq!(done.stats.packets, 2);
+    assert_eq!(
+        done.text(),
+        format!(
+            "[2024-06-01 12:00:00.000] Received from {src}: one\n\
+             [2024-06-01 12:00:00.000] Received from {src}: two\n"
+        )
+    );
+}
+
+#[test]
+fn shutdown_with_no_traffic() {
+    let listener = spawn_listener(Config::default());
+    let done = listener.stop();
+    assert_eq!(done.stats.packets, 0);
+    assert_eq!(done.text(), "");
+}
+
+#[test]
+fn connected_socket_hears_only_its_peer() {
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let config = Config {
+        connect: Some(peer.local_addr().unwrap()),
+        echo: true,
+        ..capture(Format::Text, 2)
+    };
+    let listener = spawn_listener(config);
+    // The kernel drops this before the listener sees it.
+    send_from_new_socket(listener.addr, &[b"from a stranger"]);
+    peer.send_to(b"one", listener.addr).unwrap();
+    peer.send_to(b"two", listener.addr).unwrap();
+    let done = listener.wait();
+    let src = peer.local_addr().unwrap();
+    assert_eq!(
+        done.text(),
+        format!(
+            "[2024-06-01 12:00:00.000] Received from {src}: one\n\
+             [2024-06-01 12:00:00.000] Received from {src}: two\n"
+        )
+    );
+    // The echoes went back over the connected socket.
+    let mut buf = [0; 16];
+    for expected in [b"one", b"two"] {
+        let len = peer.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], expected);
+    }
+}
+
+// Linux lets a connected socket send elsewhere with `send_to`, which this relies on.
+#[cfg(target_os = "linux")]
+#[test]
+fn connected_socket_counts_port_unreachable() {
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let config = Config {
+        connect: Some(peer.local_addr().unwrap()),
+        echo: true,
+        ..Config::default()
+    };
+    let listener = spawn_listener(config);
+    // Connected to another address, the peer's socket doesn't take the echo, so the
+    // kernel answers it with port unreachable, which comes back to the listener on its
+    // next receive.
+    peer.connect("127.0.0.1:9").unwrap();
+    peer.send_to(b"unanswered", listener.addr).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let done = listener.stop();
+    assert_eq!(done.stats.packets, 1);
+    assert_eq!(done.stats.icmp_errors, 1);
+}